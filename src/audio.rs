@@ -1,23 +1,128 @@
-use bevy::prelude::{App, Plugin, Resource};
+use crate::midi::{file_timing, FileTiming};
+use crate::state::{
+    audible_channels, ChannelMixer, ChannelStrip, Equalizer, FileGains, Interpolation, LoopRegion,
+    LoopSeam, MasterVolume, Metronome, MidiFilePath, MidiTrackInfo, MidiTracks, PlaybackSpeed,
+    PlaybackState, PlaybackStatus, Preferences, SongEnd, SoundFontGains, SoundFontLayers,
+    SoundFontPath, StatusMessage, StereoWidth, TempoOverride, TrackTranspose, TracksFocus,
+};
+use crate::tempo::{file_tempo_map, TempoMap};
+use bevy::ecs::system::SystemParam;
+use bevy::log::{debug, error, info, warn};
+use bevy::prelude::{
+    App, AppExit, Commands, Component, DetectChanges, Entity, Last, Local, MessageReader, Plugin,
+    Query, Res, ResMut, Resource, Update, With,
+};
+use bevy::tasks::{AsyncComputeTaskPool, Task};
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{BufferSize, SampleFormat, StreamConfig, SupportedBufferSize, SupportedStreamConfig};
+use futures_lite::future;
 use midly::{Smf, TrackEventKind};
-use oxisynth::{MidiEvent, SoundFont, Synth};
-use std::path::PathBuf;
-use std::sync::atomic::{AtomicU64, Ordering};
+use oxisynth::{ChorusParams, InterpolationMethod, MidiEvent, ReverbParams, SoundFont, Synth};
+use serde::Deserialize;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicU8, Ordering};
 use std::sync::mpsc::{channel, Receiver, Sender};
 use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::{Duration, Instant};
 
 pub enum AudioCommand {
     Play(PathBuf, PathBuf),
     Pause,
     Stop,
     Rewind,
+    /// Reads the file again and carries on from the same tick, clamped to
+    /// the new song length.
+    Reload(PathBuf),
+    Reset {
+        keep_soundfont: bool,
+    },
+    Seek(u64),
+    SetInterpolation(Interpolation),
+    SetReverbTail(f32),
+    /// Level of the synth's built-in reverb, `0.0..=1.0`.
+    SetReverb(f32),
+    /// Level of the synth's chorus unit; see `Preferences::chorus_level`.
+    SetChorus(f32),
+    /// Loads a SoundFont over the ones already loaded; its presets win
+    /// where they overlap.
+    AddSoundFont(PathBuf),
+    /// Drops every SoundFont added with `AddSoundFont`, keeping the one
+    /// `Play` loaded.
+    ClearSoundFonts,
+    SetLoop(Option<(u64, u64)>),
+    /// Start over from the top once the song and its tail have played. A
+    /// bar loop takes precedence.
+    SetSongLoop(bool),
+    SetLoopSeam(LoopSeam),
+    SetSampleRate(Option<u32>),
+    SetTranspose(HashMap<usize, i8>),
+    /// Plays at a fixed tempo (microseconds per beat) instead of the file's.
+    SetTempoOverride(Option<u32>),
+    /// Multiplies the tempo, clamped to `0.25..=4.0`; see `PlaybackSpeed`.
+    SetSpeed(f32),
+    SetPolyphony(u16),
+    /// Play a short snippet from each seek target while stopped or paused.
+    SetScrubOnSeek(bool),
+    /// Output gain in dB for the current file, from loudness analysis.
+    SetFileGain(f32),
+    /// Input gain in dB for the loaded SoundFont, applied to the synth output.
+    SetSoundFontGain(f32),
+    /// Output multiplier applied last, clamped to `0.0..=2.0`; see
+    /// `MasterVolume`.
+    SetVolume(f32),
+    /// Mid/side width of the output; see `StereoWidth`.
+    SetStereoWidth(f32),
+    /// Master EQ gains in dB; see `Equalizer`.
+    SetEq {
+        low: f32,
+        mid: f32,
+        high: f32,
+    },
+    /// Restrict notes to one track index, or `None` for all of them.
+    SetOnlyTrack(Option<usize>),
+    /// Whether playback stops at the last note or the End of Track marker.
+    SetSongEnd(SongEnd),
+    /// Click positions as `(tick, accented)`, sorted; empty turns the
+    /// click off.
+    SetClicks(Vec<(u64, bool)>),
+    /// Keep clicking beats while nothing plays, so the metronome doesn't
+    /// depend on playback; see `SetMetronomeMeter`.
+    SetMetronome(bool),
+    /// Beat length and bar length of the click `SetMetronome` turns on.
+    SetMetronomeMeter {
+        beat_seconds: f64,
+        beats_per_bar: u32,
+    },
+    /// Mixer volume, pan, mute and solo for each channel.
+    SetChannelMix([ChannelStrip; 16]),
+    StepEvent,
+    /// Silences the synth and closes the output stream; the audio thread
+    /// exits after handling it.
+    Shutdown,
+    /// Plays one note outside the schedule, e.g. from a piano roll click.
+    Audition {
+        channel: u8,
+        key: u8,
+        /// The track's program, for before a file has been scheduled; after
+        /// that the one at the playhead is used.
+        program: Option<u8>,
+        soundfont: PathBuf,
+    },
 }
 
 #[derive(Resource)]
 pub struct AudioSender(pub Sender<AudioCommand>);
 
+#[derive(Resource)]
+struct AudioThread(Option<thread::JoinHandle<()>>);
+
+// How long exit waits for the audio thread to release notes and close its
+// stream before giving up on it.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_millis(500);
+
 #[derive(Resource, Clone)]
 pub struct AudioState {
     pub samples_played: Arc<AtomicU64>,
@@ -27,6 +132,13 @@ pub struct AudioState {
     last_event_tick: Arc<AtomicU64>,
     next_event_sample: Arc<AtomicU64>,
     next_event_tick: Arc<AtomicU64>,
+    sample_rate: Arc<AtomicU64>,
+    channel_activity: Arc<[AtomicU64; 16]>,
+    held_notes: Arc<AtomicU64>,
+    peak_notes: Arc<AtomicU64>,
+    clip_count: Arc<AtomicU64>,
+    finished: Arc<AtomicBool>,
+    notice: Arc<Mutex<Option<String>>>,
 }
 
 pub struct AudioDebugState {
@@ -37,6 +149,8 @@ pub struct AudioDebugState {
     pub last_event_tick: u64,
     pub next_event_tick: u64,
     pub max_tick: u64,
+    pub held_notes: u64,
+    pub peak_notes: u64,
 }
 
 impl AudioState {
@@ -86,6 +200,44 @@ impl AudioState {
         Some(tick.min(max_tick))
     }
 
+    /// True once playback has rendered the last note plus the reverb tail.
+    /// A looping song never finishes.
+    pub fn is_finished(&self) -> bool {
+        self.finished.load(Ordering::Relaxed)
+    }
+
+    pub fn active_channels(&self, window_seconds: f64) -> [bool; 16] {
+        let window_samples =
+            (self.sample_rate.load(Ordering::Relaxed) as f64 * window_seconds).round() as u64;
+        let last_activity =
+            std::array::from_fn(|channel| self.channel_activity[channel].load(Ordering::Relaxed));
+        active_channels(
+            &last_activity,
+            self.samples_played.load(Ordering::Relaxed),
+            window_samples,
+        )
+    }
+
+    /// Number of output buffers so far with a sample beyond ±1.0.
+    pub fn clip_count(&self) -> u64 {
+        self.clip_count.load(Ordering::Relaxed)
+    }
+
+    pub fn sample_rate(&self) -> u64 {
+        self.sample_rate.load(Ordering::Relaxed)
+    }
+
+    /// Takes the latest message the audio thread wants shown to the user.
+    pub fn take_notice(&self) -> Option<String> {
+        self.notice.lock().ok().and_then(|mut notice| notice.take())
+    }
+
+    /// End of the ruler the playhead moves along, or `None` before a file
+    /// has been scheduled.
+    pub fn ruler_max_tick(&self) -> Option<u64> {
+        Some(self.max_tick.load(Ordering::Relaxed)).filter(|tick| *tick > 0)
+    }
+
     pub fn debug_state(&self) -> AudioDebugState {
         AudioDebugState {
             samples_played: self.samples_played.load(Ordering::Relaxed),
@@ -95,435 +247,2452 @@ impl AudioState {
             last_event_tick: self.last_event_tick.load(Ordering::Relaxed),
             next_event_tick: self.next_event_tick.load(Ordering::Relaxed),
             max_tick: self.max_tick.load(Ordering::Relaxed),
+            held_notes: self.held_notes.load(Ordering::Relaxed),
+            peak_notes: self.peak_notes.load(Ordering::Relaxed),
         }
     }
 }
 
+// Activity is stored as `sample + 1` so that zero means the channel has not
+// played since the last reset.
+fn active_channels(last_activity: &[u64; 16], samples_played: u64, window: u64) -> [bool; 16] {
+    std::array::from_fn(|channel| {
+        let last = last_activity[channel];
+        last > 0 && last - 1 <= samples_played && samples_played - (last - 1) <= window
+    })
+}
+
+fn reset_channel_activity(channel_activity: &[AtomicU64; 16]) {
+    for activity in channel_activity {
+        activity.store(0, Ordering::Relaxed);
+    }
+}
+
 pub struct AudioPlugin;
 
 impl Plugin for AudioPlugin {
     fn build(&self, app: &mut App) {
         let (cmd_tx, cmd_rx) = channel::<AudioCommand>();
-        let samples_played = Arc::new(AtomicU64::new(0));
-        let total_samples = Arc::new(AtomicU64::new(0));
-        let max_tick = Arc::new(AtomicU64::new(0));
-        let last_event_sample = Arc::new(AtomicU64::new(0));
-        let last_event_tick = Arc::new(AtomicU64::new(0));
-        let next_event_sample = Arc::new(AtomicU64::new(0));
-        let next_event_tick = Arc::new(AtomicU64::new(0));
+        let output_config = OutputConfig::load();
         let audio_state = AudioState {
-            samples_played: Arc::clone(&samples_played),
-            total_samples: Arc::clone(&total_samples),
-            max_tick: Arc::clone(&max_tick),
-            last_event_sample: Arc::clone(&last_event_sample),
-            last_event_tick: Arc::clone(&last_event_tick),
-            next_event_sample: Arc::clone(&next_event_sample),
-            next_event_tick: Arc::clone(&next_event_tick),
+            samples_played: Arc::new(AtomicU64::new(0)),
+            total_samples: Arc::new(AtomicU64::new(0)),
+            max_tick: Arc::new(AtomicU64::new(0)),
+            last_event_sample: Arc::new(AtomicU64::new(0)),
+            last_event_tick: Arc::new(AtomicU64::new(0)),
+            next_event_sample: Arc::new(AtomicU64::new(0)),
+            next_event_tick: Arc::new(AtomicU64::new(0)),
+            sample_rate: Arc::new(AtomicU64::new(0)),
+            channel_activity: Arc::new(std::array::from_fn(|_| AtomicU64::new(0))),
+            held_notes: Arc::new(AtomicU64::new(0)),
+            peak_notes: Arc::new(AtomicU64::new(0)),
+            clip_count: Arc::new(AtomicU64::new(0)),
+            finished: Arc::new(AtomicBool::new(false)),
+            notice: Arc::new(Mutex::new(None)),
         };
 
         // Start audio thread
-        let samples_played_thread = Arc::clone(&samples_played);
-        let total_samples_thread = Arc::clone(&total_samples);
-        let max_tick_thread = Arc::clone(&max_tick);
-        let last_event_sample_thread = Arc::clone(&last_event_sample);
-        let last_event_tick_thread = Arc::clone(&last_event_tick);
-        let next_event_sample_thread = Arc::clone(&next_event_sample);
-        let next_event_tick_thread = Arc::clone(&next_event_tick);
-        let _ = thread::spawn(move || {
-            println!("Audio thread spawned.");
-            audio_thread(
-                cmd_rx,
-                samples_played_thread,
-                total_samples_thread,
-                max_tick_thread,
-                last_event_sample_thread,
-                last_event_tick_thread,
-                next_event_sample_thread,
-                next_event_tick_thread,
-            );
+        let thread_state = audio_state.clone();
+        let audio_thread_handle = thread::spawn(move || {
+            info!("Audio thread spawned.");
+            audio_thread(cmd_rx, thread_state, output_config);
         });
         let _ = app
             .insert_resource(AudioSender(cmd_tx))
-            .insert_resource(audio_state);
+            .insert_resource(AudioThread(Some(audio_thread_handle)))
+            .insert_resource(audio_state)
+            .add_systems(Last, shutdown_audio_on_exit)
+            .add_systems(
+                Update,
+                (
+                    sync_audio_preferences,
+                    sync_loop_region,
+                    sync_track_transpose,
+                    sync_tempo_override,
+                    sync_playback_speed,
+                    stop_at_end,
+                    show_audio_notice,
+                    sync_file_gain,
+                    sync_soundfont_gain,
+                    sync_master_volume,
+                    sync_soundfont_layers,
+                    sync_stereo_width,
+                    sync_equalizer,
+                    sync_click_track,
+                    sync_practice_click,
+                    sync_channel_mixer,
+                    analyze_file_loudness,
+                    poll_loudness_tasks,
+                ),
+            );
     }
 }
 
-struct MidiPlaybackEvent {
-    tick: u64,
-    sample: u64,
-    event: MidiEvent,
+fn shutdown_audio_on_exit(
+    mut exits: MessageReader<AppExit>,
+    audio_tx: Res<AudioSender>,
+    mut audio_thread: ResMut<AudioThread>,
+) {
+    if exits.read().next().is_none() {
+        return;
+    }
+    let Some(handle) = audio_thread.0.take() else {
+        return;
+    };
+    if audio_tx.0.send(AudioCommand::Shutdown).is_err() {
+        return;
+    }
+    let deadline = Instant::now() + SHUTDOWN_TIMEOUT;
+    while !handle.is_finished() {
+        if Instant::now() >= deadline {
+            warn!("Audio thread did not stop in time; exiting anyway.");
+            return;
+        }
+        thread::sleep(Duration::from_millis(5));
+    }
+    if handle.join().is_err() {
+        error!("Audio thread panicked during shutdown.");
+    }
 }
 
-struct PlaybackSchedule {
-    events: Vec<MidiPlaybackEvent>,
-    ruler_max_tick: u64,
-    total_samples: u64,
+/// The preferences the audio thread has been told about, so only the ones
+/// that changed are sent again.
+#[derive(Clone, Copy)]
+struct AudioPreferences {
+    interpolation: Interpolation,
+    reverb_tail_seconds: f32,
+    reverb_level: f32,
+    chorus_level: f32,
+    output_sample_rate: Option<u32>,
+    polyphony: u16,
+    loop_seam: LoopSeam,
+    scrub_on_seek: bool,
+    song_end: SongEnd,
+    loop_song: bool,
 }
 
-#[derive(Clone, Copy)]
-struct TempoSegment {
-    tick: u64,
-    us_per_beat: u32,
-    seconds_at_tick: f64,
+impl AudioPreferences {
+    fn of(preferences: &Preferences) -> Self {
+        Self {
+            interpolation: preferences.interpolation,
+            reverb_tail_seconds: preferences.reverb_tail_seconds,
+            reverb_level: preferences.reverb_level,
+            chorus_level: preferences.chorus_level,
+            output_sample_rate: preferences.output_sample_rate,
+            polyphony: preferences.polyphony,
+            loop_seam: preferences.loop_seam,
+            scrub_on_seek: preferences.scrub_on_seek,
+            song_end: preferences.song_end,
+            loop_song: preferences.loop_song,
+        }
+    }
+}
+
+fn sync_audio_preferences(
+    preferences: Res<Preferences>,
+    audio_tx: Res<AudioSender>,
+    mut sent: Local<Option<AudioPreferences>>,
+) {
+    if !preferences.is_changed() {
+        return;
+    }
+    let current = AudioPreferences::of(&preferences);
+    let previous = sent.replace(current);
+    if previous.is_none_or(|previous| previous.interpolation != current.interpolation) {
+        let _ = audio_tx
+            .0
+            .send(AudioCommand::SetInterpolation(current.interpolation));
+    }
+    if previous.is_none_or(|previous| previous.reverb_tail_seconds != current.reverb_tail_seconds) {
+        let _ = audio_tx
+            .0
+            .send(AudioCommand::SetReverbTail(current.reverb_tail_seconds));
+    }
+    if previous.is_none_or(|previous| previous.reverb_level != current.reverb_level) {
+        let _ = audio_tx
+            .0
+            .send(AudioCommand::SetReverb(current.reverb_level));
+    }
+    if previous.is_none_or(|previous| previous.chorus_level != current.chorus_level) {
+        let _ = audio_tx
+            .0
+            .send(AudioCommand::SetChorus(current.chorus_level));
+    }
+    if previous.is_none_or(|previous| previous.output_sample_rate != current.output_sample_rate) {
+        let _ = audio_tx
+            .0
+            .send(AudioCommand::SetSampleRate(current.output_sample_rate));
+    }
+    if previous.is_none_or(|previous| previous.polyphony != current.polyphony) {
+        let _ = audio_tx
+            .0
+            .send(AudioCommand::SetPolyphony(current.polyphony));
+    }
+    if previous.is_none_or(|previous| previous.loop_seam != current.loop_seam) {
+        let _ = audio_tx
+            .0
+            .send(AudioCommand::SetLoopSeam(current.loop_seam));
+    }
+    if previous.is_none_or(|previous| previous.scrub_on_seek != current.scrub_on_seek) {
+        let _ = audio_tx
+            .0
+            .send(AudioCommand::SetScrubOnSeek(current.scrub_on_seek));
+    }
+    if previous.is_none_or(|previous| previous.song_end != current.song_end) {
+        let _ = audio_tx.0.send(AudioCommand::SetSongEnd(current.song_end));
+    }
+    if previous.is_none_or(|previous| previous.loop_song != current.loop_song) {
+        let _ = audio_tx
+            .0
+            .send(AudioCommand::SetSongLoop(current.loop_song));
+    }
 }
 
-fn build_tempo_segments(tempo_events: &[(u64, u32)], ticks_per_beat: f64) -> Vec<TempoSegment> {
-    let mut segments = Vec::new();
-    let mut sorted = tempo_events.to_vec();
-    sorted.sort_by_key(|(tick, _)| *tick);
+const LOUDNESS_TARGET_DB: f32 = -18.0;
+const LOUDNESS_GATE_DB: f64 = -70.0;
+// Loudness doesn't need full bandwidth, and a lower rate halves the render.
+const LOUDNESS_SAMPLE_RATE: u32 = 22_050;
 
-    let mut current = TempoSegment {
-        tick: 0,
-        us_per_beat: 500_000,
-        seconds_at_tick: 0.0,
-    };
-    segments.push(current);
+/// Mean power of a render in dBFS. Near-silent blocks are skipped so rests
+/// and the reverb tail don't drag quiet-ending files down.
+#[derive(Default)]
+struct LoudnessMeter {
+    sum_squares: f64,
+    samples: u64,
+}
 
-    for (tick, us_per_beat) in sorted {
-        if tick == current.tick {
-            current.us_per_beat = us_per_beat;
-            segments.last_mut().unwrap().us_per_beat = us_per_beat;
-            continue;
+impl LoudnessMeter {
+    fn add_block(&mut self, block: &[f32]) {
+        if block.is_empty() {
+            return;
         }
-        let delta_ticks = tick.saturating_sub(current.tick);
-        let seconds_delta =
-            (delta_ticks as f64 * current.us_per_beat as f64) / (1_000_000.0 * ticks_per_beat);
-        current = TempoSegment {
-            tick,
-            us_per_beat,
-            seconds_at_tick: current.seconds_at_tick + seconds_delta,
-        };
-        segments.push(current);
+        let sum_squares: f64 = block.iter().map(|sample| (*sample as f64).powi(2)).sum();
+        if power_db(sum_squares / block.len() as f64) < LOUDNESS_GATE_DB {
+            return;
+        }
+        self.sum_squares += sum_squares;
+        self.samples += block.len() as u64;
+    }
+
+    fn loudness_db(&self) -> Option<f32> {
+        (self.samples > 0).then(|| power_db(self.sum_squares / self.samples as f64) as f32)
     }
+}
 
-    segments
+fn power_db(mean_square: f64) -> f64 {
+    10.0 * mean_square.max(1e-12).log10()
 }
 
-fn ticks_to_seconds(tick: u64, segments: &[TempoSegment], ticks_per_beat: f64) -> f64 {
-    let mut active = segments[0];
-    for segment in segments.iter().skip(1) {
-        if segment.tick > tick {
-            break;
-        }
-        active = *segment;
+fn normalization_gain_db(loudness_db: f32) -> f32 {
+    (LOUDNESS_TARGET_DB - loudness_db).clamp(-FileGains::MAX_DB, FileGains::MAX_DB)
+}
+
+fn db_to_gain(db: f32) -> f32 {
+    10f32.powf(db / 20.0)
+}
+
+#[derive(Component)]
+struct LoudnessTask(Task<Option<f32>>, PathBuf);
+
+fn sync_file_gain(
+    preferences: Res<Preferences>,
+    midi_path: Res<MidiFilePath>,
+    file_gains: Res<FileGains>,
+    audio_tx: Res<AudioSender>,
+    mut sent_gain: Local<Option<f32>>,
+) {
+    if !preferences.is_changed() && !midi_path.is_changed() && !file_gains.is_changed() {
+        return;
+    }
+    let gain_db = midi_path
+        .0
+        .as_ref()
+        .filter(|_| preferences.normalize_loudness)
+        .and_then(|path| file_gains.0.get(path).copied())
+        .unwrap_or(0.0);
+    if *sent_gain != Some(gain_db) {
+        *sent_gain = Some(gain_db);
+        let _ = audio_tx.0.send(AudioCommand::SetFileGain(gain_db));
     }
-    let delta_ticks = tick.saturating_sub(active.tick);
-    let seconds_delta =
-        (delta_ticks as f64 * active.us_per_beat as f64) / (1_000_000.0 * ticks_per_beat);
-    active.seconds_at_tick + seconds_delta
 }
 
-struct ParsedMidi {
-    events: Vec<(u64, MidiEvent)>,
-    tempo_events: Vec<(u64, u32)>,
-    max_tick: u64,
-    max_note_tick: u64,
+fn sync_soundfont_gain(
+    soundfont_path: Res<SoundFontPath>,
+    soundfont_gains: Res<SoundFontGains>,
+    audio_tx: Res<AudioSender>,
+    mut sent_gain: Local<Option<f32>>,
+) {
+    if !soundfont_path.is_changed() && !soundfont_gains.is_changed() {
+        return;
+    }
+    let gain_db = soundfont_path
+        .0
+        .as_ref()
+        .and_then(|path| soundfont_gains.0.get(path).copied())
+        .unwrap_or(0.0);
+    if *sent_gain != Some(gain_db) {
+        *sent_gain = Some(gain_db);
+        let _ = audio_tx.0.send(AudioCommand::SetSoundFontGain(gain_db));
+    }
 }
 
-fn midi_message_to_event(channel: u8, message: midly::MidiMessage) -> MidiEvent {
-    match message {
-        midly::MidiMessage::NoteOff { key, .. } => MidiEvent::NoteOff {
-            channel,
-            key: key.as_int() as u8,
-        },
-        midly::MidiMessage::NoteOn { key, vel } => MidiEvent::NoteOn {
-            channel,
-            key: key.as_int() as u8,
-            vel: vel.as_int() as u8,
-        },
-        midly::MidiMessage::Aftertouch { key, vel } => MidiEvent::PolyphonicKeyPressure {
-            channel,
-            key: key.as_int() as u8,
-            value: vel.as_int() as u8,
-        },
-        midly::MidiMessage::Controller { controller, value } => MidiEvent::ControlChange {
-            channel,
-            ctrl: controller.as_int() as u8,
-            value: value.as_int() as u8,
-        },
-        midly::MidiMessage::ProgramChange { program } => MidiEvent::ProgramChange {
-            channel,
-            program_id: program.as_int() as u8,
-        },
-        midly::MidiMessage::ChannelAftertouch { vel } => MidiEvent::ChannelPressure {
-            channel,
-            value: vel.as_int() as u8,
-        },
-        midly::MidiMessage::PitchBend { bend } => MidiEvent::PitchBend {
-            channel,
-            value: bend.as_int() as u16,
-        },
+fn sync_soundfont_layers(
+    layers: Res<SoundFontLayers>,
+    audio_tx: Res<AudioSender>,
+    mut sent: Local<Vec<PathBuf>>,
+) {
+    if !layers.is_changed() {
+        return;
     }
+    for command in soundfont_layer_commands(&sent, &layers.0) {
+        let _ = audio_tx.0.send(command);
+    }
+    *sent = layers.0.clone();
 }
 
-fn parse_smf(smf: &Smf) -> ParsedMidi {
-    let mut all_events = Vec::new();
-    let mut tempo_events = Vec::new();
-    let mut max_tick = 0u64;
-    let mut max_note_tick = 0u64;
+// New layers on top of the sent ones are just added; anything else starts
+// the stack over.
+fn soundfont_layer_commands(sent: &[PathBuf], wanted: &[PathBuf]) -> Vec<AudioCommand> {
+    let mut commands = Vec::new();
+    let added = wanted.strip_prefix(sent).unwrap_or_else(|| {
+        commands.push(AudioCommand::ClearSoundFonts);
+        wanted
+    });
+    commands.extend(added.iter().cloned().map(AudioCommand::AddSoundFont));
+    commands
+}
 
-    for track in &smf.tracks {
-        let mut current_tick = 0u64;
-        let mut last_tick = 0u64;
-        let mut active_notes: Vec<Vec<u64>> = vec![Vec::new(); 128];
-        for event in track {
-            current_tick += event.delta.as_int() as u64;
-            last_tick = current_tick;
-            max_tick = max_tick.max(current_tick);
-            match event.kind {
-                TrackEventKind::Midi { channel, message } => {
-                    let channel = channel.as_int() as u8;
-                    match message {
-                        midly::MidiMessage::NoteOff { key, .. } => {
-                            let idx = key.as_int() as usize;
-                            if active_notes[idx].pop().is_some() {
-                                max_note_tick = max_note_tick.max(current_tick);
-                            }
-                        }
-                        midly::MidiMessage::NoteOn { key, vel } => {
-                            let idx = key.as_int() as usize;
-                            if vel.as_int() > 0 {
-                                active_notes[idx].push(current_tick);
-                                max_note_tick = max_note_tick.max(current_tick);
-                            } else if active_notes[idx].pop().is_some() {
-                                max_note_tick = max_note_tick.max(current_tick);
-                            }
-                        }
-                        midly::MidiMessage::Aftertouch { .. }
-                        | midly::MidiMessage::Controller { .. }
-                        | midly::MidiMessage::ProgramChange { .. }
-                        | midly::MidiMessage::ChannelAftertouch { .. }
-                        | midly::MidiMessage::PitchBend { .. } => {}
-                    }
-                    all_events.push((current_tick, midi_message_to_event(channel, message)));
-                }
-                TrackEventKind::Meta(midly::MetaMessage::Tempo(us)) => {
-                    tempo_events.push((current_tick, us.as_int()));
-                }
-                TrackEventKind::Meta(
-                    midly::MetaMessage::TrackName(_)
-                    | midly::MetaMessage::TrackNumber(_)
-                    | midly::MetaMessage::Text(_)
-                    | midly::MetaMessage::Copyright(_)
-                    | midly::MetaMessage::InstrumentName(_)
-                    | midly::MetaMessage::Lyric(_)
-                    | midly::MetaMessage::Marker(_)
-                    | midly::MetaMessage::CuePoint(_)
-                    | midly::MetaMessage::ProgramName(_)
-                    | midly::MetaMessage::DeviceName(_)
-                    | midly::MetaMessage::MidiChannel(_)
-                    | midly::MetaMessage::MidiPort(_)
-                    | midly::MetaMessage::EndOfTrack
-                    | midly::MetaMessage::SmpteOffset(_)
-                    | midly::MetaMessage::TimeSignature(_, _, _, _)
-                    | midly::MetaMessage::KeySignature(_, _)
-                    | midly::MetaMessage::SequencerSpecific(_)
-                    | midly::MetaMessage::Unknown(_, _),
-                )
-                | TrackEventKind::SysEx(_)
-                | TrackEventKind::Escape(_) => {}
-            }
-        }
-        if active_notes.iter().any(|notes| !notes.is_empty()) {
-            max_note_tick = max_note_tick.max(last_tick);
-        }
+fn sync_master_volume(master_volume: Res<MasterVolume>, audio_tx: Res<AudioSender>) {
+    if master_volume.is_changed() {
+        let _ = audio_tx.0.send(AudioCommand::SetVolume(master_volume.0));
     }
+}
 
-    all_events.sort_by_key(|(tick, _)| *tick);
+fn sync_stereo_width(stereo_width: Res<StereoWidth>, audio_tx: Res<AudioSender>) {
+    if stereo_width.is_changed() {
+        let _ = audio_tx
+            .0
+            .send(AudioCommand::SetStereoWidth(stereo_width.0));
+    }
+}
 
-    ParsedMidi {
-        events: all_events,
-        tempo_events,
-        max_tick,
-        max_note_tick,
+fn sync_click_track(
+    metronome: Res<Metronome>,
+    preferences: Res<Preferences>,
+    midi_tracks: Res<MidiTracks>,
+    tracks_focus: Res<TracksFocus>,
+    audio_tx: Res<AudioSender>,
+) {
+    // Focus only matters while clicking the focused track's notes, but then
+    // it has to follow along mid-playback.
+    let focus_changed = *metronome == Metronome::Onsets && tracks_focus.is_changed();
+    if !metronome.is_changed()
+        && !preferences.is_changed()
+        && !midi_tracks.is_changed()
+        && !focus_changed
+    {
+        return;
     }
+    // The click kept going while stopped carries on through playback as
+    // beats, unless the click track already follows something.
+    let mode = match *metronome {
+        Metronome::Off if preferences.practice_click => Metronome::Beats,
+        mode => mode,
+    };
+    let clicks = click_ticks(mode, &midi_tracks.0, tracks_focus.index);
+    let _ = audio_tx.0.send(AudioCommand::SetClicks(clicks));
 }
 
-fn build_playback_schedule_from_smf(smf: &Smf, sample_rate: u32) -> PlaybackSchedule {
-    let parsed = parse_smf(smf);
-    let ticks_per_beat = match smf.header.timing {
-        midly::Timing::Metrical(ticks) => ticks.as_int() as f64,
-        midly::Timing::Timecode(_, _) => 480.0,
+/// The file and the tempo changes layered over it: what turns ticks into
+/// seconds.
+#[derive(SystemParam)]
+struct SongTempo<'w> {
+    midi_tracks: Res<'w, MidiTracks>,
+    tempo_override: Res<'w, TempoOverride>,
+    speed: Res<'w, PlaybackSpeed>,
+}
+
+impl SongTempo<'_> {
+    fn is_changed(&self) -> bool {
+        self.midi_tracks.is_changed() || self.tempo_override.is_changed() || self.speed.is_changed()
     }
-    .max(1.0);
-    let tempo_segments = build_tempo_segments(&parsed.tempo_events, ticks_per_beat);
+}
 
-    let mut playback = Vec::with_capacity(parsed.events.len());
-    for (tick, event) in parsed.events {
-        let seconds = ticks_to_seconds(tick, &tempo_segments, ticks_per_beat);
-        let sample = (seconds * sample_rate as f64).round() as u64;
-        playback.push(MidiPlaybackEvent {
-            tick,
-            sample,
-            event,
+/// What `sync_practice_click` last sent, and the playhead tick it worked the
+/// meter out at.
+#[derive(Default)]
+struct PracticeClickSync {
+    enabled: Option<bool>,
+    meter: Option<(f64, u32)>,
+    tick: Option<u64>,
+}
+
+// While stopped the click follows the beat and bar at the playhead, so
+// pausing in a 3/4 section keeps counting in three. During playback the
+// click track does the clicking, so the meter is left alone until it stops.
+fn sync_practice_click(
+    preferences: Res<Preferences>,
+    playback_status: Res<PlaybackStatus>,
+    song: SongTempo,
+    audio_state: Res<AudioState>,
+    audio_tx: Res<AudioSender>,
+    mut sent: Local<PracticeClickSync>,
+) {
+    if sent.enabled != Some(preferences.practice_click) {
+        sent.enabled = Some(preferences.practice_click);
+        let _ = audio_tx
+            .0
+            .send(AudioCommand::SetMetronome(preferences.practice_click));
+    }
+    if !preferences.practice_click || playback_status.state == PlaybackState::Playing {
+        return;
+    }
+    let tick = audio_state.current_tick().unwrap_or(0);
+    if sent.tick == Some(tick)
+        && !preferences.is_changed()
+        && !playback_status.is_changed()
+        && !song.is_changed()
+    {
+        return;
+    }
+    sent.tick = Some(tick);
+    let meter = practice_meter(
+        &song.midi_tracks.0,
+        song.tempo_override.0,
+        song.speed.0,
+        tick,
+    );
+    if sent.meter != Some(meter) {
+        sent.meter = Some(meter);
+        let (beat_seconds, beats_per_bar) = meter;
+        let _ = audio_tx.0.send(AudioCommand::SetMetronomeMeter {
+            beat_seconds,
+            beats_per_bar,
         });
     }
+}
 
-    playback.sort_by_key(|e| e.sample);
-    let ruler_max_tick = if parsed.max_note_tick > 0 {
-        parsed.max_note_tick
-    } else {
-        parsed.max_tick
-    };
-    let total_seconds = ticks_to_seconds(ruler_max_tick, &tempo_segments, ticks_per_beat);
-    let total_samples = (total_seconds * sample_rate as f64).round() as u64;
+// Moving the focus on the mixer page changes the resource too; only the
+// strips matter to the audio thread.
+fn sync_channel_mixer(
+    mixer: Res<ChannelMixer>,
+    audio_tx: Res<AudioSender>,
+    mut sent: Local<Option<[ChannelStrip; 16]>>,
+) {
+    if !mixer.is_changed() || *sent == Some(mixer.strips) {
+        return;
+    }
+    *sent = Some(mixer.strips);
+    let _ = audio_tx.0.send(AudioCommand::SetChannelMix(mixer.strips));
+}
 
-    PlaybackSchedule {
-        events: playback,
-        ruler_max_tick,
-        total_samples,
+fn sync_equalizer(equalizer: Res<Equalizer>, audio_tx: Res<AudioSender>) {
+    if equalizer.is_changed() {
+        let _ = audio_tx.0.send(AudioCommand::SetEq {
+            low: equalizer.low_db,
+            mid: equalizer.mid_db,
+            high: equalizer.high_db,
+        });
     }
 }
 
-fn audio_thread(
-    cmd_rx: Receiver<AudioCommand>,
-    samples_played: Arc<AtomicU64>,
-    total_samples: Arc<AtomicU64>,
-    max_tick_shared: Arc<AtomicU64>,
-    last_event_sample: Arc<AtomicU64>,
-    last_event_tick: Arc<AtomicU64>,
-    next_event_sample: Arc<AtomicU64>,
-    next_event_tick: Arc<AtomicU64>,
-) {
-    println!("Audio thread: Initializing CPAL...");
-    let host = cpal::default_host();
-    let device = host
-        .default_output_device()
-        .expect("no output device available");
-    let config = device.default_output_config().unwrap();
+const EQ_LOW_HZ: f32 = 200.0;
+const EQ_MID_HZ: f32 = 1000.0;
+const EQ_MID_Q: f32 = 0.7;
+const EQ_HIGH_HZ: f32 = 4000.0;
 
-    let sample_rate = config.sample_rate();
-    let channels = config.channels() as usize;
-    println!(
-        "Audio thread: Sample rate: {:?}, Channels: {}",
-        sample_rate, channels
-    );
+/// Normalised biquad coefficients (a0 = 1), from the RBJ audio EQ cookbook.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Biquad {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+}
 
-    let synth = Arc::new(Mutex::new(Synth::default()));
-    synth.lock().unwrap().set_sample_rate(sample_rate as f32);
+impl Biquad {
+    // Shelves use a slope of 1, the steepest without overshoot.
+    fn shelf(sample_rate: u32, freq: f32, gain_db: f32, high: bool) -> Self {
+        let a = 10f32.powf(gain_db / 40.0);
+        let w0 = std::f32::consts::TAU * freq / sample_rate as f32;
+        let (sin, cos) = w0.sin_cos();
+        let alpha = sin / 2.0 * std::f32::consts::SQRT_2;
+        let root = 2.0 * a.sqrt() * alpha;
+        let sign = if high { -1.0 } else { 1.0 };
+        let a0 = (a + 1.0) + sign * (a - 1.0) * cos + root;
+        Self {
+            b0: a * ((a + 1.0) - sign * (a - 1.0) * cos + root) / a0,
+            b1: sign * 2.0 * a * ((a - 1.0) - sign * (a + 1.0) * cos) / a0,
+            b2: a * ((a + 1.0) - sign * (a - 1.0) * cos - root) / a0,
+            a1: -sign * 2.0 * ((a - 1.0) + sign * (a + 1.0) * cos) / a0,
+            a2: ((a + 1.0) + sign * (a - 1.0) * cos - root) / a0,
+        }
+    }
 
-    let playback_events = Arc::new(Mutex::new(Vec::<MidiPlaybackEvent>::new()));
-    let playback_index = Arc::new(Mutex::new(0usize));
-    let is_playing = Arc::new(Mutex::new(false));
-    let mut last_midi_path: Option<PathBuf> = None;
-    let mut last_soundfont_path: Option<PathBuf> = None;
-    let synth_clone_cb = Arc::clone(&synth);
-    let playback_events_clone_cb = Arc::clone(&playback_events);
-    let samples_played_clone_cb = Arc::clone(&samples_played);
-    let playback_index_clone_cb = Arc::clone(&playback_index);
-    let is_playing_clone_cb = Arc::clone(&is_playing);
-    let total_samples_clone_cb = Arc::clone(&total_samples);
-    let max_tick_clone_cb = Arc::clone(&max_tick_shared);
-    let last_event_sample_clone_cb = Arc::clone(&last_event_sample);
-    let last_event_tick_clone_cb = Arc::clone(&last_event_tick);
-    let next_event_sample_clone_cb = Arc::clone(&next_event_sample);
-    let next_event_tick_clone_cb = Arc::clone(&next_event_tick);
-
-    println!("Audio thread: Building output stream...");
-    let stream = device
-        .build_output_stream(
-            &config.into(),
-            move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
-                let Ok(mut synth) = synth_clone_cb.try_lock() else {
-                    return;
-                };
-                let Ok(events) = playback_events_clone_cb.try_lock() else {
-                    return;
-                };
-                let Ok(mut index) = playback_index_clone_cb.try_lock() else {
-                    return;
-                };
-                let Ok(playing_guard) = is_playing_clone_cb.try_lock() else {
-                    return;
-                };
-                let playing = *playing_guard;
-                for frame in data.chunks_mut(channels) {
-                    if playing {
-                        let current_sample = samples_played_clone_cb.load(Ordering::Relaxed);
-                        while *index < events.len() && events[*index].sample <= current_sample {
-                            let ev = &events[*index];
-                            let _ = synth.send_event(ev.event);
-                            last_event_sample_clone_cb.store(ev.sample, Ordering::Relaxed);
-                            last_event_tick_clone_cb.store(ev.tick, Ordering::Relaxed);
-                            *index += 1;
-                        }
-                        if *index < events.len() {
-                            let next = &events[*index];
-                            next_event_sample_clone_cb.store(next.sample, Ordering::Relaxed);
-                            next_event_tick_clone_cb.store(next.tick, Ordering::Relaxed);
-                        } else {
-                            next_event_sample_clone_cb.store(
-                                total_samples_clone_cb.load(Ordering::Relaxed),
-                                Ordering::Relaxed,
-                            );
-                            next_event_tick_clone_cb.store(
-                                max_tick_clone_cb.load(Ordering::Relaxed),
-                                Ordering::Relaxed,
-                            );
-                        }
+    fn peak(sample_rate: u32, freq: f32, q: f32, gain_db: f32) -> Self {
+        let a = 10f32.powf(gain_db / 40.0);
+        let w0 = std::f32::consts::TAU * freq / sample_rate as f32;
+        let (sin, cos) = w0.sin_cos();
+        let alpha = sin / (2.0 * q);
+        let a0 = 1.0 + alpha / a;
+        Self {
+            b0: (1.0 + alpha * a) / a0,
+            b1: -2.0 * cos / a0,
+            b2: (1.0 - alpha * a) / a0,
+            a1: -2.0 * cos / a0,
+            a2: (1.0 - alpha / a) / a0,
+        }
+    }
 
-                        let mut samples = [0.0f32; 2];
-                        synth.write(&mut samples[..]);
-                        for (i, s) in frame.iter_mut().enumerate() {
-                            *s = samples[i % 2];
-                        }
-                        let _prev = samples_played_clone_cb.fetch_add(1, Ordering::Relaxed);
-                    } else {
-                        for s in frame.iter_mut() {
-                            *s = 0.0;
-                        }
-                    }
-                }
-            },
-            |err| eprintln!("an error occurred on stream: {}", err),
-            None,
-        )
-        .unwrap();
+    fn process(&self, state: &mut [f32; 4], input: f32) -> f32 {
+        let [x1, x2, y1, y2] = *state;
+        let output = self.b0 * input + self.b1 * x1 + self.b2 * x2 - self.a1 * y1 - self.a2 * y2;
+        *state = [input, x1, output, y1];
+        output
+    }
+}
 
-    stream.play().unwrap();
-    println!("Audio thread: Stream started.");
+/// The three EQ bands at `sample_rate`, or `None` when all are flat so the
+/// callback can skip them.
+fn eq_filters(sample_rate: u32, low: f32, mid: f32, high: f32) -> Option<[Biquad; 3]> {
+    if [low, mid, high].iter().all(|gain| gain.abs() < 0.01) {
+        return None;
+    }
+    Some([
+        Biquad::shelf(sample_rate, EQ_LOW_HZ, low, false),
+        Biquad::peak(sample_rate, EQ_MID_HZ, EQ_MID_Q, mid),
+        Biquad::shelf(sample_rate, EQ_HIGH_HZ, high, true),
+    ])
+}
 
-    loop {
-        if let Ok(cmd) = cmd_rx.recv() {
-            match cmd {
-                AudioCommand::Play(midi_path, sf_path) => {
-                    println!("Audio thread: Play command received.");
-                    let soundfont_changed = last_soundfont_path.as_ref() != Some(&sf_path);
-                    let should_reload = last_midi_path.as_ref() != Some(&midi_path)
-                        || soundfont_changed
-                        || playback_events.lock().unwrap().is_empty();
-                    let mut should_start = !should_reload;
+/// Runs a stereo frame through the EQ bands; `state` holds each channel's
+/// filter history between callbacks.
+fn apply_eq(samples: &mut [f32; 2], filters: &[Biquad; 3], state: &mut [[[f32; 4]; 3]; 2]) {
+    for (sample, channel_state) in samples.iter_mut().zip(state.iter_mut()) {
+        for (filter, filter_state) in filters.iter().zip(channel_state.iter_mut()) {
+            *sample = filter.process(filter_state, *sample);
+        }
+    }
+}
 
-                    if should_reload {
-                        *is_playing.lock().unwrap() = false;
-                        send_all_notes_off(&mut synth.lock().unwrap());
+/// Scales the side (L-R) part of a stereo frame by `width`, keeping the mid.
+fn apply_stereo_width(samples: &mut [f32; 2], width: f32) {
+    let mid = (samples[0] + samples[1]) * 0.5;
+    let side = (samples[0] - samples[1]) * 0.5 * width;
+    *samples = [mid + side, mid - side];
+}
 
-                        if soundfont_changed {
-                            if let Ok(mut file) = std::fs::File::open(&sf_path) {
-                                if let Ok(font) = SoundFont::load(&mut file) {
-                                    let mut s = synth.lock().unwrap();
-                                    let id = s.add_font(font, true);
-                                    println!("Audio thread: SoundFont loaded ({:?})", id);
-                                }
-                            }
-                        }
+// Measures each file once, in the background, the first time it is opened
+// with a SoundFont; later runs reuse the gain stored in the session.
+fn analyze_file_loudness(
+    mut commands: Commands,
+    preferences: Res<Preferences>,
+    midi_path: Res<MidiFilePath>,
+    soundfont_path: Res<SoundFontPath>,
+    file_gains: Res<FileGains>,
+    pending: Query<(), With<LoudnessTask>>,
+    mut attempted: Local<HashSet<PathBuf>>,
+) {
+    if !preferences.normalize_loudness || !pending.is_empty() {
+        return;
+    }
+    let (Some(midi), Some(soundfont)) = (&midi_path.0, &soundfont_path.0) else {
+        return;
+    };
+    if file_gains.0.contains_key(midi) || !attempted.insert(midi.clone()) {
+        return;
+    }
+    let (task_midi, task_soundfont) = (midi.clone(), soundfont.clone());
+    let settings = SynthSettings::from(&*preferences);
+    let task = AsyncComputeTaskPool::get().spawn(async move {
+        let mut meter = LoudnessMeter::default();
+        match render_offline(
+            &task_midi,
+            &task_soundfont,
+            LOUDNESS_SAMPLE_RATE,
+            &settings,
+            |block| meter.add_block(block),
+        ) {
+            Ok(_) => meter.loudness_db().map(normalization_gain_db),
+            Err(err) => {
+                warn!("Loudness analysis failed: {err}");
+                None
+            }
+        }
+    });
+    let _ = commands.spawn(LoudnessTask(task, midi.clone()));
+}
 
-                        if let Ok(schedule) = build_playback_schedule(&midi_path, sample_rate) {
-                            let next_event = schedule
-                                .events
-                                .first()
-                                .map(|event| (event.sample, event.tick));
-                            *playback_events.lock().unwrap() = schedule.events;
-                            samples_played.store(0, Ordering::Relaxed);
-                            total_samples.store(schedule.total_samples, Ordering::Relaxed);
-                            max_tick_shared.store(schedule.ruler_max_tick, Ordering::Relaxed);
-                            last_event_sample.store(0, Ordering::Relaxed);
-                            last_event_tick.store(0, Ordering::Relaxed);
+fn poll_loudness_tasks(
+    mut commands: Commands,
+    mut tasks: Query<(Entity, &mut LoudnessTask)>,
+    mut file_gains: ResMut<FileGains>,
+) {
+    for (entity, mut task) in &mut tasks {
+        let Some(gain_db) = future::block_on(future::poll_once(&mut task.0)) else {
+            continue;
+        };
+        if let Some(gain_db) = gain_db {
+            info!("Loudness gain for {}: {gain_db:+.1} dB", task.1.display());
+            let _prev = file_gains.0.insert(task.1.clone(), gain_db);
+        }
+        commands.entity(entity).despawn();
+    }
+}
+
+fn show_audio_notice(audio_state: Res<AudioState>, mut status: ResMut<StatusMessage>) {
+    if let Some(notice) = audio_state.take_notice() {
+        status.show(notice);
+    }
+}
+
+fn sync_loop_region(
+    loop_region: Res<LoopRegion>,
+    midi_tracks: Res<MidiTracks>,
+    audio_tx: Res<AudioSender>,
+    mut sent: Local<Option<Option<(u64, u64)>>>,
+) {
+    if !loop_region.is_changed() && !midi_tracks.is_changed() {
+        return;
+    }
+    let range = loop_tick_range(&loop_region, &midi_tracks.0);
+    if *sent == Some(range) {
+        return;
+    }
+    *sent = Some(range);
+    let _ = audio_tx.0.send(AudioCommand::SetLoop(range));
+}
+
+fn sync_track_transpose(transpose: Res<TrackTranspose>, audio_tx: Res<AudioSender>) {
+    if transpose.is_changed() && !transpose.is_added() {
+        let _ = audio_tx
+            .0
+            .send(AudioCommand::SetTranspose(transpose.0.clone()));
+    }
+}
+
+fn sync_playback_speed(speed: Res<PlaybackSpeed>, audio_tx: Res<AudioSender>) {
+    if speed.is_changed() && !speed.is_added() {
+        let _ = audio_tx.0.send(AudioCommand::SetSpeed(speed.0));
+    }
+}
+
+fn sync_tempo_override(tempo_override: Res<TempoOverride>, audio_tx: Res<AudioSender>) {
+    if tempo_override.is_changed() && !tempo_override.is_added() {
+        let _ = audio_tx
+            .0
+            .send(AudioCommand::SetTempoOverride(tempo_override.0));
+    }
+}
+
+const DRUM_CHANNEL: u8 = 9;
+
+/// Shifts `key` by `semitones`, clamped to the MIDI range. Drum keys pick
+/// instruments rather than pitches, so they are left alone.
+pub fn transpose_key(key: u8, channel: u8, semitones: i8) -> u8 {
+    if channel == DRUM_CHANNEL {
+        return key;
+    }
+    (key as i16 + semitones as i16).clamp(0, 127) as u8
+}
+
+fn transpose_event(event: MidiEvent, semitones: i8) -> MidiEvent {
+    match event {
+        MidiEvent::NoteOn { channel, key, vel } => MidiEvent::NoteOn {
+            channel,
+            key: transpose_key(key, channel, semitones),
+            vel,
+        },
+        MidiEvent::NoteOff { channel, key } => MidiEvent::NoteOff {
+            channel,
+            key: transpose_key(key, channel, semitones),
+        },
+        other => other,
+    }
+}
+
+// Tracks which keys the schedule is holding down. OxiSynth does not expose
+// its live voice count, so this is a lower bound on voices in use: layered
+// presets and release tails take more voices than notes.
+#[derive(Default)]
+struct NoteMeter {
+    held: [u128; 16],
+}
+
+impl NoteMeter {
+    fn apply(&mut self, event: MidiEvent) {
+        match event {
+            MidiEvent::NoteOn { channel, key, vel } if vel > 0 => {
+                self.held[channel as usize % 16] |= 1 << (key % 128);
+            }
+            MidiEvent::NoteOn { channel, key, .. } | MidiEvent::NoteOff { channel, key } => {
+                self.held[channel as usize % 16] &= !(1 << (key % 128));
+            }
+            _ => {}
+        }
+    }
+
+    fn held(&self) -> u64 {
+        self.held.iter().map(|keys| keys.count_ones() as u64).sum()
+    }
+
+    fn clear(&mut self) {
+        self.held = [0; 16];
+    }
+}
+
+// What the command loop works out ahead for the output callback, behind one
+// lock: the EQ filters, the click positions in samples, and the channel state
+// to replay at a loop wrap, chased to the A-B loop start or the song's start.
+#[derive(Default)]
+struct BlockCues {
+    eq: Option<[Biquad; 3]>,
+    clicks: Vec<(u64, bool)>,
+    loop_start_chase: Vec<MidiEvent>,
+    song_start_chase: Vec<MidiEvent>,
+}
+
+// Notes sounded outside the schedule while stopped or paused.
+#[derive(Default)]
+struct Previews {
+    audition: Option<Audition>,
+    scrub: Option<ScrubSnippet>,
+}
+
+// The mixer as the output callback applies it. Volume and pan are the file's
+// own CC7 and CC10, scaled or replaced on their way to the synth, so the
+// latest file values are kept to apply a change between events.
+struct ChannelMix {
+    strips: [ChannelStrip; 16],
+    audible: [bool; 16],
+    file_volume: [u8; 16],
+    file_pan: [u8; 16],
+    file_program: [u8; 16],
+    changed: bool,
+}
+
+impl Default for ChannelMix {
+    fn default() -> Self {
+        Self {
+            strips: [ChannelStrip::default(); 16],
+            audible: [true; 16],
+            file_volume: [DEFAULT_CHANNEL_VOLUME; 16],
+            file_pan: [DEFAULT_CHANNEL_PAN; 16],
+            file_program: [0; 16],
+            changed: false,
+        }
+    }
+}
+
+const DEFAULT_CHANNEL_VOLUME: u8 = 100;
+const DEFAULT_CHANNEL_PAN: u8 = 64;
+
+impl ChannelMix {
+    fn set_strips(&mut self, strips: [ChannelStrip; 16]) {
+        self.strips = strips;
+        self.audible = audible_channels(&strips);
+        self.changed = true;
+    }
+
+    /// After a synth reset the channels are back at their defaults.
+    fn reset_file_controls(&mut self) {
+        self.file_volume = [DEFAULT_CHANNEL_VOLUME; 16];
+        self.file_pan = [DEFAULT_CHANNEL_PAN; 16];
+        self.file_program = [0; 16];
+        self.changed = true;
+    }
+
+    fn volume(&self, channel: usize) -> u8 {
+        (self.file_volume[channel] as u32 * self.strips[channel].volume.min(100) as u32 / 100) as u8
+    }
+
+    fn pan(&self, channel: usize) -> u8 {
+        self.strips[channel].pan.unwrap_or(self.file_pan[channel])
+    }
+
+    fn program(&self, channel: usize) -> u8 {
+        self.strips[channel]
+            .program
+            .unwrap_or(self.file_program[channel])
+    }
+
+    /// The event as the synth should get it, or `None` for a note on a
+    /// channel that is muted or soloed out and for the file's program changes
+    /// on a channel with an override.
+    fn apply(&mut self, event: MidiEvent) -> Option<MidiEvent> {
+        match event {
+            MidiEvent::NoteOn { channel, vel, .. } if vel > 0 => {
+                self.audible[channel as usize % 16].then_some(event)
+            }
+            MidiEvent::ProgramChange {
+                channel,
+                program_id,
+            } => {
+                let index = channel as usize % 16;
+                self.file_program[index] = program_id;
+                self.strips[index].program.is_none().then_some(event)
+            }
+            MidiEvent::ControlChange {
+                channel,
+                ctrl: 7,
+                value,
+            } => {
+                let index = channel as usize % 16;
+                self.file_volume[index] = value;
+                Some(MidiEvent::ControlChange {
+                    channel,
+                    ctrl: 7,
+                    value: self.volume(index),
+                })
+            }
+            MidiEvent::ControlChange {
+                channel,
+                ctrl: 10,
+                value,
+            } => {
+                let index = channel as usize % 16;
+                self.file_pan[index] = value;
+                Some(MidiEvent::ControlChange {
+                    channel,
+                    ctrl: 10,
+                    value: self.pan(index),
+                })
+            }
+            other => Some(other),
+        }
+    }
+
+    /// After a change, sends every channel's volume, pan and program and
+    /// silences the channels that can no longer be heard.
+    fn refresh(&mut self, mut send: impl FnMut(MidiEvent)) {
+        if !std::mem::take(&mut self.changed) {
+            return;
+        }
+        for channel in 0..16u8 {
+            let index = channel as usize;
+            if !self.audible[index] {
+                send(MidiEvent::AllNotesOff { channel });
+            }
+            send(MidiEvent::ControlChange {
+                channel,
+                ctrl: 7,
+                value: self.volume(index),
+            });
+            send(MidiEvent::ControlChange {
+                channel,
+                ctrl: 10,
+                value: self.pan(index),
+            });
+            send(MidiEvent::ProgramChange {
+                channel,
+                program_id: self.program(index),
+            });
+        }
+    }
+}
+
+/// Sends the event at `index` through the mixer, as stepping does while
+/// stopped, and moves past it. `None` once the events run out.
+fn send_step<'a>(
+    events: &'a [MidiPlaybackEvent],
+    index: &mut usize,
+    mix: &mut ChannelMix,
+    mut send: impl FnMut(MidiEvent),
+) -> Option<&'a MidiPlaybackEvent> {
+    let event = events.get(*index)?;
+    if let Some(mixed) = mix.apply(event.event) {
+        send(mixed);
+    }
+    *index += 1;
+    Some(event)
+}
+
+fn describe_event(event: &MidiPlaybackEvent) -> String {
+    let message = match event.event {
+        MidiEvent::NoteOn { channel, key, vel } => {
+            format!("ch {} note on {} vel {}", channel + 1, key, vel)
+        }
+        MidiEvent::NoteOff { channel, key } => format!("ch {} note off {}", channel + 1, key),
+        MidiEvent::ControlChange {
+            channel,
+            ctrl,
+            value,
+        } => format!("ch {} CC{} = {}", channel + 1, ctrl, value),
+        MidiEvent::AllNotesOff { channel } => format!("ch {} all notes off", channel + 1),
+        MidiEvent::AllSoundOff { channel } => format!("ch {} all sound off", channel + 1),
+        MidiEvent::PitchBend { channel, value } => {
+            format!("ch {} pitch bend {}", channel + 1, value)
+        }
+        MidiEvent::ProgramChange {
+            channel,
+            program_id,
+        } => format!("ch {} program {}", channel + 1, program_id),
+        MidiEvent::ChannelPressure { channel, value } => {
+            format!("ch {} pressure {}", channel + 1, value)
+        }
+        MidiEvent::PolyphonicKeyPressure {
+            channel,
+            key,
+            value,
+        } => format!("ch {} key {} pressure {}", channel + 1, key, value),
+        MidiEvent::SystemReset => "system reset".to_string(),
+    };
+    if event.port > 0 {
+        format!("tick {}: port {} {}", event.tick, event.port + 1, message)
+    } else {
+        format!("tick {}: {}", event.tick, message)
+    }
+}
+
+fn stop_at_end(
+    audio_state: Res<AudioState>,
+    audio_tx: Res<AudioSender>,
+    mut playback_status: ResMut<PlaybackStatus>,
+) {
+    if playback_status.state == PlaybackState::Playing && audio_state.is_finished() {
+        playback_status.state = PlaybackState::Stopped;
+        let _ = audio_tx.0.send(AudioCommand::Stop);
+    }
+}
+
+fn matching_rate_range(ranges: &[(u32, u32)], sample_rate: u32) -> Option<usize> {
+    ranges
+        .iter()
+        .position(|(min, max)| (*min..=*max).contains(&sample_rate))
+}
+
+// The stream callback writes `f32` frames, so only F32 configs with the
+// default channel count are considered.
+fn select_output_config(
+    device: &cpal::Device,
+    requested: Option<u32>,
+) -> (SupportedStreamConfig, Option<String>) {
+    let default = device.default_output_config().unwrap();
+    let Some(sample_rate) = requested else {
+        return (default, None);
+    };
+    let candidates = device
+        .supported_output_configs()
+        .map(|configs| {
+            configs
+                .filter(|config| {
+                    config.sample_format() == SampleFormat::F32
+                        && config.channels() == default.channels()
+                })
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    let ranges = candidates
+        .iter()
+        .map(|config| (config.min_sample_rate(), config.max_sample_rate()))
+        .collect::<Vec<_>>();
+    match matching_rate_range(&ranges, sample_rate) {
+        Some(index) => (candidates[index].with_sample_rate(sample_rate), None),
+        None => {
+            let notice = format!(
+                "{sample_rate} Hz is not supported, using {} Hz",
+                default.sample_rate()
+            );
+            (default, Some(notice))
+        }
+    }
+}
+
+const CONFIG_PATH: &str = "config.toml";
+
+/// Output overrides from the `[audio]` table of `config.toml`, read once at
+/// startup; anything the device can't do falls back to its default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct OutputConfig {
+    pub sample_rate: Option<u32>,
+    /// Frames per callback; fewer means lower latency but more risk of
+    /// dropouts.
+    pub buffer_size: Option<u32>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct ConfigFile {
+    audio: OutputConfig,
+}
+
+impl OutputConfig {
+    fn parse(content: &str) -> Option<Self> {
+        toml::from_str::<ConfigFile>(content)
+            .ok()
+            .map(|file| file.audio)
+    }
+
+    /// No `config.toml` is the usual case and leaves everything default.
+    pub fn load() -> Self {
+        let Ok(content) = std::fs::read_to_string(CONFIG_PATH) else {
+            return Self::default();
+        };
+        Self::parse(&content).unwrap_or_else(|| {
+            warn!("Failed to parse {CONFIG_PATH}");
+            Self::default()
+        })
+    }
+}
+
+// A device that doesn't report its buffer sizes gets its default, since a
+// fixed size it can't do would fail to open the stream.
+fn stream_buffer_size(
+    supported: &SupportedBufferSize,
+    requested: Option<u32>,
+) -> (BufferSize, Option<String>) {
+    let Some(frames) = requested else {
+        return (BufferSize::Default, None);
+    };
+    match supported {
+        SupportedBufferSize::Range { min, max } if (*min..=*max).contains(&frames) => {
+            (BufferSize::Fixed(frames), None)
+        }
+        _ => (
+            BufferSize::Default,
+            Some(format!(
+                "A {frames}-frame buffer is not supported, using the default"
+            )),
+        ),
+    }
+}
+
+/// The output config `config.toml` asks for, as far as the device supports
+/// it, with a notice for whatever had to fall back.
+fn try_with_config(
+    device: &cpal::Device,
+    requested: &OutputConfig,
+) -> (SupportedStreamConfig, Option<String>) {
+    let (config, rate_notice) = select_output_config(device, requested.sample_rate);
+    let (buffer_size, buffer_notice) =
+        stream_buffer_size(config.buffer_size(), requested.buffer_size);
+    info!(
+        "Audio thread: Sample rate: {}, Channels: {}, Buffer: {:?}",
+        config.sample_rate(),
+        config.channels(),
+        buffer_size
+    );
+    (config, rate_notice.or(buffer_notice))
+}
+
+fn rescale_sample(sample: u64, from_rate: u32, to_rate: u32) -> u64 {
+    (sample as f64 * to_rate as f64 / from_rate.max(1) as f64).round() as u64
+}
+
+fn reverb_tail_samples(seconds: f32, sample_rate: u32) -> u64 {
+    (seconds.max(0.0) as f64 * sample_rate as f64).round() as u64
+}
+
+// The callback starts a looping song over itself instead of finishing it,
+// and an A-B or bar loop can end past the tail when the last bar is long.
+fn playback_finished(
+    index: usize,
+    event_count: usize,
+    samples_played: u64,
+    total_samples: u64,
+    song_loop: bool,
+    loop_end_sample: u64,
+) -> bool {
+    !song_loop
+        && loop_end_sample == 0
+        && index >= event_count
+        && total_samples > 0
+        && samples_played >= total_samples
+}
+
+const PLAYBACK_FADE_SECONDS: f32 = 0.01;
+// Longest Stop or Pause waits for the fade; the stream may not be running.
+const PLAYBACK_FADE_TIMEOUT: Duration = Duration::from_millis(100);
+
+// One frame of the ramp playback starts and stops on, moving `gain` towards
+// `target` by `step`.
+fn step_fade(gain: f32, target: f32, step: f32) -> f32 {
+    if gain < target {
+        (gain + step).min(target)
+    } else {
+        (gain - step).max(target)
+    }
+}
+
+// Ramps playback down in the callback and waits for it to reach silence,
+// so stopping doesn't click. The target stays at zero until the caller puts
+// it back once playback has stopped.
+fn fade_out(fade_target: &AtomicU32, fade_gain: &AtomicU32) {
+    fade_target.store(0.0f32.to_bits(), Ordering::Relaxed);
+    let started = Instant::now();
+    while f32::from_bits(fade_gain.load(Ordering::Relaxed)) > 0.0
+        && started.elapsed() < PLAYBACK_FADE_TIMEOUT
+    {
+        thread::sleep(Duration::from_millis(1));
+    }
+}
+
+const LOOP_FADE_SECONDS: f32 = 0.05;
+
+// Output gain while approaching the loop end in `LoopSeam::Fade`; reaches
+// zero on the boundary sample so the cut that follows is silent.
+fn loop_fade_gain(current_sample: u64, loop_end: u64, fade_samples: u64) -> f32 {
+    if loop_end == 0 || fade_samples == 0 || current_sample >= loop_end {
+        return 1.0;
+    }
+    let remaining = loop_end - current_sample;
+    if remaining >= fade_samples {
+        1.0
+    } else {
+        remaining as f32 / fade_samples as f32
+    }
+}
+
+const AUDITION_VELOCITY: u8 = 100;
+const AUDITION_HOLD_SECONDS: f32 = 0.4;
+const AUDITION_RING_SECONDS: f32 = 1.0;
+
+/// A note started by `AudioCommand::Audition`. The callback counts it down,
+/// sends its note-off once the hold ends and keeps rendering while stopped
+/// until the release has rung out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Audition {
+    channel: u8,
+    key: u8,
+    hold_samples: u64,
+    ring_samples: u64,
+}
+
+impl Audition {
+    fn new(channel: u8, key: u8, sample_rate: u32) -> Self {
+        Self {
+            channel,
+            key,
+            hold_samples: reverb_tail_samples(AUDITION_HOLD_SECONDS, sample_rate).max(1),
+            ring_samples: reverb_tail_samples(AUDITION_RING_SECONDS, sample_rate),
+        }
+    }
+
+    fn note_off(&self) -> MidiEvent {
+        MidiEvent::NoteOff {
+            channel: self.channel,
+            key: self.key,
+        }
+    }
+
+    /// Advances one frame, returning the note-off when the hold runs out.
+    fn advance(&mut self) -> Option<MidiEvent> {
+        if self.hold_samples > 0 {
+            self.hold_samples -= 1;
+            return (self.hold_samples == 0).then(|| self.note_off());
+        }
+        self.ring_samples = self.ring_samples.saturating_sub(1);
+        None
+    }
+
+    fn finished(&self) -> bool {
+        self.hold_samples == 0 && self.ring_samples == 0
+    }
+}
+
+const SCRUB_SECONDS: f32 = 0.3;
+
+const CLICK_SECONDS: f32 = 0.04;
+const CLICK_DECAY_SECONDS: f32 = 0.008;
+const CLICK_GAIN: f32 = 0.3;
+const CLICK_ACCENT_GAIN: f32 = 0.45;
+
+// A short decaying sine; accents sit higher and louder.
+fn click_sample(age: u32, sample_rate: u32, accent: bool) -> f32 {
+    let t = age as f32 / sample_rate.max(1) as f32;
+    let (freq, gain) = if accent {
+        (1760.0, CLICK_ACCENT_GAIN)
+    } else {
+        (1320.0, CLICK_GAIN)
+    };
+    (std::f32::consts::TAU * freq * t).sin() * (-t / CLICK_DECAY_SECONDS).exp() * gain
+}
+
+/// Plays clicks at sample positions from the audio callback, following the
+/// playhead through seeks and loop jumps.
+#[derive(Default)]
+struct ClickPlayer {
+    next: usize,
+    /// Sample the player expects next; anything else means a jump.
+    expected: Option<u64>,
+    /// Age in samples of the sounding click, and whether it is accented.
+    voice: Option<(u32, bool)>,
+}
+
+impl ClickPlayer {
+    /// Makes the next `render` find its place in a new click list.
+    fn reset(&mut self) {
+        self.expected = None;
+    }
+
+    fn start(&mut self, accent: bool) {
+        self.voice = Some((0, accent));
+    }
+
+    /// Next sample of the sounding click, if any.
+    fn sound(&mut self, sample_rate: u32) -> f32 {
+        let Some((age, accent)) = self.voice else {
+            return 0.0;
+        };
+        let length = (CLICK_SECONDS * sample_rate as f32) as u32;
+        self.voice = (age + 1 < length).then_some((age + 1, accent));
+        click_sample(age, sample_rate, accent)
+    }
+
+    fn render(&mut self, clicks: &[(u64, bool)], sample: u64, sample_rate: u32) -> f32 {
+        if self.expected != Some(sample) {
+            self.next = clicks.partition_point(|(click, _)| *click < sample);
+        }
+        while let Some(&(click, accent)) = clicks.get(self.next) {
+            if click > sample {
+                break;
+            }
+            self.start(accent);
+            self.next += 1;
+        }
+        self.expected = Some(sample + 1);
+        self.sound(sample_rate)
+    }
+}
+
+/// Whether the free-running click starts a beat at `sample`, and if so
+/// whether it is the accented first beat of a bar.
+fn practice_click_at(sample: u64, beat_samples: u64, beats_per_bar: u64) -> Option<bool> {
+    let beat_samples = beat_samples.max(1);
+    sample
+        .is_multiple_of(beat_samples)
+        .then(|| (sample / beat_samples).is_multiple_of(beats_per_bar.max(1)))
+}
+
+/// A short preview of the schedule from a seek target, played while stopped
+/// so a seek can be heard without starting playback. Event times are frames
+/// from the target.
+struct ScrubSnippet {
+    events: Vec<(u64, MidiEvent)>,
+    next: usize,
+    position: u64,
+    length: u64,
+}
+
+impl ScrubSnippet {
+    /// Chases the notes and programs in effect at `start` so the snippet
+    /// sounds like playback would, then takes the events that fall inside
+    /// the window.
+    fn new(events: &[MidiPlaybackEvent], start: u64, length: u64) -> Self {
+        let index = seek_index(events, start);
+        let mut programs = std::collections::BTreeMap::new();
+        let mut sounding = std::collections::BTreeMap::new();
+        for event in &events[..index] {
+            match event.event {
+                MidiEvent::ProgramChange {
+                    channel,
+                    program_id,
+                } => {
+                    let _prev = programs.insert(channel, program_id);
+                }
+                MidiEvent::NoteOn { channel, key, vel } if vel > 0 => {
+                    let _prev = sounding.insert((channel, key), vel);
+                }
+                MidiEvent::NoteOn { channel, key, .. } | MidiEvent::NoteOff { channel, key } => {
+                    let _removed = sounding.remove(&(channel, key));
+                }
+                _ => {}
+            }
+        }
+        let chased = programs
+            .into_iter()
+            .map(|(channel, program_id)| MidiEvent::ProgramChange {
+                channel,
+                program_id,
+            })
+            .chain(
+                sounding
+                    .into_iter()
+                    .map(|((channel, key), vel)| MidiEvent::NoteOn { channel, key, vel }),
+            )
+            .map(|event| (0, event));
+        let window = events[index..]
+            .iter()
+            .take_while(|event| event.sample < start + length)
+            .map(|event| (event.sample - start, event.event));
+        Self {
+            events: chased.chain(window).collect(),
+            next: 0,
+            position: 0,
+            length,
+        }
+    }
+
+    /// Sends the events due this frame and reports whether the snippet has
+    /// run out.
+    fn advance(&mut self, mut send: impl FnMut(MidiEvent)) -> bool {
+        while let Some((offset, event)) = self.events.get(self.next) {
+            if *offset > self.position {
+                break;
+            }
+            send(*event);
+            self.next += 1;
+        }
+        self.position += 1;
+        self.position >= self.length
+    }
+}
+
+fn interpolation_method(mode: Interpolation) -> InterpolationMethod {
+    match mode {
+        Interpolation::None => InterpolationMethod::None,
+        Interpolation::Linear => InterpolationMethod::Linear,
+        Interpolation::FourthOrder => InterpolationMethod::FourthOrder,
+        Interpolation::SeventhOrder => InterpolationMethod::SeventhOrder,
+    }
+}
+
+struct MidiPlaybackEvent {
+    tick: u64,
+    sample: u64,
+    /// MIDI port from the track's port meta; 0 unless the file spans ports.
+    port: u8,
+    event: MidiEvent,
+}
+
+struct PlaybackSchedule {
+    events: Vec<MidiPlaybackEvent>,
+    ruler_max_tick: u64,
+    end_sample: u64,
+    total_samples: u64,
+    tempo_map: TempoMap,
+}
+
+#[derive(Clone, Copy)]
+struct BarSegment {
+    tick: u64,
+    bar: u64,
+    ticks_per_bar: u64,
+}
+
+// Beats are quarter notes, so an n/d bar spans 4n/d of them.
+fn ticks_per_bar(ticks_per_beat: u32, numerator: u8, denominator: u8) -> u64 {
+    let ticks = ticks_per_beat.max(1) as u64 * 4 * numerator.max(1) as u64;
+    (ticks / denominator.max(1) as u64).max(1)
+}
+
+#[derive(Clone)]
+pub struct BarMap {
+    segments: Vec<BarSegment>,
+}
+
+impl BarMap {
+    pub fn new(time_signatures: &[(u64, u8, u8)], ticks_per_beat: u32) -> Self {
+        let mut sorted = time_signatures.to_vec();
+        sorted.sort_by_key(|(tick, _, _)| *tick);
+        let mut segments = vec![BarSegment {
+            tick: 0,
+            bar: 0,
+            ticks_per_bar: ticks_per_bar(ticks_per_beat, 4, 4),
+        }];
+        for (tick, numerator, denominator) in sorted {
+            let ticks_per_bar = ticks_per_bar(ticks_per_beat, numerator, denominator);
+            let last = segments[segments.len() - 1];
+            if tick == last.tick {
+                let _last = segments.pop();
+                segments.push(BarSegment {
+                    ticks_per_bar,
+                    ..last
+                });
+                continue;
+            }
+            // A change that lands mid-bar starts a fresh bar.
+            let bars = (tick - last.tick).div_ceil(last.ticks_per_bar);
+            segments.push(BarSegment {
+                tick,
+                bar: last.bar + bars,
+                ticks_per_bar,
+            });
+        }
+        Self { segments }
+    }
+
+    /// Tick at which the 1-based `bar` starts.
+    pub fn bar_start(&self, bar: u32) -> u64 {
+        let index = bar.max(1) as u64 - 1;
+        let segment = self
+            .segments
+            .iter()
+            .rev()
+            .find(|segment| segment.bar <= index)
+            .unwrap_or(&self.segments[0]);
+        segment.tick + (index - segment.bar) * segment.ticks_per_bar
+    }
+
+    /// 1-based bar containing `tick`.
+    pub fn bar_at(&self, tick: u64) -> u32 {
+        let segment = self
+            .segments
+            .iter()
+            .rev()
+            .find(|segment| segment.tick <= tick)
+            .unwrap_or(&self.segments[0]);
+        (segment.bar + (tick - segment.tick) / segment.ticks_per_bar + 1) as u32
+    }
+
+    /// Length of the bar containing `tick`.
+    pub fn ticks_per_bar_at(&self, tick: u64) -> u64 {
+        self.segments
+            .iter()
+            .rev()
+            .find(|segment| segment.tick <= tick)
+            .unwrap_or(&self.segments[0])
+            .ticks_per_bar
+    }
+}
+
+pub fn file_bar_map(tracks: &[MidiTrackInfo]) -> BarMap {
+    let time_signatures = tracks
+        .iter()
+        .flat_map(|track| track.time_signature_events.iter().copied())
+        .collect::<Vec<_>>();
+    let ticks_per_beat = tracks.first().map(|t| t.ticks_per_beat).unwrap_or(480);
+    BarMap::new(&time_signatures, ticks_per_beat)
+}
+
+/// Where the click track sounds, as `(tick, accented)` in tick order.
+pub fn click_ticks(mode: Metronome, tracks: &[MidiTrackInfo], focused: usize) -> Vec<(u64, bool)> {
+    let bar_map = file_bar_map(tracks);
+    let on_downbeat = |tick: u64| bar_map.bar_start(bar_map.bar_at(tick)) == tick;
+    match mode {
+        Metronome::Off => Vec::new(),
+        Metronome::Beats => {
+            let Some(first) = tracks.first() else {
+                return Vec::new();
+            };
+            let end = tracks.iter().map(|track| track.end_tick).max().unwrap_or(0);
+            (0..end)
+                .step_by(first.ticks_per_beat.max(1) as usize)
+                .map(|tick| (tick, on_downbeat(tick)))
+                .collect()
+        }
+        Metronome::Onsets => {
+            let Some(track) = tracks.get(focused) else {
+                return Vec::new();
+            };
+            // A chord is one onset.
+            let onsets: BTreeSet<u64> = track.note_spans.iter().map(|span| span.start).collect();
+            onsets
+                .into_iter()
+                .map(|tick| (tick, on_downbeat(tick)))
+                .collect()
+        }
+    }
+}
+
+/// Seconds per beat and beats per bar at `tick`, for the click that keeps
+/// going while nothing plays. Without a file that is 120 BPM in 4/4.
+pub fn practice_meter(
+    tracks: &[MidiTrackInfo],
+    tempo_override: Option<u32>,
+    speed: f32,
+    tick: u64,
+) -> (f64, u32) {
+    let ticks_per_beat = tracks
+        .first()
+        .map_or(480, |track| track.ticks_per_beat)
+        .max(1) as u64;
+    let tempo_map = file_tempo_map(tracks, tempo_override).with_speed(speed);
+    let beat_start = tick - tick % ticks_per_beat;
+    let beat_seconds =
+        tempo_map.seconds_at(beat_start + ticks_per_beat) - tempo_map.seconds_at(beat_start);
+    let beats = file_bar_map(tracks)
+        .ticks_per_bar_at(tick)
+        .div_ceil(ticks_per_beat);
+    (beat_seconds, beats.max(1) as u32)
+}
+
+/// Start and end ticks of the loop region, or `None` when looping is off.
+pub fn loop_tick_range(region: &LoopRegion, tracks: &[MidiTrackInfo]) -> Option<(u64, u64)> {
+    if !region.enabled || tracks.is_empty() {
+        return None;
+    }
+    if let (Some(a), Some(b)) = region.points {
+        return (b > a).then_some((a, b));
+    }
+    let bar_map = file_bar_map(tracks);
+    let end_bar = region.end_bar.max(region.start_bar);
+    Some((
+        bar_map.bar_start(region.start_bar),
+        bar_map.bar_start(end_bar + 1),
+    ))
+}
+
+struct ParsedMidi {
+    /// `(tick, track, port, event)`, with channels already routed by
+    /// `route_ports`.
+    events: Vec<(u64, usize, u8, MidiEvent)>,
+    timing: FileTiming,
+}
+
+fn midi_message_to_event(channel: u8, message: midly::MidiMessage) -> MidiEvent {
+    match message {
+        midly::MidiMessage::NoteOff { key, .. } => MidiEvent::NoteOff {
+            channel,
+            key: key.as_int(),
+        },
+        midly::MidiMessage::NoteOn { key, vel } => MidiEvent::NoteOn {
+            channel,
+            key: key.as_int(),
+            vel: vel.as_int(),
+        },
+        midly::MidiMessage::Aftertouch { key, vel } => MidiEvent::PolyphonicKeyPressure {
+            channel,
+            key: key.as_int(),
+            value: vel.as_int(),
+        },
+        midly::MidiMessage::Controller { controller, value } => MidiEvent::ControlChange {
+            channel,
+            ctrl: controller.as_int(),
+            value: value.as_int(),
+        },
+        midly::MidiMessage::ProgramChange { program } => MidiEvent::ProgramChange {
+            channel,
+            program_id: program.as_int(),
+        },
+        midly::MidiMessage::ChannelAftertouch { vel } => MidiEvent::ChannelPressure {
+            channel,
+            value: vel.as_int(),
+        },
+        midly::MidiMessage::PitchBend { bend } => MidiEvent::PitchBend {
+            channel,
+            value: bend.as_int() as u16,
+        },
+    }
+}
+
+fn parse_smf(smf: &Smf) -> ParsedMidi {
+    let mut all_events = Vec::new();
+
+    for (track_index, track) in smf.tracks.iter().enumerate() {
+        let mut current_tick = 0u64;
+        let mut track_port = 0u8;
+        let mut channel_ports = [None; 16];
+        let mut channel_prefix: Option<u8> = None;
+        for event in track {
+            current_tick += event.delta.as_int() as u64;
+            match event.kind {
+                TrackEventKind::Midi { channel, message } => {
+                    let channel = channel.as_int();
+                    // A prefix only scopes the meta events up to the next
+                    // channel message.
+                    channel_prefix = None;
+                    all_events.push((
+                        current_tick,
+                        track_index,
+                        channel_ports[channel as usize].unwrap_or(track_port),
+                        midi_message_to_event(channel, message),
+                    ));
+                }
+                TrackEventKind::Meta(midly::MetaMessage::MidiChannel(channel)) => {
+                    channel_prefix = Some(channel.as_int());
+                }
+                TrackEventKind::Meta(midly::MetaMessage::MidiPort(port)) => match channel_prefix {
+                    Some(channel) => channel_ports[channel as usize] = Some(port.as_int()),
+                    None => track_port = port.as_int(),
+                },
+                TrackEventKind::Meta(
+                    midly::MetaMessage::TrackName(_)
+                    | midly::MetaMessage::TrackNumber(_)
+                    | midly::MetaMessage::Text(_)
+                    | midly::MetaMessage::Copyright(_)
+                    | midly::MetaMessage::InstrumentName(_)
+                    | midly::MetaMessage::Lyric(_)
+                    | midly::MetaMessage::Marker(_)
+                    | midly::MetaMessage::CuePoint(_)
+                    | midly::MetaMessage::ProgramName(_)
+                    | midly::MetaMessage::DeviceName(_)
+                    | midly::MetaMessage::EndOfTrack
+                    | midly::MetaMessage::Tempo(_)
+                    | midly::MetaMessage::SmpteOffset(_)
+                    | midly::MetaMessage::TimeSignature(_, _, _, _)
+                    | midly::MetaMessage::KeySignature(_, _)
+                    | midly::MetaMessage::SequencerSpecific(_)
+                    | midly::MetaMessage::Unknown(_, _),
+                )
+                | TrackEventKind::SysEx(_)
+                | TrackEventKind::Escape(_) => {}
+            }
+        }
+    }
+
+    all_events.sort_by_key(|(tick, _, _, _)| *tick);
+    route_ports(&mut all_events);
+
+    ParsedMidi {
+        events: all_events,
+        timing: file_timing(smf),
+    }
+}
+
+fn event_channel(event: MidiEvent) -> Option<u8> {
+    match event {
+        MidiEvent::NoteOn { channel, .. }
+        | MidiEvent::NoteOff { channel, .. }
+        | MidiEvent::ControlChange { channel, .. }
+        | MidiEvent::AllNotesOff { channel }
+        | MidiEvent::AllSoundOff { channel }
+        | MidiEvent::PitchBend { channel, .. }
+        | MidiEvent::ProgramChange { channel, .. }
+        | MidiEvent::ChannelPressure { channel, .. }
+        | MidiEvent::PolyphonicKeyPressure { channel, .. } => Some(channel),
+        MidiEvent::SystemReset => None,
+    }
+}
+
+fn with_channel(event: MidiEvent, channel: u8) -> MidiEvent {
+    match event {
+        MidiEvent::NoteOn { key, vel, .. } => MidiEvent::NoteOn { channel, key, vel },
+        MidiEvent::NoteOff { key, .. } => MidiEvent::NoteOff { channel, key },
+        MidiEvent::ControlChange { ctrl, value, .. } => MidiEvent::ControlChange {
+            channel,
+            ctrl,
+            value,
+        },
+        MidiEvent::AllNotesOff { .. } => MidiEvent::AllNotesOff { channel },
+        MidiEvent::AllSoundOff { .. } => MidiEvent::AllSoundOff { channel },
+        MidiEvent::PitchBend { value, .. } => MidiEvent::PitchBend { channel, value },
+        MidiEvent::ProgramChange { program_id, .. } => MidiEvent::ProgramChange {
+            channel,
+            program_id,
+        },
+        MidiEvent::ChannelPressure { value, .. } => MidiEvent::ChannelPressure { channel, value },
+        MidiEvent::PolyphonicKeyPressure { key, value, .. } => MidiEvent::PolyphonicKeyPressure {
+            channel,
+            key,
+            value,
+        },
+        MidiEvent::SystemReset => MidiEvent::SystemReset,
+    }
+}
+
+// The synth has one bank of 16 channels, so channels on later ports that
+// collide with ones already in use move to channels the file leaves free.
+// Port 0 keeps its layout and drums stay on channel 10 whatever their port.
+fn route_ports(events: &mut [(u64, usize, u8, MidiEvent)]) {
+    let used: BTreeSet<(u8, u8)> = events
+        .iter()
+        .filter_map(|&(_, _, port, event)| event_channel(event).map(|channel| (port, channel)))
+        .collect();
+    if used.iter().all(|&(port, _)| port == 0) {
+        return;
+    }
+    let mut taken = [false; 16];
+    let mut routes = HashMap::new();
+    for &(port, channel) in &used {
+        let target = if channel == 9 || !taken[channel as usize] {
+            channel
+        } else {
+            (0..16u8)
+                .find(|&free| free != 9 && !taken[free as usize])
+                .unwrap_or(channel)
+        };
+        taken[target as usize] = true;
+        let _ = routes.insert((port, channel), target);
+    }
+    for (_, _, port, event) in events.iter_mut() {
+        if let Some(channel) = event_channel(*event) {
+            *event = with_channel(*event, routes[&(*port, channel)]);
+        }
+    }
+}
+
+/// Tempo a schedule is built at: the file's tempo map or one fixed tempo,
+/// scaled by the playback speed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct PlaybackTempo {
+    /// Microseconds per beat replacing the file's tempo map.
+    fixed: Option<u32>,
+    speed: f32,
+}
+
+impl Default for PlaybackTempo {
+    fn default() -> Self {
+        Self {
+            fixed: None,
+            speed: 1.0,
+        }
+    }
+}
+
+impl PlaybackTempo {
+    #[cfg(test)]
+    fn fixed(us_per_beat: u32) -> Self {
+        Self {
+            fixed: Some(us_per_beat),
+            ..Self::default()
+        }
+    }
+}
+
+/// What the audio thread builds its schedule with besides the file and the
+/// sample rate; changing any of these rebuilds it.
+struct ScheduleOptions {
+    reverb_tail_seconds: f32,
+    transpose: HashMap<usize, i8>,
+    tempo: PlaybackTempo,
+    only_track: Option<usize>,
+    song_end: SongEnd,
+}
+
+impl Default for ScheduleOptions {
+    fn default() -> Self {
+        Self {
+            reverb_tail_seconds: Preferences::DEFAULT_REVERB_TAIL_SECONDS,
+            transpose: HashMap::new(),
+            tempo: PlaybackTempo::default(),
+            only_track: None,
+            song_end: SongEnd::default(),
+        }
+    }
+}
+
+impl ScheduleOptions {
+    fn build(&self, midi_path: &Path, sample_rate: u32) -> Result<PlaybackSchedule, ()> {
+        build_playback_schedule(
+            midi_path,
+            sample_rate,
+            self.reverb_tail_seconds,
+            &self.transpose,
+            self.tempo,
+            self.only_track,
+            self.song_end,
+        )
+    }
+}
+
+fn build_playback_schedule_from_smf(
+    smf: &Smf,
+    sample_rate: u32,
+    reverb_tail_seconds: f32,
+    transpose: &HashMap<usize, i8>,
+    tempo: PlaybackTempo,
+    only_track: Option<usize>,
+    song_end: SongEnd,
+) -> PlaybackSchedule {
+    let parsed = parse_smf(smf);
+    let tempo_map = parsed.timing.tempo_map(tempo.fixed).with_speed(tempo.speed);
+    let ruler_max_tick = parsed.timing.end_tick(song_end);
+
+    let mut playback = Vec::with_capacity(parsed.events.len());
+    for (tick, track_index, port, event) in parsed.events {
+        let other_track = only_track.is_some_and(|only| only != track_index);
+        if other_track && matches!(event, MidiEvent::NoteOn { .. } | MidiEvent::NoteOff { .. }) {
+            continue;
+        }
+        let event = match transpose.get(&track_index) {
+            Some(semitones) if *semitones != 0 => transpose_event(event, *semitones),
+            _ => event,
+        };
+        let seconds = tempo_map.seconds_at(tick);
+        let sample = (seconds * sample_rate as f64).round() as u64;
+        playback.push(MidiPlaybackEvent {
+            tick,
+            sample,
+            port,
+            event,
+        });
+    }
+
+    playback.sort_by_key(|e| e.sample);
+    let end_seconds = tempo_map.seconds_at(ruler_max_tick);
+    let end_sample = (end_seconds * sample_rate as f64).round() as u64;
+
+    PlaybackSchedule {
+        events: playback,
+        ruler_max_tick,
+        end_sample,
+        total_samples: end_sample + reverb_tail_samples(reverb_tail_seconds, sample_rate),
+        tempo_map,
+    }
+}
+
+/// Program, bank and controller events at tick 0, in schedule order. A
+/// note at tick 0 can be scheduled ahead of its channel's ProgramChange
+/// from another track, so these are sent before starting from the top.
+fn initial_channel_setup(events: &[MidiPlaybackEvent]) -> Vec<MidiEvent> {
+    events
+        .iter()
+        .take_while(|event| event.tick == 0)
+        .filter(|event| is_channel_setup(event.event))
+        .map(|event| event.event)
+        .collect()
+}
+
+/// Puts every channel back as a fresh synth has it and then sends the
+/// file's tick 0 setup, so a song loop doesn't carry a fade-out or a bend
+/// from the end into the next pass. Reset All Controllers leaves volume, pan
+/// and program alone, so those are sent as well.
+fn song_start_setup(events: &[MidiPlaybackEvent]) -> Vec<MidiEvent> {
+    let mut setup = Vec::new();
+    for channel in 0..16u8 {
+        setup.extend([
+            MidiEvent::ControlChange {
+                channel,
+                ctrl: 121,
+                value: 0,
+            },
+            MidiEvent::ControlChange {
+                channel,
+                ctrl: 7,
+                value: DEFAULT_CHANNEL_VOLUME,
+            },
+            MidiEvent::ControlChange {
+                channel,
+                ctrl: 10,
+                value: DEFAULT_CHANNEL_PAN,
+            },
+            MidiEvent::ProgramChange {
+                channel,
+                program_id: 0,
+            },
+        ]);
+    }
+    setup.extend(initial_channel_setup(events));
+    setup
+}
+
+/// Program, controller and pitch bend events ahead of `index`, in schedule
+/// order. Replaying them after a seek leaves each channel set up as if it
+/// had played up to there, without sounding any of the skipped notes.
+fn chased_channel_setup(
+    events: &[MidiPlaybackEvent],
+    index: usize,
+) -> impl Iterator<Item = MidiEvent> + '_ {
+    events[..index.min(events.len())]
+        .iter()
+        .filter(|event| is_channel_setup(event.event))
+        .map(|event| event.event)
+}
+
+/// Sends setup events through the mixer, so a program override wins over the
+/// file's and the file's program is remembered for the next refresh.
+fn apply_channel_setup(
+    mix: &mut ChannelMix,
+    setup: impl IntoIterator<Item = MidiEvent>,
+    mut send: impl FnMut(MidiEvent),
+) {
+    for event in setup {
+        if let Some(event) = mix.apply(event) {
+            send(event);
+        }
+    }
+}
+
+/// What `chased_channel_setup` would leave each channel at: the last program,
+/// controller value and pitch bend of each, in schedule order so bank selects
+/// and RPNs still land ahead of what follows them.
+fn chased_channel_state(events: &[MidiPlaybackEvent], index: usize) -> Vec<MidiEvent> {
+    let mut latest = HashMap::new();
+    for (position, event) in events[..index.min(events.len())].iter().enumerate() {
+        let key = match event.event {
+            MidiEvent::ControlChange { channel, ctrl, .. } => (channel, ctrl as u16),
+            MidiEvent::ProgramChange { channel, .. } => (channel, 128),
+            MidiEvent::PitchBend { channel, .. } => (channel, 129),
+            _ => continue,
+        };
+        let _prev = latest.insert(key, (position, event.event));
+    }
+    let mut state: Vec<_> = latest.into_values().collect();
+    state.sort_by_key(|(position, _)| *position);
+    state.into_iter().map(|(_, event)| event).collect()
+}
+
+/// The program `channel` is on at `sample`, counting a change at that very
+/// sample; `None` when the file hasn't set one by then.
+fn program_at(events: &[MidiPlaybackEvent], sample: u64, channel: u8) -> Option<u8> {
+    events[..events.partition_point(|event| event.sample <= sample)]
+        .iter()
+        .rev()
+        .find_map(|event| match event.event {
+            MidiEvent::ProgramChange {
+                channel: program_channel,
+                program_id,
+            } if program_channel == channel => Some(program_id),
+            _ => None,
+        })
+}
+
+fn is_channel_setup(event: MidiEvent) -> bool {
+    matches!(
+        event,
+        MidiEvent::ProgramChange { .. }
+            | MidiEvent::ControlChange { .. }
+            | MidiEvent::PitchBend { .. }
+    )
+}
+
+/// The playhead after a tempo or speed change, kept between the last event
+/// played and the one at `index`. Mapping through a tick rounds, and could
+/// otherwise land before an event already sent or past one still to come.
+fn retimed_position(events: &[MidiPlaybackEvent], index: usize, position: u64) -> u64 {
+    let played = index
+        .checked_sub(1)
+        .and_then(|last| events.get(last))
+        .map_or(0, |event| event.sample);
+    let next = events.get(index).map_or(u64::MAX, |event| event.sample);
+    position.clamp(played, next.max(played))
+}
+
+fn seek_index(events: &[MidiPlaybackEvent], sample: u64) -> usize {
+    events.partition_point(|event| event.sample < sample)
+}
+
+// The thread owns its clones of the shared state; the app reads the same
+// atomics through the `AudioState` resource.
+fn audio_thread(cmd_rx: Receiver<AudioCommand>, state: AudioState, output_config: OutputConfig) {
+    let AudioState {
+        samples_played,
+        total_samples,
+        max_tick: max_tick_shared,
+        last_event_sample,
+        last_event_tick,
+        next_event_sample,
+        next_event_tick,
+        sample_rate: sample_rate_shared,
+        channel_activity,
+        held_notes,
+        peak_notes,
+        clip_count,
+        finished,
+        notice,
+    } = state;
+    debug!("Audio thread: Initializing CPAL...");
+    let host = cpal::default_host();
+    let device = host
+        .default_output_device()
+        .expect("no output device available");
+    let (config, message) = try_with_config(&device, &output_config);
+    if let Some(message) = message {
+        warn!("Audio thread: {}", message);
+        *notice.lock().unwrap() = Some(message);
+    }
+
+    let mut sample_rate = config.sample_rate();
+
+    sample_rate_shared.store(sample_rate as u64, Ordering::Relaxed);
+
+    let synth = Arc::new(Mutex::new(Synth::default()));
+    synth.lock().unwrap().set_sample_rate(sample_rate as f32);
+
+    let playback_events = Arc::new(Mutex::new(Vec::<MidiPlaybackEvent>::new()));
+    let playback_index = Arc::new(Mutex::new(0usize));
+    let is_playing = Arc::new(Mutex::new(false));
+    let mut last_midi_path: Option<PathBuf> = None;
+    let mut last_soundfont_path: Option<PathBuf> = None;
+    let mut soundfont_layers: Vec<PathBuf> = Vec::new();
+    let mut tempo_map: Option<TempoMap> = None;
+    let mut options = ScheduleOptions::default();
+    let mut synth_settings = SynthSettings::default();
+    // Raised whenever this thread silences the synth so the callback stops
+    // counting the notes it had seen start.
+    let notes_released = Arc::new(AtomicBool::new(false));
+    let previews = Arc::new(Mutex::new(Previews::default()));
+    let mut scrub_on_seek = false;
+    // Set by a step while stopped so the callback keeps rendering the
+    // stepped notes; playing or stopping clears it.
+    let stepping = Arc::new(AtomicBool::new(false));
+    let file_gain = Arc::new(AtomicU32::new(1.0f32.to_bits()));
+    let soundfont_gain = Arc::new(AtomicU32::new(1.0f32.to_bits()));
+    let stereo_width = Arc::new(AtomicU32::new(1.0f32.to_bits()));
+    let master_volume = Arc::new(AtomicU32::new(1.0f32.to_bits()));
+    // Gain playback ramps towards, and where the ramp is now.
+    let fade_target = Arc::new(AtomicU32::new(1.0f32.to_bits()));
+    let fade_gain = Arc::new(AtomicU32::new(0.0f32.to_bits()));
+    // Free-running click: seconds per beat as f64 bits, zero when off, and
+    // beats per bar.
+    let mut practice_click = false;
+    let mut practice_meter_seconds = 0.5f64;
+    let practice_beat = Arc::new(AtomicU64::new(0));
+    let practice_bar = Arc::new(AtomicU64::new(4));
+    let mut eq_gains = (0.0f32, 0.0f32, 0.0f32);
+    let cues = Arc::new(Mutex::new(BlockCues::default()));
+    let release_notes = |synth: &mut Synth| {
+        send_all_notes_off(synth);
+        notes_released.store(true, Ordering::Relaxed);
+    };
+    // Sample of the last note end, without the reverb tail; used to
+    // interpolate ticks past the final event.
+    let end_sample = Arc::new(AtomicU64::new(0));
+    // Loop bounds in samples for the callback; an end of zero means no loop.
+    let mut loop_ticks: Option<(u64, u64)> = None;
+    let loop_start_sample = Arc::new(AtomicU64::new(0));
+    let loop_start_tick = Arc::new(AtomicU64::new(0));
+    let loop_end_sample = Arc::new(AtomicU64::new(0));
+    let loop_seam = Arc::new(AtomicU8::new(LoopSeam::default() as u8));
+    let song_loop = Arc::new(AtomicBool::new(false));
+    // Channel state at the loop start and at the top of the song, worked out
+    // here so a loop wrap in the callback sends a handful of events rather
+    // than every controller and bend before the loop.
+    let store_loop_chase = || {
+        let events = playback_events.lock().unwrap();
+        let loop_start = if loop_end_sample.load(Ordering::Relaxed) > 0 {
+            let index = seek_index(&events, loop_start_sample.load(Ordering::Relaxed));
+            chased_channel_state(&events, index)
+        } else {
+            Vec::new()
+        };
+        let song_start = song_start_setup(&events);
+        drop(events);
+        let mut cues = cues.lock().unwrap();
+        cues.loop_start_chase = loop_start;
+        cues.song_start_chase = song_start;
+    };
+    let store_loop = |tempo_map: Option<&TempoMap>,
+                      loop_ticks: Option<(u64, u64)>,
+                      sample_rate: u32| {
+        let to_sample =
+            |map: &TempoMap, tick: u64| (map.seconds_at(tick) * sample_rate as f64).round() as u64;
+        match (tempo_map, loop_ticks) {
+            (Some(map), Some((start, end))) if end > start => {
+                loop_start_sample.store(to_sample(map, start), Ordering::Relaxed);
+                loop_start_tick.store(start, Ordering::Relaxed);
+                loop_end_sample.store(to_sample(map, end), Ordering::Relaxed);
+            }
+            _ => loop_end_sample.store(0, Ordering::Relaxed),
+        }
+        store_loop_chase();
+    };
+    // Click positions from `SetClicks`, kept in ticks so they can be placed
+    // again whenever the tempo map or sample rate changes.
+    let mut click_tick_list: Vec<(u64, bool)> = Vec::new();
+    let clicks_changed = Arc::new(AtomicBool::new(false));
+    let channel_mix = Arc::new(Mutex::new(ChannelMix::default()));
+    let store_clicks = |tempo_map: Option<&TempoMap>, ticks: &[(u64, bool)], sample_rate: u32| {
+        let samples = match tempo_map {
+            Some(map) => ticks
+                .iter()
+                .map(|&(tick, accent)| {
+                    let sample = (map.seconds_at(tick) * sample_rate as f64).round() as u64;
+                    (sample, accent)
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+        cues.lock().unwrap().clicks = samples;
+        clicks_changed.store(true, Ordering::Relaxed);
+    };
+    // Swaps in a rebuilt schedule while keeping the playback position;
+    // `index` is the next event to play from it.
+    let install_schedule = |schedule: PlaybackSchedule, index: usize| -> TempoMap {
+        let (next_sample, next_tick) = schedule
+            .events
+            .get(index)
+            .map(|event| (event.sample, event.tick))
+            .unwrap_or((schedule.end_sample, schedule.ruler_max_tick));
+        next_event_sample.store(next_sample, Ordering::Relaxed);
+        next_event_tick.store(next_tick, Ordering::Relaxed);
+        total_samples.store(schedule.total_samples, Ordering::Relaxed);
+        end_sample.store(schedule.end_sample, Ordering::Relaxed);
+        finished.store(false, Ordering::Relaxed);
+        *playback_events.lock().unwrap() = schedule.events;
+        *playback_index.lock().unwrap() = index;
+        store_loop_chase();
+        schedule.tempo_map
+    };
+    // Rebuilds the loaded file's schedule after a change to which notes it
+    // plays, at the same sample. The sounding notes may no longer be in it,
+    // so they are released.
+    let rebuild_schedule =
+        |path: Option<&PathBuf>, options: &ScheduleOptions, sample_rate: u32| -> Option<TempoMap> {
+            let schedule = options.build(path?, sample_rate).ok()?;
+            release_notes(&mut synth.lock().unwrap());
+            let position = samples_played.load(Ordering::Relaxed);
+            let index = seek_index(&schedule.events, position);
+            Some(install_schedule(schedule, index))
+        };
+    // Rebuilds the schedule at another tempo or speed, keeping the musical
+    // position: the same tick lands on a different sample. No key changes,
+    // so the sounding notes carry on.
+    let retime = |path: Option<&PathBuf>,
+                  old_map: Option<&TempoMap>,
+                  options: &ScheduleOptions,
+                  click_ticks: &[(u64, bool)],
+                  loop_ticks: Option<(u64, u64)>,
+                  sample_rate: u32|
+     -> Option<TempoMap> {
+        let old_map = old_map?;
+        let schedule = options.build(path?, sample_rate).ok()?;
+        let rescale = |sample: u64| {
+            let tick = old_map.tick_at(sample as f64 / sample_rate as f64);
+            (schedule.tempo_map.seconds_at(tick) * sample_rate as f64).round() as u64
+        };
+        // Same events in the same order, so the next one to play is too.
+        let index = *playback_index.lock().unwrap();
+        let position = retimed_position(
+            &schedule.events,
+            index,
+            rescale(samples_played.load(Ordering::Relaxed)),
+        );
+        let last_event = rescale(last_event_sample.load(Ordering::Relaxed)).min(position);
+        samples_played.store(position, Ordering::Relaxed);
+        last_event_sample.store(last_event, Ordering::Relaxed);
+        let tempo_map = install_schedule(schedule, index);
+        store_loop(Some(&tempo_map), loop_ticks, sample_rate);
+        store_clicks(Some(&tempo_map), click_ticks, sample_rate);
+        Some(tempo_map)
+    };
+    let build_stream = |config: &SupportedStreamConfig| -> cpal::Stream {
+        let channels = config.channels() as usize;
+        let synth_clone_cb = Arc::clone(&synth);
+        let playback_events_clone_cb = Arc::clone(&playback_events);
+        let samples_played_clone_cb = Arc::clone(&samples_played);
+        let playback_index_clone_cb = Arc::clone(&playback_index);
+        let is_playing_clone_cb = Arc::clone(&is_playing);
+        let end_sample_clone_cb = Arc::clone(&end_sample);
+        let max_tick_clone_cb = Arc::clone(&max_tick_shared);
+        let last_event_sample_clone_cb = Arc::clone(&last_event_sample);
+        let last_event_tick_clone_cb = Arc::clone(&last_event_tick);
+        let next_event_sample_clone_cb = Arc::clone(&next_event_sample);
+        let next_event_tick_clone_cb = Arc::clone(&next_event_tick);
+        let channel_activity_clone_cb = Arc::clone(&channel_activity);
+        let loop_start_sample_clone_cb = Arc::clone(&loop_start_sample);
+        let loop_start_tick_clone_cb = Arc::clone(&loop_start_tick);
+        let loop_end_sample_clone_cb = Arc::clone(&loop_end_sample);
+        let loop_seam_clone_cb = Arc::clone(&loop_seam);
+        let song_loop_clone_cb = Arc::clone(&song_loop);
+        let total_samples_clone_cb = Arc::clone(&total_samples);
+        let finished_clone_cb = Arc::clone(&finished);
+        let loop_fade_samples = reverb_tail_samples(LOOP_FADE_SECONDS, config.sample_rate());
+        let fade_step =
+            1.0 / reverb_tail_samples(PLAYBACK_FADE_SECONDS, config.sample_rate()).max(1) as f32;
+        let fade_target_clone_cb = Arc::clone(&fade_target);
+        let fade_gain_clone_cb = Arc::clone(&fade_gain);
+        let mut fade = 0.0f32;
+        let practice_beat_clone_cb = Arc::clone(&practice_beat);
+        let practice_bar_clone_cb = Arc::clone(&practice_bar);
+        let mut practice_sample = 0u64;
+        let mut practice_player = ClickPlayer::default();
+        let held_notes_clone_cb = Arc::clone(&held_notes);
+        let peak_notes_clone_cb = Arc::clone(&peak_notes);
+        let clip_count_clone_cb = Arc::clone(&clip_count);
+        let notes_released_clone_cb = Arc::clone(&notes_released);
+        let previews_clone_cb = Arc::clone(&previews);
+        let stepping_clone_cb = Arc::clone(&stepping);
+        let file_gain_clone_cb = Arc::clone(&file_gain);
+        let soundfont_gain_clone_cb = Arc::clone(&soundfont_gain);
+        let stereo_width_clone_cb = Arc::clone(&stereo_width);
+        let master_volume_clone_cb = Arc::clone(&master_volume);
+        let cues_clone_cb = Arc::clone(&cues);
+        let mut eq_state = [[[0.0f32; 4]; 3]; 2];
+        let clicks_changed_clone_cb = Arc::clone(&clicks_changed);
+        let channel_mix_clone_cb = Arc::clone(&channel_mix);
+        let click_rate = config.sample_rate();
+        let mut click_player = ClickPlayer::default();
+        let mut note_meter = NoteMeter::default();
+
+        debug!("Audio thread: Building output stream...");
+        let (buffer_size, _) = stream_buffer_size(config.buffer_size(), output_config.buffer_size);
+        let stream = device
+            .build_output_stream(
+                &StreamConfig {
+                    buffer_size,
+                    ..config.config()
+                },
+                move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
+                    // The command loop holds a lock only while it changes
+                    // what plays; the block it lands on is silent rather
+                    // than whatever the device buffer last held.
+                    let (
+                        Ok(mut synth),
+                        Ok(events),
+                        Ok(mut index),
+                        Ok(playing_guard),
+                        Ok(mut previews),
+                        Ok(cues),
+                        Ok(mut mix),
+                    ) = (
+                        synth_clone_cb.try_lock(),
+                        playback_events_clone_cb.try_lock(),
+                        playback_index_clone_cb.try_lock(),
+                        is_playing_clone_cb.try_lock(),
+                        previews_clone_cb.try_lock(),
+                        cues_clone_cb.try_lock(),
+                        channel_mix_clone_cb.try_lock(),
+                    )
+                    else {
+                        data.fill(0.0);
+                        return;
+                    };
+                    let playing = *playing_guard;
+                    let Previews { audition, scrub } = &mut *previews;
+                    let eq = cues.eq;
+                    if clicks_changed_clone_cb.swap(false, Ordering::Relaxed) {
+                        click_player.reset();
+                    }
+                    mix.refresh(|event| {
+                        let _ = synth.send_event(event);
+                    });
+                    if eq.is_none() {
+                        eq_state = [[[0.0; 4]; 3]; 2];
+                    }
+                    if playing {
+                        *scrub = None;
+                        stepping_clone_cb.store(false, Ordering::Relaxed);
+                    }
+                    let stepping = stepping_clone_cb.load(Ordering::Relaxed);
+                    if notes_released_clone_cb.swap(false, Ordering::Relaxed) {
+                        note_meter.clear();
+                    }
+                    let mut peak = 0.0f32;
+                    // SoundFont trim and file normalization, shared by
+                    // playback and the previews so both sound as loud.
+                    let gain = f32::from_bits(soundfont_gain_clone_cb.load(Ordering::Relaxed))
+                        * f32::from_bits(file_gain_clone_cb.load(Ordering::Relaxed));
+                    let width = f32::from_bits(stereo_width_clone_cb.load(Ordering::Relaxed));
+                    let volume = f32::from_bits(master_volume_clone_cb.load(Ordering::Relaxed));
+                    let target = f32::from_bits(fade_target_clone_cb.load(Ordering::Relaxed));
+                    if !playing {
+                        fade = 0.0;
+                    }
+                    let practice_beat_samples =
+                        (f64::from_bits(practice_beat_clone_cb.load(Ordering::Relaxed))
+                            * click_rate as f64)
+                            .round() as u64;
+                    let practice_bar = practice_bar_clone_cb.load(Ordering::Relaxed);
+                    for frame in data.chunks_mut(channels) {
+                        // Playing restarts the free-running click on a
+                        // downbeat once it stops.
+                        let practice = if playing || practice_beat_samples == 0 {
+                            practice_sample = 0;
+                            0.0
+                        } else {
+                            if let Some(accent) = practice_click_at(
+                                practice_sample,
+                                practice_beat_samples,
+                                practice_bar,
+                            ) {
+                                practice_player.start(accent);
+                            }
+                            practice_sample += 1;
+                            practice_player.sound(click_rate)
+                        };
+                        let auditioning = audition.is_some();
+                        if let Some(note) = audition.as_mut() {
+                            if let Some(note_off) = note.advance() {
+                                let _ = synth.send_event(note_off);
+                            }
+                            if note.finished() {
+                                *audition = None;
+                            }
+                        }
+                        let scrubbing = scrub.is_some();
+                        if let Some(snippet) = scrub.as_mut() {
+                            if snippet.advance(|event| {
+                                if let Some(event) = mix.apply(event) {
+                                    let _ = synth.send_event(event);
+                                }
+                            }) {
+                                send_all_notes_off(&mut synth);
+                                *scrub = None;
+                            }
+                        }
+                        if playing {
+                            let mut current_sample =
+                                samples_played_clone_cb.load(Ordering::Relaxed);
+                            let loop_end = loop_end_sample_clone_cb.load(Ordering::Relaxed);
+                            let seam = loop_seam_clone_cb.load(Ordering::Relaxed);
+                            if loop_end > 0 && current_sample >= loop_end {
+                                let loop_start = loop_start_sample_clone_cb.load(Ordering::Relaxed);
+                                if seam == LoopSeam::Release as u8 {
+                                    release_held_notes(&mut synth);
+                                } else {
+                                    send_all_notes_off(&mut synth);
+                                }
+                                note_meter.clear();
+                                samples_played_clone_cb.store(loop_start, Ordering::Relaxed);
+                                last_event_sample_clone_cb.store(loop_start, Ordering::Relaxed);
+                                last_event_tick_clone_cb.store(
+                                    loop_start_tick_clone_cb.load(Ordering::Relaxed),
+                                    Ordering::Relaxed,
+                                );
+                                *index = seek_index(&events, loop_start);
+                                for &event in cues.loop_start_chase.iter() {
+                                    if let Some(event) = mix.apply(event) {
+                                        let _ = synth.send_event(event);
+                                    }
+                                }
+                                current_sample = loop_start;
+                            } else if song_loop_clone_cb.load(Ordering::Relaxed)
+                                && current_sample >= total_samples_clone_cb.load(Ordering::Relaxed)
+                                && current_sample > 0
+                            {
+                                // The reverb tail has died away by now, so
+                                // cutting what is left doesn't click.
+                                send_all_notes_off(&mut synth);
+                                note_meter.clear();
+                                samples_played_clone_cb.store(0, Ordering::Relaxed);
+                                last_event_sample_clone_cb.store(0, Ordering::Relaxed);
+                                last_event_tick_clone_cb.store(0, Ordering::Relaxed);
+                                *index = 0;
+                                for &event in cues.song_start_chase.iter() {
+                                    if let Some(event) = mix.apply(event) {
+                                        let _ = synth.send_event(event);
+                                    }
+                                }
+                                current_sample = 0;
+                            }
+                            while *index < events.len() && events[*index].sample <= current_sample {
+                                let ev = &events[*index];
+                                if let Some(event) = mix.apply(ev.event) {
+                                    let _ = synth.send_event(event);
+                                    note_meter.apply(event);
+                                }
+                                if let MidiEvent::NoteOn { channel, vel, .. } = ev.event {
+                                    if vel > 0 {
+                                        if let Some(activity) =
+                                            channel_activity_clone_cb.get(channel as usize)
+                                        {
+                                            activity.store(current_sample + 1, Ordering::Relaxed);
+                                        }
+                                    }
+                                }
+                                last_event_sample_clone_cb.store(ev.sample, Ordering::Relaxed);
+                                last_event_tick_clone_cb.store(ev.tick, Ordering::Relaxed);
+                                *index += 1;
+                            }
+                            if *index < events.len() {
+                                let next = &events[*index];
+                                next_event_sample_clone_cb.store(next.sample, Ordering::Relaxed);
+                                next_event_tick_clone_cb.store(next.tick, Ordering::Relaxed);
+                            } else {
+                                next_event_sample_clone_cb.store(
+                                    end_sample_clone_cb.load(Ordering::Relaxed),
+                                    Ordering::Relaxed,
+                                );
+                                next_event_tick_clone_cb.store(
+                                    max_tick_clone_cb.load(Ordering::Relaxed),
+                                    Ordering::Relaxed,
+                                );
+                            }
+
+                            let click =
+                                click_player.render(&cues.clicks, current_sample, click_rate);
+                            fade = step_fade(fade, target, fade_step);
+                            let mut samples = [0.0f32; 2];
+                            synth.write(&mut samples[..]);
+                            let seam_gain = if seam == LoopSeam::Fade as u8 {
+                                loop_fade_gain(current_sample, loop_end, loop_fade_samples)
+                            } else {
+                                1.0
+                            };
+                            apply_stereo_width(&mut samples, width);
+                            if let Some(filters) = &eq {
+                                apply_eq(&mut samples, filters, &mut eq_state);
+                            }
+                            for sample in &mut samples {
+                                *sample = (*sample * gain * seam_gain + click) * volume * fade;
+                                peak = peak.max(sample.abs());
+                            }
+                            for (i, s) in frame.iter_mut().enumerate() {
+                                *s = samples[i % 2];
+                            }
+                            let _prev = samples_played_clone_cb.fetch_add(1, Ordering::Relaxed);
+                            finished_clone_cb.store(
+                                playback_finished(
+                                    *index,
+                                    events.len(),
+                                    current_sample + 1,
+                                    total_samples_clone_cb.load(Ordering::Relaxed),
+                                    song_loop_clone_cb.load(Ordering::Relaxed),
+                                    loop_end,
+                                ),
+                                Ordering::Relaxed,
+                            );
+                        } else if auditioning || scrubbing || stepping {
+                            let mut samples = [0.0f32; 2];
+                            synth.write(&mut samples[..]);
+                            apply_stereo_width(&mut samples, width);
+                            if let Some(filters) = &eq {
+                                apply_eq(&mut samples, filters, &mut eq_state);
+                            }
+                            for sample in &mut samples {
+                                *sample = (*sample * gain + practice) * volume;
+                                peak = peak.max(sample.abs());
+                            }
+                            for (i, s) in frame.iter_mut().enumerate() {
+                                *s = samples[i % 2];
+                            }
+                        } else {
+                            for s in frame.iter_mut() {
+                                *s = practice * volume;
+                            }
+                        }
+                    }
+                    fade_gain_clone_cb.store(fade.to_bits(), Ordering::Relaxed);
+                    if peak > 1.0 {
+                        let _prev = clip_count_clone_cb.fetch_add(1, Ordering::Relaxed);
+                    }
+                    let held = note_meter.held();
+                    held_notes_clone_cb.store(held, Ordering::Relaxed);
+                    let _prev = peak_notes_clone_cb.fetch_max(held, Ordering::Relaxed);
+                },
+                |err| error!("an error occurred on stream: {}", err),
+                None,
+            )
+            .unwrap();
+
+        stream.play().unwrap();
+        info!("Audio thread: Stream started.");
+        stream
+    };
+    let mut stream = build_stream(&config);
+
+    loop {
+        if let Ok(cmd) = cmd_rx.recv() {
+            match cmd {
+                AudioCommand::Play(midi_path, sf_path) => {
+                    debug!("Audio thread: Play command received.");
+                    let soundfont_changed = last_soundfont_path.as_ref() != Some(&sf_path);
+                    let should_reload = last_midi_path.as_ref() != Some(&midi_path)
+                        || soundfont_changed
+                        || playback_events.lock().unwrap().is_empty();
+                    let mut should_start = !should_reload;
+
+                    if should_reload {
+                        *is_playing.lock().unwrap() = false;
+                        release_notes(&mut synth.lock().unwrap());
+
+                        // Reloading keeps the layers above the new font.
+                        if soundfont_changed {
+                            hard_reset_synth(
+                                &mut synth.lock().unwrap(),
+                                sample_rate as f32,
+                                &synth_settings,
+                                Some(&sf_path),
+                                &soundfont_layers,
+                            );
+                            channel_mix.lock().unwrap().reset_file_controls();
+                        }
+
+                        if let Ok(schedule) = options.build(&midi_path, sample_rate) {
+                            let next_event = schedule
+                                .events
+                                .first()
+                                .map(|event| (event.sample, event.tick));
+                            *playback_events.lock().unwrap() = schedule.events;
+                            tempo_map = Some(schedule.tempo_map);
+                            store_loop(tempo_map.as_ref(), loop_ticks, sample_rate);
+                            store_clicks(tempo_map.as_ref(), &click_tick_list, sample_rate);
+                            samples_played.store(0, Ordering::Relaxed);
+                            reset_channel_activity(&channel_activity);
+                            total_samples.store(schedule.total_samples, Ordering::Relaxed);
+                            end_sample.store(schedule.end_sample, Ordering::Relaxed);
+                            max_tick_shared.store(schedule.ruler_max_tick, Ordering::Relaxed);
+                            last_event_sample.store(0, Ordering::Relaxed);
+                            last_event_tick.store(0, Ordering::Relaxed);
                             if let Some((next_sample, next_tick)) = next_event {
                                 next_event_sample.store(next_sample, Ordering::Relaxed);
                                 next_event_tick.store(next_tick, Ordering::Relaxed);
                             } else {
-                                next_event_sample.store(schedule.total_samples, Ordering::Relaxed);
+                                next_event_sample.store(schedule.end_sample, Ordering::Relaxed);
                                 next_event_tick.store(schedule.ruler_max_tick, Ordering::Relaxed);
                             }
                             *playback_index.lock().unwrap() = 0;
@@ -532,95 +2701,1825 @@ fn audio_thread(
                             should_start = true;
                         }
                     }
-                    if should_start {
-                        *is_playing.lock().unwrap() = true;
-                        println!("Audio thread: Playback started.");
+                    if should_start {
+                        if samples_played.load(Ordering::Relaxed) == 0 {
+                            let setup = initial_channel_setup(&playback_events.lock().unwrap());
+                            let mut synth = synth.lock().unwrap();
+                            apply_channel_setup(&mut channel_mix.lock().unwrap(), setup, |event| {
+                                let _ = synth.send_event(event);
+                            });
+                        }
+                        *is_playing.lock().unwrap() = true;
+                        debug!("Audio thread: Playback started.");
+                    }
+                }
+                AudioCommand::Pause => {
+                    debug!("Audio thread: Pause command received.");
+                    fade_out(&fade_target, &fade_gain);
+                    *is_playing.lock().unwrap() = false;
+                    fade_target.store(1.0f32.to_bits(), Ordering::Relaxed);
+                    release_notes(&mut synth.lock().unwrap());
+                }
+                AudioCommand::Stop => {
+                    debug!("Audio thread: Stop command received.");
+                    fade_out(&fade_target, &fade_gain);
+                    *is_playing.lock().unwrap() = false;
+                    fade_target.store(1.0f32.to_bits(), Ordering::Relaxed);
+                    samples_played.store(0, Ordering::Relaxed);
+                    finished.store(false, Ordering::Relaxed);
+                    reset_channel_activity(&channel_activity);
+                    *playback_index.lock().unwrap() = 0;
+                    hard_reset_synth(
+                        &mut synth.lock().unwrap(),
+                        sample_rate as f32,
+                        &synth_settings,
+                        last_soundfont_path.as_ref(),
+                        &soundfont_layers,
+                    );
+                    channel_mix.lock().unwrap().reset_file_controls();
+                    notes_released.store(true, Ordering::Relaxed);
+                    stepping.store(false, Ordering::Relaxed);
+                }
+                AudioCommand::Rewind => {
+                    debug!("Audio thread: Rewind command received.");
+                    samples_played.store(0, Ordering::Relaxed);
+                    finished.store(false, Ordering::Relaxed);
+                    reset_channel_activity(&channel_activity);
+                    *playback_index.lock().unwrap() = 0;
+                    let mut synth = synth.lock().unwrap();
+                    hard_reset_synth(
+                        &mut synth,
+                        sample_rate as f32,
+                        &synth_settings,
+                        last_soundfont_path.as_ref(),
+                        &soundfont_layers,
+                    );
+                    let mut mix = channel_mix.lock().unwrap();
+                    mix.reset_file_controls();
+                    let setup = initial_channel_setup(&playback_events.lock().unwrap());
+                    apply_channel_setup(&mut mix, setup, |event| {
+                        let _ = synth.send_event(event);
+                    });
+                    notes_released.store(true, Ordering::Relaxed);
+                }
+                AudioCommand::Reload(path) => {
+                    debug!("Audio thread: Reload command received.");
+                    // Not scheduled yet, or a different file: the next Play
+                    // builds it from the top.
+                    let Some(old_map) = tempo_map
+                        .as_ref()
+                        .filter(|_| last_midi_path.as_ref() == Some(&path))
+                    else {
+                        last_midi_path = None;
+                        continue;
+                    };
+                    let Ok(schedule) = options.build(&path, sample_rate) else {
+                        last_midi_path = None;
+                        continue;
+                    };
+                    // The tempos may have changed, so the position carries
+                    // over as a tick rather than a sample.
+                    let seconds =
+                        samples_played.load(Ordering::Relaxed) as f64 / sample_rate as f64;
+                    let tick = old_map.tick_at(seconds).min(schedule.ruler_max_tick);
+                    let position =
+                        (schedule.tempo_map.seconds_at(tick) * sample_rate as f64).round() as u64;
+                    let index = seek_index(&schedule.events, position);
+                    let setup: Vec<_> = chased_channel_setup(&schedule.events, index).collect();
+                    {
+                        let mut synth = synth.lock().unwrap();
+                        release_notes(&mut synth);
+                        apply_channel_setup(&mut channel_mix.lock().unwrap(), setup, |event| {
+                            let _ = synth.send_event(event);
+                        });
+                    }
+                    samples_played.store(position, Ordering::Relaxed);
+                    last_event_sample.store(position, Ordering::Relaxed);
+                    last_event_tick.store(tick, Ordering::Relaxed);
+                    max_tick_shared.store(schedule.ruler_max_tick, Ordering::Relaxed);
+                    reset_channel_activity(&channel_activity);
+                    let map = install_schedule(schedule, index);
+                    store_loop(Some(&map), loop_ticks, sample_rate);
+                    store_clicks(Some(&map), &click_tick_list, sample_rate);
+                    tempo_map = Some(map);
+                }
+                AudioCommand::Reset { keep_soundfont } => {
+                    debug!("Audio thread: Reset command received.");
+                    *is_playing.lock().unwrap() = false;
+                    stepping.store(false, Ordering::Relaxed);
+                    playback_events.lock().unwrap().clear();
+                    *playback_index.lock().unwrap() = 0;
+                    samples_played.store(0, Ordering::Relaxed);
+                    total_samples.store(0, Ordering::Relaxed);
+                    finished.store(false, Ordering::Relaxed);
+                    end_sample.store(0, Ordering::Relaxed);
+                    max_tick_shared.store(0, Ordering::Relaxed);
+                    last_event_sample.store(0, Ordering::Relaxed);
+                    last_event_tick.store(0, Ordering::Relaxed);
+                    next_event_sample.store(0, Ordering::Relaxed);
+                    next_event_tick.store(0, Ordering::Relaxed);
+                    reset_channel_activity(&channel_activity);
+                    peak_notes.store(0, Ordering::Relaxed);
+                    last_midi_path = None;
+                    tempo_map = None;
+                    store_loop(None, None, sample_rate);
+                    store_clicks(None, &click_tick_list, sample_rate);
+                    if !keep_soundfont {
+                        last_soundfont_path = None;
+                        soundfont_layers.clear();
+                    }
+                    hard_reset_synth(
+                        &mut synth.lock().unwrap(),
+                        sample_rate as f32,
+                        &synth_settings,
+                        last_soundfont_path.as_ref(),
+                        &soundfont_layers,
+                    );
+                    channel_mix.lock().unwrap().reset_file_controls();
+                    notes_released.store(true, Ordering::Relaxed);
+                }
+                AudioCommand::Seek(tick) => {
+                    debug!("Audio thread: Seek command received ({}).", tick);
+                    let Some(tempo_map) = &tempo_map else {
+                        continue;
+                    };
+                    let tick = tick.min(max_tick_shared.load(Ordering::Relaxed));
+                    let sample = (tempo_map.seconds_at(tick) * sample_rate as f64).round() as u64;
+                    let events = playback_events.lock().unwrap();
+                    let index = seek_index(&events, sample);
+                    let (next_sample, next_tick) = events
+                        .get(index)
+                        .map(|event| (event.sample, event.tick))
+                        .unwrap_or((
+                            end_sample.load(Ordering::Relaxed),
+                            max_tick_shared.load(Ordering::Relaxed),
+                        ));
+                    let setup: Vec<_> = chased_channel_setup(&events, index).collect();
+                    drop(events);
+                    {
+                        let mut synth = synth.lock().unwrap();
+                        release_notes(&mut synth);
+                        let mut mix = channel_mix.lock().unwrap();
+                        for event in setup {
+                            if let Some(event) = mix.apply(event) {
+                                let _ = synth.send_event(event);
+                            }
+                        }
+                    }
+                    samples_played.store(sample, Ordering::Relaxed);
+                    finished.store(false, Ordering::Relaxed);
+                    last_event_sample.store(sample, Ordering::Relaxed);
+                    last_event_tick.store(tick, Ordering::Relaxed);
+                    next_event_sample.store(next_sample, Ordering::Relaxed);
+                    next_event_tick.store(next_tick, Ordering::Relaxed);
+                    reset_channel_activity(&channel_activity);
+                    *playback_index.lock().unwrap() = index;
+                    if scrub_on_seek && !*is_playing.lock().unwrap() {
+                        let length = (SCRUB_SECONDS as f64 * sample_rate as f64).round() as u64;
+                        let snippet =
+                            ScrubSnippet::new(&playback_events.lock().unwrap(), sample, length);
+                        previews.lock().unwrap().scrub = Some(snippet);
+                    }
+                }
+                AudioCommand::SetScrubOnSeek(enabled) => {
+                    debug!("Audio thread: Scrub on seek set to {}.", enabled);
+                    scrub_on_seek = enabled;
+                    if !enabled {
+                        previews.lock().unwrap().scrub = None;
+                    }
+                }
+                AudioCommand::SetInterpolation(mode) => {
+                    debug!("Audio thread: Interpolation set to {:?}.", mode);
+                    synth_settings.interpolation = mode;
+                    synth
+                        .lock()
+                        .unwrap()
+                        .set_interpolation_method(None, interpolation_method(mode));
+                }
+                AudioCommand::SetSongLoop(enabled) => {
+                    debug!("Audio thread: Song loop set to {}.", enabled);
+                    song_loop.store(enabled, Ordering::Relaxed);
+                }
+                AudioCommand::SetLoop(range) => {
+                    debug!("Audio thread: Loop set to {:?}.", range);
+                    loop_ticks = range;
+                    store_loop(tempo_map.as_ref(), loop_ticks, sample_rate);
+                }
+                AudioCommand::SetSampleRate(requested) => {
+                    // Going back to the default rate means the one from
+                    // `config.toml`, if it set one.
+                    let (new_config, message) =
+                        select_output_config(&device, requested.or(output_config.sample_rate));
+                    if let Some(message) = message {
+                        warn!("Audio thread: {}", message);
+                        *notice.lock().unwrap() = Some(message);
+                    }
+                    let new_rate = new_config.sample_rate();
+                    if new_rate == sample_rate {
+                        continue;
+                    }
+                    info!("Audio thread: Sample rate changed to {}.", new_rate);
+                    drop(stream);
+                    let old_rate = sample_rate;
+                    sample_rate = new_rate;
+                    sample_rate_shared.store(sample_rate as u64, Ordering::Relaxed);
+                    {
+                        let mut synth = synth.lock().unwrap();
+                        release_notes(&mut synth);
+                        synth.set_sample_rate(sample_rate as f32);
+                    }
+                    let position =
+                        rescale_sample(samples_played.load(Ordering::Relaxed), old_rate, new_rate);
+                    samples_played.store(position, Ordering::Relaxed);
+                    last_event_sample.store(
+                        rescale_sample(
+                            last_event_sample.load(Ordering::Relaxed),
+                            old_rate,
+                            new_rate,
+                        ),
+                        Ordering::Relaxed,
+                    );
+                    reset_channel_activity(&channel_activity);
+                    // Event times are baked in samples, so the schedule has
+                    // to be rebuilt at the new rate.
+                    if let Some(path) = &last_midi_path {
+                        if let Ok(schedule) = options.build(path, sample_rate) {
+                            let index = seek_index(&schedule.events, position);
+                            tempo_map = Some(install_schedule(schedule, index));
+                        }
+                    }
+                    store_loop(tempo_map.as_ref(), loop_ticks, sample_rate);
+                    store_clicks(tempo_map.as_ref(), &click_tick_list, sample_rate);
+                    cues.lock().unwrap().eq =
+                        eq_filters(sample_rate, eq_gains.0, eq_gains.1, eq_gains.2);
+                    stream = build_stream(&new_config);
+                }
+                AudioCommand::SetTranspose(shifts) => {
+                    debug!("Audio thread: Transpose set to {:?}.", shifts);
+                    options.transpose = shifts;
+                    if let Some(map) =
+                        rebuild_schedule(last_midi_path.as_ref(), &options, sample_rate)
+                    {
+                        tempo_map = Some(map);
+                    }
+                }
+                AudioCommand::SetOnlyTrack(track) => {
+                    debug!("Audio thread: Only track set to {:?}.", track);
+                    if options.only_track == track {
+                        continue;
+                    }
+                    options.only_track = track;
+                    if let Some(map) =
+                        rebuild_schedule(last_midi_path.as_ref(), &options, sample_rate)
+                    {
+                        tempo_map = Some(map);
+                    }
+                }
+                AudioCommand::SetSongEnd(end) => {
+                    debug!("Audio thread: Song end set to {:?}.", end);
+                    if options.song_end == end {
+                        continue;
+                    }
+                    options.song_end = end;
+                    if let Some(map) =
+                        rebuild_schedule(last_midi_path.as_ref(), &options, sample_rate)
+                    {
+                        tempo_map = Some(map);
+                    }
+                }
+                AudioCommand::SetTempoOverride(us_per_beat) => {
+                    debug!("Audio thread: Tempo override set to {:?}.", us_per_beat);
+                    options.tempo = PlaybackTempo {
+                        fixed: us_per_beat,
+                        ..options.tempo
+                    };
+                    if let Some(map) = retime(
+                        last_midi_path.as_ref(),
+                        tempo_map.as_ref(),
+                        &options,
+                        &click_tick_list,
+                        loop_ticks,
+                        sample_rate,
+                    ) {
+                        tempo_map = Some(map);
+                    }
+                }
+                AudioCommand::SetSpeed(speed) => {
+                    let speed = PlaybackSpeed::clamped(speed);
+                    debug!("Audio thread: Speed set to {:.2}.", speed);
+                    options.tempo = PlaybackTempo {
+                        speed,
+                        ..options.tempo
+                    };
+                    if let Some(map) = retime(
+                        last_midi_path.as_ref(),
+                        tempo_map.as_ref(),
+                        &options,
+                        &click_tick_list,
+                        loop_ticks,
+                        sample_rate,
+                    ) {
+                        tempo_map = Some(map);
+                    }
+                }
+                AudioCommand::SetChannelMix(strips) => {
+                    debug!("Audio thread: Channel mix set.");
+                    channel_mix.lock().unwrap().set_strips(strips);
+                }
+                AudioCommand::SetClicks(ticks) => {
+                    debug!("Audio thread: {} clicks set.", ticks.len());
+                    click_tick_list = ticks;
+                    store_clicks(tempo_map.as_ref(), &click_tick_list, sample_rate);
+                }
+                AudioCommand::SetMetronome(on) => {
+                    debug!(
+                        "Audio thread: Free-running click {}.",
+                        if on { "on" } else { "off" }
+                    );
+                    practice_click = on;
+                    let seconds = if on { practice_meter_seconds } else { 0.0 };
+                    practice_beat.store(seconds.to_bits(), Ordering::Relaxed);
+                }
+                AudioCommand::SetMetronomeMeter {
+                    beat_seconds,
+                    beats_per_bar,
+                } => {
+                    practice_meter_seconds = beat_seconds.max(0.05);
+                    practice_bar.store(beats_per_bar as u64, Ordering::Relaxed);
+                    if practice_click {
+                        practice_beat.store(practice_meter_seconds.to_bits(), Ordering::Relaxed);
+                    }
+                }
+                AudioCommand::SetLoopSeam(seam) => {
+                    debug!("Audio thread: Loop seam set to {:?}.", seam);
+                    loop_seam.store(seam as u8, Ordering::Relaxed);
+                }
+                AudioCommand::SetReverb(level) => {
+                    debug!("Audio thread: Reverb level set to {:.1}.", level);
+                    synth_settings.reverb_level = level.clamp(0.0, 1.0);
+                    set_reverb_level(&mut synth.lock().unwrap(), synth_settings.reverb_level);
+                }
+                AudioCommand::SetChorus(level) => {
+                    debug!("Audio thread: Chorus level set to {:.1}.", level);
+                    synth_settings.chorus_level = level.max(0.0);
+                    set_chorus_level(&mut synth.lock().unwrap(), synth_settings.chorus_level);
+                }
+                AudioCommand::AddSoundFont(path) => {
+                    debug!("Audio thread: Adding SoundFont {}.", path.display());
+                    if load_soundfont(&mut synth.lock().unwrap(), &path) {
+                        soundfont_layers.push(path);
+                    }
+                }
+                AudioCommand::ClearSoundFonts => {
+                    debug!("Audio thread: Clearing layered SoundFonts.");
+                    soundfont_layers.clear();
+                    let mut synth = synth.lock().unwrap();
+                    hard_reset_synth(
+                        &mut synth,
+                        sample_rate as f32,
+                        &synth_settings,
+                        last_soundfont_path.as_ref(),
+                        &soundfont_layers,
+                    );
+                    let mut mix = channel_mix.lock().unwrap();
+                    mix.reset_file_controls();
+                    // Programs set earlier in the file were lost with the
+                    // old synth.
+                    let events = playback_events.lock().unwrap();
+                    let index = *playback_index.lock().unwrap();
+                    for event in chased_channel_setup(&events, index) {
+                        if let Some(event) = mix.apply(event) {
+                            let _ = synth.send_event(event);
+                        }
+                    }
+                }
+                AudioCommand::SetPolyphony(limit) => {
+                    debug!("Audio thread: Polyphony set to {} voices.", limit);
+                    match synth.lock().unwrap().set_polyphony(limit) {
+                        Ok(()) => synth_settings.polyphony = limit,
+                        Err(err) => {
+                            warn!("Audio thread: Invalid polyphony {}: {:?}", limit, err)
+                        }
                     }
                 }
-                AudioCommand::Pause => {
-                    println!("Audio thread: Pause command received.");
+                AudioCommand::Shutdown => {
+                    debug!("Audio thread: Shutdown command received.");
+                    // Same ramp as Stop, so the stream closes on silence.
+                    fade_out(&fade_target, &fade_gain);
                     *is_playing.lock().unwrap() = false;
-                    send_all_notes_off(&mut synth.lock().unwrap());
+                    *previews.lock().unwrap() = Previews::default();
+                    release_notes(&mut synth.lock().unwrap());
+                    if let Err(err) = stream.pause() {
+                        warn!("Audio thread: Could not pause stream: {}", err);
+                    }
+                    drop(stream);
+                    info!("Audio thread: Stream closed.");
+                    break;
                 }
-                AudioCommand::Stop => {
-                    println!("Audio thread: Stop command received.");
-                    *is_playing.lock().unwrap() = false;
-                    samples_played.store(0, Ordering::Relaxed);
-                    *playback_index.lock().unwrap() = 0;
-                    hard_reset_synth(
-                        &mut synth.lock().unwrap(),
-                        sample_rate as f32,
-                        last_soundfont_path.as_ref(),
+                AudioCommand::StepEvent => {
+                    if *is_playing.lock().unwrap() {
+                        continue;
+                    }
+                    let events = playback_events.lock().unwrap();
+                    let mut index = playback_index.lock().unwrap();
+                    let mut synth = synth.lock().unwrap();
+                    let stepped = send_step(
+                        &events,
+                        &mut index,
+                        &mut channel_mix.lock().unwrap(),
+                        |event| {
+                            let _ = synth.send_event(event);
+                        },
                     );
+                    let message = match stepped {
+                        Some(event) => {
+                            stepping.store(true, Ordering::Relaxed);
+                            samples_played.store(event.sample, Ordering::Relaxed);
+                            last_event_sample.store(event.sample, Ordering::Relaxed);
+                            last_event_tick.store(event.tick, Ordering::Relaxed);
+                            let (next_sample, next_tick) = events
+                                .get(*index)
+                                .map(|next| (next.sample, next.tick))
+                                .unwrap_or((
+                                    end_sample.load(Ordering::Relaxed),
+                                    max_tick_shared.load(Ordering::Relaxed),
+                                ));
+                            next_event_sample.store(next_sample, Ordering::Relaxed);
+                            next_event_tick.store(next_tick, Ordering::Relaxed);
+                            format!("Step: {}", describe_event(event))
+                        }
+                        None if events.is_empty() => continue,
+                        None => "Step: end of events".to_string(),
+                    };
+                    *notice.lock().unwrap() = Some(message);
                 }
-                AudioCommand::Rewind => {
-                    println!("Audio thread: Rewind command received.");
-                    samples_played.store(0, Ordering::Relaxed);
-                    *playback_index.lock().unwrap() = 0;
-                    hard_reset_synth(
-                        &mut synth.lock().unwrap(),
-                        sample_rate as f32,
-                        last_soundfont_path.as_ref(),
+                AudioCommand::Audition {
+                    channel,
+                    key,
+                    program,
+                    soundfont,
+                } => {
+                    if last_soundfont_path.is_none() {
+                        hard_reset_synth(
+                            &mut synth.lock().unwrap(),
+                            sample_rate as f32,
+                            &synth_settings,
+                            Some(&soundfont),
+                            &soundfont_layers,
+                        );
+                        channel_mix.lock().unwrap().reset_file_controls();
+                        last_soundfont_path = Some(soundfont);
+                    }
+                    let playing = *is_playing.lock().unwrap();
+                    // Once a file is scheduled, the program in effect at the
+                    // playhead, so resuming carries on with the same one.
+                    let program = {
+                        let events = playback_events.lock().unwrap();
+                        if events.is_empty() {
+                            program
+                        } else {
+                            let position = samples_played.load(Ordering::Relaxed);
+                            Some(program_at(&events, position, channel).unwrap_or(0))
+                        }
+                    };
+                    let mut synth = synth.lock().unwrap();
+                    let mut previews = previews.lock().unwrap();
+                    // A new click cuts the previous note so rapid clicks
+                    // never leave one hanging.
+                    if let Some(previous) = previews.audition.take() {
+                        let _ = synth.send_event(previous.note_off());
+                    }
+                    // While playing, the file's own program changes apply.
+                    // The mixer keeps a program override and remembers the
+                    // file's program for the next refresh.
+                    if let Some(program_id) = program.filter(|_| !playing) {
+                        let change = MidiEvent::ProgramChange {
+                            channel,
+                            program_id,
+                        };
+                        if let Some(event) = channel_mix.lock().unwrap().apply(change) {
+                            let _ = synth.send_event(event);
+                        }
+                    }
+                    let _ = synth.send_event(MidiEvent::NoteOn {
+                        channel,
+                        key,
+                        vel: AUDITION_VELOCITY,
+                    });
+                    previews.audition = Some(Audition::new(channel, key, sample_rate));
+                }
+                AudioCommand::SetFileGain(gain_db) => {
+                    debug!("Audio thread: File gain set to {:+.1} dB.", gain_db);
+                    file_gain.store(db_to_gain(gain_db).to_bits(), Ordering::Relaxed);
+                }
+                AudioCommand::SetSoundFontGain(gain_db) => {
+                    debug!("Audio thread: SoundFont gain set to {:+.1} dB.", gain_db);
+                    soundfont_gain.store(db_to_gain(gain_db).to_bits(), Ordering::Relaxed);
+                }
+                AudioCommand::SetVolume(volume) => {
+                    let volume = MasterVolume::clamped(volume);
+                    debug!("Audio thread: Master volume set to {:.2}.", volume);
+                    master_volume.store(volume.to_bits(), Ordering::Relaxed);
+                }
+                AudioCommand::SetStereoWidth(width) => {
+                    let width = StereoWidth::clamped(width);
+                    debug!("Audio thread: Stereo width set to {:.2}.", width);
+                    stereo_width.store(width.to_bits(), Ordering::Relaxed);
+                }
+                AudioCommand::SetEq { low, mid, high } => {
+                    eq_gains = (
+                        Equalizer::clamped(low),
+                        Equalizer::clamped(mid),
+                        Equalizer::clamped(high),
                     );
+                    debug!("Audio thread: EQ set to {:?} dB.", eq_gains);
+                    cues.lock().unwrap().eq =
+                        eq_filters(sample_rate, eq_gains.0, eq_gains.1, eq_gains.2);
+                }
+                AudioCommand::SetReverbTail(seconds) => {
+                    debug!("Audio thread: Reverb tail set to {:.1}s.", seconds);
+                    options.reverb_tail_seconds = seconds;
+                    if tempo_map.is_some() {
+                        total_samples.store(
+                            end_sample.load(Ordering::Relaxed)
+                                + reverb_tail_samples(seconds, sample_rate),
+                            Ordering::Relaxed,
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Synth settings from the preferences, reapplied whenever the synth is
+/// rebuilt from scratch.
+#[derive(Clone, Copy)]
+pub struct SynthSettings {
+    interpolation: Interpolation,
+    polyphony: u16,
+    reverb_level: f32,
+    chorus_level: f32,
+}
+
+impl Default for SynthSettings {
+    fn default() -> Self {
+        Self {
+            interpolation: Interpolation::default(),
+            polyphony: Preferences::DEFAULT_POLYPHONY,
+            reverb_level: Preferences::DEFAULT_REVERB_LEVEL,
+            chorus_level: Preferences::DEFAULT_CHORUS_LEVEL,
+        }
+    }
+}
+
+impl From<&Preferences> for SynthSettings {
+    fn from(preferences: &Preferences) -> Self {
+        Self {
+            interpolation: preferences.interpolation,
+            polyphony: preferences.polyphony,
+            reverb_level: preferences.reverb_level,
+            chorus_level: preferences.chorus_level,
+        }
+    }
+}
+
+fn hard_reset_synth(
+    synth: &mut Synth,
+    sample_rate: f32,
+    settings: &SynthSettings,
+    soundfont_path: Option<&PathBuf>,
+    layers: &[PathBuf],
+) {
+    *synth = Synth::default();
+    synth.set_sample_rate(sample_rate);
+    let _ = synth.set_polyphony(settings.polyphony);
+    synth.set_interpolation_method(None, interpolation_method(settings.interpolation));
+    set_reverb_level(synth, settings.reverb_level);
+    set_chorus_level(synth, settings.chorus_level);
+
+    for path in soundfont_path.into_iter().chain(layers) {
+        let _ = load_soundfont(synth, path);
+    }
+}
+
+// Each font goes on top of the stack, so later ones win for shared presets.
+fn load_soundfont(synth: &mut Synth, path: &Path) -> bool {
+    let Ok(mut file) = std::fs::File::open(path) else {
+        warn!("Could not open SoundFont {}", path.display());
+        return false;
+    };
+    let Ok(font) = SoundFont::load(&mut file) else {
+        warn!("Could not load SoundFont {}", path.display());
+        return false;
+    };
+    let id = synth.add_font(font, true);
+    info!("SoundFont loaded ({:?})", id);
+    true
+}
+
+// Room size, damping and width stay at the synth's defaults.
+fn set_reverb_level(synth: &mut Synth, level: f32) {
+    let params = ReverbParams {
+        level,
+        ..synth.reverb_params()
+    };
+    synth.set_reverb_params(&params);
+}
+
+// The chorus unit replaces levels it rejects instead of failing, so read
+// the level back to tell whether it took.
+fn set_chorus_level(synth: &mut Synth, level: f32) {
+    let params = ChorusParams {
+        level,
+        ..synth.chorus_params()
+    };
+    synth.set_chorus_params(&params);
+    let applied = synth.chorus_params().level;
+    if (applied - level).abs() > f32::EPSILON {
+        warn!("Chorus level {level:.1} is not supported; the chorus is at {applied:.1}");
+    }
+}
+
+// Note-off for everything held, but unlike `send_all_notes_off` the voices
+// keep their release and reverb tails.
+fn release_held_notes(synth: &mut Synth) {
+    for channel in 0u8..16 {
+        let _ = synth.send_event(MidiEvent::ControlChange {
+            channel,
+            ctrl: 64,
+            value: 0,
+        });
+        let _ = synth.send_event(MidiEvent::AllNotesOff { channel });
+    }
+}
+
+fn send_all_notes_off(synth: &mut Synth) {
+    for channel in 0u8..16 {
+        let _ = synth.send_event(MidiEvent::ControlChange {
+            channel,
+            ctrl: 64,
+            value: 0,
+        });
+        let _ = synth.send_event(MidiEvent::ControlChange {
+            channel,
+            ctrl: 120,
+            value: 0,
+        });
+        let _ = synth.send_event(MidiEvent::ControlChange {
+            channel,
+            ctrl: 123,
+            value: 0,
+        });
+        let _ = synth.send_event(MidiEvent::ControlChange {
+            channel,
+            ctrl: 121,
+            value: 0,
+        });
+        for key in 0u8..128 {
+            let _ = synth.send_event(MidiEvent::NoteOff { channel, key });
+        }
+    }
+}
+
+const RENDER_BLOCK_FRAMES: usize = 1024;
+
+/// Timings from `render_offline`, split so the scheduling and synth
+/// rendering costs can be profiled separately.
+pub struct OfflineRenderStats {
+    pub frames: u64,
+    pub sample_rate: u32,
+    pub schedule_time: Duration,
+    pub render_time: Duration,
+}
+
+/// Renders a whole file, reverb tail included, as fast as possible without
+/// an output device. `sink` receives interleaved stereo blocks.
+pub fn render_offline(
+    midi_path: &Path,
+    soundfont_path: &Path,
+    sample_rate: u32,
+    settings: &SynthSettings,
+    sink: impl FnMut(&[f32]),
+) -> Result<OfflineRenderStats, String> {
+    // Set up as playback sets up the live synth, so a bounce sounds the same.
+    let mut synth = Synth::default();
+    hard_reset_synth(&mut synth, sample_rate as f32, settings, None, &[]);
+    if !load_soundfont(&mut synth, soundfont_path) {
+        return Err(format!(
+            "Could not load SoundFont {}",
+            soundfont_path.display()
+        ));
+    }
+
+    let started = Instant::now();
+    let schedule = build_playback_schedule(
+        midi_path,
+        sample_rate,
+        Preferences::DEFAULT_REVERB_TAIL_SECONDS,
+        &HashMap::new(),
+        PlaybackTempo::default(),
+        None,
+        SongEnd::default(),
+    )
+    .map_err(|_| format!("Could not read MIDI file {}", midi_path.display()))?;
+    let schedule_time = started.elapsed();
+
+    let started = Instant::now();
+    let frames = render_schedule(&mut synth, &schedule, sink);
+    Ok(OfflineRenderStats {
+        frames,
+        sample_rate,
+        schedule_time,
+        render_time: started.elapsed(),
+    })
+}
+
+/// Bounces a file to a 16-bit stereo WAV, rendered the way `render_offline`
+/// does. A failed render removes the partly written file.
+pub fn render_to_wav(
+    midi: &Path,
+    sf: &Path,
+    sample_rate: u32,
+    settings: &SynthSettings,
+    out: &Path,
+) -> Result<OfflineRenderStats, String> {
+    let result = write_wav(midi, sf, sample_rate, settings, out);
+    if result.is_err() {
+        let _ = std::fs::remove_file(out);
+    }
+    result
+}
+
+fn write_wav(
+    midi: &Path,
+    sf: &Path,
+    sample_rate: u32,
+    settings: &SynthSettings,
+    out: &Path,
+) -> Result<OfflineRenderStats, String> {
+    let write_error = |err: std::io::Error| format!("Could not write {}: {err}", out.display());
+    let mut writer = BufWriter::new(std::fs::File::create(out).map_err(write_error)?);
+    // Sizes are patched in once the length is known.
+    writer
+        .write_all(&wav_header(sample_rate, 0))
+        .map_err(write_error)?;
+    let mut failed = None;
+    let stats = render_offline(midi, sf, sample_rate, settings, |block| {
+        if failed.is_some() {
+            return;
+        }
+        let bytes = block
+            .iter()
+            .flat_map(|sample| pcm16(*sample).to_le_bytes())
+            .collect::<Vec<_>>();
+        failed = writer.write_all(&bytes).err();
+    })?;
+    if let Some(err) = failed {
+        return Err(write_error(err));
+    }
+    let _position = writer.seek(SeekFrom::Start(0)).map_err(write_error)?;
+    writer
+        .write_all(&wav_header(sample_rate, stats.frames))
+        .map_err(write_error)?;
+    writer.flush().map_err(write_error)?;
+    Ok(stats)
+}
+
+fn pcm16(sample: f32) -> i16 {
+    (sample.clamp(-1.0, 1.0) * i16::MAX as f32).round() as i16
+}
+
+// Canonical 44-byte RIFF header for interleaved 16-bit stereo PCM.
+fn wav_header(sample_rate: u32, frames: u64) -> [u8; 44] {
+    const CHANNELS: u16 = 2;
+    const BYTES_PER_FRAME: u16 = CHANNELS * 2;
+    let data_len = (frames * BYTES_PER_FRAME as u64).min(u32::MAX as u64 - 36) as u32;
+    let mut header = [0u8; 44];
+    let fields: [&[u8]; 13] = [
+        b"RIFF",
+        &(36 + data_len).to_le_bytes(),
+        b"WAVE",
+        b"fmt ",
+        &16u32.to_le_bytes(),
+        &1u16.to_le_bytes(),
+        &CHANNELS.to_le_bytes(),
+        &sample_rate.to_le_bytes(),
+        &(sample_rate * BYTES_PER_FRAME as u32).to_le_bytes(),
+        &BYTES_PER_FRAME.to_le_bytes(),
+        &16u16.to_le_bytes(),
+        b"data",
+        &data_len.to_le_bytes(),
+    ];
+    let mut offset = 0;
+    for field in fields {
+        header[offset..offset + field.len()].copy_from_slice(field);
+        offset += field.len();
+    }
+    header
+}
+
+// Renders in blocks that end at the next event, so each event lands on the
+// same frame it would in the output callback.
+fn render_schedule(
+    synth: &mut Synth,
+    schedule: &PlaybackSchedule,
+    mut sink: impl FnMut(&[f32]),
+) -> u64 {
+    let mut buffer = vec![0.0f32; RENDER_BLOCK_FRAMES * 2];
+    let mut index = 0;
+    let mut frame = 0u64;
+    while frame < schedule.total_samples {
+        while index < schedule.events.len() && schedule.events[index].sample <= frame {
+            let _ = synth.send_event(schedule.events[index].event);
+            index += 1;
+        }
+        let block_end = schedule
+            .events
+            .get(index)
+            .map_or(schedule.total_samples, |event| event.sample)
+            .min(schedule.total_samples);
+        let frames = (block_end - frame).clamp(1, RENDER_BLOCK_FRAMES as u64) as usize;
+        let block = &mut buffer[..frames * 2];
+        synth.write(&mut *block);
+        sink(block);
+        frame += frames as u64;
+    }
+    frame
+}
+
+fn build_playback_schedule(
+    midi_path: &Path,
+    sample_rate: u32,
+    reverb_tail_seconds: f32,
+    transpose: &HashMap<usize, i8>,
+    tempo: PlaybackTempo,
+    only_track: Option<usize>,
+    song_end: SongEnd,
+) -> Result<PlaybackSchedule, ()> {
+    let data = std::fs::read(midi_path).map_err(|_| ())?;
+    let smf = Smf::parse(&data).map_err(|_| ())?;
+    Ok(build_playback_schedule_from_smf(
+        &smf,
+        sample_rate,
+        reverb_tail_seconds,
+        transpose,
+        tempo,
+        only_track,
+        song_end,
+    ))
+}
+
+#[cfg(test)]
+// The parser tests build their tracks an event at a time.
+#[allow(clippy::vec_init_then_push)]
+mod tests {
+    use super::{
+        active_channels, apply_channel_setup, apply_stereo_width, build_playback_schedule_from_smf,
+        chased_channel_setup, chased_channel_state, click_ticks, db_to_gain, describe_event,
+        eq_filters, event_channel, initial_channel_setup, loop_fade_gain, matching_rate_range,
+        midi_message_to_event, normalization_gain_db, parse_smf, pcm16, playback_finished,
+        practice_click_at, practice_meter, program_at, render_schedule, rescale_sample,
+        retimed_position, seek_index, send_step, song_start_setup, soundfont_layer_commands,
+        step_fade, stream_buffer_size, wav_header, AudioCommand, Audition, BarMap, Biquad,
+        ChannelMix, ClickPlayer, LoudnessMeter, MidiPlaybackEvent, NoteMeter, OutputConfig,
+        PlaybackTempo, ScrubSnippet,
+    };
+    use crate::input::parse_midi_tracks;
+    use crate::midi::file_timing;
+    use crate::state::{ChannelStrip, Metronome, NotePairing, PreviewSize, SongEnd};
+    use cpal::{BufferSize, SupportedBufferSize};
+    use midly::{Format, Smf, Timing, TrackEvent, TrackEventKind};
+    use oxisynth::{MidiEvent, Synth};
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+
+    #[test]
+    fn click_track_follows_beats_or_focused_onsets() {
+        let note = |delta: u32, key: u8, on: bool| TrackEvent {
+            delta: delta.into(),
+            kind: TrackEventKind::Midi {
+                channel: 0.into(),
+                message: if on {
+                    midly::MidiMessage::NoteOn {
+                        key: key.into(),
+                        vel: 100.into(),
+                    }
+                } else {
+                    midly::MidiMessage::NoteOff {
+                        key: key.into(),
+                        vel: 0.into(),
+                    }
+                },
+            },
+        };
+        let smf = Smf {
+            header: midly::Header {
+                format: Format::Parallel,
+                timing: Timing::Metrical(100.into()),
+            },
+            tracks: vec![
+                vec![note(0, 40, true), note(600, 40, false)],
+                // A chord on the downbeat, then an offbeat note.
+                vec![
+                    note(0, 60, true),
+                    note(0, 64, true),
+                    note(150, 60, false),
+                    note(0, 64, false),
+                    note(0, 67, true),
+                    note(50, 67, false),
+                ],
+            ],
+        };
+        let tracks = parse_midi_tracks(&smf, NotePairing::default(), PreviewSize::default());
+        assert!(click_ticks(Metronome::Off, &tracks, 0).is_empty());
+        assert_eq!(
+            click_ticks(Metronome::Beats, &tracks, 1),
+            vec![
+                (0, true),
+                (100, false),
+                (200, false),
+                (300, false),
+                (400, true),
+                (500, false)
+            ]
+        );
+        assert_eq!(
+            click_ticks(Metronome::Onsets, &tracks, 1),
+            vec![(0, true), (150, false)]
+        );
+    }
+
+    #[test]
+    fn practice_click_counts_beats_without_a_file() {
+        assert_eq!(practice_meter(&[], None, 1.0, 0), (0.5, 4));
+        assert_eq!(practice_meter(&[], Some(1_000_000), 2.0, 0), (0.5, 4));
+        let starts: Vec<_> = (0..500)
+            .filter_map(|sample| practice_click_at(sample, 100, 3).map(|accent| (sample, accent)))
+            .collect();
+        assert_eq!(
+            starts,
+            vec![
+                (0, true),
+                (100, false),
+                (200, false),
+                (300, true),
+                (400, false)
+            ]
+        );
+    }
+
+    #[test]
+    fn initial_setup_keeps_the_file_program_and_the_override_across_stop_and_play() {
+        let program = |channel: u8, program_id: u8| MidiPlaybackEvent {
+            sample: 0,
+            tick: 0,
+            port: 0,
+            event: MidiEvent::ProgramChange {
+                channel,
+                program_id,
+            },
+        };
+        let events = [program(0, 24), program(1, 10)];
+        let mut strips = [ChannelStrip::default(); 16];
+        strips[1].program = Some(40);
+        let mut mix = ChannelMix::default();
+        mix.set_strips(strips);
+        let mut programs = [0u8; 16];
+        let mut record = |event: MidiEvent| {
+            if let MidiEvent::ProgramChange {
+                channel,
+                program_id,
+            } = event
+            {
+                programs[channel as usize] = program_id;
+            }
+        };
+        // The mixer refreshes while stopped, then Play sets the channels up.
+        mix.refresh(&mut record);
+        apply_channel_setup(&mut mix, initial_channel_setup(&events), &mut record);
+        // A later mixer change sends every program again.
+        mix.set_strips(strips);
+        mix.refresh(&mut record);
+        assert_eq!(programs[0], 24);
+        assert_eq!(programs[1], 40);
+    }
+
+    #[test]
+    fn channel_mix_scales_volume_and_drops_silenced_notes() {
+        let cc = |channel: u8, ctrl: u8, value: u8| MidiEvent::ControlChange {
+            channel,
+            ctrl,
+            value,
+        };
+        let note_on = |channel: u8| MidiEvent::NoteOn {
+            channel,
+            key: 60,
+            vel: 100,
+        };
+        let mut mix = ChannelMix::default();
+        assert!(matches!(
+            mix.apply(note_on(3)),
+            Some(MidiEvent::NoteOn { .. })
+        ));
+        assert!(matches!(
+            mix.apply(cc(0, 7, 80)),
+            Some(MidiEvent::ControlChange { value: 80, .. })
+        ));
+
+        let mut strips = [ChannelStrip::default(); 16];
+        strips[0].volume = 50;
+        strips[0].pan = Some(0);
+        strips[1].solo = true;
+        mix.set_strips(strips);
+        let mut sent = Vec::new();
+        mix.refresh(|event| sent.push(event));
+        // Channel 0 is held at half the file's 80 and panned hard left.
+        assert!(matches!(
+            sent[..2],
+            [
+                MidiEvent::AllNotesOff { channel: 0 },
+                MidiEvent::ControlChange {
+                    ctrl: 7,
+                    value: 40,
+                    ..
                 }
+            ]
+        ));
+        assert!(matches!(
+            sent[2],
+            MidiEvent::ControlChange {
+                ctrl: 10,
+                value: 0,
+                ..
+            }
+        ));
+        assert!(matches!(
+            sent[3],
+            MidiEvent::ProgramChange { program_id: 0, .. }
+        ));
+        // Only the soloed channel goes without a note-off.
+        let silenced = sent
+            .iter()
+            .filter(|event| matches!(event, MidiEvent::AllNotesOff { .. }))
+            .count();
+        assert_eq!(silenced, 15);
+        let mut again = 0;
+        mix.refresh(|_| again += 1);
+        assert_eq!(again, 0);
+
+        assert!(mix.apply(note_on(3)).is_none());
+        assert!(mix.apply(note_on(1)).is_some());
+        assert!(matches!(
+            mix.apply(cc(0, 7, 100)),
+            Some(MidiEvent::ControlChange { value: 50, .. })
+        ));
+        assert!(matches!(
+            mix.apply(cc(0, 10, 100)),
+            Some(MidiEvent::ControlChange { value: 0, .. })
+        ));
+        assert!(matches!(
+            mix.apply(cc(1, 10, 100)),
+            Some(MidiEvent::ControlChange { value: 100, .. })
+        ));
+    }
+
+    #[test]
+    fn send_step_skips_notes_on_a_muted_channel() {
+        let note_on = |sample: u64, channel: u8| MidiPlaybackEvent {
+            sample,
+            tick: sample,
+            port: 0,
+            event: MidiEvent::NoteOn {
+                channel,
+                key: 60,
+                vel: 100,
+            },
+        };
+        let events = [note_on(0, 0), note_on(10, 1)];
+        let mut strips = [ChannelStrip::default(); 16];
+        strips[0].mute = true;
+        let mut mix = ChannelMix::default();
+        mix.set_strips(strips);
+        let mut index = 0;
+        let mut sent = Vec::new();
+
+        let stepped = send_step(&events, &mut index, &mut mix, |event| sent.push(event));
+        assert_eq!(stepped.map(|event| event.sample), Some(0));
+        assert!(sent.is_empty());
+        let stepped = send_step(&events, &mut index, &mut mix, |event| sent.push(event));
+        assert_eq!(stepped.map(|event| event.sample), Some(10));
+        assert!(matches!(sent[..], [MidiEvent::NoteOn { channel: 1, .. }]));
+        assert!(send_step(&events, &mut index, &mut mix, |event| sent.push(event)).is_none());
+        assert_eq!(index, 2);
+    }
+
+    #[test]
+    fn program_override_replaces_the_files_program_changes() {
+        let program = |channel: u8, program_id: u8| MidiEvent::ProgramChange {
+            channel,
+            program_id,
+        };
+        let mut mix = ChannelMix::default();
+        assert!(mix.apply(program(2, 40)).is_some());
+
+        let mut strips = [ChannelStrip::default(); 16];
+        strips[2].program = Some(73);
+        mix.set_strips(strips);
+        let mut sent = Vec::new();
+        mix.refresh(|event| sent.push(event));
+        let programs = sent
+            .iter()
+            .filter_map(|event| match event {
+                MidiEvent::ProgramChange {
+                    channel,
+                    program_id,
+                } => Some((*channel, *program_id)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(programs.len(), 16);
+        assert_eq!(programs[2], (2, 73));
+        assert_eq!(programs[3], (3, 0));
+        assert!(mix.apply(program(2, 41)).is_none());
+        assert!(mix.apply(program(3, 41)).is_some());
+
+        // A synth reset brings the override back with the channel defaults.
+        mix.reset_file_controls();
+        let mut after_reset = Vec::new();
+        mix.refresh(|event| after_reset.push(event));
+        assert!(after_reset.iter().any(|event| matches!(
+            event,
+            MidiEvent::ProgramChange {
+                channel: 2,
+                program_id: 73
+            }
+        )));
+
+        // Dropping the override restores the file's last program.
+        assert!(mix.apply(program(2, 19)).is_none());
+        strips[2].program = None;
+        mix.set_strips(strips);
+        let mut restored = Vec::new();
+        mix.refresh(|event| restored.push(event));
+        assert!(restored.iter().any(|event| matches!(
+            event,
+            MidiEvent::ProgramChange {
+                channel: 2,
+                program_id: 19
             }
+        )));
+        assert!(mix.apply(program(2, 41)).is_some());
+    }
+
+    #[test]
+    fn click_player_sounds_at_clicks_and_follows_jumps() {
+        let clicks = [(2, true), (100, false)];
+        let mut player = ClickPlayer::default();
+        let rate = 1000;
+        // Silent before the first click, then sounding.
+        assert_eq!(player.render(&clicks, 0, rate), 0.0);
+        assert_eq!(player.render(&clicks, 1, rate), 0.0);
+        let _start = player.render(&clicks, 2, rate);
+        assert!(player.render(&clicks, 3, rate).abs() > 0.0);
+        // A click lasts CLICK_SECONDS, 40 samples here.
+        for sample in 4..42 {
+            let _ = player.render(&clicks, sample, rate);
+        }
+        assert_eq!(player.render(&clicks, 42, rate), 0.0);
+        // Jumping back before a click plays it again.
+        assert_eq!(player.render(&clicks, 1, rate), 0.0);
+        let _start = player.render(&clicks, 2, rate);
+        assert!(player.render(&clicks, 3, rate).abs() > 0.0);
+        // Jumping past a click skips it.
+        for sample in 4..42 {
+            let _ = player.render(&clicks, sample, rate);
+        }
+        assert_eq!(player.render(&clicks, 101, rate), 0.0);
+    }
+
+    #[test]
+    fn audition_releases_after_hold_then_rings_out() {
+        let mut audition = Audition::new(2, 64, 10);
+        assert_eq!((audition.hold_samples, audition.ring_samples), (4, 10));
+        let note_offs: Vec<MidiEvent> = (0..13).filter_map(|_| audition.advance()).collect();
+        assert!(matches!(
+            note_offs.as_slice(),
+            [MidiEvent::NoteOff {
+                channel: 2,
+                key: 64
+            }]
+        ));
+        assert!(!audition.finished());
+        assert!(audition.advance().is_none());
+        assert!(audition.finished());
+    }
+
+    #[test]
+    fn scrub_snippet_chases_held_notes_then_plays_the_window() {
+        let event = |sample, event| MidiPlaybackEvent {
+            tick: sample,
+            sample,
+            port: 0,
+            event,
+        };
+        let events = [
+            event(
+                0,
+                MidiEvent::ProgramChange {
+                    channel: 0,
+                    program_id: 5,
+                },
+            ),
+            event(
+                0,
+                MidiEvent::NoteOn {
+                    channel: 0,
+                    key: 60,
+                    vel: 90,
+                },
+            ),
+            event(
+                10,
+                MidiEvent::NoteOn {
+                    channel: 0,
+                    key: 62,
+                    vel: 80,
+                },
+            ),
+            event(
+                20,
+                MidiEvent::NoteOff {
+                    channel: 0,
+                    key: 62,
+                },
+            ),
+            event(
+                104,
+                MidiEvent::NoteOn {
+                    channel: 0,
+                    key: 64,
+                    vel: 70,
+                },
+            ),
+            event(
+                200,
+                MidiEvent::NoteOff {
+                    channel: 0,
+                    key: 60,
+                },
+            ),
+        ];
+        let mut snippet = ScrubSnippet::new(&events, 100, 8);
+        let mut sent = Vec::new();
+        let mut finished = false;
+        for _ in 0..8 {
+            finished = snippet.advance(|event| sent.push(event));
+        }
+        assert!(finished);
+        assert!(matches!(
+            sent.as_slice(),
+            [
+                MidiEvent::ProgramChange {
+                    channel: 0,
+                    program_id: 5
+                },
+                MidiEvent::NoteOn {
+                    channel: 0,
+                    key: 60,
+                    vel: 90
+                },
+                MidiEvent::NoteOn {
+                    channel: 0,
+                    key: 64,
+                    vel: 70
+                },
+            ]
+        ));
+    }
+
+    #[test]
+    fn program_at_takes_the_change_in_effect_at_the_playhead() {
+        let program = |sample, channel, program_id| MidiPlaybackEvent {
+            tick: sample,
+            sample,
+            port: 0,
+            event: MidiEvent::ProgramChange {
+                channel,
+                program_id,
+            },
+        };
+        let events = vec![
+            program(0, 0, 24),
+            program(0, 1, 40),
+            program(500, 0, 33),
+            program(900, 0, 56),
+        ];
+        assert_eq!(program_at(&events, 0, 0), Some(24));
+        assert_eq!(program_at(&events, 499, 0), Some(24));
+        assert_eq!(program_at(&events, 500, 0), Some(33));
+        assert_eq!(program_at(&events, 700, 1), Some(40));
+        assert_eq!(program_at(&events, 700, 2), None);
+    }
+
+    #[test]
+    fn song_start_setup_resets_every_channel_before_the_tick_zero_setup() {
+        let event = |tick, event| MidiPlaybackEvent {
+            tick,
+            sample: tick,
+            port: 0,
+            event,
+        };
+        let events = vec![
+            event(
+                0,
+                MidiEvent::ProgramChange {
+                    channel: 2,
+                    program_id: 48,
+                },
+            ),
+            event(
+                480,
+                MidiEvent::ControlChange {
+                    channel: 2,
+                    ctrl: 7,
+                    value: 20,
+                },
+            ),
+        ];
+        let setup = song_start_setup(&events);
+        assert_eq!(setup.len(), 16 * 4 + 1);
+        assert!(matches!(
+            setup[8..12],
+            [
+                MidiEvent::ControlChange {
+                    channel: 2,
+                    ctrl: 121,
+                    ..
+                },
+                MidiEvent::ControlChange {
+                    channel: 2,
+                    ctrl: 7,
+                    value: 100,
+                },
+                MidiEvent::ControlChange {
+                    channel: 2,
+                    ctrl: 10,
+                    value: 64,
+                },
+                MidiEvent::ProgramChange {
+                    channel: 2,
+                    program_id: 0,
+                },
+            ]
+        ));
+        assert!(matches!(
+            setup.last(),
+            Some(MidiEvent::ProgramChange {
+                channel: 2,
+                program_id: 48,
+            })
+        ));
+    }
+
+    #[test]
+    fn loop_chase_keeps_only_the_latest_state_per_channel() {
+        let event = |sample, event| MidiPlaybackEvent {
+            tick: sample,
+            sample,
+            port: 0,
+            event,
+        };
+        let cc = |channel: u8, ctrl: u8, value: u8| MidiEvent::ControlChange {
+            channel,
+            ctrl,
+            value,
+        };
+        let mut events = vec![
+            event(0, cc(0, 0, 1)),
+            event(
+                0,
+                MidiEvent::ProgramChange {
+                    channel: 0,
+                    program_id: 5,
+                },
+            ),
+        ];
+        for sample in 1..1000 {
+            events.push(event(sample, cc(0, 11, (sample % 128) as u8)));
+            events.push(event(
+                sample,
+                MidiEvent::PitchBend {
+                    channel: 1,
+                    value: sample as u16,
+                },
+            ));
+        }
+        events.push(event(
+            1000,
+            MidiEvent::NoteOn {
+                channel: 0,
+                key: 60,
+                vel: 90,
+            },
+        ));
+        let state = chased_channel_state(&events, events.len());
+        assert_eq!(state.len(), 4);
+        assert!(matches!(
+            state[..],
+            [
+                MidiEvent::ControlChange {
+                    ctrl: 0,
+                    value: 1,
+                    ..
+                },
+                MidiEvent::ProgramChange { program_id: 5, .. },
+                MidiEvent::ControlChange {
+                    ctrl: 11,
+                    value: 103,
+                    ..
+                },
+                MidiEvent::PitchBend { value: 999, .. },
+            ]
+        ));
+        assert!(chased_channel_state(&events, 0).is_empty());
+    }
+
+    #[test]
+    fn seek_chases_channel_setup_but_not_notes() {
+        let event = |sample, event| MidiPlaybackEvent {
+            tick: sample,
+            sample,
+            port: 0,
+            event,
+        };
+        let events = [
+            event(
+                0,
+                MidiEvent::NoteOn {
+                    channel: 1,
+                    key: 60,
+                    vel: 90,
+                },
+            ),
+            event(
+                10,
+                MidiEvent::ControlChange {
+                    channel: 1,
+                    ctrl: 7,
+                    value: 64,
+                },
+            ),
+            event(
+                20,
+                MidiEvent::PitchBend {
+                    channel: 1,
+                    value: 9000,
+                },
+            ),
+            event(
+                30,
+                MidiEvent::NoteOff {
+                    channel: 1,
+                    key: 60,
+                },
+            ),
+            event(
+                40,
+                MidiEvent::ProgramChange {
+                    channel: 1,
+                    program_id: 12,
+                },
+            ),
+        ];
+        let setup: Vec<_> = chased_channel_setup(&events, seek_index(&events, 35)).collect();
+        assert!(matches!(
+            setup.as_slice(),
+            [
+                MidiEvent::ControlChange {
+                    channel: 1,
+                    ctrl: 7,
+                    value: 64
+                },
+                MidiEvent::PitchBend {
+                    channel: 1,
+                    value: 9000
+                },
+            ]
+        ));
+        assert_eq!(chased_channel_setup(&events, 99).count(), 3);
+        assert_eq!(chased_channel_setup(&events, 0).count(), 0);
+    }
+
+    #[test]
+    fn loudness_meter_gates_silence_and_clamps_gain() {
+        let mut meter = LoudnessMeter::default();
+        assert_eq!(meter.loudness_db(), None);
+        meter.add_block(&[0.0; 64]);
+        assert_eq!(meter.loudness_db(), None);
+        meter.add_block(&[0.5, -0.5, 0.5, -0.5]);
+        meter.add_block(&[0.0; 64]);
+        let loudness = meter.loudness_db().expect("loud block counted");
+        assert!((loudness - -6.0206).abs() < 1e-3);
+        assert!((normalization_gain_db(loudness) - -11.9794).abs() < 1e-3);
+        assert_eq!(normalization_gain_db(-60.0), 12.0);
+        assert_eq!(normalization_gain_db(3.0), -12.0);
+        assert!((db_to_gain(-6.0206) - 0.5).abs() < 1e-4);
+    }
+
+    #[test]
+    fn step_fade_ramps_linearly_and_stops_at_the_target() {
+        let step = 1.0 / 480.0;
+        let mut gain = 0.0;
+        for _ in 0..240 {
+            gain = step_fade(gain, 1.0, step);
+        }
+        assert!((gain - 0.5).abs() < 1e-4);
+        for _ in 0..480 {
+            gain = step_fade(gain, 1.0, step);
         }
+        assert_eq!(gain, 1.0);
+        for _ in 0..480 {
+            gain = step_fade(gain, 0.0, step);
+        }
+        assert!(gain.abs() < 1e-4);
+        assert_eq!(step_fade(gain, 0.0, step), 0.0);
     }
-}
 
-fn hard_reset_synth(synth: &mut Synth, sample_rate: f32, soundfont_path: Option<&PathBuf>) {
-    *synth = Synth::default();
-    synth.set_sample_rate(sample_rate);
+    #[test]
+    fn loop_fade_gain_reaches_zero_at_the_boundary() {
+        assert_eq!(loop_fade_gain(0, 48_000, 2_400), 1.0);
+        assert_eq!(loop_fade_gain(45_600, 48_000, 2_400), 1.0);
+        assert_eq!(loop_fade_gain(46_800, 48_000, 2_400), 0.5);
+        assert_eq!(loop_fade_gain(47_999, 48_000, 2_400), 1.0 / 2_400.0);
+        assert_eq!(loop_fade_gain(48_000, 48_000, 2_400), 1.0);
+        assert_eq!(loop_fade_gain(47_000, 0, 2_400), 1.0);
+        assert_eq!(loop_fade_gain(47_000, 48_000, 0), 1.0);
+    }
+
+    #[test]
+    fn soundfont_layer_commands_add_on_top_or_start_over() {
+        let fonts = [PathBuf::from("a.sf2"), PathBuf::from("b.sf2")];
+        let commands = soundfont_layer_commands(&fonts[..1], &fonts);
+        assert!(matches!(
+            commands.as_slice(),
+            [AudioCommand::AddSoundFont(path)] if *path == fonts[1]
+        ));
+        let commands = soundfont_layer_commands(&fonts, &[]);
+        assert!(matches!(
+            commands.as_slice(),
+            [AudioCommand::ClearSoundFonts]
+        ));
+        let commands = soundfont_layer_commands(&fonts[1..], &fonts);
+        assert!(matches!(
+            commands.as_slice(),
+            [
+                AudioCommand::ClearSoundFonts,
+                AudioCommand::AddSoundFont(_),
+                AudioCommand::AddSoundFont(_)
+            ]
+        ));
+        assert!(soundfont_layer_commands(&fonts, &fonts).is_empty());
+    }
+
+    #[test]
+    fn wav_header_describes_16_bit_stereo() {
+        let header = wav_header(48_000, 1_000);
+        assert_eq!(&header[0..4], b"RIFF");
+        assert_eq!(&header[4..8], &(36u32 + 4_000).to_le_bytes());
+        assert_eq!(&header[8..16], b"WAVEfmt ");
+        assert_eq!(&header[22..24], &2u16.to_le_bytes());
+        assert_eq!(&header[24..28], &48_000u32.to_le_bytes());
+        assert_eq!(&header[28..32], &192_000u32.to_le_bytes());
+        assert_eq!(&header[34..36], &16u16.to_le_bytes());
+        assert_eq!(&header[36..40], b"data");
+        assert_eq!(&header[40..44], &4_000u32.to_le_bytes());
+        assert_eq!(pcm16(1.5), i16::MAX);
+        assert_eq!(pcm16(-1.0), -i16::MAX);
+        assert_eq!(pcm16(0.0), 0);
+    }
+
+    #[test]
+    fn playback_finishes_after_the_last_event_and_tail_unless_looping() {
+        assert!(playback_finished(3, 3, 96_000, 96_000, false, 0));
+        assert!(!playback_finished(3, 3, 95_999, 96_000, false, 0));
+        assert!(!playback_finished(2, 3, 96_000, 96_000, false, 0));
+        assert!(!playback_finished(3, 3, 96_000, 96_000, true, 0));
+        assert!(!playback_finished(0, 0, 0, 0, false, 0));
+    }
+
+    #[test]
+    fn playback_does_not_finish_in_a_loop_that_ends_past_the_tail() {
+        // The last bar runs on past the reverb tail, so the loop wraps at
+        // 120_000 rather than the song ending at 96_000.
+        assert!(!playback_finished(3, 3, 100_000, 96_000, false, 120_000));
+        assert!(playback_finished(3, 3, 100_000, 96_000, false, 0));
+    }
 
-    if let Some(path) = soundfont_path {
-        if let Ok(mut file) = std::fs::File::open(path) {
-            if let Ok(font) = SoundFont::load(&mut file) {
-                let id = synth.add_font(font, true);
-                println!("SoundFont loaded ({:?})", id);
+    #[test]
+    fn track_summaries_and_schedule_share_the_ruler_end() {
+        let note = |delta: u32, key: u8, on: bool| TrackEvent {
+            delta: delta.into(),
+            kind: TrackEventKind::Midi {
+                channel: 0.into(),
+                message: if on {
+                    midly::MidiMessage::NoteOn {
+                        key: key.into(),
+                        vel: 100.into(),
+                    }
+                } else {
+                    midly::MidiMessage::NoteOff {
+                        key: key.into(),
+                        vel: 0.into(),
+                    }
+                },
+            },
+        };
+        let text = |delta: u32| TrackEvent {
+            delta: delta.into(),
+            kind: TrackEventKind::Meta(midly::MetaMessage::Text(b"x")),
+        };
+        let files = [
+            // A stray note-off after the last note doesn't extend the ruler.
+            vec![
+                vec![
+                    note(0, 60, true),
+                    note(480, 60, false),
+                    note(480, 62, false),
+                ],
+                vec![text(4000)],
+            ],
+            // A note left held runs to the end of its track.
+            vec![
+                vec![
+                    note(0, 60, true),
+                    note(100, 64, true),
+                    note(200, 60, false),
+                    text(700),
+                ],
+                vec![note(0, 67, true), note(960, 67, false)],
+            ],
+            // No notes at all: the last event ends the ruler.
+            vec![vec![text(300)], vec![text(1200)]],
+        ];
+        for (index, tracks) in files.into_iter().enumerate() {
+            let smf = Smf {
+                header: midly::Header {
+                    format: Format::Parallel,
+                    timing: Timing::Metrical(480.into()),
+                },
+                tracks,
+            };
+            let schedule = build_playback_schedule_from_smf(
+                &smf,
+                48_000,
+                0.0,
+                &HashMap::new(),
+                PlaybackTempo::default(),
+                None,
+                SongEnd::LastNote,
+            );
+            for pairing in [NotePairing::Fifo, NotePairing::Lifo] {
+                let tracks = parse_midi_tracks(&smf, pairing, PreviewSize::default());
+                assert_eq!(
+                    tracks[0].timing.end_tick(SongEnd::LastNote),
+                    schedule.ruler_max_tick,
+                    "file {index}"
+                );
             }
+            assert_eq!(file_timing(&smf).ruler_max_tick(), schedule.ruler_max_tick);
         }
     }
-}
 
-fn send_all_notes_off(synth: &mut Synth) {
-    for channel in 0u8..16 {
-        let _ = synth.send_event(MidiEvent::ControlChange {
-            channel,
-            ctrl: 64,
-            value: 0,
-        });
-        let _ = synth.send_event(MidiEvent::ControlChange {
-            channel,
-            ctrl: 120,
-            value: 0,
-        });
-        let _ = synth.send_event(MidiEvent::ControlChange {
-            channel,
-            ctrl: 123,
-            value: 0,
-        });
-        let _ = synth.send_event(MidiEvent::ControlChange {
-            channel,
-            ctrl: 121,
-            value: 0,
-        });
-        for key in 0u8..128 {
-            let _ = synth.send_event(MidiEvent::NoteOff { channel, key });
+    #[test]
+    fn end_of_track_marker_can_bound_the_song() {
+        let event = |delta: u32, kind| TrackEvent {
+            delta: delta.into(),
+            kind,
+        };
+        let note = |key: u8, vel: u8| TrackEventKind::Midi {
+            channel: 0.into(),
+            message: midly::MidiMessage::NoteOn {
+                key: key.into(),
+                vel: vel.into(),
+            },
+        };
+        let end = TrackEventKind::Meta(midly::MetaMessage::EndOfTrack);
+        // The last note ends on beat 1; the markers leave a bar of silence.
+        let smf = Smf {
+            header: midly::Header {
+                format: Format::Parallel,
+                timing: Timing::Metrical(480.into()),
+            },
+            tracks: vec![
+                vec![
+                    event(0, note(60, 100)),
+                    event(480, note(60, 0)),
+                    event(0, end),
+                ],
+                vec![event(1920, end)],
+            ],
+        };
+
+        let timing = file_timing(&smf);
+        assert_eq!(timing.end_of_track_tick, Some(1920));
+        assert_eq!(timing.end_tick(SongEnd::LastNote), 480);
+        assert_eq!(timing.end_tick(SongEnd::EndOfTrack), 1920);
+
+        for (song_end, tick) in [(SongEnd::LastNote, 480), (SongEnd::EndOfTrack, 1920)] {
+            let schedule = build_playback_schedule_from_smf(
+                &smf,
+                48_000,
+                0.0,
+                &HashMap::new(),
+                PlaybackTempo::default(),
+                None,
+                song_end,
+            );
+            assert_eq!(schedule.ruler_max_tick, tick);
+            assert_eq!(schedule.end_sample, tick * 50);
         }
     }
-}
 
-fn build_playback_schedule(midi_path: &PathBuf, sample_rate: u32) -> Result<PlaybackSchedule, ()> {
-    let data = std::fs::read(midi_path).map_err(|_| ())?;
-    let smf = Smf::parse(&data).map_err(|_| ())?;
-    Ok(build_playback_schedule_from_smf(&smf, sample_rate))
-}
+    #[test]
+    fn tick_zero_program_is_set_up_before_the_first_note() {
+        let notes = vec![
+            TrackEvent {
+                delta: 0.into(),
+                kind: TrackEventKind::Midi {
+                    channel: 0.into(),
+                    message: midly::MidiMessage::NoteOn {
+                        key: 60.into(),
+                        vel: 100.into(),
+                    },
+                },
+            },
+            TrackEvent {
+                delta: 480.into(),
+                kind: TrackEventKind::Midi {
+                    channel: 0.into(),
+                    message: midly::MidiMessage::ProgramChange { program: 5.into() },
+                },
+            },
+        ];
+        let setup = vec![
+            TrackEvent {
+                delta: 0.into(),
+                kind: TrackEventKind::Midi {
+                    channel: 0.into(),
+                    message: midly::MidiMessage::Controller {
+                        controller: 0.into(),
+                        value: 1.into(),
+                    },
+                },
+            },
+            TrackEvent {
+                delta: 0.into(),
+                kind: TrackEventKind::Midi {
+                    channel: 0.into(),
+                    message: midly::MidiMessage::ProgramChange { program: 40.into() },
+                },
+            },
+        ];
+        let smf = Smf {
+            header: midly::Header {
+                format: Format::Parallel,
+                timing: Timing::Metrical(480.into()),
+            },
+            tracks: vec![notes, setup],
+        };
 
-#[cfg(test)]
-mod tests {
-    use super::{build_playback_schedule_from_smf, midi_message_to_event, parse_smf};
-    use midly::{Format, Smf, Timing, TrackEvent, TrackEventKind};
-    use oxisynth::MidiEvent;
+        let schedule = build_playback_schedule_from_smf(
+            &smf,
+            48_000,
+            0.0,
+            &HashMap::new(),
+            PlaybackTempo::default(),
+            None,
+            SongEnd::LastNote,
+        );
+        assert!(matches!(
+            schedule.events[0].event,
+            MidiEvent::NoteOn { key: 60, .. }
+        ));
+        let setup = initial_channel_setup(&schedule.events);
+        assert_eq!(setup.len(), 2);
+        assert!(matches!(
+            setup[0],
+            MidiEvent::ControlChange {
+                channel: 0,
+                ctrl: 0,
+                value: 1
+            }
+        ));
+        assert!(matches!(
+            setup[1],
+            MidiEvent::ProgramChange {
+                channel: 0,
+                program_id: 40
+            }
+        ));
+    }
 
     #[test]
     fn build_playback_schedule_respects_note_range() {
@@ -658,12 +4557,460 @@ mod tests {
             tracks: vec![track],
         };
 
-        let schedule = build_playback_schedule_from_smf(&smf, 48_000);
+        let schedule = build_playback_schedule_from_smf(
+            &smf,
+            48_000,
+            0.0,
+            &HashMap::new(),
+            PlaybackTempo::default(),
+            None,
+            SongEnd::LastNote,
+        );
         assert!(schedule.ruler_max_tick > 0);
         assert_eq!(schedule.events.len(), 2);
         assert!(schedule.total_samples > 0);
     }
 
+    #[test]
+    fn schedule_follows_a_fixed_tempo_and_speed() {
+        let build = |tempo: PlaybackTempo| {
+            build_playback_schedule_from_smf(
+                &one_note_smf(),
+                48_000,
+                0.0,
+                &HashMap::new(),
+                tempo,
+                None,
+                SongEnd::LastNote,
+            )
+        };
+        let normal = build(PlaybackTempo::default());
+        // 60 BPM doubles every event time against the default 120.
+        let fixed = build(PlaybackTempo::fixed(1_000_000));
+        assert_eq!(fixed.end_sample, 24_000);
+        assert_eq!(fixed.events[1].sample, 24_000);
+
+        // Half speed lands the same ticks twice as late.
+        let slow = build(PlaybackTempo {
+            fixed: None,
+            speed: 0.5,
+        });
+        assert_eq!(slow.end_sample, normal.end_sample * 2);
+        assert_eq!(slow.events[1].tick, normal.events[1].tick);
+        assert_eq!(slow.events[1].sample, normal.events[1].sample * 2);
+        assert_eq!(slow.tempo_map.tick_at(1.0), normal.tempo_map.tick_at(0.5));
+    }
+
+    #[test]
+    fn retiming_neither_replays_nor_skips_an_event() {
+        let build = |speed: f32| {
+            build_playback_schedule_from_smf(
+                &one_note_smf(),
+                48_000,
+                0.0,
+                &HashMap::new(),
+                PlaybackTempo { fixed: None, speed },
+                None,
+                SongEnd::LastNote,
+            )
+        };
+        let normal = build(1.0);
+        let slow = build(0.5);
+        let rescale = |sample: u64| {
+            let tick = normal.tempo_map.tick_at(sample as f64 / 48_000.0);
+            (slow.tempo_map.seconds_at(tick) * 48_000.0).round() as u64
+        };
+        // Just after the note on, the tick rounds back onto it: seeking
+        // would send it again, so the next event stays the note off.
+        let after = rescale(10);
+        assert_eq!(seek_index(&slow.events, after), 0);
+        assert_eq!(retimed_position(&slow.events, 1, after), 0);
+        // Just before the note off, the playhead stays short of it or on it,
+        // so it still plays.
+        let before = rescale(11_990);
+        assert!(retimed_position(&slow.events, 1, before) <= slow.events[1].sample);
+        assert_eq!(
+            retimed_position(&slow.events, 1, slow.events[1].sample + 10),
+            slow.events[1].sample
+        );
+        assert_eq!(retimed_position(&slow.events, 2, 10), slow.events[1].sample);
+    }
+
+    // A note held for two eighths at 480 ticks per beat, with a meta event
+    // between its on and off that isn't scheduled.
+    fn one_note_smf() -> Smf<'static> {
+        let note = |delta: u32, on: bool| TrackEvent {
+            delta: delta.into(),
+            kind: TrackEventKind::Midi {
+                channel: 0.into(),
+                message: if on {
+                    midly::MidiMessage::NoteOn {
+                        key: 60.into(),
+                        vel: 100.into(),
+                    }
+                } else {
+                    midly::MidiMessage::NoteOff {
+                        key: 60.into(),
+                        vel: 0.into(),
+                    }
+                },
+            },
+        };
+        Smf {
+            header: midly::Header {
+                format: Format::SingleTrack,
+                timing: Timing::Metrical(480.into()),
+            },
+            tracks: vec![vec![
+                note(0, true),
+                TrackEvent {
+                    delta: 120.into(),
+                    kind: TrackEventKind::Meta(midly::MetaMessage::TrackName(b"Test")),
+                },
+                note(120, false),
+            ]],
+        }
+    }
+
+    #[test]
+    fn reverb_tail_extends_the_schedule_past_the_last_note() {
+        let schedule = |tail: f32| {
+            build_playback_schedule_from_smf(
+                &one_note_smf(),
+                48_000,
+                tail,
+                &HashMap::new(),
+                PlaybackTempo::default(),
+                None,
+                SongEnd::LastNote,
+            )
+        };
+        let without_tail = schedule(0.0);
+        assert_eq!(without_tail.total_samples, without_tail.end_sample);
+        let with_tail = schedule(1.5);
+        assert_eq!(with_tail.end_sample, 12_000);
+        assert_eq!(with_tail.total_samples, 12_000 + 72_000);
+        let mut rendered = 0usize;
+        let frames = render_schedule(&mut Synth::default(), &with_tail, |block| {
+            rendered += block.len();
+        });
+        assert_eq!(frames, with_tail.total_samples);
+        assert_eq!(rendered as u64, frames * 2);
+    }
+
+    #[test]
+    fn schedule_at_another_rate_matches_rescaled_positions() {
+        let schedule = |sample_rate: u32| {
+            build_playback_schedule_from_smf(
+                &one_note_smf(),
+                sample_rate,
+                0.0,
+                &HashMap::new(),
+                PlaybackTempo::default(),
+                None,
+                SongEnd::LastNote,
+            )
+        };
+        let original = schedule(48_000);
+        let resampled = schedule(44_100);
+        assert_eq!(resampled.end_sample, 11_025);
+        assert_eq!(
+            rescale_sample(original.end_sample, 48_000, 44_100),
+            resampled.end_sample
+        );
+    }
+
+    #[test]
+    fn seek_index_finds_the_first_event_at_or_after_a_sample() {
+        let schedule = build_playback_schedule_from_smf(
+            &one_note_smf(),
+            48_000,
+            0.0,
+            &HashMap::new(),
+            PlaybackTempo::default(),
+            None,
+            SongEnd::LastNote,
+        );
+        assert_eq!(seek_index(&schedule.events, 0), 0);
+        assert_eq!(seek_index(&schedule.events, 1), 1);
+        assert_eq!(seek_index(&schedule.events, schedule.total_samples + 1), 2);
+    }
+
+    #[test]
+    fn matching_rate_range_finds_supported_rate() {
+        let ranges = [(8_000, 44_100), (44_100, 192_000)];
+        assert_eq!(matching_rate_range(&ranges, 22_050), Some(0));
+        assert_eq!(matching_rate_range(&ranges, 48_000), Some(1));
+        assert_eq!(matching_rate_range(&ranges, 384_000), None);
+        assert_eq!(matching_rate_range(&[], 48_000), None);
+    }
+
+    #[test]
+    fn output_config_reads_the_audio_table_and_checks_buffer_sizes() {
+        assert_eq!(
+            OutputConfig::parse("[audio]\nsample_rate = 44100\nbuffer_size = 256\n"),
+            Some(OutputConfig {
+                sample_rate: Some(44_100),
+                buffer_size: Some(256),
+            })
+        );
+        assert_eq!(OutputConfig::parse(""), Some(OutputConfig::default()));
+        assert_eq!(
+            OutputConfig::parse("[audio]\nsample_rate = \"fast\"\n"),
+            None
+        );
+
+        let range = SupportedBufferSize::Range { min: 64, max: 4096 };
+        assert_eq!(
+            stream_buffer_size(&range, None),
+            (BufferSize::Default, None)
+        );
+        assert_eq!(
+            stream_buffer_size(&range, Some(256)),
+            (BufferSize::Fixed(256), None)
+        );
+        let (size, notice) = stream_buffer_size(&range, Some(16));
+        assert_eq!(size, BufferSize::Default);
+        assert!(notice.is_some());
+        let (size, notice) = stream_buffer_size(&SupportedBufferSize::Unknown, Some(256));
+        assert_eq!(size, BufferSize::Default);
+        assert!(notice.is_some());
+    }
+
+    #[test]
+    fn build_playback_schedule_counts_smpte_ticks_in_real_time() {
+        let note = |delta: u32, vel: u8| TrackEvent {
+            delta: delta.into(),
+            kind: TrackEventKind::Midi {
+                channel: 0.into(),
+                message: midly::MidiMessage::NoteOn {
+                    key: 60.into(),
+                    vel: vel.into(),
+                },
+            },
+        };
+        let track = vec![
+            note(0, 100),
+            TrackEvent {
+                delta: 500.into(),
+                kind: TrackEventKind::Meta(midly::MetaMessage::Tempo(250_000.into())),
+            },
+            note(500, 0),
+        ];
+        // 25 frames of 40 subframes: 1000 ticks a second.
+        let smf = Smf {
+            header: midly::Header {
+                format: Format::SingleTrack,
+                timing: Timing::Timecode(midly::Fps::Fps25, 40),
+            },
+            tracks: vec![track],
+        };
+        assert_eq!(file_timing(&smf).ticks_per_beat, 500);
+
+        for tempo in [PlaybackTempo::default(), PlaybackTempo::fixed(1_000_000)] {
+            let schedule = build_playback_schedule_from_smf(
+                &smf,
+                48_000,
+                0.0,
+                &HashMap::new(),
+                tempo,
+                None,
+                SongEnd::LastNote,
+            );
+            let samples = schedule
+                .events
+                .iter()
+                .map(|event| event.sample)
+                .collect::<Vec<_>>();
+            assert_eq!(samples, [0, 48_000]);
+        }
+    }
+
+    #[test]
+    fn build_playback_schedule_transposes_only_the_target_track() {
+        let note_track = |channel: u8| {
+            vec![
+                TrackEvent {
+                    delta: 0.into(),
+                    kind: TrackEventKind::Midi {
+                        channel: channel.into(),
+                        message: midly::MidiMessage::NoteOn {
+                            key: 60.into(),
+                            vel: 100.into(),
+                        },
+                    },
+                },
+                TrackEvent {
+                    delta: 480.into(),
+                    kind: TrackEventKind::Midi {
+                        channel: channel.into(),
+                        message: midly::MidiMessage::NoteOff {
+                            key: 60.into(),
+                            vel: 0.into(),
+                        },
+                    },
+                },
+            ]
+        };
+        let smf = Smf {
+            header: midly::Header {
+                format: Format::Parallel,
+                timing: Timing::Metrical(480.into()),
+            },
+            tracks: vec![note_track(0), note_track(1), note_track(9)],
+        };
+
+        let transpose = HashMap::from([(1, -12), (2, 12)]);
+        let schedule = build_playback_schedule_from_smf(
+            &smf,
+            48_000,
+            0.0,
+            &transpose,
+            PlaybackTempo::default(),
+            None,
+            SongEnd::LastNote,
+        );
+        let keys = schedule
+            .events
+            .iter()
+            .filter_map(|event| match event.event {
+                MidiEvent::NoteOn { channel, key, .. } => Some((channel, key)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(keys, vec![(0, 60), (1, 48), (9, 60)]);
+        assert!(schedule.events.iter().any(|event| matches!(
+            event.event,
+            MidiEvent::NoteOff {
+                channel: 1,
+                key: 48
+            }
+        )));
+
+        let schedule = build_playback_schedule_from_smf(
+            &smf,
+            48_000,
+            0.0,
+            &HashMap::new(),
+            PlaybackTempo::default(),
+            Some(1),
+            SongEnd::LastNote,
+        );
+        assert!(schedule.events.iter().all(|event| matches!(
+            event.event,
+            MidiEvent::NoteOn { channel: 1, .. } | MidiEvent::NoteOff { channel: 1, .. }
+        )));
+        assert_eq!(schedule.events.len(), 2);
+    }
+
+    #[test]
+    fn note_meter_counts_held_keys() {
+        let mut meter = NoteMeter::default();
+        meter.apply(MidiEvent::NoteOn {
+            channel: 0,
+            key: 60,
+            vel: 100,
+        });
+        meter.apply(MidiEvent::NoteOn {
+            channel: 0,
+            key: 60,
+            vel: 90,
+        });
+        meter.apply(MidiEvent::NoteOn {
+            channel: 1,
+            key: 60,
+            vel: 100,
+        });
+        meter.apply(MidiEvent::NoteOn {
+            channel: 0,
+            key: 64,
+            vel: 100,
+        });
+        assert_eq!(meter.held(), 3);
+        meter.apply(MidiEvent::NoteOff {
+            channel: 0,
+            key: 60,
+        });
+        meter.apply(MidiEvent::NoteOn {
+            channel: 1,
+            key: 60,
+            vel: 0,
+        });
+        assert_eq!(meter.held(), 1);
+        meter.clear();
+        assert_eq!(meter.held(), 0);
+    }
+
+    #[test]
+    fn stereo_width_folds_to_mono_and_keeps_mid() {
+        let widened = |width| {
+            let mut frame = [0.8f32, 0.2];
+            apply_stereo_width(&mut frame, width);
+            frame
+        };
+        let close = |frame: [f32; 2], expected: [f32; 2]| {
+            (frame[0] - expected[0]).abs() < 1e-6 && (frame[1] - expected[1]).abs() < 1e-6
+        };
+        let mono = widened(0.0);
+        assert_eq!(mono[0], mono[1]);
+        assert!(close(mono, [0.5, 0.5]));
+        assert!(close(widened(1.0), [0.8, 0.2]));
+        assert!(close(widened(2.0), [1.1, -0.1]));
+    }
+
+    #[test]
+    fn shelf_biquads_boost_only_their_end_of_the_spectrum() {
+        // |H| at DC is the transfer function at z = 1, at Nyquist z = -1.
+        let dc =
+            |filter: Biquad| (filter.b0 + filter.b1 + filter.b2) / (1.0 + filter.a1 + filter.a2);
+        let nyquist =
+            |filter: Biquad| (filter.b0 - filter.b1 + filter.b2) / (1.0 - filter.a1 + filter.a2);
+        let boost = 10f32.powf(6.0 / 20.0);
+        let low = Biquad::shelf(48_000, 200.0, 6.0, false);
+        assert!((dc(low) - boost).abs() < 1e-3);
+        assert!((nyquist(low).abs() - 1.0).abs() < 1e-3);
+        let high = Biquad::shelf(48_000, 4000.0, 6.0, true);
+        assert!((dc(high) - 1.0).abs() < 1e-3);
+        assert!((nyquist(high).abs() - boost).abs() < 1e-3);
+
+        // A constant input settles at the DC gain.
+        let mut state = [0.0; 4];
+        let mut output = 0.0;
+        for _ in 0..48_000 {
+            output = low.process(&mut state, 0.5);
+        }
+        assert!((output - 0.5 * boost).abs() < 1e-3);
+        assert_eq!(eq_filters(48_000, 0.0, 0.0, 0.0), None);
+    }
+
+    #[test]
+    fn describe_event_formats_channel_one_based() {
+        let event = MidiPlaybackEvent {
+            tick: 480,
+            sample: 24_000,
+            port: 0,
+            event: MidiEvent::NoteOn {
+                channel: 9,
+                key: 36,
+                vel: 100,
+            },
+        };
+        assert_eq!(describe_event(&event), "tick 480: ch 10 note on 36 vel 100");
+        let event = MidiPlaybackEvent {
+            tick: 0,
+            sample: 0,
+            port: 0,
+            event: MidiEvent::ControlChange {
+                channel: 0,
+                ctrl: 64,
+                value: 127,
+            },
+        };
+        assert_eq!(describe_event(&event), "tick 0: ch 1 CC64 = 127");
+        let event = MidiPlaybackEvent { port: 1, ..event };
+        assert_eq!(describe_event(&event), "tick 0: port 2 ch 1 CC64 = 127");
+    }
+
     #[test]
     fn midi_message_to_event_maps_note_on() {
         let event = midi_message_to_event(
@@ -683,6 +5030,63 @@ mod tests {
         }
     }
 
+    #[test]
+    fn parse_smf_routes_ports_and_channel_prefixed_ports() {
+        let note = |delta: u32, channel: u8| TrackEvent {
+            delta: delta.into(),
+            kind: TrackEventKind::Midi {
+                channel: channel.into(),
+                message: midly::MidiMessage::NoteOn {
+                    key: 60.into(),
+                    vel: 100.into(),
+                },
+            },
+        };
+        let meta = |message| TrackEvent {
+            delta: 0.into(),
+            kind: TrackEventKind::Meta(message),
+        };
+        let first = vec![note(0, 0), note(0, 9)];
+        // The prefix scopes the port meta to channel 2 only; channel 1
+        // stays on the track's port 0.
+        let second = vec![
+            meta(midly::MetaMessage::MidiChannel(1.into())),
+            meta(midly::MetaMessage::MidiPort(1.into())),
+            note(10, 1),
+            note(0, 0),
+        ];
+        let third = vec![
+            meta(midly::MetaMessage::MidiPort(2.into())),
+            note(20, 0),
+            note(0, 9),
+        ];
+        let smf = Smf {
+            header: midly::Header {
+                format: Format::Parallel,
+                timing: Timing::Metrical(480.into()),
+            },
+            tracks: vec![first, second, third],
+        };
+
+        let parsed = parse_smf(&smf);
+        let routed: Vec<(usize, u8, Option<u8>)> = parsed
+            .events
+            .iter()
+            .map(|&(_, track, port, event)| (track, port, event_channel(event)))
+            .collect();
+        assert_eq!(
+            routed,
+            vec![
+                (0, 0, Some(0)),
+                (0, 0, Some(9)),
+                (1, 1, Some(1)),
+                (1, 0, Some(0)),
+                (2, 2, Some(2)),
+                (2, 2, Some(9)),
+            ]
+        );
+    }
+
     #[test]
     fn parse_smf_collects_tempo_and_ticks() {
         let mut track = Vec::new();
@@ -724,9 +5128,36 @@ mod tests {
         };
 
         let parsed = parse_smf(&smf);
-        assert_eq!(parsed.tempo_events.len(), 2);
-        assert!(parsed.max_tick > 0);
-        assert!(parsed.max_note_tick > 0);
+        assert_eq!(parsed.timing.tempo_events.len(), 2);
+        assert!(parsed.timing.max_tick > 0);
+        assert!(parsed.timing.max_note_tick > 0);
         assert_eq!(parsed.events.len(), 2);
     }
+
+    #[test]
+    fn bar_map_accumulates_across_signature_changes() {
+        let bar_map = BarMap::new(&[(0, 4, 4), (3840, 3, 4)], 480);
+        assert_eq!(bar_map.bar_start(1), 0);
+        assert_eq!(bar_map.bar_start(2), 1920);
+        assert_eq!(bar_map.bar_start(3), 3840);
+        assert_eq!(bar_map.bar_start(4), 5280);
+        assert_eq!(bar_map.bar_start(5), 6720);
+        assert_eq!(bar_map.bar_at(5279), 3);
+        assert_eq!(bar_map.bar_at(5280), 4);
+
+        let mid_bar = BarMap::new(&[(1000, 6, 8)], 480);
+        assert_eq!(mid_bar.bar_start(2), 1000);
+        assert_eq!(mid_bar.bar_start(3), 2440);
+    }
+
+    #[test]
+    fn active_channels_respects_window() {
+        let mut last_activity = [0u64; 16];
+        last_activity[0] = 1_001;
+        last_activity[15] = 9_001;
+        assert!(active_channels(&last_activity, 5_800, 4_800)[0]);
+        assert!(!active_channels(&last_activity, 5_801, 4_800)[0]);
+        assert!(!active_channels(&last_activity, 5_000, 4_800)[1]);
+        assert!(!active_channels(&last_activity, 5_000, 4_800)[15]);
+    }
 }
@@ -1,19 +1,35 @@
-use crate::audio::{AudioCommand, AudioSender};
+use crate::audio::{file_bar_map, AudioCommand, AudioSender, AudioState, BarMap};
+use crate::midi::file_timing;
 use crate::state::{
-    MidiFilePath, MidiTrackInfo, MidiTracks, NoteSpan, PianoRollViewState, PlaybackState,
-    PlaybackStatus, SoundFontPath, TrackDetailsPopup, TracksFocus, UiPage, UiSelection, UiState,
+    file_markers, listed_track_indices, note_name, ArticulationCounts, ChannelMixer,
+    ChannelPalette, CompareFile, DisplayTranspose, Equalizer, FileSummary, GotoEntry,
+    Interpolation, LoopRegion, LoopSeam, MarkerList, MasterVolume, Metronome, MidiFilePath,
+    MidiStandard, MidiTrackInfo, MidiTracks, NotePairing, NoteSpan, PianoRollViewState,
+    PlaybackSpeed, PlaybackState, PlaybackStatus, Preferences, PreviewMode, PreviewSize,
+    RecentFiles, RecentKind, RhythmSummary, SettingsFocus, SettingsItem, SongEnd, SoundFontGains,
+    SoundFontLayers, SoundFontPath, StatusMessage, StereoWidth, TapTempo, TempoOverride,
+    TimeDisplay, TrackDetailsPopup, TrackTranspose, TracksFocus, UiPage, UiSelection, UiState,
 };
+use crate::tempo::{file_duration_seconds, file_tempo_map, TempoMap};
+use crate::ui::position_label;
+use bevy::ecs::system::SystemParam;
+use bevy::input::keyboard::KeyboardInput;
+use bevy::input::{ButtonState, InputSystems};
+use bevy::log::{debug, error, info, trace, warn};
 use bevy::prelude::{
-    App, ButtonInput, Commands, Component, Entity, KeyCode, Plugin, Query, Res, ResMut, Resource,
-    Startup, Update,
+    App, ButtonInput, Commands, Component, DetectChanges, Entity, IntoScheduleConfigs, KeyCode,
+    Local, MessageReader, Plugin, PreUpdate, Query, Res, ResMut, Resource, Startup, Time, Update,
+    With,
 };
 use bevy::tasks::IoTaskPool;
+use bevy::window::FileDragAndDrop;
 use futures_lite::future;
 use midly::{MetaMessage, Smf, TrackEvent, TrackEventKind};
 use rfd::FileDialog;
 use serde::Deserialize;
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 #[derive(Resource, Default, Deserialize)]
 pub struct Keybindings {
@@ -27,7 +43,7 @@ impl Keybindings {
             .and_then(|s| match Self::of_str(s) {
                 Ok(res) => Some(res),
                 Err(e) => {
-                    eprintln!("WARNING: {}", e);
+                    warn!("WARNING: {}", e);
                     None
                 }
             })
@@ -45,48 +61,204 @@ impl Keybindings {
             "backspace" => Ok(KeyCode::Backspace),
             "escape" | "esc" => Ok(KeyCode::Escape),
             "p" => Ok(KeyCode::KeyP),
+            "r" => Ok(KeyCode::KeyR),
             "s" => Ok(KeyCode::KeyS),
             "t" => Ok(KeyCode::KeyT),
+            "i" => Ok(KeyCode::KeyI),
             other => Err(format!("Unable to parse Keybinding: {}", other)),
         }
     }
 
     pub fn load_from_conf(mut keybindings: ResMut<Keybindings>) {
-        println!("Loading keybindings...");
+        debug!("Loading keybindings...");
         if let Ok(content) = std::fs::read_to_string("keybindings.toml") {
             if let Ok(config) = toml::from_str::<Keybindings>(&content) {
                 *keybindings = config;
-                println!("Keybindings loaded successfully.");
+                info!("Keybindings loaded successfully.");
             } else {
-                eprintln!("Failed to parse keybindings.toml");
+                warn!("Failed to parse keybindings.toml");
             }
         } else {
-            eprintln!("Failed to read keybindings.toml");
+            warn!("Failed to read keybindings.toml");
         }
     }
 }
 
+#[cfg(test)]
+fn str_to_keycode(s: &str) -> Option<KeyCode> {
+    Keybindings::of_str(s).ok()
+}
+
+#[derive(Resource, Default)]
+pub struct ViewHistory {
+    back: Vec<PianoRollViewState>,
+    forward: Vec<PianoRollViewState>,
+    last_record: Option<f64>,
+}
+
+impl ViewHistory {
+    const LIMIT: usize = 64;
+    const COALESCE_SECS: f64 = 0.5;
+
+    fn record(&mut self, before: PianoRollViewState, now: f64) {
+        let coalesce = self
+            .last_record
+            .is_some_and(|last| now - last < Self::COALESCE_SECS);
+        self.last_record = Some(now);
+        if coalesce {
+            return;
+        }
+        self.back.push(before);
+        if self.back.len() > Self::LIMIT {
+            let _oldest = self.back.remove(0);
+        }
+        self.forward.clear();
+    }
+
+    fn undo(&mut self, current: PianoRollViewState) -> Option<PianoRollViewState> {
+        let previous = self.back.pop()?;
+        self.forward.push(current);
+        self.last_record = None;
+        Some(previous)
+    }
+
+    fn redo(&mut self, current: PianoRollViewState) -> Option<PianoRollViewState> {
+        let next = self.forward.pop()?;
+        self.back.push(current);
+        self.last_record = None;
+        Some(next)
+    }
+}
+
 #[derive(Component)]
 pub struct FileDialogTask(pub bevy::tasks::Task<Option<PathBuf>>, pub UiSelection);
 
+/// Pending pick of a SoundFont to layer over the loaded ones.
+#[derive(Component)]
+pub struct SoundFontLayerDialogTask(pub bevy::tasks::Task<Option<PathBuf>>);
+
+/// Pending pick of a second MIDI file to compare against.
+#[derive(Component)]
+pub struct CompareDialogTask(pub bevy::tasks::Task<Option<PathBuf>>);
+
 pub struct InputPlugin;
 
 impl Plugin for InputPlugin {
     fn build(&self, app: &mut App) {
         let _app = app
             .init_resource::<Keybindings>()
+            .init_resource::<ViewHistory>()
             .add_systems(Startup, Keybindings::load_from_conf)
+            .add_systems(
+                PreUpdate,
+                (handle_marker_list, handle_goto_entry)
+                    .chain()
+                    .after(InputSystems),
+            )
+            .add_systems(
+                Update,
+                (
+                    keyboard_navigation,
+                    handle_settings_input,
+                    handle_input,
+                    open_recent_file,
+                    reload_midi,
+                    reset_session,
+                    adjust_loop_region,
+                    adjust_track_transpose,
+                    reparse_on_note_pairing_change,
+                    tap_tempo,
+                    step_event,
+                    swap_focused_track,
+                    adjust_soundfont_gain,
+                    hold_to_preview_track,
+                    seek_by_bar,
+                    jump_to_densest_bar,
+                    poll_file_dialogs,
+                    focus_prominent_track,
+                    open_midi_shortcut,
+                    open_dropped_files,
+                ),
+            )
             .add_systems(
                 Update,
-                (keyboard_navigation, handle_input, poll_file_dialogs),
+                (
+                    adjust_stereo_width,
+                    adjust_equalizer,
+                    update_file_summary,
+                    compare_file_shortcut,
+                    poll_compare_dialog,
+                    cycle_metronome,
+                    handle_mixer_input,
+                    adjust_master_volume,
+                    adjust_playback_speed,
+                    toggle_song_loop,
+                    toggle_focused_track_mix,
+                    edit_soundfont_layers,
+                    poll_soundfont_layer_dialog,
+                ),
             );
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SplashMove {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+// Splash menu rows from top to bottom; Left/Right move within a row.
+fn splash_rows(recent_count: usize) -> Vec<Vec<UiSelection>> {
+    let mut rows = vec![
+        vec![UiSelection::MidiFile],
+        vec![UiSelection::SoundFont],
+        vec![UiSelection::Play, UiSelection::Stop, UiSelection::Rewind],
+    ];
+    rows.extend((0..recent_count).map(|index| vec![UiSelection::Recent(index)]));
+    rows
+}
+
+// Moving onto a shorter row keeps the column where it can, so Up from
+// Rewind lands on SoundFont and Down from SoundFont on Play.
+fn splash_move(
+    selection: UiSelection,
+    step: SplashMove,
+    recent_count: usize,
+    wrap: bool,
+) -> UiSelection {
+    let rows = splash_rows(recent_count);
+    let Some((row, col)) = rows.iter().enumerate().find_map(|(row, items)| {
+        items
+            .iter()
+            .position(|item| *item == selection)
+            .map(|col| (row, col))
+    }) else {
+        return selection;
+    };
+    let last_row = rows.len() - 1;
+    let target_row = match step {
+        SplashMove::Up if row > 0 => row - 1,
+        SplashMove::Up if wrap => last_row,
+        SplashMove::Down if row < last_row => row + 1,
+        SplashMove::Down if wrap => 0,
+        SplashMove::Up | SplashMove::Down => return selection,
+        SplashMove::Left => {
+            return col.checked_sub(1).map_or(selection, |col| rows[row][col]);
+        }
+        SplashMove::Right => return rows[row].get(col + 1).copied().unwrap_or(selection),
+    };
+    let items = &rows[target_row];
+    items[col.min(items.len() - 1)]
+}
+
 fn keyboard_navigation(
     mut ui_state: ResMut<UiState>,
     keyboard_input: Res<ButtonInput<KeyCode>>,
     keybindings: Res<Keybindings>,
+    recent: Res<RecentFiles>,
+    preferences: Res<Preferences>,
 ) {
     if ui_state.page != UiPage::Splash {
         return;
@@ -96,7 +268,7 @@ fn keyboard_navigation(
         match keybindings.get_keycode(s) {
             Some(kc) => kc,
             None => {
-                eprintln!("Using default keycode {:?} for action {}", default, s);
+                trace!("Using default keycode {:?} for action {}", default, s);
                 default
             }
         }
@@ -107,59 +279,615 @@ fn keyboard_navigation(
     let left = lookup_with_default("NavigateLeft", KeyCode::ArrowLeft);
     let right = lookup_with_default("NavigateRight", KeyCode::ArrowRight);
 
-    if keyboard_input.just_pressed(down) {
-        println!("Key: Down");
-        ui_state.selection = match ui_state.selection {
-            UiSelection::MidiFile => UiSelection::SoundFont,
-            UiSelection::SoundFont => UiSelection::Play,
-            UiSelection::Play | UiSelection::Stop | UiSelection::Rewind => ui_state.selection,
-        };
+    let step = if keyboard_input.just_pressed(down) {
+        SplashMove::Down
     } else if keyboard_input.just_pressed(up) {
-        println!("Key: Up");
-        ui_state.selection = match ui_state.selection {
-            UiSelection::SoundFont => UiSelection::MidiFile,
-            UiSelection::Play | UiSelection::Stop | UiSelection::Rewind => UiSelection::SoundFont,
-            UiSelection::MidiFile => ui_state.selection,
-        };
+        SplashMove::Up
     } else if keyboard_input.just_pressed(right) {
-        println!("Key: Right");
-        ui_state.selection = match ui_state.selection {
-            UiSelection::Play => UiSelection::Stop,
-            UiSelection::Stop => UiSelection::Rewind,
-            UiSelection::MidiFile | UiSelection::SoundFont | UiSelection::Rewind => {
-                ui_state.selection
-            }
-        };
+        SplashMove::Right
     } else if keyboard_input.just_pressed(left) {
-        println!("Key: Left");
-        ui_state.selection = match ui_state.selection {
-            UiSelection::Rewind => UiSelection::Stop,
-            UiSelection::Stop => UiSelection::Play,
-            UiSelection::MidiFile | UiSelection::SoundFont | UiSelection::Play => {
-                ui_state.selection
+        SplashMove::Left
+    } else {
+        return;
+    };
+    debug!("Key: {:?}", step);
+    ui_state.selection = splash_move(
+        ui_state.selection,
+        step,
+        recent.0.len(),
+        preferences.menu_wrap,
+    );
+}
+
+fn handle_settings_input(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mut ui_state: ResMut<UiState>,
+    mut settings_focus: ResMut<SettingsFocus>,
+    mut preferences: ResMut<Preferences>,
+) {
+    if keyboard_input.just_pressed(KeyCode::F10) {
+        ui_state.toggle_page(UiPage::Settings);
+        return;
+    }
+    if ui_state.page != UiPage::Settings {
+        return;
+    }
+
+    if keyboard_input.just_pressed(KeyCode::Escape) {
+        ui_state.back();
+        return;
+    }
+
+    let item_count = SettingsItem::ALL.len();
+    if keyboard_input.just_pressed(KeyCode::ArrowUp) {
+        settings_focus.index = (settings_focus.index + item_count - 1) % item_count;
+    }
+    if keyboard_input.just_pressed(KeyCode::ArrowDown) {
+        settings_focus.index = (settings_focus.index + 1) % item_count;
+    }
+
+    let forward = keyboard_input.just_pressed(KeyCode::ArrowRight)
+        || keyboard_input.just_pressed(KeyCode::Enter);
+    let backward = keyboard_input.just_pressed(KeyCode::ArrowLeft);
+    if forward || backward {
+        let item = SettingsItem::ALL[settings_focus.index.min(item_count - 1)];
+        cycle_setting(&mut preferences, item, forward);
+    }
+}
+
+// Shift+M on the tracks page mutes the focused track's channels and Shift+S
+// solos them; pressing either again once they all are undoes it.
+fn toggle_focused_track_mix(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    ui_state: Res<UiState>,
+    tracks_focus: Res<TracksFocus>,
+    midi_tracks: Res<MidiTracks>,
+    mut mixer: ResMut<ChannelMixer>,
+    mut status: ResMut<StatusMessage>,
+) {
+    let shift =
+        keyboard_input.pressed(KeyCode::ShiftLeft) || keyboard_input.pressed(KeyCode::ShiftRight);
+    let solo = keyboard_input.just_pressed(KeyCode::KeyS);
+    if !ui_state.page.shows_tracks()
+        || !shift
+        || !(solo || keyboard_input.just_pressed(KeyCode::KeyM))
+    {
+        return;
+    }
+    let Some(track) = midi_tracks.0.get(tracks_focus.index) else {
+        return;
+    };
+    if track.channels.is_empty() {
+        status.show("This track plays on no channel");
+        return;
+    }
+    let channels = track
+        .channels
+        .iter()
+        .map(|channel| (channel + 1).to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
+    status.show(if solo {
+        if mixer.toggle_solo(&track.channels) {
+            format!("Soloed channel {channels}")
+        } else {
+            format!("Cleared solo on channel {channels}")
+        }
+    } else if mixer.toggle_mute(&track.channels) {
+        format!("Muted channel {channels}")
+    } else {
+        format!("Unmuted channel {channels}")
+    });
+}
+
+// F9 opens the mixer. Its strips are laid out like the splash menu's rows,
+// so it takes the same navigation keys: left and right pick a channel, up
+// and down change the focused control, and Tab moves between controls.
+fn handle_mixer_input(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    keybindings: Res<Keybindings>,
+    preferences: Res<Preferences>,
+    mut ui_state: ResMut<UiState>,
+    mut mixer: ResMut<ChannelMixer>,
+) {
+    if keyboard_input.just_pressed(KeyCode::F9) {
+        ui_state.toggle_page(UiPage::Mixer);
+        return;
+    }
+    if ui_state.page != UiPage::Mixer {
+        return;
+    }
+    if keyboard_input.just_pressed(KeyCode::Escape) {
+        ui_state.back();
+        return;
+    }
+
+    let key = |action: &str, default: KeyCode| keybindings.get_keycode(action).unwrap_or(default);
+    if keyboard_input.just_pressed(key("NavigateLeft", KeyCode::ArrowLeft)) {
+        mixer.step_channel(false, preferences.menu_wrap);
+    }
+    if keyboard_input.just_pressed(key("NavigateRight", KeyCode::ArrowRight)) {
+        mixer.step_channel(true, preferences.menu_wrap);
+    }
+    if keyboard_input.just_pressed(KeyCode::Tab) {
+        let shift = keyboard_input.pressed(KeyCode::ShiftLeft)
+            || keyboard_input.pressed(KeyCode::ShiftRight);
+        mixer.step_control(!shift);
+    }
+    if keyboard_input.just_pressed(key("NavigateUp", KeyCode::ArrowUp)) {
+        mixer.adjust(true);
+    }
+    if keyboard_input.just_pressed(key("NavigateDown", KeyCode::ArrowDown)) {
+        mixer.adjust(false);
+    }
+    if keyboard_input.just_pressed(KeyCode::PageUp) {
+        mixer.step_program(8);
+    }
+    if keyboard_input.just_pressed(KeyCode::PageDown) {
+        mixer.step_program(-8);
+    }
+    if keyboard_input.just_pressed(key("Select", KeyCode::Enter)) {
+        mixer.reset_focused();
+    }
+}
+
+fn next_zoom_level(current: f32, forward: bool) -> f32 {
+    let levels = Preferences::DEFAULT_ZOOM_LEVELS;
+    let index = levels
+        .iter()
+        .position(|level| *level >= current)
+        .unwrap_or(levels.len() - 1);
+    let next = if forward {
+        (index + 1).min(levels.len() - 1)
+    } else {
+        index.saturating_sub(1)
+    };
+    levels[next]
+}
+
+fn cycle_setting(preferences: &mut Preferences, item: SettingsItem, forward: bool) {
+    match item {
+        SettingsItem::TimeDisplay => {
+            preferences.time_display = match preferences.time_display {
+                TimeDisplay::Time => TimeDisplay::Ticks,
+                TimeDisplay::Ticks => TimeDisplay::Time,
+            };
+        }
+        SettingsItem::Interpolation => {
+            const MODES: [Interpolation; 4] = [
+                Interpolation::None,
+                Interpolation::Linear,
+                Interpolation::FourthOrder,
+                Interpolation::SeventhOrder,
+            ];
+            let current = MODES
+                .iter()
+                .position(|mode| *mode == preferences.interpolation)
+                .unwrap_or(0);
+            let next = if forward {
+                (current + 1) % MODES.len()
+            } else {
+                (current + MODES.len() - 1) % MODES.len()
+            };
+            preferences.interpolation = MODES[next];
+        }
+        SettingsItem::BeatPulse => preferences.beat_pulse = !preferences.beat_pulse,
+        SettingsItem::ReverbTail => {
+            const TAILS: [f32; 7] = [0.0, 0.5, 1.0, 1.5, 2.0, 3.0, 5.0];
+            let current = TAILS
+                .iter()
+                .position(|tail| *tail >= preferences.reverb_tail_seconds)
+                .unwrap_or(TAILS.len() - 1);
+            let next = if forward {
+                (current + 1).min(TAILS.len() - 1)
+            } else {
+                current.saturating_sub(1)
+            };
+            preferences.reverb_tail_seconds = TAILS[next];
+        }
+        SettingsItem::ReverbLevel => {
+            let levels = Preferences::REVERB_LEVELS;
+            let current = levels
+                .iter()
+                .position(|level| *level >= preferences.reverb_level)
+                .unwrap_or(levels.len() - 1);
+            let next = if forward {
+                (current + 1).min(levels.len() - 1)
+            } else {
+                current.saturating_sub(1)
+            };
+            preferences.reverb_level = levels[next];
+        }
+        SettingsItem::ChorusLevel => {
+            let levels = Preferences::CHORUS_LEVELS;
+            let current = levels
+                .iter()
+                .position(|level| *level >= preferences.chorus_level)
+                .unwrap_or(levels.len() - 1);
+            let next = if forward {
+                (current + 1).min(levels.len() - 1)
+            } else {
+                current.saturating_sub(1)
+            };
+            preferences.chorus_level = levels[next];
+        }
+        SettingsItem::SampleRate => {
+            const RATES: [Option<u32>; 5] =
+                [None, Some(44_100), Some(48_000), Some(88_200), Some(96_000)];
+            let current = RATES
+                .iter()
+                .position(|rate| *rate == preferences.output_sample_rate)
+                .unwrap_or(0);
+            let next = if forward {
+                (current + 1) % RATES.len()
+            } else {
+                (current + RATES.len() - 1) % RATES.len()
+            };
+            preferences.output_sample_rate = RATES[next];
+        }
+        SettingsItem::Polyphony => {
+            const LIMITS: [u16; 5] = [64, 128, 256, 512, 1024];
+            let current = LIMITS
+                .iter()
+                .position(|limit| *limit >= preferences.polyphony)
+                .unwrap_or(LIMITS.len() - 1);
+            let next = if forward {
+                (current + 1).min(LIMITS.len() - 1)
+            } else {
+                current.saturating_sub(1)
+            };
+            preferences.polyphony = LIMITS[next];
+        }
+        SettingsItem::MenuAnimation => preferences.menu_animation = !preferences.menu_animation,
+        SettingsItem::MenuWrap => preferences.menu_wrap = !preferences.menu_wrap,
+        SettingsItem::ScrubOnSeek => preferences.scrub_on_seek = !preferences.scrub_on_seek,
+        SettingsItem::LoopSong => preferences.loop_song = !preferences.loop_song,
+        SettingsItem::PracticeClick => preferences.practice_click = !preferences.practice_click,
+        SettingsItem::Autoplay => preferences.autoplay = !preferences.autoplay,
+        SettingsItem::HideEmptyTracks => {
+            preferences.hide_empty_tracks = !preferences.hide_empty_tracks;
+        }
+        SettingsItem::SongEnd => {
+            preferences.song_end = match preferences.song_end {
+                SongEnd::LastNote => SongEnd::EndOfTrack,
+                SongEnd::EndOfTrack => SongEnd::LastNote,
+            };
+        }
+        SettingsItem::ChannelPalette => {
+            const PALETTES: [Option<ChannelPalette>; 3] = [
+                None,
+                Some(ChannelPalette::Vibrant),
+                Some(ChannelPalette::ColorBlindSafe),
+            ];
+            let current = PALETTES
+                .iter()
+                .position(|palette| *palette == preferences.channel_palette)
+                .unwrap_or(0);
+            let next = if forward {
+                (current + 1) % PALETTES.len()
+            } else {
+                (current + PALETTES.len() - 1) % PALETTES.len()
+            };
+            preferences.channel_palette = PALETTES[next];
+        }
+        SettingsItem::HoldToPreview => {
+            preferences.hold_to_preview = !preferences.hold_to_preview;
+        }
+        SettingsItem::FocusProminentTrack => {
+            preferences.focus_prominent_track = !preferences.focus_prominent_track;
+        }
+        SettingsItem::PreviewMode => {
+            preferences.preview_mode = match preferences.preview_mode {
+                PreviewMode::Sustain => PreviewMode::Onset,
+                PreviewMode::Onset => PreviewMode::Sustain,
+            };
+        }
+        SettingsItem::MiniRollPreview => {
+            preferences.mini_roll_preview = !preferences.mini_roll_preview;
+        }
+        SettingsItem::NotePairing => {
+            preferences.note_pairing = match preferences.note_pairing {
+                NotePairing::Fifo => NotePairing::Lifo,
+                NotePairing::Lifo => NotePairing::Fifo,
+            };
+        }
+        SettingsItem::LoopSeam => {
+            const SEAMS: [LoopSeam; 3] = [LoopSeam::Cut, LoopSeam::Release, LoopSeam::Fade];
+            let current = SEAMS
+                .iter()
+                .position(|seam| *seam == preferences.loop_seam)
+                .unwrap_or(0);
+            let next = if forward {
+                (current + 1) % SEAMS.len()
+            } else {
+                (current + SEAMS.len() - 1) % SEAMS.len()
+            };
+            preferences.loop_seam = SEAMS[next];
+        }
+        SettingsItem::DefaultZoomX => {
+            preferences.default_zoom_x = next_zoom_level(preferences.default_zoom_x, forward);
+        }
+        SettingsItem::DefaultZoomY => {
+            preferences.default_zoom_y = next_zoom_level(preferences.default_zoom_y, forward);
+        }
+        SettingsItem::QuickLoopBars => {
+            let levels = Preferences::QUICK_LOOP_BAR_LEVELS;
+            let current = levels
+                .iter()
+                .position(|level| *level >= preferences.quick_loop_bars)
+                .unwrap_or(levels.len() - 1);
+            let next = if forward {
+                (current + 1).min(levels.len() - 1)
+            } else {
+                current.saturating_sub(1)
+            };
+            preferences.quick_loop_bars = levels[next];
+        }
+        SettingsItem::SplitDivider => {
+            let levels = Preferences::SPLIT_DIVIDER_LEVELS;
+            let current = levels
+                .iter()
+                .position(|level| *level >= preferences.split_divider_percent)
+                .unwrap_or(levels.len() - 1);
+            let next = if forward {
+                (current + 1).min(levels.len() - 1)
+            } else {
+                current.saturating_sub(1)
+            };
+            preferences.split_divider_percent = levels[next];
+        }
+        SettingsItem::ClickAudition => {
+            preferences.click_audition = !preferences.click_audition;
+        }
+        SettingsItem::NormalizeLoudness => {
+            preferences.normalize_loudness = !preferences.normalize_loudness;
+        }
+        SettingsItem::IdleTimeout => {
+            const TIMEOUTS: [Option<f32>; 4] = [None, Some(2.0), Some(10.0), Some(30.0)];
+            let current = TIMEOUTS
+                .iter()
+                .position(|timeout| *timeout == preferences.idle_timeout_seconds)
+                .unwrap_or(0);
+            let next = if forward {
+                (current + 1) % TIMEOUTS.len()
+            } else {
+                (current + TIMEOUTS.len() - 1) % TIMEOUTS.len()
+            };
+            preferences.idle_timeout_seconds = TIMEOUTS[next];
+        }
+    }
+}
+
+// Which splash item to jump to, and what to tell the user, when Play is
+// pressed without both files chosen.
+fn play_hint(
+    midi_path: &MidiFilePath,
+    soundfont_path: &SoundFontPath,
+) -> Option<(UiSelection, &'static str)> {
+    if midi_path.0.is_none() {
+        Some((UiSelection::MidiFile, "Select a MIDI file first"))
+    } else if soundfont_path.0.is_none() {
+        Some((UiSelection::SoundFont, "Select a SoundFont first"))
+    } else {
+        None
+    }
+}
+
+// What to do once a MIDI file has loaded: play it when autoplay is on and a
+// SoundFont is ready, otherwise point at what is missing.
+fn autoplay_on_load(
+    autoplay: bool,
+    midi_path: &MidiFilePath,
+    soundfont_path: &SoundFontPath,
+) -> Option<Result<AudioCommand, (UiSelection, &'static str)>> {
+    if !autoplay {
+        return None;
+    }
+    match (&midi_path.0, &soundfont_path.0) {
+        (Some(midi), Some(sf)) => Some(Ok(AudioCommand::Play(midi.clone(), sf.clone()))),
+        _ => play_hint(midi_path, soundfont_path).map(Err),
+    }
+}
+
+fn start_autoplay(
+    preferences: &Preferences,
+    midi_path: &MidiFilePath,
+    soundfont_path: &SoundFontPath,
+    playback_status: &mut PlaybackStatus,
+    audio_tx: &AudioSender,
+    ui_state: &mut UiState,
+    status: &mut StatusMessage,
+) {
+    match autoplay_on_load(preferences.autoplay, midi_path, soundfont_path) {
+        Some(Ok(command)) => {
+            playback_status.state = PlaybackState::Playing;
+            let _ = audio_tx.0.send(command);
+        }
+        Some(Err((selection, hint))) => {
+            ui_state.selection = selection;
+            status.show(hint);
+        }
+        None => {}
+    }
+}
+
+/// Pauses while playing, otherwise plays, or points at the missing file
+/// when there is nothing to play.
+pub(crate) fn toggle_playback(
+    playback_status: &mut PlaybackStatus,
+    midi_path: &MidiFilePath,
+    soundfont_path: &SoundFontPath,
+    audio_tx: &AudioSender,
+    ui_state: &mut UiState,
+    status: &mut StatusMessage,
+) {
+    match playback_status.state {
+        PlaybackState::Playing => {
+            playback_status.state = PlaybackState::Paused;
+            let _ = audio_tx.0.send(AudioCommand::Pause);
+        }
+        PlaybackState::Paused | PlaybackState::Stopped => {
+            if let (Some(midi), Some(sf)) = (&midi_path.0, &soundfont_path.0) {
+                playback_status.state = PlaybackState::Playing;
+                let _ = audio_tx
+                    .0
+                    .send(AudioCommand::Play(midi.clone(), sf.clone()));
+            } else if let Some((selection, hint)) = play_hint(midi_path, soundfont_path) {
+                ui_state.selection = selection;
+                status.show(hint);
             }
-        };
+        }
+    }
+}
+
+/// The pressed keys and what they are bound to.
+#[derive(SystemParam)]
+struct BoundKeys<'w> {
+    keyboard_input: Res<'w, ButtonInput<KeyCode>>,
+    keybindings: Res<'w, Keybindings>,
+}
+
+/// The chosen files and the playback they drive.
+#[derive(SystemParam)]
+struct Transport<'w> {
+    midi_path: Res<'w, MidiFilePath>,
+    soundfont_path: Res<'w, SoundFontPath>,
+    playback_status: ResMut<'w, PlaybackStatus>,
+    audio_state: Res<'w, AudioState>,
+    audio_tx: Res<'w, AudioSender>,
+}
+
+/// The track list, its focus and the details popup over it.
+#[derive(SystemParam)]
+struct TrackBrowser<'w> {
+    midi_tracks: Res<'w, MidiTracks>,
+    tracks_focus: ResMut<'w, TracksFocus>,
+    track_popup: ResMut<'w, TrackDetailsPopup>,
+    preferences: Res<'w, Preferences>,
+}
+
+/// The piano roll view and its undo history.
+#[derive(SystemParam)]
+struct RollNavigation<'w> {
+    piano_roll: ResMut<'w, PianoRollViewState>,
+    view_history: ResMut<'w, ViewHistory>,
+    time: Res<'w, Time>,
+}
+
+/// The loaded tracks and the tempo their ticks are timed at.
+#[derive(SystemParam)]
+struct SongTiming<'w> {
+    midi_tracks: Res<'w, MidiTracks>,
+    tempo_override: Res<'w, TempoOverride>,
+}
+
+/// Everything opening a file touches. The file dialogs, drag and drop, the
+/// recent list and the remote all load through it.
+#[derive(SystemParam)]
+pub(crate) struct FileLoader<'w> {
+    pub(crate) midi_path: ResMut<'w, MidiFilePath>,
+    pub(crate) soundfont_path: ResMut<'w, SoundFontPath>,
+    pub(crate) midi_tracks: ResMut<'w, MidiTracks>,
+    pub(crate) tracks_focus: ResMut<'w, TracksFocus>,
+    pub(crate) playback_status: ResMut<'w, PlaybackStatus>,
+    pub(crate) ui_state: ResMut<'w, UiState>,
+    pub(crate) status: ResMut<'w, StatusMessage>,
+    pub(crate) preferences: Res<'w, Preferences>,
+    pub(crate) audio_tx: Res<'w, AudioSender>,
+}
+
+impl FileLoader<'_> {
+    /// Loads the MIDI file at `path` and starts it when autoplay is on.
+    /// Recent files, the prominent track and loudness follow the changed
+    /// resources.
+    pub(crate) fn load_midi(&mut self, path: PathBuf) {
+        self.midi_tracks.0 = load_midi_tracks(
+            &path,
+            self.preferences.note_pairing,
+            self.preferences.preview_size,
+        );
+        self.tracks_focus.reset(0);
+        self.midi_path.0 = Some(path);
+        if !self.midi_tracks.0.is_empty() {
+            start_autoplay(
+                &self.preferences,
+                &self.midi_path,
+                &self.soundfont_path,
+                &mut self.playback_status,
+                &self.audio_tx,
+                &mut self.ui_state,
+                &mut self.status,
+            );
+        }
     }
 }
 
 fn handle_input(
     mut commands: Commands,
-    keyboard_input: Res<ButtonInput<KeyCode>>,
+    keys: BoundKeys,
     mut ui_state: ResMut<UiState>,
-    midi_path: Res<MidiFilePath>,
-    soundfont_path: Res<SoundFontPath>,
-    mut playback_status: ResMut<PlaybackStatus>,
-    audio_tx: Res<AudioSender>,
-    keybindings: Res<Keybindings>,
-    mut tracks_focus: ResMut<TracksFocus>,
-    midi_tracks: Res<MidiTracks>,
-    mut track_popup: ResMut<TrackDetailsPopup>,
-    mut piano_roll: ResMut<PianoRollViewState>,
+    transport: Transport,
+    browser: TrackBrowser,
+    roll: RollNavigation,
+    mut status: ResMut<StatusMessage>,
 ) {
+    let BoundKeys {
+        keyboard_input,
+        keybindings,
+    } = keys;
+    let Transport {
+        midi_path,
+        soundfont_path,
+        mut playback_status,
+        audio_tx,
+        ..
+    } = transport;
+    let TrackBrowser {
+        midi_tracks,
+        mut tracks_focus,
+        mut track_popup,
+        preferences,
+    } = browser;
+    let RollNavigation {
+        mut piano_roll,
+        mut view_history,
+        time,
+    } = roll;
+    if matches!(
+        ui_state.page,
+        UiPage::Tracks | UiPage::PianoRoll | UiPage::Split
+    ) && keyboard_input.just_pressed(KeyCode::KeyV)
+    {
+        ui_state.toggle_page(UiPage::Split);
+        return;
+    }
+
     if ui_state.page == UiPage::PianoRoll {
         if keyboard_input.just_pressed(KeyCode::Escape) {
-            ui_state.page = UiPage::Tracks;
+            ui_state.back();
+        }
+        let ctrl = keyboard_input.pressed(KeyCode::ControlLeft)
+            || keyboard_input.pressed(KeyCode::ControlRight);
+        let shift = keyboard_input.pressed(KeyCode::ShiftLeft)
+            || keyboard_input.pressed(KeyCode::ShiftRight);
+        if ctrl && keyboard_input.just_pressed(KeyCode::KeyZ) {
+            let restored = if shift {
+                view_history.redo(*piano_roll)
+            } else {
+                view_history.undo(*piano_roll)
+            };
+            if let Some(view) = restored {
+                *piano_roll = view;
+            }
+            return;
         }
+        if ctrl && keyboard_input.just_pressed(KeyCode::KeyY) {
+            if let Some(view) = view_history.redo(*piano_roll) {
+                *piano_roll = view;
+            }
+            return;
+        }
+        let before = *piano_roll;
         if let Some(track) = midi_tracks.0.get(tracks_focus.index) {
             let step_ticks = track.ticks_per_beat.max(1) as f32;
             let step_pitch = 12.0;
@@ -169,8 +897,6 @@ fn handle_input(
             if keyboard_input.just_pressed(KeyCode::ArrowRight) {
                 piano_roll.offset_ticks += step_ticks;
             }
-            let shift = keyboard_input.pressed(KeyCode::ShiftLeft)
-                || keyboard_input.pressed(KeyCode::ShiftRight);
             if shift {
                 if keyboard_input.just_pressed(KeyCode::ArrowUp) {
                     piano_roll.zoom_y = (piano_roll.zoom_y * 1.25).min(16.0);
@@ -186,22 +912,39 @@ fn handle_input(
                     piano_roll.offset_pitch += step_pitch;
                 }
             }
-            if keyboard_input.just_pressed(KeyCode::Equal)
-                || keyboard_input.just_pressed(KeyCode::NumpadAdd)
-            {
-                piano_roll.zoom_x = (piano_roll.zoom_x * 1.25).min(16.0);
-            }
-            if keyboard_input.just_pressed(KeyCode::Minus)
-                || keyboard_input.just_pressed(KeyCode::NumpadSubtract)
-            {
-                piano_roll.zoom_x = (piano_roll.zoom_x / 1.25).max(1.0);
-            }
+            zoom_piano_roll(&keyboard_input, &mut piano_roll);
+        }
+        if *piano_roll != before {
+            view_history.record(before, time.elapsed_secs_f64());
         }
         return;
     }
 
+    // The split view keeps the arrows for the track list, but +/- still
+    // zooms its roll.
+    if ui_state.page == UiPage::Split {
+        let before = *piano_roll;
+        zoom_piano_roll(&keyboard_input, &mut piano_roll);
+        if *piano_roll != before {
+            view_history.record(before, time.elapsed_secs_f64());
+            return;
+        }
+    }
+
     if ui_state.page == UiPage::Tracks && keyboard_input.just_pressed(KeyCode::KeyP) {
-        ui_state.page = UiPage::PianoRoll;
+        ui_state.open_page(UiPage::PianoRoll);
+        return;
+    }
+
+    if ui_state.page == UiPage::Tracks && keyboard_input.just_pressed(KeyCode::KeyK) {
+        ui_state.open_page(UiPage::Lyrics);
+        return;
+    }
+    if ui_state.page == UiPage::Lyrics
+        && (keyboard_input.just_pressed(KeyCode::KeyK)
+            || keyboard_input.just_pressed(KeyCode::Escape))
+    {
+        ui_state.back();
         return;
     }
 
@@ -209,47 +952,59 @@ fn handle_input(
         && (keyboard_input.pressed(KeyCode::ShiftLeft)
             || keyboard_input.pressed(KeyCode::ShiftRight));
     if about_toggle {
-        ui_state.page = match ui_state.page {
-            UiPage::Splash => UiPage::About,
-            UiPage::About => UiPage::Splash,
-            UiPage::Tracks => UiPage::About,
-            UiPage::PianoRoll => UiPage::About,
-        };
+        ui_state.toggle_page(UiPage::About);
         return;
     }
 
     let tracks_key = keybindings.get_keycode("Tracks").unwrap_or(KeyCode::KeyT);
     if keyboard_input.just_pressed(tracks_key) {
-        ui_state.page = if ui_state.page == UiPage::Tracks {
-            UiPage::Splash
-        } else {
-            UiPage::Tracks
-        };
+        ui_state.toggle_page(UiPage::Tracks);
         if ui_state.page == UiPage::Tracks {
-            tracks_focus.index = 0;
+            let home = tracks_focus.home;
+            tracks_focus.focus(home);
         }
         return;
     }
 
     if ui_state.page != UiPage::Splash {
-        if ui_state.page == UiPage::Tracks {
+        if ui_state.page.shows_tracks() {
+            let details_key = keybindings
+                .get_keycode("TrackDetails")
+                .unwrap_or(KeyCode::Enter);
+            let close_key = keybindings
+                .get_keycode("CloseDetails")
+                .unwrap_or(KeyCode::Escape);
+            let play_pause_key = keybindings
+                .get_keycode("PlayPause")
+                .unwrap_or(KeyCode::Space);
             if keyboard_input.just_pressed(KeyCode::ArrowUp)
                 || keyboard_input.just_pressed(KeyCode::ArrowDown)
             {
-                let track_count = midi_tracks.0.len();
-                if track_count == 0 {
+                let listed = listed_track_indices(&midi_tracks.0, preferences.hide_empty_tracks);
+                if listed.is_empty() {
                     return;
                 }
-                if keyboard_input.just_pressed(KeyCode::ArrowUp) {
-                    tracks_focus.index = (tracks_focus.index + track_count - 1) % track_count;
-                } else {
-                    tracks_focus.index = (tracks_focus.index + 1) % track_count;
-                }
+                // A hidden focused track steps to the nearest listed row.
+                let position = match listed.binary_search(&tracks_focus.index) {
+                    Ok(position) if keyboard_input.just_pressed(KeyCode::ArrowUp) => {
+                        (position + listed.len() - 1) % listed.len()
+                    }
+                    Ok(position) => (position + 1) % listed.len(),
+                    Err(position) if keyboard_input.just_pressed(KeyCode::ArrowUp) => {
+                        (position + listed.len() - 1) % listed.len()
+                    }
+                    Err(position) => position % listed.len(),
+                };
+                tracks_focus.focus(listed[position]);
             }
-            if keyboard_input.just_pressed(KeyCode::Escape) {
+            if keyboard_input.just_pressed(close_key) {
+                if ui_state.page == UiPage::Split && !track_popup.visible {
+                    ui_state.back();
+                    return;
+                }
                 track_popup.visible = false;
             }
-            if keyboard_input.just_pressed(KeyCode::Enter) {
+            if keyboard_input.just_pressed(details_key) {
                 let track_count = midi_tracks.0.len();
                 if track_count == 0 {
                     return;
@@ -257,21 +1012,15 @@ fn handle_input(
                 track_popup.visible = true;
                 track_popup.track_index = tracks_focus.index.min(track_count.saturating_sub(1));
             }
-            if keyboard_input.just_pressed(KeyCode::Space) {
-                match playback_status.state {
-                    PlaybackState::Playing => {
-                        playback_status.state = PlaybackState::Paused;
-                        let _ = audio_tx.0.send(AudioCommand::Pause);
-                    }
-                    PlaybackState::Paused | PlaybackState::Stopped => {
-                        if let (Some(midi), Some(sf)) = (&midi_path.0, &soundfont_path.0) {
-                            playback_status.state = PlaybackState::Playing;
-                            let _ = audio_tx
-                                .0
-                                .send(AudioCommand::Play(midi.clone(), sf.clone()));
-                        }
-                    }
-                }
+            if keyboard_input.just_pressed(play_pause_key) {
+                toggle_playback(
+                    &mut playback_status,
+                    &midi_path,
+                    &soundfont_path,
+                    &audio_tx,
+                    &mut ui_state,
+                    &mut status,
+                );
             }
         }
         return;
@@ -282,17 +1031,9 @@ fn handle_input(
     let stop_key = keybindings.get_keycode("Stop").unwrap_or(KeyCode::KeyS);
 
     if keyboard_input.just_pressed(select_key) {
-        println!("Key: Select");
+        debug!("Key: Select");
         match ui_state.selection {
-            UiSelection::MidiFile => {
-                let thread_pool = IoTaskPool::get();
-                let task = thread_pool.spawn(async move {
-                    FileDialog::new()
-                        .add_filter("MIDI", &["mid", "midi"])
-                        .pick_file()
-                });
-                let _ = commands.spawn(FileDialogTask(task, UiSelection::MidiFile));
-            }
+            UiSelection::MidiFile => spawn_midi_dialog(&mut commands),
             UiSelection::SoundFont => {
                 let thread_pool = IoTaskPool::get();
                 let task = thread_pool.spawn(async move {
@@ -302,20 +1043,14 @@ fn handle_input(
                 });
                 let _ = commands.spawn(FileDialogTask(task, UiSelection::SoundFont));
             }
-            UiSelection::Play => match playback_status.state {
-                PlaybackState::Playing => {
-                    playback_status.state = PlaybackState::Paused;
-                    let _ = audio_tx.0.send(AudioCommand::Pause);
-                }
-                PlaybackState::Paused | PlaybackState::Stopped => {
-                    if let (Some(midi), Some(sf)) = (&midi_path.0, &soundfont_path.0) {
-                        playback_status.state = PlaybackState::Playing;
-                        let _ = audio_tx
-                            .0
-                            .send(AudioCommand::Play(midi.clone(), sf.clone()));
-                    }
-                }
-            },
+            UiSelection::Play => toggle_playback(
+                &mut playback_status,
+                &midi_path,
+                &soundfont_path,
+                &audio_tx,
+                &mut ui_state,
+                &mut status,
+            ),
             UiSelection::Stop => {
                 playback_status.state = PlaybackState::Stopped;
                 let _ = audio_tx.0.send(AudioCommand::Stop);
@@ -323,24 +1058,19 @@ fn handle_input(
             UiSelection::Rewind => {
                 let _ = audio_tx.0.send(AudioCommand::Rewind);
             }
+            UiSelection::Recent(_) => {}
         }
     }
 
     if keyboard_input.just_pressed(play_key) {
-        match playback_status.state {
-            PlaybackState::Playing => {
-                playback_status.state = PlaybackState::Paused;
-                let _ = audio_tx.0.send(AudioCommand::Pause);
-            }
-            PlaybackState::Paused | PlaybackState::Stopped => {
-                if let (Some(midi), Some(sf)) = (&midi_path.0, &soundfont_path.0) {
-                    playback_status.state = PlaybackState::Playing;
-                    let _ = audio_tx
-                        .0
-                        .send(AudioCommand::Play(midi.clone(), sf.clone()));
-                }
-            }
-        }
+        toggle_playback(
+            &mut playback_status,
+            &midi_path,
+            &soundfont_path,
+            &audio_tx,
+            &mut ui_state,
+            &mut status,
+        );
     }
 
     if keyboard_input.just_pressed(stop_key) {
@@ -349,49 +1079,1365 @@ fn handle_input(
     }
 }
 
-fn poll_file_dialogs(
-    mut commands: Commands,
-    mut tasks: Query<(Entity, &mut FileDialogTask)>,
-    mut midi_path: ResMut<MidiFilePath>,
-    mut soundfont_path: ResMut<SoundFontPath>,
-    mut midi_tracks: ResMut<MidiTracks>,
-) {
-    for (entity, mut task) in &mut tasks {
-        if let Some(result) = future::block_on(future::poll_once(&mut task.0)) {
-            println!("File dialog result received.");
-            if let Some(path) = result {
-                match task.1 {
-                    UiSelection::MidiFile => {
-                        midi_path.0 = Some(path.clone());
-                        midi_tracks.0 = load_midi_tracks(&path);
-                    }
-                    UiSelection::SoundFont => soundfont_path.0 = Some(path),
-                    UiSelection::Play | UiSelection::Stop | UiSelection::Rewind => {}
-                }
-            }
-            commands.entity(entity).despawn();
-        }
+// +/- zooms the roll in time. Alt with them is the SoundFont gain and Ctrl
+// the UI scale, not zoom.
+fn zoom_piano_roll(keyboard_input: &ButtonInput<KeyCode>, piano_roll: &mut PianoRollViewState) {
+    let modified = [
+        KeyCode::ControlLeft,
+        KeyCode::ControlRight,
+        KeyCode::AltLeft,
+        KeyCode::AltRight,
+    ]
+    .iter()
+    .any(|key| keyboard_input.pressed(*key));
+    if modified {
+        return;
+    }
+    if keyboard_input.just_pressed(KeyCode::Equal)
+        || keyboard_input.just_pressed(KeyCode::NumpadAdd)
+    {
+        piano_roll.zoom_x = (piano_roll.zoom_x * 1.25).min(16.0);
+    }
+    if keyboard_input.just_pressed(KeyCode::Minus)
+        || keyboard_input.just_pressed(KeyCode::NumpadSubtract)
+    {
+        piano_roll.zoom_x = (piano_roll.zoom_x / 1.25).max(1.0);
     }
 }
 
-pub(crate) fn load_midi_tracks(path: &PathBuf) -> Vec<MidiTrackInfo> {
-    let data = match std::fs::read(path) {
-        Ok(data) => data,
-        Err(err) => {
-            eprintln!("Failed to read MIDI file: {err}");
-            return Vec::new();
-        }
+fn reload_midi(keys: BoundKeys, loader: FileLoader) {
+    let BoundKeys {
+        keyboard_input,
+        keybindings,
+    } = keys;
+    let FileLoader {
+        midi_path,
+        mut midi_tracks,
+        mut tracks_focus,
+        ui_state,
+        mut status,
+        preferences,
+        audio_tx,
+        ..
+    } = loader;
+    let reload_key = keybindings.get_keycode("Reload").unwrap_or(KeyCode::KeyR);
+    let ctrl = keyboard_input.pressed(KeyCode::ControlLeft)
+        || keyboard_input.pressed(KeyCode::ControlRight);
+    if ui_state.page == UiPage::Settings || ctrl || !keyboard_input.just_pressed(reload_key) {
+        return;
+    }
+    let Some(path) = &midi_path.0 else {
+        return;
+    };
+    if !path.is_file() {
+        status.show(format!("Reload failed: {} not found", path.display()));
+        return;
+    }
+
+    let tracks = load_midi_tracks(path, preferences.note_pairing, preferences.preview_size);
+    if tracks.len() != midi_tracks.0.len() {
+        tracks_focus.reset(0);
+    }
+    midi_tracks.0 = tracks;
+
+    // Playing or paused, the audio thread keeps the position.
+    let _ = audio_tx.0.send(AudioCommand::Reload(path.clone()));
+    status.show("Reloaded");
+}
+
+/// The per-file views and settings a session reset puts back.
+#[derive(SystemParam)]
+struct SessionViews<'w> {
+    track_popup: ResMut<'w, TrackDetailsPopup>,
+    piano_roll: ResMut<'w, PianoRollViewState>,
+    loop_region: ResMut<'w, LoopRegion>,
+    transpose: ResMut<'w, TrackTranspose>,
+    display_transpose: ResMut<'w, DisplayTranspose>,
+    tempo_override: ResMut<'w, TempoOverride>,
+    mixer: ResMut<'w, ChannelMixer>,
+}
+
+fn reset_session(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    loader: FileLoader,
+    views: SessionViews,
+) {
+    let FileLoader {
+        mut midi_path,
+        mut soundfont_path,
+        mut midi_tracks,
+        mut tracks_focus,
+        mut playback_status,
+        mut ui_state,
+        mut status,
+        audio_tx,
+        ..
+    } = loader;
+    let SessionViews {
+        mut track_popup,
+        mut piano_roll,
+        mut loop_region,
+        mut transpose,
+        mut display_transpose,
+        mut tempo_override,
+        mut mixer,
+    } = views;
+    let ctrl = keyboard_input.pressed(KeyCode::ControlLeft)
+        || keyboard_input.pressed(KeyCode::ControlRight);
+    if !ctrl || !keyboard_input.just_pressed(KeyCode::KeyR) {
+        return;
+    }
+    let keep_soundfont = !(keyboard_input.pressed(KeyCode::ShiftLeft)
+        || keyboard_input.pressed(KeyCode::ShiftRight));
+
+    let _ = audio_tx.0.send(AudioCommand::Reset { keep_soundfont });
+    playback_status.state = PlaybackState::Stopped;
+    midi_tracks.0.clear();
+    midi_path.0 = None;
+    if !keep_soundfont {
+        soundfont_path.0 = None;
+    }
+    *tracks_focus = TracksFocus::default();
+    *track_popup = TrackDetailsPopup::default();
+    *piano_roll = PianoRollViewState::default();
+    *loop_region = LoopRegion::default();
+    *transpose = TrackTranspose::default();
+    *display_transpose = DisplayTranspose::default();
+    *tempo_override = TempoOverride::default();
+    mixer.clear_programs();
+    *ui_state = UiState::default();
+    status.show(if keep_soundfont {
+        "Reset (SoundFont kept)"
+    } else {
+        "Reset"
+    });
+}
+
+fn nudge_loop_region(
+    mut region: LoopRegion,
+    move_end: bool,
+    delta: i64,
+    last_bar: u32,
+) -> LoopRegion {
+    let nudge = |bar: u32| (bar as i64 + delta).clamp(1, last_bar.max(1) as i64) as u32;
+    if move_end {
+        region.end_bar = nudge(region.end_bar);
+        region.start_bar = region.start_bar.min(region.end_bar);
+    } else {
+        region.start_bar = nudge(region.start_bar);
+        region.end_bar = region.end_bar.max(region.start_bar);
+    }
+    region
+}
+
+/// The `count` whole bars before the bar holding `tick`: the phrase just
+/// heard. Near the top of the file the loop is cut short at bar 1, and from
+/// inside bar 1 it is bar 1 alone.
+fn last_bars_loop(bar_map: &BarMap, tick: u64, count: u32) -> LoopRegion {
+    let end_bar = bar_map.bar_at(tick).saturating_sub(1).max(1);
+    LoopRegion {
+        enabled: true,
+        start_bar: end_bar.saturating_sub(count.max(1) - 1).max(1),
+        end_bar,
+        points: (None, None),
+    }
+}
+
+// L toggles the loop and the brackets move its ends; Shift+L loops the last
+// few bars behind the playhead and resumes if paused. In the piano roll `;`
+// and `'` drop the A and B points at the playhead instead.
+fn adjust_loop_region(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    ui_state: Res<UiState>,
+    song: SongTiming,
+    preferences: Res<Preferences>,
+    transport: Transport,
+    mut loop_region: ResMut<LoopRegion>,
+    mut status: ResMut<StatusMessage>,
+) {
+    let SongTiming {
+        midi_tracks,
+        tempo_override,
+    } = song;
+    let Transport {
+        midi_path,
+        soundfont_path,
+        mut playback_status,
+        audio_state,
+        audio_tx,
+    } = transport;
+    if !matches!(
+        ui_state.page,
+        UiPage::Tracks | UiPage::PianoRoll | UiPage::Split
+    ) {
+        return;
+    }
+    let shift =
+        keyboard_input.pressed(KeyCode::ShiftLeft) || keyboard_input.pressed(KeyCode::ShiftRight);
+
+    let mut region = *loop_region;
+    if keyboard_input.just_pressed(KeyCode::KeyL) && shift {
+        let tick = audio_state.current_tick().unwrap_or(0);
+        region = last_bars_loop(
+            &file_bar_map(&midi_tracks.0),
+            tick,
+            preferences.quick_loop_bars,
+        );
+        *loop_region = region;
+        status.show(format!(
+            "Looping the last {}: bars {}-{}",
+            preferences.quick_loop_bars, region.start_bar, region.end_bar
+        ));
+        if playback_status.state == PlaybackState::Paused {
+            if let (Some(midi), Some(sf)) = (&midi_path.0, &soundfont_path.0) {
+                playback_status.state = PlaybackState::Playing;
+                let _ = audio_tx
+                    .0
+                    .send(AudioCommand::Play(midi.clone(), sf.clone()));
+            }
+        }
+        return;
+    }
+    if keyboard_input.just_pressed(KeyCode::KeyL) {
+        region.enabled = !region.enabled;
+    }
+    if ui_state.page.shows_piano_roll() {
+        let tick = audio_state.current_tick().unwrap_or(0);
+        if keyboard_input.just_pressed(KeyCode::Semicolon) {
+            region.points.0 = Some(tick);
+        }
+        if keyboard_input.just_pressed(KeyCode::Quote) {
+            region.points.1 = Some(tick);
+            region.enabled = true;
+        }
+    }
+    let delta = if keyboard_input.just_pressed(KeyCode::BracketLeft) {
+        -1
+    } else if keyboard_input.just_pressed(KeyCode::BracketRight) {
+        1
+    } else {
+        0
+    };
+    if delta != 0 {
+        let end_tick = midi_tracks
+            .0
+            .iter()
+            .map(|track| track.end_tick)
+            .max()
+            .unwrap_or(0);
+        let last_bar = file_bar_map(&midi_tracks.0).bar_at(end_tick.saturating_sub(1));
+        region = nudge_loop_region(region, shift, delta, last_bar);
+        region.enabled = true;
+        region.points = (None, None);
+    }
+    if region == *loop_region {
+        return;
+    }
+
+    *loop_region = region;
+    let tempo_map = file_tempo_map(&midi_tracks.0, tempo_override.0);
+    status.show(loop_status(&region, &tempo_map, preferences.time_display));
+}
+
+fn loop_status(region: &LoopRegion, tempo_map: &TempoMap, display: TimeDisplay) -> String {
+    let at = |tick| position_label(tick, tempo_map, display);
+    match region.points {
+        (Some(a), None) => format!("Loop A at {}; set B with '", at(a)),
+        (Some(a), Some(b)) if region.enabled && b > a => {
+            format!("Loop A-B: {}-{}", at(a), at(b))
+        }
+        (Some(_), Some(_)) if region.enabled => "Loop off: B is not after A".to_string(),
+        _ if region.enabled => format!("Loop bars {}-{}", region.start_bar, region.end_bar),
+        _ => "Loop off".to_string(),
+    }
+}
+
+fn shift_transpose(current: i8, delta: i8) -> i8 {
+    current.saturating_add(delta).clamp(
+        -TrackTranspose::MAX_SEMITONES,
+        TrackTranspose::MAX_SEMITONES,
+    )
+}
+
+// Comma/Period shift the focused track by an octave, with Shift by a
+// semitone.
+fn adjust_track_transpose(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    ui_state: Res<UiState>,
+    tracks_focus: Res<TracksFocus>,
+    midi_tracks: Res<MidiTracks>,
+    mut transpose: ResMut<TrackTranspose>,
+    mut display_transpose: ResMut<DisplayTranspose>,
+    mut status: ResMut<StatusMessage>,
+) {
+    if !matches!(
+        ui_state.page,
+        UiPage::Tracks | UiPage::PianoRoll | UiPage::Split
+    ) {
+        return;
+    }
+    let Some(track) = midi_tracks.0.get(tracks_focus.index) else {
+        return;
+    };
+    let shift =
+        keyboard_input.pressed(KeyCode::ShiftLeft) || keyboard_input.pressed(KeyCode::ShiftRight);
+    let alt = keyboard_input.pressed(KeyCode::AltLeft) || keyboard_input.pressed(KeyCode::AltRight);
+    let step = if shift || alt { 1 } else { 12 };
+    let delta = if keyboard_input.just_pressed(KeyCode::Comma) {
+        -step
+    } else if keyboard_input.just_pressed(KeyCode::Period) {
+        step
+    } else {
+        return;
+    };
+
+    // With Alt only the displayed note names move, a semitone at a time.
+    if alt {
+        let next = shift_transpose(display_transpose.get(track.index), delta);
+        if next == 0 {
+            let _removed = display_transpose.0.remove(&track.index);
+        } else {
+            let _prev = display_transpose.0.insert(track.index, next);
+        }
+        status.show(format!(
+            "Track {} note names: {:+} semitones",
+            track.index + 1,
+            next
+        ));
+        return;
+    }
+
+    let current = transpose.get(track.index);
+    let next = shift_transpose(current, delta);
+    if next == current {
+        return;
+    }
+    if next == 0 {
+        let _removed = transpose.0.remove(&track.index);
+    } else {
+        let _prev = transpose.0.insert(track.index, next);
+    }
+    status.show(format!("Track {} transpose: {:+}", track.index + 1, next));
+}
+
+// Intervals further than this fraction from the median are dropped as
+// missed or doubled taps.
+const TAP_OUTLIER_TOLERANCE: f64 = 0.3;
+
+// Tempo from tap times: the mean of the intervals close to the median.
+fn tap_tempo_bpm(taps: &[f64]) -> Option<f64> {
+    let mut intervals = taps
+        .windows(2)
+        .map(|pair| pair[1] - pair[0])
+        .filter(|interval| *interval > 0.0)
+        .collect::<Vec<_>>();
+    if intervals.is_empty() {
+        return None;
+    }
+    let mut sorted = intervals.clone();
+    sorted.sort_by(f64::total_cmp);
+    let median = sorted[sorted.len() / 2];
+    intervals.retain(|interval| (interval - median).abs() <= median * TAP_OUTLIER_TOLERANCE);
+    let mean = intervals.iter().sum::<f64>() / intervals.len() as f64;
+    Some(60.0 / mean)
+}
+
+fn bpm_to_us_per_beat(bpm: f64) -> u32 {
+    (60_000_000.0 / bpm).round() as u32
+}
+
+// B taps a tempo; Shift+B plays at the tapped tempo, or back at the file's
+// own tempo when nothing has been tapped.
+fn tap_tempo(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    ui_state: Res<UiState>,
+    time: Res<Time>,
+    mut taps: ResMut<TapTempo>,
+    mut tempo_override: ResMut<TempoOverride>,
+    mut status: ResMut<StatusMessage>,
+) {
+    if !matches!(
+        ui_state.page,
+        UiPage::Tracks | UiPage::PianoRoll | UiPage::Split
+    ) || !keyboard_input.just_pressed(KeyCode::KeyB)
+    {
+        return;
+    }
+    let shift =
+        keyboard_input.pressed(KeyCode::ShiftLeft) || keyboard_input.pressed(KeyCode::ShiftRight);
+    if shift {
+        match tap_tempo_bpm(&taps.taps) {
+            Some(bpm) => {
+                tempo_override.0 = Some(bpm_to_us_per_beat(bpm));
+                taps.taps.clear();
+                status.show(format!("Tempo fixed at {:.1} BPM", bpm));
+            }
+            None if tempo_override.0.is_some() => {
+                tempo_override.0 = None;
+                status.show("Tempo: from file");
+            }
+            None => status.show("Tap B in time first"),
+        }
+        return;
+    }
+
+    taps.tap(time.elapsed_secs_f64());
+    status.show(match tap_tempo_bpm(&taps.taps) {
+        Some(bpm) => format!("Tap tempo: {:.1} BPM (Shift+B to apply)", bpm),
+        None => "Tap tempo: keep tapping".to_string(),
+    });
+}
+
+// Note onsets per bar across all tracks; index 0 is bar 1.
+fn bar_onset_histogram(tracks: &[MidiTrackInfo]) -> Vec<u32> {
+    let bar_map = file_bar_map(tracks);
+    let mut histogram = Vec::new();
+    for span in tracks.iter().flat_map(|track| &track.note_spans) {
+        let bar = bar_map.bar_at(span.start) as usize;
+        if histogram.len() < bar {
+            histogram.resize(bar, 0);
+        }
+        histogram[bar - 1] += 1;
+    }
+    histogram
+}
+
+/// 1-based bar with the most onsets; ties go to the earliest bar.
+fn peak_bar(histogram: &[u32]) -> Option<u32> {
+    let (index, count) = histogram
+        .iter()
+        .enumerate()
+        .rev()
+        .max_by_key(|(_, count)| **count)?;
+    (*count > 0).then_some(index as u32 + 1)
+}
+
+fn jump_to_densest_bar(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    ui_state: Res<UiState>,
+    midi_tracks: Res<MidiTracks>,
+    audio_tx: Res<AudioSender>,
+    mut status: ResMut<StatusMessage>,
+    mut histogram: Local<Option<Vec<u32>>>,
+) {
+    if midi_tracks.is_changed() {
+        *histogram = None;
+    }
+    if !matches!(
+        ui_state.page,
+        UiPage::Tracks | UiPage::PianoRoll | UiPage::Split
+    ) || !keyboard_input.just_pressed(KeyCode::KeyD)
+    {
+        return;
+    }
+    let histogram = histogram.get_or_insert_with(|| bar_onset_histogram(&midi_tracks.0));
+    let Some(bar) = peak_bar(histogram) else {
+        status.show("No notes to jump to");
+        return;
+    };
+    let tick = file_bar_map(&midi_tracks.0).bar_start(bar);
+    let _ = audio_tx.0.send(AudioCommand::Seek(tick));
+    status.show(format!(
+        "Densest bar: {} ({} notes)",
+        bar,
+        histogram[bar as usize - 1]
+    ));
+}
+
+fn most_prominent_track(tracks: &[MidiTrackInfo]) -> Option<usize> {
+    tracks
+        .iter()
+        .enumerate()
+        .filter(|(_, track)| track.note_count > 0)
+        .rev()
+        .max_by_key(|(_, track)| track.note_count)
+        .map(|(index, _)| index)
+}
+
+// Keyed on the path, which only a load sets, so a reload or reparse of the
+// same file keeps the focus.
+fn focus_prominent_track(
+    midi_path: Res<MidiFilePath>,
+    midi_tracks: Res<MidiTracks>,
+    preferences: Res<Preferences>,
+    mut tracks_focus: ResMut<TracksFocus>,
+) {
+    if !midi_path.is_changed() {
+        return;
+    }
+    tracks_focus.home = 0;
+    if preferences.focus_prominent_track {
+        if let Some(index) = most_prominent_track(&midi_tracks.0) {
+            tracks_focus.home = index;
+            tracks_focus.reset(index);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum GotoTarget {
+    Bar(u32),
+    Seconds(f64),
+}
+
+const GOTO_MAX_LEN: usize = 16;
+
+// Accepts "32", "bar 12", "b12", "1:45", "1:45.5" and "1:02:03".
+fn parse_goto(input: &str) -> Option<GotoTarget> {
+    let text = input.trim().to_ascii_lowercase();
+    let bar = |digits: &str| {
+        digits
+            .trim()
+            .parse::<u32>()
+            .ok()
+            .filter(|bar| *bar > 0)
+            .map(GotoTarget::Bar)
+    };
+    if let Some(rest) = text.strip_prefix("bar").or_else(|| text.strip_prefix('b')) {
+        return bar(rest);
+    }
+    if !text.contains(':') {
+        return bar(&text);
+    }
+    let parts: Vec<&str> = text.split(':').collect();
+    if parts.len() > 3 {
+        return None;
+    }
+    let mut seconds = 0.0;
+    for (index, part) in parts.iter().enumerate() {
+        let value = part.trim().parse::<f64>().ok()?;
+        let last = index == parts.len() - 1;
+        if !value.is_finite()
+            || value < 0.0
+            || (index > 0 && value >= 60.0)
+            || (!last && value.fract() != 0.0)
+        {
+            return None;
+        }
+        seconds = seconds * 60.0 + value;
+    }
+    Some(GotoTarget::Seconds(seconds))
+}
+
+fn resolve_goto(
+    text: &str,
+    tracks: &[MidiTrackInfo],
+    tempo_override: Option<u32>,
+) -> Result<u64, &'static str> {
+    if text.trim().is_empty() {
+        return Err("type a bar or m:ss");
+    }
+    let tick = match parse_goto(text).ok_or("not a bar or time")? {
+        GotoTarget::Bar(bar) => file_bar_map(tracks).bar_start(bar),
+        GotoTarget::Seconds(seconds) => file_tempo_map(tracks, tempo_override).tick_at(seconds),
+    };
+    let end_tick = tracks.iter().map(|track| track.end_tick).max().unwrap_or(0);
+    if tick > end_tick {
+        return Err("past the end");
+    }
+    Ok(tick)
+}
+
+fn goto_preview(text: &str, tracks: &[MidiTrackInfo], tempo_override: Option<u32>) -> String {
+    match resolve_goto(text, tracks, tempo_override) {
+        Ok(tick) => format!("bar {}, tick {}", file_bar_map(tracks).bar_at(tick), tick),
+        Err(reason) => reason.to_string(),
+    }
+}
+
+/// The key presses the goto box reads, and swallows while it is open.
+#[derive(SystemParam)]
+struct GotoKeys<'w, 's> {
+    keyboard_input: ResMut<'w, ButtonInput<KeyCode>>,
+    typed: MessageReader<'w, 's, KeyboardInput>,
+}
+
+// Runs before the Update input systems and swallows key presses while the
+// box is open, so typing "bar" doesn't also trigger shortcuts.
+fn handle_goto_entry(
+    keys: GotoKeys,
+    ui_state: Res<UiState>,
+    midi_tracks: Res<MidiTracks>,
+    tempo_override: Res<TempoOverride>,
+    audio_tx: Res<AudioSender>,
+    mut entry: ResMut<GotoEntry>,
+    mut status: ResMut<StatusMessage>,
+) {
+    let GotoKeys {
+        mut keyboard_input,
+        mut typed,
+    } = keys;
+    if !entry.open {
+        typed.clear();
+        if matches!(
+            ui_state.page,
+            UiPage::Tracks | UiPage::PianoRoll | UiPage::Split
+        ) && keyboard_input.just_pressed(KeyCode::KeyJ)
+            && !midi_tracks.0.is_empty()
+        {
+            entry.open = true;
+            entry.text.clear();
+            entry.preview = goto_preview("", &midi_tracks.0, tempo_override.0);
+            keyboard_input.clear();
+        }
+        return;
+    }
+    if !matches!(
+        ui_state.page,
+        UiPage::Tracks | UiPage::PianoRoll | UiPage::Split
+    ) {
+        entry.open = false;
+        return;
+    }
+
+    for key in typed.read() {
+        if key.state != ButtonState::Pressed {
+            continue;
+        }
+        match key.key_code {
+            KeyCode::Escape => {
+                entry.open = false;
+                return;
+            }
+            KeyCode::Enter | KeyCode::NumpadEnter => {
+                if let Ok(tick) = resolve_goto(&entry.text, &midi_tracks.0, tempo_override.0) {
+                    let _ = audio_tx.0.send(AudioCommand::Seek(tick));
+                    status.show(format!(
+                        "Jumped to bar {}",
+                        file_bar_map(&midi_tracks.0).bar_at(tick)
+                    ));
+                    entry.open = false;
+                    keyboard_input.clear();
+                    return;
+                }
+            }
+            KeyCode::Backspace => {
+                let _removed = entry.text.pop();
+            }
+            _ => {
+                if let Some(text) = &key.text {
+                    if entry.text.len() + text.len() <= GOTO_MAX_LEN
+                        && text.chars().all(|c| !c.is_control())
+                    {
+                        entry.text.push_str(text);
+                    }
+                }
+            }
+        }
+        entry.preview = goto_preview(&entry.text, &midi_tracks.0, tempo_override.0);
+    }
+    keyboard_input.clear();
+}
+
+// M on the tracks page lists the file's markers; while the list is open it
+// takes the arrow keys, Enter and Esc from the rest of the page.
+fn handle_marker_list(
+    mut keyboard_input: ResMut<ButtonInput<KeyCode>>,
+    ui_state: Res<UiState>,
+    midi_tracks: Res<MidiTracks>,
+    entry: Res<GotoEntry>,
+    audio_tx: Res<AudioSender>,
+    mut markers: ResMut<MarkerList>,
+    mut status: ResMut<StatusMessage>,
+) {
+    if !markers.open {
+        let shift = keyboard_input.pressed(KeyCode::ShiftLeft)
+            || keyboard_input.pressed(KeyCode::ShiftRight);
+        if ui_state.page.shows_tracks()
+            && !entry.open
+            && !shift
+            && keyboard_input.just_pressed(KeyCode::KeyM)
+        {
+            if file_markers(&midi_tracks.0).is_empty() {
+                status.show("No markers in this file");
+            } else {
+                markers.open = true;
+                markers.selected = 0;
+            }
+            keyboard_input.clear();
+        }
+        return;
+    }
+    if !ui_state.page.shows_tracks() {
+        markers.open = false;
+        return;
+    }
+
+    let list = file_markers(&midi_tracks.0);
+    if keyboard_input.just_pressed(KeyCode::Escape) || keyboard_input.just_pressed(KeyCode::KeyM) {
+        markers.open = false;
+    } else if keyboard_input.just_pressed(KeyCode::ArrowUp) {
+        markers.selected = markers.selected.saturating_sub(1);
+    } else if keyboard_input.just_pressed(KeyCode::ArrowDown) {
+        markers.selected = (markers.selected + 1).min(list.len().saturating_sub(1));
+    } else if keyboard_input.just_pressed(KeyCode::Enter)
+        || keyboard_input.just_pressed(KeyCode::NumpadEnter)
+    {
+        if let Some((tick, name)) = list.get(markers.selected) {
+            let _ = audio_tx.0.send(AudioCommand::Seek(*tick));
+            status.show(format!("Jumped to marker {name}"));
+        }
+        markers.open = false;
+    }
+    keyboard_input.clear();
+}
+
+fn step_event(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    ui_state: Res<UiState>,
+    playback_status: Res<PlaybackStatus>,
+    audio_tx: Res<AudioSender>,
+) {
+    if !matches!(
+        ui_state.page,
+        UiPage::Tracks | UiPage::PianoRoll | UiPage::Split
+    ) || playback_status.state == PlaybackState::Playing
+    {
+        return;
+    }
+    if keyboard_input.just_pressed(KeyCode::KeyN) {
+        let _ = audio_tx.0.send(AudioCommand::StepEvent);
+    }
+}
+
+// Holding H plays only the focused track from the current position; letting
+// go pauses and restores the full arrangement. Space stays the toggle.
+fn hold_to_preview_track(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    ui_state: Res<UiState>,
+    preferences: Res<Preferences>,
+    tracks_focus: Res<TracksFocus>,
+    midi_tracks: Res<MidiTracks>,
+    transport: Transport,
+    mut previewing: Local<bool>,
+) {
+    let Transport {
+        midi_path,
+        soundfont_path,
+        mut playback_status,
+        audio_tx,
+        ..
+    } = transport;
+    if *previewing && !keyboard_input.pressed(KeyCode::KeyH) {
+        *previewing = false;
+        playback_status.state = PlaybackState::Paused;
+        let _ = audio_tx.0.send(AudioCommand::Pause);
+        let _ = audio_tx.0.send(AudioCommand::SetOnlyTrack(None));
+        return;
+    }
+    if !preferences.hold_to_preview
+        || !matches!(
+            ui_state.page,
+            UiPage::Tracks | UiPage::PianoRoll | UiPage::Split
+        )
+        || !keyboard_input.just_pressed(KeyCode::KeyH)
+        || playback_status.state == PlaybackState::Playing
+    {
+        return;
+    }
+    let (Some(midi), Some(sf)) = (&midi_path.0, &soundfont_path.0) else {
+        return;
+    };
+    let Some(track) = midi_tracks.0.get(tracks_focus.index) else {
+        return;
+    };
+    *previewing = true;
+    playback_status.state = PlaybackState::Playing;
+    let _ = audio_tx
+        .0
+        .send(AudioCommand::SetOnlyTrack(Some(track.index)));
+    let _ = audio_tx
+        .0
+        .send(AudioCommand::Play(midi.clone(), sf.clone()));
+}
+
+// Alt with +/- trims the loaded SoundFont's input gain a dB at a time.
+fn adjust_soundfont_gain(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    soundfont_path: Res<SoundFontPath>,
+    mut soundfont_gains: ResMut<SoundFontGains>,
+    mut status: ResMut<StatusMessage>,
+) {
+    let alt = keyboard_input.pressed(KeyCode::AltLeft) || keyboard_input.pressed(KeyCode::AltRight);
+    if !alt {
+        return;
+    }
+    let delta = if keyboard_input.just_pressed(KeyCode::Equal)
+        || keyboard_input.just_pressed(KeyCode::NumpadAdd)
+    {
+        SoundFontGains::STEP_DB
+    } else if keyboard_input.just_pressed(KeyCode::Minus)
+        || keyboard_input.just_pressed(KeyCode::NumpadSubtract)
+    {
+        -SoundFontGains::STEP_DB
+    } else {
+        return;
+    };
+    let Some(path) = &soundfont_path.0 else {
+        status.show("Select a SoundFont to set its gain");
+        return;
+    };
+    let gain_db = soundfont_gains.stepped(path, delta);
+    let _prev = soundfont_gains.0.insert(path.clone(), gain_db);
+    status.show(format!("SoundFont gain: {gain_db:+.1} dB"));
+}
+
+// Plain +/- turns the master volume up or down a step. Ctrl and Alt keep
+// them for UI scale and SoundFont gain, and the piano roll zooms with them.
+fn adjust_master_volume(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    ui_state: Res<UiState>,
+    mut master_volume: ResMut<MasterVolume>,
+    mut status: ResMut<StatusMessage>,
+) {
+    let modified = [
+        KeyCode::ControlLeft,
+        KeyCode::ControlRight,
+        KeyCode::AltLeft,
+        KeyCode::AltRight,
+    ]
+    .iter()
+    .any(|key| keyboard_input.pressed(*key));
+    if modified || ui_state.page.shows_piano_roll() {
+        return;
+    }
+    let delta = if keyboard_input.just_pressed(KeyCode::Equal)
+        || keyboard_input.just_pressed(KeyCode::NumpadAdd)
+    {
+        MasterVolume::STEP
+    } else if keyboard_input.just_pressed(KeyCode::Minus)
+        || keyboard_input.just_pressed(KeyCode::NumpadSubtract)
+    {
+        -MasterVolume::STEP
+    } else {
+        return;
+    };
+    master_volume.0 = MasterVolume::clamped(master_volume.0 + delta);
+    status.show(format!("Volume: {:.0}%", master_volume.0 * 100.0));
+}
+
+// U speeds playback up a step, Shift+U slows it down.
+fn adjust_playback_speed(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mut speed: ResMut<PlaybackSpeed>,
+    mut status: ResMut<StatusMessage>,
+) {
+    if !keyboard_input.just_pressed(KeyCode::KeyU) {
+        return;
+    }
+    let shift =
+        keyboard_input.pressed(KeyCode::ShiftLeft) || keyboard_input.pressed(KeyCode::ShiftRight);
+    speed.0 = speed.stepped(!shift);
+    status.show(format!("Speed: {:.0}%", speed.0 * 100.0));
+}
+
+// I (the "LoopSong" binding) repeats the whole song until pressed again.
+fn toggle_song_loop(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    keybindings: Res<Keybindings>,
+    mut preferences: ResMut<Preferences>,
+    mut status: ResMut<StatusMessage>,
+) {
+    let key = keybindings.get_keycode("LoopSong").unwrap_or(KeyCode::KeyI);
+    if !keyboard_input.just_pressed(key) {
+        return;
+    }
+    preferences.loop_song = !preferences.loop_song;
+    status.show(if preferences.loop_song {
+        "Looping the whole song"
+    } else {
+        "Song loop off"
+    });
+}
+
+// W widens the stereo image a step, Shift+W narrows it towards mono.
+fn adjust_stereo_width(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mut stereo_width: ResMut<StereoWidth>,
+    mut status: ResMut<StatusMessage>,
+) {
+    if !keyboard_input.just_pressed(KeyCode::KeyW) {
+        return;
+    }
+    let shift =
+        keyboard_input.pressed(KeyCode::ShiftLeft) || keyboard_input.pressed(KeyCode::ShiftRight);
+    let delta = if shift {
+        -StereoWidth::STEP
+    } else {
+        StereoWidth::STEP
+    };
+    stereo_width.0 = StereoWidth::clamped(stereo_width.0 + delta);
+    status.show(stereo_width_label(stereo_width.0));
+}
+
+// X cycles the click track between off, beats and the focused track's notes;
+// Shift+X turns the metronome on, clicking beats whether or not anything plays.
+fn cycle_metronome(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mut metronome: ResMut<Metronome>,
+    mut preferences: ResMut<Preferences>,
+    mut status: ResMut<StatusMessage>,
+) {
+    if !keyboard_input.just_pressed(KeyCode::KeyX) {
+        return;
+    }
+    if keyboard_input.pressed(KeyCode::ShiftLeft) || keyboard_input.pressed(KeyCode::ShiftRight) {
+        preferences.practice_click = !preferences.practice_click;
+        status.show(if preferences.practice_click {
+            "Metronome on"
+        } else {
+            "Metronome off"
+        });
+        return;
+    }
+    *metronome = metronome.next();
+    status.show(format!("Click: {}", metronome.label()));
+}
+
+// F5, F6 and F7 boost the low, mid and high EQ bands a step; with Shift
+// they cut instead.
+fn adjust_equalizer(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mut equalizer: ResMut<Equalizer>,
+    mut status: ResMut<StatusMessage>,
+) {
+    let band = if keyboard_input.just_pressed(KeyCode::F5) {
+        &mut equalizer.low_db
+    } else if keyboard_input.just_pressed(KeyCode::F6) {
+        &mut equalizer.mid_db
+    } else if keyboard_input.just_pressed(KeyCode::F7) {
+        &mut equalizer.high_db
+    } else {
+        return;
+    };
+    let shift =
+        keyboard_input.pressed(KeyCode::ShiftLeft) || keyboard_input.pressed(KeyCode::ShiftRight);
+    let delta = if shift {
+        -Equalizer::STEP_DB
+    } else {
+        Equalizer::STEP_DB
+    };
+    *band = Equalizer::clamped(*band + delta);
+    status.show(equalizer_label(&equalizer));
+}
+
+fn equalizer_label(equalizer: &Equalizer) -> String {
+    format!(
+        "EQ: low {:+.1} dB, mid {:+.1} dB, high {:+.1} dB",
+        equalizer.low_db, equalizer.mid_db, equalizer.high_db
+    )
+}
+
+fn stereo_width_label(width: f32) -> String {
+    if width <= 0.0 {
+        "Stereo width: Mono".to_string()
+    } else {
+        format!("Stereo width: {:.0}%", width * 100.0)
+    }
+}
+
+// Tab flips between the focused track and the one focused before it.
+fn swap_focused_track(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    ui_state: Res<UiState>,
+    midi_tracks: Res<MidiTracks>,
+    mut tracks_focus: ResMut<TracksFocus>,
+    mut status: ResMut<StatusMessage>,
+) {
+    if !matches!(
+        ui_state.page,
+        UiPage::Tracks | UiPage::PianoRoll | UiPage::Split
+    ) || !keyboard_input.just_pressed(KeyCode::Tab)
+    {
+        return;
+    }
+    if tracks_focus.swap_with_previous(midi_tracks.0.len()) {
+        status.show(format!("Track {}", tracks_focus.index + 1));
+    } else {
+        status.show("No previous track to switch to");
+    }
+}
+
+const SEEK_REPEAT_DELAY: f64 = 0.4;
+const SEEK_REPEAT_INTERVAL: f64 = 0.12;
+
+/// Tick one bar on from `tick`. Stepping back from inside a bar lands on
+/// that bar's start first, like a transport's previous-track button.
+fn bar_step_target(bar_map: &BarMap, tick: u64, forward: bool, end_tick: u64) -> u64 {
+    let bar = bar_map.bar_at(tick);
+    let target = if forward {
+        bar_map.bar_start(bar + 1)
+    } else if bar_map.bar_start(bar) < tick {
+        bar_map.bar_start(bar)
+    } else {
+        bar_map.bar_start(bar.saturating_sub(1))
+    };
+    target.min(end_tick)
+}
+
+// Left/Right on the tracks page seek a bar at a time, repeating while held.
+fn seek_by_bar(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    time: Res<Time>,
+    ui_state: Res<UiState>,
+    midi_tracks: Res<MidiTracks>,
+    audio_state: Res<AudioState>,
+    audio_tx: Res<AudioSender>,
+    mut next_repeat: Local<f64>,
+) {
+    if !ui_state.page.shows_tracks() {
+        return;
+    }
+    let forward = keyboard_input.pressed(KeyCode::ArrowRight);
+    if !forward && !keyboard_input.pressed(KeyCode::ArrowLeft) {
+        return;
+    }
+    let now = time.elapsed_secs_f64();
+    if keyboard_input.just_pressed(KeyCode::ArrowRight)
+        || keyboard_input.just_pressed(KeyCode::ArrowLeft)
+    {
+        *next_repeat = now + SEEK_REPEAT_DELAY;
+    } else if now >= *next_repeat {
+        *next_repeat = now + SEEK_REPEAT_INTERVAL;
+    } else {
+        return;
+    }
+    let Some(tick) = audio_state.current_tick() else {
+        return;
+    };
+    let end_tick = midi_tracks
+        .0
+        .iter()
+        .map(|track| track.end_tick)
+        .max()
+        .unwrap_or(0);
+    let target = bar_step_target(&file_bar_map(&midi_tracks.0), tick, forward, end_tick);
+    let _ = audio_tx.0.send(AudioCommand::Seek(target));
+}
+
+fn update_file_summary(
+    midi_tracks: Res<MidiTracks>,
+    preferences: Res<Preferences>,
+    mut summary: ResMut<FileSummary>,
+) {
+    if !midi_tracks.is_changed() && !preferences.is_changed() {
+        return;
+    }
+    let duration_seconds = file_duration_seconds(&midi_tracks.0, preferences.song_end);
+    if summary.duration_seconds != duration_seconds {
+        summary.duration_seconds = duration_seconds;
+    }
+}
+
+// Note spans are built at load time, so a new pairing policy needs the
+// file parsed again.
+fn reparse_on_note_pairing_change(
+    preferences: Res<Preferences>,
+    midi_path: Res<MidiFilePath>,
+    mut midi_tracks: ResMut<MidiTracks>,
+    mut applied: Local<NotePairing>,
+) {
+    if preferences.note_pairing == *applied {
+        return;
+    }
+    *applied = preferences.note_pairing;
+    if let Some(path) = &midi_path.0 {
+        midi_tracks.0 = load_midi_tracks(path, preferences.note_pairing, preferences.preview_size);
+    }
+}
+
+fn open_recent_file(keys: BoundKeys, mut recent: ResMut<RecentFiles>, mut loader: FileLoader) {
+    let select_key = keys
+        .keybindings
+        .get_keycode("Select")
+        .unwrap_or(KeyCode::Enter);
+    if loader.ui_state.page != UiPage::Splash || !keys.keyboard_input.just_pressed(select_key) {
+        return;
+    }
+    let UiSelection::Recent(index) = loader.ui_state.selection else {
+        return;
+    };
+    let Some(file) = recent.0.get(index).cloned() else {
+        return;
+    };
+    if !file.path.is_file() {
+        loader
+            .status
+            .show(format!("{} not found", file.path.display()));
+        recent.retain_existing(|path| path.is_file());
+        loader.ui_state.selection = if recent.0.is_empty() {
+            UiSelection::Play
+        } else {
+            UiSelection::Recent(index.min(recent.0.len() - 1))
+        };
+        return;
+    }
+    // Opening moves the entry to the front of the list.
+    loader.ui_state.selection = UiSelection::Recent(0);
+    match file.kind {
+        RecentKind::Midi => loader.load_midi(file.path),
+        RecentKind::SoundFont => loader.soundfont_path.0 = Some(file.path),
+    }
+}
+
+fn spawn_midi_dialog(commands: &mut Commands) {
+    let thread_pool = IoTaskPool::get();
+    let task = thread_pool.spawn(async move {
+        FileDialog::new()
+            .add_filter("MIDI", &["mid", "midi"])
+            .pick_file()
+    });
+    let _ = commands.spawn(FileDialogTask(task, UiSelection::MidiFile));
+}
+
+fn open_midi_shortcut(
+    mut commands: Commands,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    ui_state: Res<UiState>,
+    pending: Query<(), With<FileDialogTask>>,
+) {
+    if !matches!(
+        ui_state.page,
+        UiPage::Tracks | UiPage::PianoRoll | UiPage::Split
+    ) || !keyboard_input.just_pressed(KeyCode::KeyO)
+        || !pending.is_empty()
+    {
+        return;
+    }
+    spawn_midi_dialog(&mut commands);
+}
+
+// Shift+C picks a file to compare the loaded one against; C shows or hides
+// its notes on the piano roll.
+fn compare_file_shortcut(
+    mut commands: Commands,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    ui_state: Res<UiState>,
+    mut compare: ResMut<CompareFile>,
+    mut status: ResMut<StatusMessage>,
+    pending: Query<(), With<CompareDialogTask>>,
+) {
+    if !ui_state.page.shows_piano_roll() || !keyboard_input.just_pressed(KeyCode::KeyC) {
+        return;
+    }
+    let shift =
+        keyboard_input.pressed(KeyCode::ShiftLeft) || keyboard_input.pressed(KeyCode::ShiftRight);
+    if shift {
+        if pending.is_empty() {
+            let task = IoTaskPool::get().spawn(async move {
+                FileDialog::new()
+                    .add_filter("MIDI", &["mid", "midi"])
+                    .pick_file()
+            });
+            let _ = commands.spawn(CompareDialogTask(task));
+        }
+        return;
+    }
+    if compare.tracks.is_empty() {
+        status.show("No comparison file (Shift+C to load one)");
+        return;
+    }
+    compare.visible = !compare.visible;
+    status.show(if compare.visible {
+        format!("Comparing with {}", compare.file_name())
+    } else {
+        "Comparison: hidden".to_string()
+    });
+}
+
+fn poll_compare_dialog(
+    mut commands: Commands,
+    mut tasks: Query<(Entity, &mut CompareDialogTask)>,
+    preferences: Res<Preferences>,
+    mut compare: ResMut<CompareFile>,
+    mut status: ResMut<StatusMessage>,
+) {
+    for (entity, mut task) in &mut tasks {
+        let Some(result) = future::block_on(future::poll_once(&mut task.0)) else {
+            continue;
+        };
+        commands.entity(entity).despawn();
+        let Some(path) = result else {
+            continue;
+        };
+        let tracks = load_midi_tracks(&path, preferences.note_pairing, preferences.preview_size);
+        if tracks.is_empty() {
+            status.show("Comparison file has no tracks");
+            continue;
+        }
+        *compare = CompareFile {
+            path: Some(path),
+            tracks,
+            visible: true,
+        };
+        status.show(format!("Comparing with {}", compare.file_name()));
+    }
+}
+
+// On the splash SoundFont row, A picks a SoundFont to layer over the loaded
+// ones and Backspace drops the layers again. Unloading the SoundFont drops
+// them too.
+fn edit_soundfont_layers(
+    mut commands: Commands,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    ui_state: Res<UiState>,
+    soundfont_path: Res<SoundFontPath>,
+    mut layers: ResMut<SoundFontLayers>,
+    mut status: ResMut<StatusMessage>,
+    pending: Query<(), With<SoundFontLayerDialogTask>>,
+) {
+    if soundfont_path.is_changed() && soundfont_path.0.is_none() && !layers.0.is_empty() {
+        layers.0.clear();
+    }
+    if ui_state.page != UiPage::Splash || ui_state.selection != UiSelection::SoundFont {
+        return;
+    }
+    if keyboard_input.just_pressed(KeyCode::KeyA) && pending.is_empty() {
+        let task = IoTaskPool::get().spawn(async move {
+            FileDialog::new()
+                .add_filter("SoundFont", &["sf2"])
+                .pick_file()
+        });
+        let _ = commands.spawn(SoundFontLayerDialogTask(task));
+    } else if keyboard_input.just_pressed(KeyCode::Backspace) && !layers.0.is_empty() {
+        layers.0.clear();
+        status.show("Removed the layered SoundFonts");
+    }
+}
+
+fn poll_soundfont_layer_dialog(
+    mut commands: Commands,
+    mut tasks: Query<(Entity, &mut SoundFontLayerDialogTask)>,
+    mut layers: ResMut<SoundFontLayers>,
+    mut status: ResMut<StatusMessage>,
+) {
+    for (entity, mut task) in &mut tasks {
+        let Some(result) = future::block_on(future::poll_once(&mut task.0)) else {
+            continue;
+        };
+        commands.entity(entity).despawn();
+        let Some(path) = result else {
+            continue;
+        };
+        if layers.0.contains(&path) {
+            status.show(format!("{} is already layered", path.display()));
+            continue;
+        }
+        status.show(format!("Layered {}", path.display()));
+        layers.0.push(path);
+    }
+}
+
+fn dropped_file_kind(path: &Path) -> Option<RecentKind> {
+    let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+    match extension.as_str() {
+        "mid" | "midi" => Some(RecentKind::Midi),
+        "sf2" => Some(RecentKind::SoundFont),
+        _ => None,
+    }
+}
+
+fn open_dropped_files(mut drops: MessageReader<FileDragAndDrop>, mut loader: FileLoader) {
+    for drop in drops.read() {
+        let FileDragAndDrop::DroppedFile { path_buf, .. } = drop else {
+            continue;
+        };
+        match dropped_file_kind(path_buf) {
+            Some(RecentKind::Midi) => loader.load_midi(path_buf.clone()),
+            Some(RecentKind::SoundFont) => loader.soundfont_path.0 = Some(path_buf.clone()),
+            None => loader.status.show(format!(
+                "Not a MIDI file or SoundFont: {}",
+                path_buf.display()
+            )),
+        }
+    }
+}
+
+fn poll_file_dialogs(
+    mut commands: Commands,
+    mut tasks: Query<(Entity, &mut FileDialogTask)>,
+    mut loader: FileLoader,
+) {
+    for (entity, mut task) in &mut tasks {
+        if let Some(result) = future::block_on(future::poll_once(&mut task.0)) {
+            debug!("File dialog result received.");
+            if let Some(path) = result {
+                match task.1 {
+                    UiSelection::MidiFile => loader.load_midi(path),
+                    UiSelection::SoundFont => loader.soundfont_path.0 = Some(path),
+                    UiSelection::Play
+                    | UiSelection::Stop
+                    | UiSelection::Rewind
+                    | UiSelection::Recent(_) => {}
+                }
+            }
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+pub(crate) fn load_midi_tracks(
+    path: &PathBuf,
+    pairing: NotePairing,
+    preview_size: PreviewSize,
+) -> Vec<MidiTrackInfo> {
+    let data = match std::fs::read(path) {
+        Ok(data) => data,
+        Err(err) => {
+            error!("Failed to read MIDI file: {err}");
+            return Vec::new();
+        }
     };
 
     let smf = match Smf::parse(&data) {
         Ok(smf) => smf,
         Err(err) => {
-            eprintln!("Failed to parse MIDI file: {err:?}");
+            error!("Failed to parse MIDI file: {err:?}");
             return Vec::new();
         }
     };
 
-    parse_midi_tracks(&smf)
+    parse_midi_tracks(&smf, pairing, preview_size)
+}
+
+/// Every note across tracks as CSV, one row per `NoteSpan` ordered by track
+/// then start. Track and channel are 1-based, as shown in the UI.
+pub(crate) fn notes_csv(tracks: &[MidiTrackInfo]) -> String {
+    let tempo_map = file_tempo_map(tracks, None);
+    let mut csv = String::from(
+        "track,channel,pitch,note_name,start_tick,end_tick,start_seconds,end_seconds,velocity\n",
+    );
+    for track in tracks {
+        let mut spans = track.note_spans.iter().collect::<Vec<_>>();
+        spans.sort_by_key(|span| (span.start, span.pitch, span.end));
+        for span in spans {
+            csv.push_str(&format!(
+                "{},{},{},{},{},{},{:.6},{:.6},{}\n",
+                track.index + 1,
+                span.channel + 1,
+                span.pitch,
+                note_name(span.pitch),
+                span.start,
+                span.end,
+                tempo_map.seconds_at(span.start),
+                tempo_map.seconds_at(span.end),
+                span.velocity,
+            ));
+        }
+    }
+    csv
+}
+
+/// Writes `notes_csv` for `midi` to `out`, returning the number of notes.
+pub(crate) fn export_notes_csv(midi: &Path, out: &Path) -> Result<usize, String> {
+    let data = std::fs::read(midi)
+        .map_err(|err| format!("Could not read MIDI file {}: {err}", midi.display()))?;
+    let smf = Smf::parse(&data)
+        .map_err(|err| format!("Could not parse MIDI file {}: {err}", midi.display()))?;
+    let tracks = parse_midi_tracks(&smf, NotePairing::default(), PreviewSize::default());
+    std::fs::write(out, notes_csv(&tracks))
+        .map_err(|err| format!("Could not write {}: {err}", out.display()))?;
+    Ok(tracks.iter().map(|track| track.note_spans.len()).sum())
 }
 
 struct TrackParse {
@@ -399,26 +2445,70 @@ struct TrackParse {
     event_count: usize,
     end_tick: u64,
     spans: Vec<NoteSpan>,
-    note_end_tick: u64,
     channels: Vec<u8>,
     programs: Vec<(u8, u8)>,
     banks: Vec<(u8, u8, u8)>,
-    tempo_changes: usize,
     time_signature: Option<(u8, u8)>,
+    time_signature_events: Vec<(u64, u8, u8)>,
     key_signature: Option<(i8, bool)>,
+    sustain_events: Vec<(u64, u8, bool)>,
+    pan_events: Vec<(u64, u8, u8)>,
+    lyric_events: Vec<(u64, String)>,
+    midi_standard: Option<MidiStandard>,
+    instrument_name: Option<String>,
+    midi_port: Option<u8>,
+    channel_prefix: Option<u8>,
+    copyright: Option<String>,
+    cue_points: Vec<(u64, String)>,
+    markers: Vec<(u64, String)>,
+}
+
+/// Recognizes the reset messages that switch a synth into GM, GM2, GS or XG
+/// mode. `data` is the SysEx payload; the framing `F0`/`F7` bytes are optional.
+fn classify_sysex(data: &[u8]) -> Option<MidiStandard> {
+    let data = data.strip_prefix(&[0xF0]).unwrap_or(data);
+    let data = data.strip_suffix(&[0xF7]).unwrap_or(data);
+    match data {
+        [0x7E, _, 0x09, 0x01] => Some(MidiStandard::Gm),
+        [0x7E, _, 0x09, 0x03] => Some(MidiStandard::Gm2),
+        [0x41, _, 0x42, 0x12, 0x40, 0x00, 0x7F, 0x00, _] => Some(MidiStandard::Gs),
+        [0x41, _, 0x42, 0x12, 0x00, 0x00, 0x7F, _, _] => Some(MidiStandard::Gs),
+        [0x43, device, 0x4C, 0x00, 0x00, 0x7E, 0x00] if device & 0xF0 == 0x10 => {
+            Some(MidiStandard::Xg)
+        }
+        _ => None,
+    }
+}
+
+fn take_note_start<T>(starts: &mut Vec<T>, pairing: NotePairing) -> Option<T> {
+    match pairing {
+        NotePairing::Fifo if !starts.is_empty() => Some(starts.remove(0)),
+        NotePairing::Fifo => None,
+        NotePairing::Lifo => starts.pop(),
+    }
 }
 
-fn parse_track(track: &[TrackEvent<'_>]) -> TrackParse {
+fn parse_track(track: &[TrackEvent<'_>], pairing: NotePairing) -> TrackParse {
     let mut current_tick = 0u64;
     let mut last_tick = 0u64;
     let mut spans = Vec::new();
-    let mut active_notes: Vec<Vec<u64>> = vec![Vec::new(); 128];
+    let mut active_notes: Vec<Vec<(u64, u8, u8)>> = vec![Vec::new(); 128];
     let mut channels = std::collections::BTreeSet::new();
     let mut programs = std::collections::BTreeMap::new();
     let mut banks = std::collections::BTreeMap::<u8, (Option<u8>, Option<u8>)>::new();
-    let mut tempo_changes = 0usize;
     let mut time_signature = None;
+    let mut time_signature_events = Vec::new();
     let mut key_signature = None;
+    let mut sustain_events = Vec::new();
+    let mut pan_events = Vec::new();
+    let mut lyric_events = Vec::new();
+    let mut midi_standard = None;
+    let mut instrument_name = None;
+    let mut midi_port = None;
+    let mut channel_prefix = None;
+    let mut copyright = None;
+    let mut cue_points = Vec::new();
+    let mut markers = Vec::new();
     let name = track.iter().find_map(|event| match event.kind {
         TrackEventKind::Meta(MetaMessage::TrackName(name)) => {
             Some(String::from_utf8_lossy(name).to_string())
@@ -453,40 +2543,58 @@ fn parse_track(track: &[TrackEvent<'_>]) -> TrackParse {
         last_tick = current_tick;
         match event.kind {
             TrackEventKind::Midi { channel, message } => {
-                let channel = channel.as_int() as u8;
+                let channel = channel.as_int();
                 let _inserted = channels.insert(channel);
                 match message {
                     midly::MidiMessage::NoteOn { key, vel } => {
                         if vel.as_int() > 0 {
-                            active_notes[key.as_int() as usize].push(current_tick);
-                        } else if let Some(start) = active_notes[key.as_int() as usize].pop() {
+                            active_notes[key.as_int() as usize].push((
+                                current_tick,
+                                channel,
+                                vel.as_int(),
+                            ));
+                        } else if let Some((start, channel, velocity)) =
+                            take_note_start(&mut active_notes[key.as_int() as usize], pairing)
+                        {
                             spans.push(NoteSpan {
-                                pitch: key.as_int() as u8,
+                                channel,
+                                pitch: key.as_int(),
                                 start,
                                 end: current_tick,
+                                velocity,
                             });
                         }
                     }
                     midly::MidiMessage::NoteOff { key, vel: _ } => {
-                        if let Some(start) = active_notes[key.as_int() as usize].pop() {
+                        if let Some((start, channel, velocity)) =
+                            take_note_start(&mut active_notes[key.as_int() as usize], pairing)
+                        {
                             spans.push(NoteSpan {
-                                pitch: key.as_int() as u8,
+                                channel,
+                                pitch: key.as_int(),
                                 start,
                                 end: current_tick,
+                                velocity,
                             });
                         }
                     }
                     midly::MidiMessage::ProgramChange { program } => {
-                        let _prev = programs.insert(channel, program.as_int() as u8);
+                        let _prev = programs.insert(channel, program.as_int());
                     }
                     midly::MidiMessage::Controller { controller, value } => {
-                        let ctrl = controller.as_int() as u8;
+                        let ctrl = controller.as_int();
+                        if ctrl == SUSTAIN_CONTROLLER {
+                            sustain_events.push((current_tick, channel, value.as_int() >= 64));
+                        }
+                        if ctrl == PAN_CONTROLLER {
+                            pan_events.push((current_tick, channel, value.as_int()));
+                        }
                         if ctrl == 0 || ctrl == 32 {
                             let entry = banks.entry(channel).or_insert((None, None));
                             if ctrl == 0 {
-                                entry.0 = Some(value.as_int() as u8);
+                                entry.0 = Some(value.as_int());
                             } else {
-                                entry.1 = Some(value.as_int() as u8);
+                                entry.1 = Some(value.as_int());
                             }
                         }
                     }
@@ -495,50 +2603,79 @@ fn parse_track(track: &[TrackEvent<'_>]) -> TrackParse {
                     | midly::MidiMessage::PitchBend { .. } => {}
                 }
             }
-            TrackEventKind::Meta(MetaMessage::Tempo(_)) => {
-                tempo_changes += 1;
-            }
             TrackEventKind::Meta(MetaMessage::TimeSignature(num, denom, _, _)) => {
-                time_signature = Some((num, 2u8.pow(denom as u32)));
+                let denom = 2u8.saturating_pow(denom as u32);
+                time_signature = Some((num, denom));
+                time_signature_events.push((current_tick, num, denom));
             }
             TrackEventKind::Meta(MetaMessage::KeySignature(sharps, is_minor)) => {
                 key_signature = Some((sharps, is_minor));
             }
+            TrackEventKind::Meta(MetaMessage::Lyric(text)) => {
+                lyric_events.push((current_tick, String::from_utf8_lossy(text).to_string()));
+            }
+            TrackEventKind::Meta(MetaMessage::InstrumentName(text)) => {
+                if instrument_name.is_none() {
+                    instrument_name = Some(String::from_utf8_lossy(text).trim().to_string());
+                }
+            }
+            TrackEventKind::Meta(MetaMessage::Copyright(text)) => {
+                if copyright.is_none() {
+                    copyright = Some(String::from_utf8_lossy(text).trim().to_string());
+                }
+            }
+            TrackEventKind::Meta(MetaMessage::CuePoint(text)) => {
+                cue_points.push((current_tick, String::from_utf8_lossy(text).to_string()));
+            }
+            TrackEventKind::Meta(MetaMessage::Marker(text)) => {
+                let text = String::from_utf8_lossy(text).trim().to_string();
+                if !text.is_empty() {
+                    markers.push((current_tick, text));
+                }
+            }
+            TrackEventKind::Meta(MetaMessage::MidiPort(port)) => {
+                if midi_port.is_none() {
+                    midi_port = Some(port.as_int());
+                }
+            }
+            TrackEventKind::Meta(MetaMessage::MidiChannel(channel)) => {
+                if channel_prefix.is_none() {
+                    channel_prefix = Some(channel.as_int());
+                }
+            }
+            TrackEventKind::SysEx(data) => {
+                if midi_standard.is_none() {
+                    midi_standard = classify_sysex(data);
+                }
+            }
             TrackEventKind::Meta(
                 MetaMessage::TrackName(_)
                 | MetaMessage::TrackNumber(_)
                 | MetaMessage::Text(_)
-                | MetaMessage::Copyright(_)
-                | MetaMessage::InstrumentName(_)
-                | MetaMessage::Lyric(_)
-                | MetaMessage::Marker(_)
-                | MetaMessage::CuePoint(_)
                 | MetaMessage::ProgramName(_)
                 | MetaMessage::DeviceName(_)
-                | MetaMessage::MidiChannel(_)
-                | MetaMessage::MidiPort(_)
                 | MetaMessage::EndOfTrack
+                | MetaMessage::Tempo(_)
                 | MetaMessage::SmpteOffset(_)
                 | MetaMessage::SequencerSpecific(_)
                 | MetaMessage::Unknown(_, _),
             )
-            | TrackEventKind::SysEx(_)
             | TrackEventKind::Escape(_) => {}
         }
     }
 
     for (pitch, starts) in active_notes.iter_mut().enumerate() {
-        for start in starts.drain(..) {
+        for (start, channel, velocity) in starts.drain(..) {
             spans.push(NoteSpan {
+                channel,
                 pitch: pitch as u8,
                 start,
                 end: last_tick,
+                velocity,
             });
         }
     }
 
-    let note_end_tick = spans.iter().map(|span| span.end).max().unwrap_or(0);
-
     let programs = programs.into_iter().collect();
     let banks = banks
         .into_iter()
@@ -553,33 +2690,37 @@ fn parse_track(track: &[TrackEvent<'_>]) -> TrackParse {
         event_count: track.len(),
         end_tick: last_tick,
         spans,
-        note_end_tick,
         channels: channels.into_iter().collect(),
         programs,
         banks,
-        tempo_changes,
         time_signature,
+        time_signature_events,
         key_signature,
+        sustain_events,
+        pan_events,
+        lyric_events,
+        midi_standard,
+        instrument_name: instrument_name.filter(|name| !name.is_empty()),
+        midi_port,
+        channel_prefix,
+        copyright: copyright.filter(|text| !text.is_empty()),
+        cue_points,
+        markers,
     }
 }
 
-fn parse_midi_tracks(smf: &Smf) -> Vec<MidiTrackInfo> {
-    let ticks_per_beat = match smf.header.timing {
-        midly::Timing::Metrical(ticks) => ticks.as_int() as u32,
-        midly::Timing::Timecode(_, _) => 480,
-    }
-    .max(1);
+pub(crate) fn parse_midi_tracks(
+    smf: &Smf,
+    pairing: NotePairing,
+    preview_size: PreviewSize,
+) -> Vec<MidiTrackInfo> {
+    let timing = Arc::new(file_timing(smf));
+    let ticks_per_beat = timing.ticks_per_beat;
     let mut track_spans: Vec<Vec<NoteSpan>> = Vec::new();
     let mut track_info: Vec<TrackInfo> = Vec::new();
-    let mut max_tick = 0u64;
-    let mut max_note_tick = 0u64;
 
     for (index, track) in smf.tracks.iter().enumerate() {
-        let parsed = parse_track(track);
-        if parsed.note_end_tick > 0 {
-            max_note_tick = max_note_tick.max(parsed.note_end_tick);
-        }
-        max_tick = max_tick.max(parsed.end_tick);
+        let parsed = parse_track(track, pairing);
         track_spans.push(parsed.spans);
         track_info.push(TrackInfo {
             index,
@@ -589,172 +2730,906 @@ fn parse_midi_tracks(smf: &Smf) -> Vec<MidiTrackInfo> {
             channels: parsed.channels,
             programs: parsed.programs,
             banks: parsed.banks,
-            tempo_changes: parsed.tempo_changes,
             time_signature: parsed.time_signature,
+            time_signature_events: parsed.time_signature_events,
             key_signature: parsed.key_signature,
+            sustain_events: parsed.sustain_events,
+            pan_events: parsed.pan_events,
+            lyric_events: parsed.lyric_events,
+            midi_standard: parsed.midi_standard,
+            instrument_name: parsed.instrument_name,
+            midi_port: parsed.midi_port,
+            channel_prefix: parsed.channel_prefix,
+            copyright: parsed.copyright,
+            cue_points: parsed.cue_points,
+            markers: parsed.markers,
         });
     }
 
-    let preview_height = 64usize;
-    let max_preview_width = 240usize;
-    let ruler_max_tick = if max_note_tick > 0 {
-        max_note_tick
-    } else {
-        max_tick
-    };
+    let preview_height = preview_size.height;
+    let max_preview_width = preview_size.max_width;
+    let ruler_max_tick = timing.ruler_max_tick();
     let ticks_per_column = ticks_per_column_for_width(ruler_max_tick, max_preview_width);
     let preview_width = (ruler_max_tick / ticks_per_column) as usize + 1;
     track_info
         .into_iter()
-        .zip(track_spans.into_iter())
+        .zip(track_spans)
         .map(|(info, spans)| {
             let (min_pitch, max_pitch) = note_range(&spans);
             let note_count = spans.len();
-            let preview_cells = build_track_preview(
-                preview_width,
-                preview_height,
-                ticks_per_column,
-                ruler_max_tick,
-                info.end_tick,
-                min_pitch,
-                max_pitch,
-                &spans,
-            );
+            let articulation = articulation_counts(&spans);
+            let rhythm = rhythm_summary(&spans, ticks_per_beat);
+            let preview = |mode| {
+                build_track_preview(
+                    preview_width,
+                    preview_height,
+                    ticks_per_column,
+                    min_pitch,
+                    max_pitch,
+                    &spans,
+                    mode,
+                )
+            };
+            let preview_cells = preview(PreviewMode::Sustain);
+            let onset_preview_cells = preview(PreviewMode::Onset);
             MidiTrackInfo {
                 index: info.index,
                 name: info.name,
                 event_count: info.event_count,
                 end_tick: info.end_tick,
                 ticks_per_beat,
+                timing: Arc::clone(&timing),
                 note_count,
                 min_pitch,
                 max_pitch,
                 channels: info.channels,
                 programs: info.programs,
                 banks: info.banks,
-                tempo_changes: info.tempo_changes,
                 time_signature: info.time_signature,
+                time_signature_events: info.time_signature_events,
                 key_signature: info.key_signature,
+                sustain_events: info.sustain_events,
+                pan_events: info.pan_events,
+                lyric_events: info.lyric_events,
+                midi_standard: info.midi_standard,
+                instrument_name: info.instrument_name,
+                midi_port: info.midi_port,
+                channel_prefix: info.channel_prefix,
+                copyright: info.copyright,
+                cue_points: info.cue_points,
+                markers: info.markers,
+                articulation,
+                rhythm,
                 note_spans: spans,
                 preview_width,
                 preview_height,
                 preview_cells,
+                onset_preview_cells,
             }
         })
         .collect()
 }
 
-struct TrackInfo {
-    index: usize,
-    name: Option<String>,
-    event_count: usize,
-    end_tick: u64,
-    channels: Vec<u8>,
-    programs: Vec<(u8, u8)>,
-    banks: Vec<(u8, u8, u8)>,
-    tempo_changes: usize,
-    time_signature: Option<(u8, u8)>,
-    key_signature: Option<(i8, bool)>,
-}
+struct TrackInfo {
+    index: usize,
+    name: Option<String>,
+    event_count: usize,
+    end_tick: u64,
+    channels: Vec<u8>,
+    programs: Vec<(u8, u8)>,
+    banks: Vec<(u8, u8, u8)>,
+    time_signature: Option<(u8, u8)>,
+    time_signature_events: Vec<(u64, u8, u8)>,
+    key_signature: Option<(i8, bool)>,
+    sustain_events: Vec<(u64, u8, bool)>,
+    pan_events: Vec<(u64, u8, u8)>,
+    lyric_events: Vec<(u64, String)>,
+    midi_standard: Option<MidiStandard>,
+    instrument_name: Option<String>,
+    midi_port: Option<u8>,
+    channel_prefix: Option<u8>,
+    copyright: Option<String>,
+    cue_points: Vec<(u64, String)>,
+    markers: Vec<(u64, String)>,
+}
+
+const SUSTAIN_CONTROLLER: u8 = 64;
+const PAN_CONTROLLER: u8 = 10;
+const STACCATO_MAX_RATIO: f64 = 0.5;
+const LEGATO_MIN_RATIO: f64 = 0.95;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Articulation {
+    Staccato,
+    Normal,
+    Legato,
+}
+
+fn classify_articulation(duration: u64, inter_onset: u64) -> Articulation {
+    let ratio = duration as f64 / inter_onset.max(1) as f64;
+    if ratio < STACCATO_MAX_RATIO {
+        Articulation::Staccato
+    } else if ratio >= LEGATO_MIN_RATIO {
+        Articulation::Legato
+    } else {
+        Articulation::Normal
+    }
+}
+
+// Notes are compared against the next onset on the same channel and pitch;
+// the last note of each pitch has nothing to compare against and is skipped.
+fn articulation_counts(spans: &[NoteSpan]) -> ArticulationCounts {
+    let mut sorted: Vec<&NoteSpan> = spans.iter().collect();
+    sorted.sort_by_key(|span| (span.channel, span.pitch, span.start));
+
+    let mut counts = ArticulationCounts::default();
+    for pair in sorted.windows(2) {
+        let (note, next) = (pair[0], pair[1]);
+        if note.channel != next.channel || note.pitch != next.pitch || next.start <= note.start {
+            continue;
+        }
+        let duration = note.end.saturating_sub(note.start);
+        match classify_articulation(duration, next.start - note.start) {
+            Articulation::Staccato => counts.staccato += 1,
+            Articulation::Normal => counts.normal += 1,
+            Articulation::Legato => counts.legato += 1,
+        }
+    }
+    counts
+}
+
+// Relative distance from a standard length still counted as that length.
+const NOTE_LENGTH_TOLERANCE: f64 = 0.15;
+
+fn quantize_note_length(duration: u64, ticks_per_beat: u32) -> Option<usize> {
+    let beats = duration as f64 / ticks_per_beat.max(1) as f64;
+    RhythmSummary::LENGTH_BEATS
+        .iter()
+        .position(|length| (beats - length).abs() <= length * NOTE_LENGTH_TOLERANCE)
+}
+
+fn rhythm_summary(spans: &[NoteSpan], ticks_per_beat: u32) -> RhythmSummary {
+    let mut summary = RhythmSummary::default();
+    for span in spans {
+        match quantize_note_length(span.end.saturating_sub(span.start), ticks_per_beat) {
+            Some(index) => summary.lengths[index] += 1,
+            None => summary.irregular += 1,
+        }
+    }
+
+    let mut sorted: Vec<(u64, u64)> = spans.iter().map(|span| (span.start, span.end)).collect();
+    sorted.sort_unstable();
+    let mut covered_until = 0u64;
+    for (start, end) in sorted {
+        summary.rest_ticks += start.saturating_sub(covered_until);
+        covered_until = covered_until.max(end);
+    }
+    summary
+}
+
+fn note_range(spans: &[NoteSpan]) -> (u8, u8) {
+    let mut min_pitch = 127u8;
+    let mut max_pitch = 0u8;
+    for span in spans {
+        min_pitch = min_pitch.min(span.pitch);
+        max_pitch = max_pitch.max(span.pitch);
+    }
+    if spans.is_empty() {
+        (60, 60)
+    } else {
+        (min_pitch, max_pitch)
+    }
+}
+
+fn ticks_per_column_for_width(max_tick: u64, max_width: usize) -> u64 {
+    if max_width == 0 {
+        return 1;
+    }
+    let denom = max_width.saturating_sub(1).max(1) as u64;
+    let mut ticks_per_column = max_tick.div_ceil(denom);
+    if ticks_per_column == 0 {
+        ticks_per_column = 1;
+    }
+    ticks_per_column
+}
+
+fn build_track_preview(
+    width: usize,
+    height: usize,
+    ticks_per_column: u64,
+    min_pitch: u8,
+    max_pitch: u8,
+    spans: &[NoteSpan],
+    mode: PreviewMode,
+) -> Vec<u16> {
+    if width == 0 || height == 0 {
+        return Vec::new();
+    }
+
+    let mut cells = vec![0u16; width * height];
+
+    for span in spans {
+        let pitch = span.pitch;
+        let start = span.start;
+        // A span that ends before it starts is drawn as a bare onset.
+        let end = span.end.max(start);
+        let start_col = (start / ticks_per_column) as usize;
+        let end_col = (end / ticks_per_column) as usize;
+        if start_col >= width {
+            continue;
+        }
+        let row = pitch_to_row_range(height, min_pitch, max_pitch, pitch);
+        let row_offset = row * width;
+        let end_col = match mode {
+            PreviewMode::Sustain => end_col.min(width.saturating_sub(1)),
+            PreviewMode::Onset => start_col,
+        };
+        for col in start_col..=end_col {
+            let idx = row_offset + col;
+            if let Some(cell) = cells.get_mut(idx) {
+                *cell = cell.saturating_add(1);
+            }
+        }
+    }
+
+    cells
+}
+
+fn pitch_to_row_range(height: usize, min_pitch: u8, max_pitch: u8, pitch: u8) -> usize {
+    if height == 0 {
+        return 0;
+    }
+    let padding = ((height as f32) * 0.08).round() as usize;
+    let padding = padding.min(height.saturating_sub(1) / 2);
+    let usable_height = height.saturating_sub(padding * 2).max(1);
+    if min_pitch >= max_pitch {
+        return padding + ((usable_height - 1) as f32 / 2.0).round() as usize;
+    }
+    let span = (max_pitch - min_pitch) as f32;
+    let t = (max_pitch.saturating_sub(pitch) as f32) / span;
+    let row = t * (usable_height as f32 - 1.0);
+    (padding as f32 + row)
+        .round()
+        .clamp(0.0, (height - 1) as f32) as usize
+}
+
+#[cfg(test)]
+// The parser tests build their tracks an event at a time.
+#[allow(clippy::vec_init_then_push)]
+mod tests {
+    use super::{
+        articulation_counts, autoplay_on_load, bar_step_target, build_track_preview,
+        classify_articulation, classify_sysex, cycle_setting, dropped_file_kind, last_bars_loop,
+        loop_status, most_prominent_track, note_range, notes_csv, nudge_loop_region, parse_goto,
+        parse_midi_tracks, parse_track, peak_bar, pitch_to_row_range, play_hint,
+        quantize_note_length, resolve_goto, rhythm_summary, shift_transpose, splash_move,
+        str_to_keycode, tap_tempo_bpm, ticks_per_column_for_width, Articulation, GotoTarget,
+        SplashMove, ViewHistory,
+    };
+    use crate::audio::{AudioCommand, BarMap};
+    use crate::state::MidiStandard;
+    use crate::state::MidiTrackInfo;
+    use crate::state::NoteSpan;
+    use crate::state::{
+        audible_channels, listed_track_indices, ArticulationCounts, ChannelMixer, Interpolation,
+        LoopRegion, LoopSeam, MidiFilePath, MixerControl, NotePairing, PianoRollViewState,
+        Preferences, PreviewMode, PreviewSize, RecentKind, RhythmSummary, SettingsItem, SongEnd,
+        SoundFontPath, TapTempo, TimeDisplay, UiPage, UiSelection, UiState,
+    };
+    use crate::tempo::{file_duration_seconds, TempoMap};
+    use midly::{Format, Smf, Timing, TrackEvent, TrackEventKind};
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn soundfont_gain_steps_clamp_to_the_range() {
+        let mut gains = crate::state::SoundFontGains::default();
+        let path = PathBuf::from("hot.sf2");
+        assert_eq!(gains.stepped(&path, -1.0), -1.0);
+        let _prev = gains.0.insert(path.clone(), 17.5);
+        assert_eq!(gains.stepped(&path, 1.0), 18.0);
+        let _prev = gains.0.insert(path.clone(), -18.0);
+        assert_eq!(gains.stepped(&path, -1.0), -18.0);
+        assert_eq!(gains.stepped(&path, 1.0), -17.0);
+    }
+
+    #[test]
+    fn tracks_focus_swaps_with_the_previous_track() {
+        let mut focus = crate::state::TracksFocus::default();
+        assert!(!focus.swap_with_previous(4));
+        assert_eq!(focus.index, 0);
+
+        focus.focus(2);
+        focus.focus(2);
+        assert_eq!(focus.previous, Some(0));
+        assert!(focus.swap_with_previous(4));
+        assert_eq!((focus.index, focus.previous), (0, Some(2)));
+        assert!(focus.swap_with_previous(4));
+        assert_eq!((focus.index, focus.previous), (2, Some(0)));
+
+        focus.focus(3);
+        assert!(!focus.swap_with_previous(2));
+        focus.reset(1);
+        assert!(!focus.swap_with_previous(4));
+    }
+
+    #[test]
+    fn tap_tempo_averages_intervals_and_drops_outliers() {
+        assert_eq!(tap_tempo_bpm(&[]), None);
+        assert_eq!(tap_tempo_bpm(&[1.0]), None);
+        assert_eq!(tap_tempo_bpm(&[0.0, 0.5, 1.0, 1.5]), Some(120.0));
+        // A missed beat (1.0 s gap) and a stray double tap are ignored.
+        let bpm = tap_tempo_bpm(&[0.0, 0.5, 1.0, 2.0, 2.5, 3.0, 3.05]).unwrap();
+        assert!((bpm - 120.0).abs() < 1e-9);
+        let bpm = tap_tempo_bpm(&[0.0, 0.62, 1.2, 1.8]).unwrap();
+        assert!((bpm - 100.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn tap_tempo_restarts_after_a_pause_and_keeps_recent_taps() {
+        let mut taps = TapTempo::default();
+        for i in 0..12 {
+            taps.tap(i as f64 * 0.5);
+        }
+        assert_eq!(taps.taps.len(), TapTempo::MAX_TAPS);
+        assert_eq!(taps.taps[0], 2.0);
+        taps.tap(10.0);
+        assert_eq!(taps.taps, vec![10.0]);
+    }
+
+    #[test]
+    fn splash_move_steps_rows_and_wraps_when_enabled() {
+        use SplashMove::{Down, Left, Right, Up};
+        use UiSelection::{MidiFile, Play, Recent, Rewind, SoundFont, Stop};
+        assert_eq!(splash_move(MidiFile, Up, 2, false), MidiFile);
+        assert_eq!(splash_move(MidiFile, Up, 2, true), Recent(1));
+        assert_eq!(splash_move(MidiFile, Up, 0, true), Play);
+        assert_eq!(splash_move(Recent(1), Down, 2, false), Recent(1));
+        assert_eq!(splash_move(Recent(1), Down, 2, true), MidiFile);
+        assert_eq!(splash_move(Stop, Down, 0, false), Stop);
+        assert_eq!(splash_move(Rewind, Down, 0, true), MidiFile);
+        assert_eq!(splash_move(SoundFont, Down, 0, false), Play);
+        assert_eq!(splash_move(Rewind, Up, 0, false), SoundFont);
+        assert_eq!(splash_move(Stop, Down, 1, false), Recent(0));
+        assert_eq!(splash_move(Recent(0), Up, 1, false), Play);
+        assert_eq!(splash_move(Play, Right, 0, true), Stop);
+        assert_eq!(splash_move(Rewind, Right, 0, true), Rewind);
+        assert_eq!(splash_move(Play, Left, 0, true), Play);
+        assert_eq!(splash_move(SoundFont, Left, 0, true), SoundFont);
+    }
+
+    fn visit(pages: &[UiPage]) -> UiState {
+        let mut state = UiState::default();
+        for &page in pages {
+            state.open_page(page);
+        }
+        state
+    }
+
+    #[test]
+    fn back_returns_to_the_page_you_came_from() {
+        let mut state = visit(&[UiPage::Tracks, UiPage::PianoRoll, UiPage::About]);
+        state.back();
+        assert_eq!(state.page, UiPage::PianoRoll);
+        state.back();
+        assert_eq!(state.page, UiPage::Tracks);
+        state.back();
+        assert_eq!(state.page, UiPage::Splash);
+        state.back();
+        assert_eq!(state.page, UiPage::Splash);
+        assert!(state.page_stack.is_empty());
+    }
+
+    #[test]
+    fn toggling_a_page_closes_it_back_to_the_previous_one() {
+        let mut state = visit(&[UiPage::Tracks, UiPage::Lyrics]);
+        state.toggle_page(UiPage::About);
+        assert_eq!(state.page, UiPage::About);
+        state.toggle_page(UiPage::About);
+        assert_eq!(state.page, UiPage::Lyrics);
+        state.toggle_page(UiPage::Settings);
+        state.toggle_page(UiPage::Settings);
+        assert_eq!(state.page, UiPage::Lyrics);
+    }
+
+    #[test]
+    fn reopening_a_page_on_the_stack_unwinds_to_it() {
+        let mut state = visit(&[UiPage::Tracks, UiPage::PianoRoll, UiPage::About]);
+        state.open_page(UiPage::Tracks);
+        assert_eq!(state.page, UiPage::Tracks);
+        assert_eq!(state.page_stack, vec![UiPage::Splash]);
+        state.open_page(UiPage::Tracks);
+        assert_eq!(state.page_stack, vec![UiPage::Splash]);
+    }
+
+    #[test]
+    fn str_to_keycode_handles_known_keys() {
+        assert_eq!(str_to_keycode("up"), Some(bevy::prelude::KeyCode::ArrowUp));
+        assert_eq!(str_to_keycode("P"), Some(bevy::prelude::KeyCode::KeyP));
+        assert_eq!(str_to_keycode("unknown"), None);
+    }
+
+    #[test]
+    fn str_to_keycode_handles_space_and_escape() {
+        assert_eq!(str_to_keycode("Space"), Some(bevy::prelude::KeyCode::Space));
+        assert_eq!(str_to_keycode("Esc"), Some(bevy::prelude::KeyCode::Escape));
+    }
+
+    fn view_at(offset_ticks: f32) -> PianoRollViewState {
+        PianoRollViewState {
+            offset_ticks,
+            ..PianoRollViewState::default()
+        }
+    }
+
+    #[test]
+    fn view_history_coalesces_rapid_pans() {
+        let mut history = ViewHistory::default();
+        history.record(view_at(0.0), 0.0);
+        history.record(view_at(10.0), 0.1);
+        history.record(view_at(20.0), 0.2);
+        history.record(view_at(30.0), 1.0);
+        assert_eq!(history.undo(view_at(40.0)), Some(view_at(30.0)));
+        assert_eq!(history.undo(view_at(30.0)), Some(view_at(0.0)));
+        assert_eq!(history.undo(view_at(0.0)), None);
+    }
+
+    #[test]
+    fn view_history_redo_and_branching() {
+        let mut history = ViewHistory::default();
+        history.record(view_at(0.0), 0.0);
+        history.record(view_at(10.0), 1.0);
+        assert_eq!(history.undo(view_at(20.0)), Some(view_at(10.0)));
+        assert_eq!(history.redo(view_at(10.0)), Some(view_at(20.0)));
+        assert_eq!(history.undo(view_at(20.0)), Some(view_at(10.0)));
+        history.record(view_at(10.0), 5.0);
+        assert_eq!(history.redo(view_at(50.0)), None);
+    }
+
+    #[test]
+    fn view_history_is_bounded() {
+        let mut history = ViewHistory::default();
+        for step in 0..(ViewHistory::LIMIT + 10) {
+            history.record(view_at(step as f32), step as f64);
+        }
+        let mut undone = 0;
+        let mut current = view_at(-1.0);
+        while let Some(view) = history.undo(current) {
+            current = view;
+            undone += 1;
+        }
+        assert_eq!(undone, ViewHistory::LIMIT);
+        assert_eq!(current, view_at(10.0));
+    }
+
+    #[test]
+    fn cycle_setting_wraps_interpolation_modes() {
+        let mut preferences = Preferences::default();
+        assert_eq!(preferences.interpolation, Interpolation::FourthOrder);
+        cycle_setting(&mut preferences, SettingsItem::Interpolation, true);
+        assert_eq!(preferences.interpolation, Interpolation::SeventhOrder);
+        cycle_setting(&mut preferences, SettingsItem::Interpolation, true);
+        assert_eq!(preferences.interpolation, Interpolation::None);
+        cycle_setting(&mut preferences, SettingsItem::Interpolation, false);
+        assert_eq!(preferences.interpolation, Interpolation::SeventhOrder);
+        cycle_setting(&mut preferences, SettingsItem::TimeDisplay, true);
+        assert_eq!(preferences.time_display, TimeDisplay::Ticks);
+        cycle_setting(&mut preferences, SettingsItem::LoopSeam, false);
+        assert_eq!(preferences.loop_seam, LoopSeam::Fade);
+        cycle_setting(&mut preferences, SettingsItem::LoopSeam, true);
+        assert_eq!(preferences.loop_seam, LoopSeam::Cut);
+    }
+
+    #[test]
+    fn cycle_setting_wraps_idle_timeout() {
+        let mut preferences = Preferences::default();
+        cycle_setting(&mut preferences, SettingsItem::IdleTimeout, true);
+        assert_eq!(preferences.idle_timeout_seconds, Some(30.0));
+        cycle_setting(&mut preferences, SettingsItem::IdleTimeout, true);
+        assert_eq!(preferences.idle_timeout_seconds, None);
+        cycle_setting(&mut preferences, SettingsItem::IdleTimeout, false);
+        assert_eq!(preferences.idle_timeout_seconds, Some(30.0));
+    }
+
+    #[test]
+    fn cycle_setting_steps_default_zoom() {
+        let mut preferences = Preferences::default();
+        cycle_setting(&mut preferences, SettingsItem::DefaultZoomX, true);
+        cycle_setting(&mut preferences, SettingsItem::DefaultZoomX, true);
+        assert_eq!(preferences.default_zoom_x, 4.0);
+        cycle_setting(&mut preferences, SettingsItem::DefaultZoomY, false);
+        assert_eq!(preferences.default_zoom_y, 1.0);
+        for _ in 0..10 {
+            cycle_setting(&mut preferences, SettingsItem::DefaultZoomY, true);
+        }
+        assert_eq!(preferences.default_zoom_y, 16.0);
+        preferences.default_zoom_x = 3.0;
+        cycle_setting(&mut preferences, SettingsItem::DefaultZoomX, true);
+        assert_eq!(preferences.default_zoom_x, 8.0);
+    }
+
+    #[test]
+    fn autoplay_plays_only_when_enabled_and_a_soundfont_is_ready() {
+        let midi = MidiFilePath(Some(PathBuf::from("song.mid")));
+        let soundfont = SoundFontPath(Some(PathBuf::from("font.sf2")));
+        assert!(autoplay_on_load(false, &midi, &soundfont).is_none());
+        assert!(matches!(
+            autoplay_on_load(true, &midi, &soundfont),
+            Some(Ok(AudioCommand::Play(_, _)))
+        ));
+        assert!(matches!(
+            autoplay_on_load(true, &midi, &SoundFontPath::default()),
+            Some(Err((UiSelection::SoundFont, _)))
+        ));
+    }
+
+    #[test]
+    fn play_hint_points_at_missing_file() {
+        let midi = MidiFilePath(Some(PathBuf::from("song.mid")));
+        let soundfont = SoundFontPath(Some(PathBuf::from("font.sf2")));
+        assert_eq!(
+            play_hint(&MidiFilePath::default(), &SoundFontPath::default()),
+            Some((UiSelection::MidiFile, "Select a MIDI file first"))
+        );
+        assert_eq!(
+            play_hint(&midi, &SoundFontPath::default()),
+            Some((UiSelection::SoundFont, "Select a SoundFont first"))
+        );
+        assert_eq!(play_hint(&midi, &soundfont), None);
+    }
+
+    #[test]
+    fn shift_transpose_clamps_to_limit() {
+        assert_eq!(shift_transpose(0, -12), -12);
+        assert_eq!(shift_transpose(-12, 1), -11);
+        assert_eq!(shift_transpose(44, 12), 48);
+        assert_eq!(shift_transpose(-48, -12), -48);
+    }
+
+    #[test]
+    fn last_bars_loop_covers_the_bars_before_the_playhead() {
+        // 4/4 at 480 PPQN, then 3/4 from bar 5.
+        let bar_map = BarMap::new(&[(0, 4, 4), (7680, 3, 4)], 480);
+        let bars = |tick: u64, count: u32| {
+            let region = last_bars_loop(&bar_map, tick, count);
+            assert!(region.enabled);
+            (region.start_bar, region.end_bar)
+        };
+        // Mid bar 4: bars 2 and 3 were just heard.
+        assert_eq!(bars(3 * 1920 + 100, 2), (2, 3));
+        assert_eq!(bars(3 * 1920, 1), (3, 3));
+        // Across the meter change, bar 6 starts 1440 ticks into the 3/4.
+        assert_eq!(bars(7680 + 1440 + 10, 2), (4, 5));
+        assert_eq!(bar_map.bar_start(4), 5760);
+        assert_eq!(bar_map.bar_start(6), 9120);
+        // The start clamps to the top of the file.
+        assert_eq!(bars(2 * 1920 + 5, 4), (1, 2));
+        assert_eq!(bars(0, 2), (1, 1));
+        assert_eq!(bars(1000, 0), (1, 1));
+    }
+
+    #[test]
+    fn loop_status_prefers_the_a_b_points() {
+        let mut region = LoopRegion {
+            enabled: true,
+            start_bar: 3,
+            end_bar: 4,
+            ..LoopRegion::default()
+        };
+        let tempo_map = TempoMap::new(&[], 480);
+        let status = |region: &LoopRegion| loop_status(region, &tempo_map, TimeDisplay::Ticks);
+        assert_eq!(status(&region), "Loop bars 3-4");
+        region.points = (Some(480), None);
+        assert_eq!(status(&region), "Loop A at 480; set B with '");
+        region.points.1 = Some(1920);
+        assert_eq!(status(&region), "Loop A-B: 480-1920");
+        assert_eq!(
+            loop_status(&region, &tempo_map, TimeDisplay::Time),
+            "Loop A-B: 00:01-00:02"
+        );
+        region.points.1 = Some(240);
+        assert_eq!(status(&region), "Loop off: B is not after A");
+        region.enabled = false;
+        assert_eq!(status(&region), "Loop off");
+    }
 
-fn note_range(spans: &[NoteSpan]) -> (u8, u8) {
-    let mut min_pitch = 127u8;
-    let mut max_pitch = 0u8;
-    for span in spans {
-        min_pitch = min_pitch.min(span.pitch);
-        max_pitch = max_pitch.max(span.pitch);
+    #[test]
+    fn nudge_loop_region_keeps_bars_ordered() {
+        let region = LoopRegion {
+            enabled: true,
+            start_bar: 9,
+            end_bar: 16,
+            ..LoopRegion::default()
+        };
+        let moved = nudge_loop_region(region, false, 1, 32);
+        assert_eq!((moved.start_bar, moved.end_bar), (10, 16));
+        let pushed = nudge_loop_region(
+            LoopRegion {
+                start_bar: 16,
+                ..region
+            },
+            false,
+            1,
+            32,
+        );
+        assert_eq!((pushed.start_bar, pushed.end_bar), (17, 17));
+        let pulled = nudge_loop_region(region, true, -8, 32);
+        assert_eq!((pulled.start_bar, pulled.end_bar), (8, 8));
+        let clamped = nudge_loop_region(region, true, 40, 32);
+        assert_eq!(clamped.end_bar, 32);
+        let floor = nudge_loop_region(region, false, -20, 32);
+        assert_eq!(floor.start_bar, 1);
     }
-    if spans.is_empty() {
-        (60, 60)
-    } else {
-        (min_pitch, max_pitch)
+
+    #[test]
+    fn peak_bar_picks_the_busiest_bar() {
+        assert_eq!(peak_bar(&[2, 5, 9, 9, 1]), Some(3));
+        assert_eq!(peak_bar(&[0, 0, 4]), Some(3));
+        assert_eq!(peak_bar(&[0, 0]), None);
+        assert_eq!(peak_bar(&[]), None);
     }
-}
 
-fn ticks_per_column_for_width(max_tick: u64, max_width: usize) -> u64 {
-    if max_width == 0 {
-        return 1;
+    #[test]
+    fn most_prominent_track_skips_empty_tracks() {
+        let track = Vec::new();
+        let smf = Smf {
+            header: midly::Header {
+                format: Format::Parallel,
+                timing: Timing::Metrical(480.into()),
+            },
+            tracks: vec![track],
+        };
+        let template =
+            parse_midi_tracks(&smf, NotePairing::default(), PreviewSize::default()).remove(0);
+        let tracks: Vec<MidiTrackInfo> = [0, 3, 7, 7, 2]
+            .into_iter()
+            .map(|note_count| MidiTrackInfo {
+                note_count,
+                ..template.clone()
+            })
+            .collect();
+        assert_eq!(most_prominent_track(&tracks), Some(2));
+        assert_eq!(most_prominent_track(&tracks[..1]), None);
+        assert_eq!(most_prominent_track(&[]), None);
     }
-    let denom = max_width.saturating_sub(1).max(1) as u64;
-    let mut ticks_per_column = (max_tick + denom - 1) / denom;
-    if ticks_per_column == 0 {
-        ticks_per_column = 1;
+
+    #[test]
+    fn mixer_steps_channels_and_adjusts_the_focused_control() {
+        let mut mixer = ChannelMixer::default();
+        mixer.step_channel(false, false);
+        assert_eq!(mixer.channel, 0);
+        mixer.step_channel(false, true);
+        assert_eq!(mixer.channel, 15);
+        mixer.step_channel(true, false);
+        assert_eq!(mixer.channel, 15);
+        mixer.step_channel(true, true);
+        assert_eq!(mixer.channel, 0);
+
+        mixer.adjust(true);
+        assert_eq!(mixer.strips[0].volume, 100);
+        mixer.adjust(false);
+        assert_eq!(mixer.strips[0].volume, 95);
+        mixer.reset_focused();
+        assert_eq!(mixer.strips[0].volume, 100);
+
+        mixer.step_control(true);
+        assert_eq!(mixer.control, MixerControl::Pan);
+        mixer.adjust(false);
+        assert_eq!(mixer.strips[0].pan, Some(56));
+        mixer.reset_focused();
+        assert_eq!(mixer.strips[0].pan, None);
+
+        mixer.step_control(true);
+        assert_eq!(mixer.control, MixerControl::Program);
+        mixer.adjust(false);
+        assert_eq!(mixer.strips[0].program, Some(0));
+        mixer.step_program(8);
+        mixer.adjust(true);
+        assert_eq!(mixer.strips[0].program, Some(9));
+        mixer.step_program(200);
+        assert_eq!(mixer.strips[0].program, Some(127));
+        mixer.clear_programs();
+        assert_eq!(mixer.strips[0].program, None);
+
+        mixer.step_control(false);
+        mixer.step_control(false);
+        mixer.step_control(false);
+        assert_eq!(mixer.control, MixerControl::Solo);
+        mixer.adjust(true);
+        mixer.step_channel(true, false);
+        mixer.step_control(false);
+        mixer.reset_focused();
+        assert!(mixer.strips[0].solo && mixer.strips[1].mute);
+        let audible = audible_channels(&mixer.strips);
+        assert_eq!(audible.iter().filter(|audible| **audible).count(), 1);
+        assert!(audible[0]);
     }
-    ticks_per_column
-}
 
-fn build_track_preview(
-    width: usize,
-    height: usize,
-    ticks_per_column: u64,
-    max_tick: u64,
-    track_end: u64,
-    min_pitch: u8,
-    max_pitch: u8,
-    spans: &[NoteSpan],
-) -> Vec<u16> {
-    if width == 0 || height == 0 {
-        return Vec::new();
+    #[test]
+    fn toggle_mute_mutes_every_channel_unless_all_are_muted() {
+        let mut mixer = ChannelMixer::default();
+        mixer.strips[3].mute = true;
+        assert!(mixer.toggle_mute(&[2, 3]));
+        assert!(mixer.strips[2].mute && mixer.strips[3].mute);
+        assert!(!mixer.toggle_mute(&[2, 3]));
+        assert!(!mixer.strips[2].mute && !mixer.strips[3].mute);
     }
 
-    let mut cells = vec![0u16; width * height];
-    let max_tick = max_tick.max(1);
-    let _ = track_end;
-    let _ = max_tick;
+    #[test]
+    fn toggle_solo_solos_every_channel_unless_all_are_soloed() {
+        let mut mixer = ChannelMixer::default();
+        mixer.strips[1].mute = true;
+        mixer.strips[4].mute = true;
+        assert!(mixer.toggle_solo(&[4, 5]));
+        // A muted channel stays silent even when soloed.
+        let audible = audible_channels(&mixer.strips);
+        assert_eq!(audible.iter().filter(|audible| **audible).count(), 1);
+        assert!(audible[5]);
+        assert!(!mixer.toggle_solo(&[4, 5]));
+        let audible = audible_channels(&mixer.strips);
+        assert!(!audible[1] && !audible[4] && audible[0] && audible[5]);
+    }
 
-    for span in spans {
-        let pitch = span.pitch;
-        let start = span.start;
-        let end = span.end;
-        let start_col = (start / ticks_per_column) as usize;
-        let end_col = (end / ticks_per_column) as usize;
-        let row = pitch_to_row_range(height, min_pitch, max_pitch, pitch);
-        let row_offset = row * width;
-        let end_col = end_col.min(width.saturating_sub(1));
-        for col in start_col..=end_col {
-            let idx = row_offset + col;
-            if let Some(cell) = cells.get_mut(idx) {
-                *cell = cell.saturating_add(1);
-            }
-        }
+    #[test]
+    fn hiding_empty_tracks_keeps_file_indices() {
+        let smf = Smf {
+            header: midly::Header {
+                format: Format::Parallel,
+                timing: Timing::Metrical(480.into()),
+            },
+            tracks: vec![Vec::new()],
+        };
+        let template =
+            parse_midi_tracks(&smf, NotePairing::default(), PreviewSize::default()).remove(0);
+        let tracks: Vec<MidiTrackInfo> = [0, 3, 0, 5]
+            .into_iter()
+            .map(|note_count| MidiTrackInfo {
+                note_count,
+                ..template.clone()
+            })
+            .collect();
+        assert_eq!(listed_track_indices(&tracks, true), vec![1, 3]);
+        assert_eq!(listed_track_indices(&tracks, false), vec![0, 1, 2, 3]);
+        // A file of nothing but empty tracks still lists them.
+        assert_eq!(listed_track_indices(&tracks[..1], true), vec![0]);
     }
 
-    cells
-}
+    #[test]
+    fn parse_goto_accepts_bars_and_times() {
+        assert_eq!(parse_goto("32"), Some(GotoTarget::Bar(32)));
+        assert_eq!(parse_goto(" bar 12 "), Some(GotoTarget::Bar(12)));
+        assert_eq!(parse_goto("B7"), Some(GotoTarget::Bar(7)));
+        assert_eq!(parse_goto("1:45"), Some(GotoTarget::Seconds(105.0)));
+        assert_eq!(parse_goto("0:02.5"), Some(GotoTarget::Seconds(2.5)));
+        assert_eq!(parse_goto("1:02:03"), Some(GotoTarget::Seconds(3723.0)));
+        assert_eq!(parse_goto("0"), None);
+        assert_eq!(parse_goto("bar"), None);
+        assert_eq!(parse_goto("1:75"), None);
+        assert_eq!(parse_goto("1.5:00"), None);
+        assert_eq!(parse_goto("1:2:3:4"), None);
+        assert_eq!(parse_goto("-1:00"), None);
+        assert_eq!(parse_goto("soon"), None);
+        assert_eq!(parse_goto(""), None);
+    }
 
-fn pitch_to_row_range(height: usize, min_pitch: u8, max_pitch: u8, pitch: u8) -> usize {
-    if height == 0 {
-        return 0;
+    #[test]
+    fn resolve_goto_converts_and_bounds_targets() {
+        let smf = Smf {
+            header: midly::Header {
+                format: Format::Parallel,
+                timing: Timing::Metrical(480.into()),
+            },
+            tracks: vec![Vec::new()],
+        };
+        let template =
+            parse_midi_tracks(&smf, NotePairing::default(), PreviewSize::default()).remove(0);
+        // Eight 4/4 bars at the default 120 BPM, so one second is 960 ticks.
+        let tracks = vec![MidiTrackInfo {
+            end_tick: 8 * 1920,
+            ..template
+        }];
+        assert_eq!(resolve_goto("bar 3", &tracks, None), Ok(3840));
+        assert_eq!(resolve_goto("0:04", &tracks, None), Ok(3840));
+        assert_eq!(resolve_goto("9", &tracks, None), Ok(15_360));
+        assert_eq!(resolve_goto("10", &tracks, None), Err("past the end"));
+        assert_eq!(resolve_goto("x", &tracks, None), Err("not a bar or time"));
+        assert_eq!(resolve_goto(" ", &tracks, None), Err("type a bar or m:ss"));
     }
-    let padding = ((height as f32) * 0.08).round() as usize;
-    let padding = padding.min(height.saturating_sub(1) / 2);
-    let usable_height = height.saturating_sub(padding * 2).max(1);
-    if min_pitch >= max_pitch {
-        return padding + ((usable_height - 1) as f32 / 2.0).round() as usize;
+
+    #[test]
+    fn dropped_file_kind_matches_extensions() {
+        assert_eq!(
+            dropped_file_kind(Path::new("song.MID")),
+            Some(RecentKind::Midi)
+        );
+        assert_eq!(
+            dropped_file_kind(Path::new("a/b.midi")),
+            Some(RecentKind::Midi)
+        );
+        assert_eq!(
+            dropped_file_kind(Path::new("piano.sf2")),
+            Some(RecentKind::SoundFont)
+        );
+        assert_eq!(dropped_file_kind(Path::new("notes.txt")), None);
+        assert_eq!(dropped_file_kind(Path::new("README")), None);
     }
-    let span = (max_pitch - min_pitch) as f32;
-    let t = (max_pitch.saturating_sub(pitch) as f32) / span;
-    let row = t * (usable_height as f32 - 1.0);
-    (padding as f32 + row)
-        .round()
-        .clamp(0.0, (height - 1) as f32) as usize
-}
 
-#[cfg(test)]
-mod tests {
-    use super::{
-        build_track_preview, note_range, parse_midi_tracks, parse_track, pitch_to_row_range,
-        str_to_keycode, ticks_per_column_for_width,
-    };
-    use crate::state::MidiTrackInfo;
-    use crate::state::NoteSpan;
-    use midly::{Format, Smf, Timing, TrackEvent, TrackEventKind};
+    #[test]
+    fn cycle_setting_clamps_polyphony() {
+        let mut preferences = Preferences::default();
+        cycle_setting(&mut preferences, SettingsItem::Polyphony, true);
+        assert_eq!(preferences.polyphony, 512);
+        for _ in 0..10 {
+            cycle_setting(&mut preferences, SettingsItem::Polyphony, true);
+        }
+        assert_eq!(preferences.polyphony, 1024);
+        for _ in 0..10 {
+            cycle_setting(&mut preferences, SettingsItem::Polyphony, false);
+        }
+        assert_eq!(preferences.polyphony, 64);
+    }
 
     #[test]
-    fn str_to_keycode_handles_known_keys() {
-        assert_eq!(str_to_keycode("up"), Some(bevy::prelude::KeyCode::ArrowUp));
-        assert_eq!(str_to_keycode("P"), Some(bevy::prelude::KeyCode::KeyP));
-        assert_eq!(str_to_keycode("unknown"), None);
+    fn cycle_setting_clamps_reverb_tail() {
+        let mut preferences = Preferences::default();
+        assert_eq!(preferences.reverb_tail_seconds, 1.5);
+        cycle_setting(&mut preferences, SettingsItem::ReverbTail, true);
+        assert_eq!(preferences.reverb_tail_seconds, 2.0);
+        for _ in 0..10 {
+            cycle_setting(&mut preferences, SettingsItem::ReverbTail, true);
+        }
+        assert_eq!(preferences.reverb_tail_seconds, 5.0);
+        for _ in 0..10 {
+            cycle_setting(&mut preferences, SettingsItem::ReverbTail, false);
+        }
+        assert_eq!(preferences.reverb_tail_seconds, 0.0);
+
+        cycle_setting(&mut preferences, SettingsItem::ReverbLevel, true);
+        assert_eq!(preferences.reverb_level, 1.0);
+        cycle_setting(&mut preferences, SettingsItem::ReverbLevel, true);
+        assert_eq!(preferences.reverb_level, 1.0);
+        for _ in 0..10 {
+            cycle_setting(&mut preferences, SettingsItem::ReverbLevel, false);
+        }
+        assert_eq!(preferences.reverb_level, 0.0);
+
+        cycle_setting(&mut preferences, SettingsItem::ChorusLevel, true);
+        assert_eq!(preferences.chorus_level, 4.0);
+        cycle_setting(&mut preferences, SettingsItem::ChorusLevel, false);
+        cycle_setting(&mut preferences, SettingsItem::ChorusLevel, false);
+        assert_eq!(preferences.chorus_level, 1.0);
+    }
+
+    #[test]
+    fn classify_sysex_recognizes_resets() {
+        let gm_on = [0xF0, 0x7E, 0x7F, 0x09, 0x01, 0xF7];
+        assert_eq!(classify_sysex(&gm_on[1..]), Some(MidiStandard::Gm));
+        assert_eq!(classify_sysex(&gm_on), Some(MidiStandard::Gm));
+        assert_eq!(
+            classify_sysex(&[0x7E, 0x7F, 0x09, 0x03, 0xF7]),
+            Some(MidiStandard::Gm2)
+        );
+        assert_eq!(
+            classify_sysex(&[0x41, 0x10, 0x42, 0x12, 0x40, 0x00, 0x7F, 0x00, 0x41, 0xF7]),
+            Some(MidiStandard::Gs)
+        );
+        assert_eq!(
+            classify_sysex(&[0x43, 0x10, 0x4C, 0x00, 0x00, 0x7E, 0x00, 0xF7]),
+            Some(MidiStandard::Xg)
+        );
+        assert_eq!(classify_sysex(&[0x7E, 0x7F, 0x09, 0x02, 0xF7]), None);
+        assert_eq!(
+            classify_sysex(&[0x43, 0x10, 0x4C, 0x08, 0x00, 0x07, 0x01, 0xF7]),
+            None
+        );
+
+        let track = vec![TrackEvent {
+            delta: 0.into(),
+            kind: TrackEventKind::SysEx(&gm_on[1..]),
+        }];
+        assert_eq!(
+            parse_track(&track, NotePairing::default()).midi_standard,
+            Some(MidiStandard::Gm)
+        );
     }
 
     #[test]
@@ -792,7 +3667,7 @@ mod tests {
             },
         });
 
-        let parsed = parse_track(&track);
+        let parsed = parse_track(&track, NotePairing::default());
         assert_eq!(parsed.name.as_deref(), Some("Test"));
         assert_eq!(parsed.spans.len(), 1);
         assert_eq!(parsed.event_count, 4);
@@ -802,6 +3677,28 @@ mod tests {
         assert_eq!(parsed.programs, vec![(1, 40)]);
     }
 
+    #[test]
+    fn parse_track_records_pan_changes() {
+        let pan = |delta: u32, channel: u8, value: u8| TrackEvent {
+            delta: delta.into(),
+            kind: TrackEventKind::Midi {
+                channel: channel.into(),
+                message: midly::MidiMessage::Controller {
+                    controller: 10.into(),
+                    value: value.into(),
+                },
+            },
+        };
+        let track = vec![pan(0, 0, 64), pan(0, 1, 0), pan(480, 0, 127)];
+        assert_eq!(
+            parse_track(&track, NotePairing::default()).pan_events,
+            vec![(0, 0, 64), (0, 1, 0), (480, 0, 127)]
+        );
+        assert!(parse_track(&track[..0], NotePairing::default())
+            .pan_events
+            .is_empty());
+    }
+
     #[test]
     fn parse_midi_tracks_builds_track_info() {
         let mut track = Vec::new();
@@ -833,7 +3730,7 @@ mod tests {
             tracks: vec![track],
         };
 
-        let tracks = parse_midi_tracks(&smf);
+        let tracks = parse_midi_tracks(&smf, NotePairing::default(), PreviewSize::default());
         assert_eq!(tracks.len(), 1);
         let MidiTrackInfo {
             preview_width,
@@ -847,7 +3744,7 @@ mod tests {
             channels,
             programs,
             banks,
-            tempo_changes,
+            timing,
             time_signature,
             key_signature,
             note_spans,
@@ -864,12 +3761,102 @@ mod tests {
         assert_eq!(channels.as_slice(), &[0]);
         assert!(programs.is_empty());
         assert!(banks.is_empty());
-        assert_eq!(*tempo_changes, 0);
+        assert!(timing.tempo_events.is_empty());
         assert!(time_signature.is_none());
         assert!(key_signature.is_none());
         assert_eq!(note_spans.len(), 1);
     }
 
+    #[test]
+    fn parse_midi_tracks_follows_the_preview_size() {
+        let note = |delta: u32, on: bool| TrackEvent {
+            delta: delta.into(),
+            kind: TrackEventKind::Midi {
+                channel: 0.into(),
+                message: if on {
+                    midly::MidiMessage::NoteOn {
+                        key: 60.into(),
+                        vel: 100.into(),
+                    }
+                } else {
+                    midly::MidiMessage::NoteOff {
+                        key: 60.into(),
+                        vel: 0.into(),
+                    }
+                },
+            },
+        };
+        let smf = Smf {
+            header: midly::Header {
+                format: Format::SingleTrack,
+                timing: Timing::Metrical(480.into()),
+            },
+            tracks: vec![vec![note(0, true), note(120, false)]],
+        };
+
+        let coarse = PreviewSize::new(32, 16);
+        let fine = PreviewSize::new(1024, 128);
+        for size in [coarse, fine] {
+            let track = parse_midi_tracks(&smf, NotePairing::default(), size).remove(0);
+            assert_eq!(track.preview_height, size.height);
+            assert!(track.preview_width <= size.max_width);
+            assert_eq!(
+                track.preview_cells.len(),
+                track.preview_width * track.preview_height
+            );
+            assert_eq!(track.onset_preview_cells.len(), track.preview_cells.len());
+            assert!(track.preview_cells.iter().any(|cell| *cell > 0));
+        }
+        assert_eq!(PreviewSize::new(0, 100_000), PreviewSize::default());
+    }
+
+    #[test]
+    fn file_duration_follows_the_tempo_map() {
+        let tempo = |delta: u32, us_per_beat: u32| TrackEvent {
+            delta: delta.into(),
+            kind: TrackEventKind::Meta(midly::MetaMessage::Tempo(us_per_beat.into())),
+        };
+        let note = |delta: u32, on: bool| TrackEvent {
+            delta: delta.into(),
+            kind: TrackEventKind::Midi {
+                channel: 0.into(),
+                message: if on {
+                    midly::MidiMessage::NoteOn {
+                        key: 60.into(),
+                        vel: 100.into(),
+                    }
+                } else {
+                    midly::MidiMessage::NoteOff {
+                        key: 60.into(),
+                        vel: 0.into(),
+                    }
+                },
+            },
+        };
+        // Two beats at 120 BPM, then two at 60 BPM, with a trailing event
+        // that doesn't count towards the length.
+        let smf = Smf {
+            header: midly::Header {
+                format: Format::Parallel,
+                timing: Timing::Metrical(480.into()),
+            },
+            tracks: vec![
+                vec![tempo(0, 500_000), tempo(960, 1_000_000)],
+                vec![
+                    note(0, true),
+                    note(1920, false),
+                    TrackEvent {
+                        delta: 480.into(),
+                        kind: TrackEventKind::Meta(midly::MetaMessage::Text(b"end")),
+                    },
+                ],
+            ],
+        };
+        let tracks = parse_midi_tracks(&smf, NotePairing::default(), PreviewSize::default());
+        assert!((file_duration_seconds(&tracks, SongEnd::LastNote) - 3.0).abs() < 1e-9);
+        assert_eq!(file_duration_seconds(&[], SongEnd::LastNote), 0.0);
+    }
+
     #[test]
     fn note_range_defaults_for_empty() {
         assert_eq!(note_range(&[]), (60, 60));
@@ -882,18 +3869,275 @@ mod tests {
         assert!(ticks_per_column_for_width(100, 10) > 0);
     }
 
+    #[test]
+    fn classify_articulation_uses_thresholds() {
+        assert_eq!(classify_articulation(40, 100), Articulation::Staccato);
+        assert_eq!(classify_articulation(50, 100), Articulation::Normal);
+        assert_eq!(classify_articulation(94, 100), Articulation::Normal);
+        assert_eq!(classify_articulation(95, 100), Articulation::Legato);
+        assert_eq!(classify_articulation(120, 100), Articulation::Legato);
+    }
+
+    #[test]
+    fn articulation_counts_compares_same_pitch_and_channel() {
+        let span = |channel, pitch, start, end| NoteSpan {
+            channel,
+            pitch,
+            start,
+            end,
+            velocity: 100,
+        };
+        let spans = vec![
+            span(0, 60, 0, 30),
+            span(0, 60, 100, 170),
+            span(0, 60, 200, 300),
+            span(0, 60, 300, 400),
+            span(1, 60, 10, 20),
+            span(0, 62, 0, 10),
+        ];
+        assert_eq!(
+            articulation_counts(&spans),
+            ArticulationCounts {
+                staccato: 1,
+                normal: 1,
+                legato: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn rhythm_summary_buckets_lengths_and_sums_rests() {
+        let span = |start, end| NoteSpan {
+            channel: 0,
+            pitch: 60,
+            start,
+            end,
+            velocity: 100,
+        };
+        // At 480 ticks per beat: a whole, a slightly short half, a quarter,
+        // two overlapping eighths, a 16th, a triplet eighth and a 32nd.
+        let spans = vec![
+            span(0, 1920),
+            span(1920, 2800),
+            span(3840, 4320),
+            span(4320, 4560),
+            span(4400, 4640),
+            span(4800, 4920),
+            span(4920, 5080),
+            span(5080, 5140),
+        ];
+        assert_eq!(quantize_note_length(0, 480), None);
+        assert_eq!(quantize_note_length(160, 480), None);
+        assert_eq!(quantize_note_length(880, 480), Some(1));
+        let summary = rhythm_summary(&spans, 480);
+        assert_eq!(summary.lengths, [1, 1, 1, 2, 1, 1]);
+        assert_eq!(summary.irregular, 1);
+        // Gaps at 2800..3840 and 4640..4800.
+        assert_eq!(summary.rest_ticks, 1040 + 160);
+        assert_eq!(rhythm_summary(&[], 480), RhythmSummary::default());
+    }
+
+    #[test]
+    fn parse_track_captures_instrument_ports_copyright_cues_and_markers() {
+        let track = vec![
+            TrackEvent {
+                delta: 0.into(),
+                kind: TrackEventKind::Meta(midly::MetaMessage::Copyright(b"(c) 1994 Someone")),
+            },
+            TrackEvent {
+                delta: 0.into(),
+                kind: TrackEventKind::Meta(midly::MetaMessage::InstrumentName(
+                    b"Fretless Bass \xff",
+                )),
+            },
+            TrackEvent {
+                delta: 0.into(),
+                kind: TrackEventKind::Meta(midly::MetaMessage::MidiChannel(3.into())),
+            },
+            TrackEvent {
+                delta: 0.into(),
+                kind: TrackEventKind::Meta(midly::MetaMessage::MidiPort(1.into())),
+            },
+            TrackEvent {
+                delta: 480.into(),
+                kind: TrackEventKind::Meta(midly::MetaMessage::CuePoint(b"Door slam")),
+            },
+            TrackEvent {
+                delta: 0.into(),
+                kind: TrackEventKind::Meta(midly::MetaMessage::Marker(b" Chorus ")),
+            },
+            TrackEvent {
+                delta: 0.into(),
+                kind: TrackEventKind::Meta(midly::MetaMessage::Marker(b"")),
+            },
+            TrackEvent {
+                delta: 0.into(),
+                kind: TrackEventKind::Meta(midly::MetaMessage::InstrumentName(b"Second")),
+            },
+        ];
+        let parsed = parse_track(&track, NotePairing::default());
+        assert_eq!(
+            parsed.instrument_name.as_deref(),
+            Some("Fretless Bass \u{fffd}")
+        );
+        assert_eq!(parsed.copyright.as_deref(), Some("(c) 1994 Someone"));
+        assert_eq!(parsed.cue_points, vec![(480, "Door slam".to_string())]);
+        assert_eq!(parsed.markers, vec![(480, "Chorus".to_string())]);
+        assert_eq!(parsed.midi_port, Some(1));
+        assert_eq!(parsed.channel_prefix, Some(3));
+
+        let blank = vec![TrackEvent {
+            delta: 0.into(),
+            kind: TrackEventKind::Meta(midly::MetaMessage::InstrumentName(b"  ")),
+        }];
+        assert_eq!(
+            parse_track(&blank, NotePairing::default()).instrument_name,
+            None
+        );
+    }
+
+    #[test]
+    fn overlapping_same_pitch_notes_pair_by_policy() {
+        // Two C4s: the first starts at 0, the second at 100, and NoteOffs
+        // arrive at 200 and 300.
+        let mut track = Vec::new();
+        for (delta, vel) in [(0, 90), (100, 60), (100, 0), (100, 0)] {
+            track.push(TrackEvent {
+                delta: delta.into(),
+                kind: TrackEventKind::Midi {
+                    channel: 0.into(),
+                    message: midly::MidiMessage::NoteOn {
+                        key: 60.into(),
+                        vel: vel.into(),
+                    },
+                },
+            });
+        }
+        let spans = |pairing| {
+            let mut spans = parse_track(&track, pairing)
+                .spans
+                .iter()
+                .map(|span| (span.start, span.end, span.velocity))
+                .collect::<Vec<_>>();
+            spans.sort();
+            spans
+        };
+        assert_eq!(spans(NotePairing::Fifo), vec![(0, 200, 90), (100, 300, 60)]);
+        assert_eq!(spans(NotePairing::Lifo), vec![(0, 300, 90), (100, 200, 60)]);
+    }
+
+    #[test]
+    fn notes_csv_lists_spans_with_seconds() {
+        let mut track = Vec::new();
+        track.push(TrackEvent {
+            delta: 0.into(),
+            kind: TrackEventKind::Meta(midly::MetaMessage::Tempo(1_000_000.into())),
+        });
+        for (delta, key, vel) in [(0, 64, 90), (0, 60, 100), (480, 60, 0), (480, 64, 0)] {
+            track.push(TrackEvent {
+                delta: delta.into(),
+                kind: TrackEventKind::Midi {
+                    channel: 2.into(),
+                    message: midly::MidiMessage::NoteOn {
+                        key: key.into(),
+                        vel: vel.into(),
+                    },
+                },
+            });
+        }
+        let smf = Smf {
+            header: midly::Header {
+                format: Format::SingleTrack,
+                timing: Timing::Metrical(480.into()),
+            },
+            tracks: vec![track],
+        };
+        let csv = notes_csv(&parse_midi_tracks(
+            &smf,
+            NotePairing::default(),
+            PreviewSize::default(),
+        ));
+        let lines = csv.lines().collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            [
+                "track,channel,pitch,note_name,start_tick,end_tick,start_seconds,end_seconds,velocity",
+                "1,3,60,C4,0,480,0.000000,1.000000,100",
+                "1,3,64,E4,0,960,0.000000,2.000000,90",
+            ]
+        );
+        assert_eq!(notes_csv(&[]).lines().count(), 1);
+    }
+
+    #[test]
+    fn bar_step_target_snaps_back_to_the_bar_start_first() {
+        let bar_map = crate::audio::BarMap::new(&[], 480);
+        assert_eq!(bar_step_target(&bar_map, 0, true, 10_000), 1920);
+        assert_eq!(bar_step_target(&bar_map, 2500, true, 10_000), 3840);
+        assert_eq!(bar_step_target(&bar_map, 2500, false, 10_000), 1920);
+        assert_eq!(bar_step_target(&bar_map, 1920, false, 10_000), 0);
+        assert_eq!(bar_step_target(&bar_map, 0, false, 10_000), 0);
+        assert_eq!(bar_step_target(&bar_map, 9000, true, 9500), 9500);
+    }
+
     #[test]
     fn build_track_preview_marks_cells() {
         let spans = vec![NoteSpan {
+            channel: 0,
             pitch: 60,
             start: 0,
             end: 10,
+            velocity: 100,
         }];
-        let cells = build_track_preview(4, 4, 5, 10, 10, 60, 60, &spans);
+        let cells = build_track_preview(4, 4, 5, 60, 60, &spans, PreviewMode::Sustain);
         assert_eq!(cells.len(), 16);
         assert!(cells.iter().any(|cell| *cell > 0));
     }
 
+    #[test]
+    fn build_track_preview_onset_mode_marks_only_the_start() {
+        // A note from column 1 through column 6 at 10 ticks per column.
+        let spans = vec![NoteSpan {
+            channel: 0,
+            pitch: 60,
+            start: 10,
+            end: 65,
+            velocity: 100,
+        }];
+        let lit = |mode| {
+            build_track_preview(8, 1, 10, 60, 60, &spans, mode)
+                .iter()
+                .enumerate()
+                .filter(|(_, cell)| **cell > 0)
+                .map(|(col, _)| col)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(lit(PreviewMode::Sustain), vec![1, 2, 3, 4, 5, 6]);
+        assert_eq!(lit(PreviewMode::Onset), vec![1]);
+    }
+
+    #[test]
+    fn build_track_preview_keeps_degenerate_spans_in_their_row() {
+        let span = |start, end| NoteSpan {
+            channel: 0,
+            pitch: 72,
+            start,
+            end,
+            velocity: 100,
+        };
+        let spans = vec![span(20, 20), span(50, 30), span(95, 90)];
+        for mode in [PreviewMode::Sustain, PreviewMode::Onset] {
+            let cells = build_track_preview(8, 2, 10, 60, 72, &spans, mode);
+            let lit: Vec<usize> = cells
+                .iter()
+                .enumerate()
+                .filter(|(_, cell)| **cell > 0)
+                .map(|(idx, _)| idx)
+                .collect();
+            assert_eq!(lit, vec![2, 5]);
+        }
+    }
+
     #[test]
     fn pitch_to_row_range_within_bounds() {
         let row = pitch_to_row_range(10, 40, 80, 60);
@@ -1,69 +1,139 @@
 mod audio;
 mod input;
+mod midi;
+mod remote;
+mod session;
 mod state;
+mod tempo;
 mod ui;
 
-use crate::audio::AudioPlugin;
-use crate::input::{load_midi_tracks, InputPlugin};
+use crate::audio::{
+    render_offline, render_to_wav, AudioPlugin, OfflineRenderStats, OutputConfig, SynthSettings,
+};
+use crate::input::{export_notes_csv, load_midi_tracks, InputPlugin};
+use crate::remote::RemotePlugin;
+use crate::session::SessionPlugin;
 use crate::state::{
-    MidiFilePath, MidiTracks, PianoRollViewState, PlaybackStatus, SoundFontPath, TrackDetailsPopup,
+    ChannelMixer, CompareFile, DisplayTranspose, FileSummary, GotoEntry, LoopRegion, MarkerList,
+    Metronome, MidiFilePath, MidiTracks, NotePairing, PianoRollViewState, PixelRender,
+    PlaybackSpeed, PlaybackStatus, Preferences, PreviewSize, SettingsFocus, SoundFontLayers,
+    SoundFontPath, StatusMessage, TapTempo, TempoOverride, TrackDetailsPopup, TrackTranspose,
     TracksFocus, UiState,
 };
-use crate::ui::UiPlugin;
+use crate::ui::{clamp_ui_scale, UiPlugin};
+use bevy::log::{error, info, warn, Level, LogPlugin};
 use bevy::prelude::{
-    default, App, DefaultPlugins, PluginGroup, Query, Startup, Window, WindowPlugin, With,
+    default, App, DefaultPlugins, PluginGroup, Query, Startup, UiScale, Window, WindowPlugin, With,
 };
 use bevy::window::PrimaryWindow;
 use clap::Parser;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 fn main() {
-    println!("Starting Sona...");
     let cli = CliArgs::parse();
+    if let Some([midi, soundfont]) = cli.bench.as_deref() {
+        std::process::exit(run_bench(midi, soundfont));
+    }
+    if let Some([midi, out]) = cli.notes_csv.as_deref() {
+        std::process::exit(run_notes_csv(midi, out));
+    }
+    if let (Some(out), Some(midi), Some(soundfont)) = (&cli.render, &cli.midi, &cli.soundfont) {
+        std::process::exit(run_render(midi, soundfont, out));
+    }
+    let mut app = App::new();
+    // The log subscriber is installed by `LogPlugin`, so report nothing
+    // before the default plugins are added. `RUST_LOG` still overrides the
+    // level chosen here.
+    let _app = app.add_plugins(
+        DefaultPlugins
+            .set(WindowPlugin {
+                primary_window: Some(Window {
+                    title: "Sona - Retro MIDI Player".to_string(),
+                    ..default()
+                }),
+                ..default()
+            })
+            .set(LogPlugin {
+                level: log_level(cli.verbose),
+                ..default()
+            }),
+    );
+    info!("Starting Sona...");
+    let ui_scale = cli.ui_scale.map(clamp_ui_scale).unwrap_or(1.0);
+    let remote_port = cli.remote;
     let original_midi = cli.midi.clone();
     let original_soundfont = cli.soundfont.clone();
+    let (original_preview_width, original_preview_height) = (cli.preview_width, cli.preview_height);
+    let pixel_render = cli.pixel_resolution.map(|(width, height)| PixelRender {
+        width,
+        height,
+        scale: cli.pixel_scale,
+    });
+    if pixel_render.is_none() && cli.pixel_scale.is_some() {
+        warn!("--pixel-scale has no effect without --pixel-resolution");
+    }
     let cli = validate_cli_paths_with(cli.midi, cli.soundfont, |path| path.is_file());
-    if original_midi.is_some() && cli.midi.is_none() {
-        eprintln!("MIDI file not found: {}", original_midi.unwrap().display());
+    if let (Some(path), None) = (&original_midi, &cli.midi) {
+        error!("MIDI file not found: {}", path.display());
     }
-    if original_soundfont.is_some() && cli.soundfont.is_none() {
-        eprintln!(
-            "SoundFont file not found: {}",
-            original_soundfont.unwrap().display()
-        );
+    if let (Some(path), None) = (&original_soundfont, &cli.soundfont) {
+        error!("SoundFont file not found: {}", path.display());
     }
-    let midi_tracks = cli.midi.as_ref().map(load_midi_tracks).unwrap_or_default();
+    let preview_size = preview_size_from_cli(original_preview_width, original_preview_height);
+    let midi_tracks = cli
+        .midi
+        .as_ref()
+        .map(|path| load_midi_tracks(path, NotePairing::default(), preview_size))
+        .unwrap_or_default();
 
     let start_on_tracks = cli.midi.is_some() && cli.soundfont.is_some();
     let mut ui_state = UiState::default();
     if start_on_tracks {
-        ui_state.page = crate::state::UiPage::Tracks;
+        ui_state.open_page(crate::state::UiPage::Tracks);
     }
 
-    let _app = App::new()
-        .add_plugins(DefaultPlugins.set(WindowPlugin {
-            primary_window: Some(Window {
-                title: "Sona - Retro MIDI Player".to_string(),
-                ..default()
-            }),
-            ..default()
-        }))
+    let _app = app
         .add_systems(Startup, maximize_primary_window)
         .insert_resource(ui_state)
+        .insert_resource(UiScale(ui_scale))
         .insert_resource(MidiTracks(midi_tracks))
         .insert_resource(MidiFilePath(cli.midi))
         .insert_resource(SoundFontPath(cli.soundfont))
+        .init_resource::<SoundFontLayers>()
         .init_resource::<PlaybackStatus>()
         .init_resource::<TrackDetailsPopup>()
         .init_resource::<PianoRollViewState>()
+        .init_resource::<LoopRegion>()
+        .init_resource::<TrackTranspose>()
+        .init_resource::<DisplayTranspose>()
+        .init_resource::<TapTempo>()
+        .init_resource::<TempoOverride>()
+        .init_resource::<PlaybackSpeed>()
         .init_resource::<TracksFocus>()
+        .insert_resource(Preferences {
+            preview_size,
+            ..default()
+        })
+        .init_resource::<SettingsFocus>()
+        .init_resource::<StatusMessage>()
+        .init_resource::<GotoEntry>()
+        .init_resource::<MarkerList>()
+        .init_resource::<FileSummary>()
+        .init_resource::<CompareFile>()
+        .init_resource::<Metronome>()
+        .init_resource::<ChannelMixer>()
         .add_plugins(AudioPlugin)
         .add_plugins(InputPlugin)
+        .add_plugins(SessionPlugin)
         .add_plugins(UiPlugin)
-        .run();
+        .add_plugins(RemotePlugin { port: remote_port });
+    if let Some(pixel_render) = pixel_render {
+        let _app = app.insert_resource(pixel_render);
+    }
+    let _exit = app.run();
 }
 
-#[derive(Parser)]
+#[derive(Parser, Default)]
 #[command(
     name = "sona",
     version,
@@ -74,6 +144,122 @@ struct CliArgs {
     midi: Option<PathBuf>,
     #[arg(short, long)]
     soundfont: Option<PathBuf>,
+    #[arg(long)]
+    ui_scale: Option<f32>,
+    /// Accept text commands on this localhost TCP port.
+    #[arg(long, value_name = "PORT")]
+    remote: Option<u16>,
+    /// Log more detail; repeat for debug output. Only warnings and errors
+    /// are shown by default.
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    verbose: u8,
+    /// Most columns in a track preview; lower saves memory on long files.
+    #[arg(long, value_name = "COLUMNS")]
+    preview_width: Option<usize>,
+    /// Rows in a track preview.
+    #[arg(long, value_name = "ROWS")]
+    preview_height: Option<usize>,
+    /// Draw the UI at this fixed resolution, e.g. 640x360, and scale it up
+    /// to the window by whole pixels. Mouse input on the UI is not
+    /// supported in this mode.
+    #[arg(long, value_name = "WIDTHxHEIGHT", value_parser = parse_resolution)]
+    pixel_resolution: Option<(u32, u32)>,
+    /// Upscale factor for `--pixel-resolution`; fits the window by default.
+    #[arg(long, value_name = "FACTOR", value_parser = clap::value_parser!(u32).range(1..=16))]
+    pixel_scale: Option<u32>,
+    /// Render a file offline as fast as possible, print timings and exit,
+    /// without opening a window or an audio device.
+    #[arg(long, num_args = 2, value_names = ["MIDI", "SOUNDFONT"])]
+    bench: Option<Vec<PathBuf>>,
+    /// Write every note in a file to a CSV (track, channel, pitch, timing in
+    /// ticks and seconds, velocity) and exit.
+    #[arg(long, num_args = 2, value_names = ["MIDI", "OUT"])]
+    notes_csv: Option<Vec<PathBuf>>,
+    /// Bounce `--midi` through `--soundfont` to a 16-bit WAV and exit.
+    #[arg(long, value_name = "OUT", requires_all = ["midi", "soundfont"])]
+    render: Option<PathBuf>,
+}
+
+const OFFLINE_SAMPLE_RATE: u32 = 48_000;
+
+// Reports go to stdout rather than the log, since the timings are the
+// whole point of the run and logging is quiet by default.
+fn run_bench(midi: &Path, soundfont: &Path) -> i32 {
+    match render_offline(
+        midi,
+        soundfont,
+        OFFLINE_SAMPLE_RATE,
+        &SynthSettings::default(),
+        |_| {},
+    ) {
+        Ok(stats) => {
+            println!("{}", bench_report(&stats));
+            0
+        }
+        Err(err) => {
+            eprintln!("{err}");
+            1
+        }
+    }
+}
+
+fn run_render(midi: &Path, soundfont: &Path, out: &Path) -> i32 {
+    // Bounce at the rate `config.toml` plays at, if it sets one.
+    let sample_rate = OutputConfig::load()
+        .sample_rate
+        .unwrap_or(OFFLINE_SAMPLE_RATE);
+    // Synth preferences aren't saved between runs, so the bounce uses the
+    // ones a fresh session starts with.
+    match render_to_wav(midi, soundfont, sample_rate, &SynthSettings::default(), out) {
+        Ok(stats) => {
+            println!(
+                "Wrote {:.1}s of audio to {}",
+                stats.frames as f64 / stats.sample_rate.max(1) as f64,
+                out.display()
+            );
+            0
+        }
+        Err(err) => {
+            eprintln!("{err}");
+            1
+        }
+    }
+}
+
+fn run_notes_csv(midi: &Path, out: &Path) -> i32 {
+    match export_notes_csv(midi, out) {
+        Ok(notes) => {
+            println!("Wrote {} notes to {}", notes, out.display());
+            0
+        }
+        Err(err) => {
+            eprintln!("{err}");
+            1
+        }
+    }
+}
+
+fn bench_report(stats: &OfflineRenderStats) -> String {
+    let audio_seconds = stats.frames as f64 / stats.sample_rate.max(1) as f64;
+    let render_seconds = stats.render_time.as_secs_f64();
+    format!(
+        "Rendered {} frames ({:.1}s of audio at {} Hz)\nSchedule: {:.3}s\nRender: {:.3}s ({:.1}x realtime)",
+        stats.frames,
+        audio_seconds,
+        stats.sample_rate,
+        stats.schedule_time.as_secs_f64(),
+        render_seconds,
+        audio_seconds / render_seconds.max(f64::EPSILON),
+    )
+}
+
+fn log_level(verbose: u8) -> Level {
+    match verbose {
+        0 => Level::WARN,
+        1 => Level::INFO,
+        2 => Level::DEBUG,
+        _ => Level::TRACE,
+    }
 }
 
 fn validate_cli_paths_with<F>(
@@ -86,7 +272,58 @@ where
 {
     let midi = midi.filter(|path| exists(path));
     let soundfont = soundfont.filter(|path| exists(path));
-    CliArgs { midi, soundfont }
+    CliArgs {
+        midi,
+        soundfont,
+        ..default()
+    }
+}
+
+// Out-of-range sizes fall back to the defaults with a warning rather than
+// refusing to start.
+fn preview_size_from_cli(width: Option<usize>, height: Option<usize>) -> PreviewSize {
+    let default = PreviewSize::default();
+    let size = PreviewSize::new(
+        width.unwrap_or(default.max_width),
+        height.unwrap_or(default.height),
+    );
+    if let Some(width) = width.filter(|width| *width != size.max_width) {
+        warn!(
+            "Preview width {width} is outside {:?}; using {}",
+            PreviewSize::WIDTH_RANGE,
+            size.max_width
+        );
+    }
+    if let Some(height) = height.filter(|height| *height != size.height) {
+        warn!(
+            "Preview height {height} is outside {:?}; using {}",
+            PreviewSize::HEIGHT_RANGE,
+            size.height
+        );
+    }
+    size
+}
+
+fn parse_resolution(value: &str) -> Result<(u32, u32), String> {
+    let (width, height) = value
+        .split_once(['x', 'X'])
+        .ok_or_else(|| format!("expected WIDTHxHEIGHT, got {value:?}"))?;
+    let width: u32 = width
+        .trim()
+        .parse()
+        .map_err(|_| format!("bad width {width:?}"))?;
+    let height: u32 = height
+        .trim()
+        .parse()
+        .map_err(|_| format!("bad height {height:?}"))?;
+    if !PixelRender::WIDTH_RANGE.contains(&width) || !PixelRender::HEIGHT_RANGE.contains(&height) {
+        return Err(format!(
+            "{width}x{height} is outside {:?} by {:?}",
+            PixelRender::WIDTH_RANGE,
+            PixelRender::HEIGHT_RANGE
+        ));
+    }
+    Ok((width, height))
 }
 
 fn maximize_primary_window(mut windows: Query<&mut Window, With<PrimaryWindow>>) {
@@ -98,10 +335,13 @@ fn maximize_primary_window(mut windows: Query<&mut Window, With<PrimaryWindow>>)
 
 #[cfg(test)]
 mod tests {
-    use super::{validate_cli_paths_with, CliArgs};
+    use super::{bench_report, log_level, validate_cli_paths_with, CliArgs};
+    use crate::audio::OfflineRenderStats;
+    use bevy::log::Level;
     use clap::Parser;
     use std::collections::HashSet;
     use std::path::PathBuf;
+    use std::time::Duration;
 
     #[test]
     fn parse_cli_args_reads_paths() {
@@ -111,6 +351,54 @@ mod tests {
         assert_eq!(parsed.soundfont.unwrap().to_string_lossy(), "piano.sf2");
     }
 
+    #[test]
+    fn verbose_flag_raises_log_level() {
+        let quiet = CliArgs::try_parse_from(["sona"]).expect("parse args");
+        assert_eq!(log_level(quiet.verbose), Level::WARN);
+        let verbose = CliArgs::try_parse_from(["sona", "-vv"]).expect("parse args");
+        assert_eq!(log_level(verbose.verbose), Level::DEBUG);
+        assert_eq!(log_level(9), Level::TRACE);
+    }
+
+    #[test]
+    fn bench_takes_midi_and_soundfont() {
+        let parsed = CliArgs::try_parse_from(["sona", "--bench", "song.mid", "piano.sf2"])
+            .expect("parse args");
+        assert_eq!(
+            parsed.bench,
+            Some(vec![PathBuf::from("song.mid"), PathBuf::from("piano.sf2")])
+        );
+        assert!(CliArgs::try_parse_from(["sona", "--bench", "song.mid"]).is_err());
+        let report = bench_report(&OfflineRenderStats {
+            frames: 96_000,
+            sample_rate: 48_000,
+            schedule_time: Duration::from_millis(5),
+            render_time: Duration::from_millis(500),
+        });
+        assert_eq!(
+            report,
+            "Rendered 96000 frames (2.0s of audio at 48000 Hz)\nSchedule: 0.005s\nRender: 0.500s (4.0x realtime)"
+        );
+    }
+
+    #[test]
+    fn render_needs_midi_and_soundfont() {
+        let parsed = CliArgs::try_parse_from([
+            "sona",
+            "-m",
+            "song.mid",
+            "-s",
+            "piano.sf2",
+            "--render",
+            "out.wav",
+        ])
+        .expect("parse args");
+        assert_eq!(parsed.render, Some(PathBuf::from("out.wav")));
+        assert!(
+            CliArgs::try_parse_from(["sona", "-m", "song.mid", "--render", "out.wav"]).is_err()
+        );
+    }
+
     #[test]
     fn parse_cli_args_short_flags() {
         let args = vec!["sona", "-m", "song.mid"];
@@ -119,6 +407,39 @@ mod tests {
         assert!(parsed.soundfont.is_none());
     }
 
+    #[test]
+    fn parse_cli_args_reads_ui_scale() {
+        let args = vec!["sona", "--ui-scale", "1.5"];
+        let parsed = CliArgs::try_parse_from(args).expect("parse args");
+        assert_eq!(parsed.ui_scale, Some(1.5));
+    }
+
+    #[test]
+    fn parse_cli_args_reads_pixel_resolution() {
+        let args = vec![
+            "sona",
+            "--pixel-resolution",
+            "640x360",
+            "--pixel-scale",
+            "2",
+        ];
+        let parsed = CliArgs::try_parse_from(args).expect("parse args");
+        assert_eq!(parsed.pixel_resolution, Some((640, 360)));
+        assert_eq!(parsed.pixel_scale, Some(2));
+        assert!(CliArgs::try_parse_from(vec!["sona", "--pixel-resolution", "640"]).is_err());
+        assert!(CliArgs::try_parse_from(vec!["sona", "--pixel-resolution", "16x16"]).is_err());
+        assert!(CliArgs::try_parse_from(vec!["sona", "--pixel-scale", "0"]).is_err());
+    }
+
+    #[test]
+    fn parse_cli_args_reads_remote_port() {
+        let args = vec!["sona", "--remote", "7070"];
+        let parsed = CliArgs::try_parse_from(args).expect("parse args");
+        assert_eq!(parsed.remote, Some(7070));
+        let parsed = CliArgs::try_parse_from(vec!["sona"]).expect("parse args");
+        assert!(parsed.remote.is_none());
+    }
+
     #[test]
     fn start_on_tracks_when_both_paths_present() {
         let args = vec!["sona", "--midi", "song.mid", "--soundfont", "piano.sf2"];
@@ -0,0 +1,136 @@
+use crate::state::SongEnd;
+use crate::tempo::TempoMap;
+use midly::{MetaMessage, Smf, TrackEventKind};
+
+/// File-wide timing read straight from the SMF. The track summaries and the
+/// playback schedule both take their ruler and tempo from here, so what is
+/// shown always ends where playback does.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct FileTiming {
+    pub ticks_per_beat: u32,
+    /// Set for SMPTE-timed files, whose ticks are a fixed fraction of a
+    /// second whatever their tempo events say.
+    pub ticks_per_second: Option<f64>,
+    /// `(tick, microseconds per beat)` from every track, in file order.
+    pub tempo_events: Vec<(u64, u32)>,
+    /// Last event of any kind.
+    pub max_tick: u64,
+    /// Last note-on or matched note-off, or the end of a track that leaves
+    /// notes held.
+    pub max_note_tick: u64,
+    /// Latest End of Track meta across the tracks.
+    pub end_of_track_tick: Option<u64>,
+}
+
+impl FileTiming {
+    /// End of the ruler and of playback before the reverb tail: the last
+    /// note, or the last event in a file without notes.
+    pub fn ruler_max_tick(&self) -> u64 {
+        if self.max_note_tick > 0 {
+            self.max_note_tick
+        } else {
+            self.max_tick
+        }
+    }
+
+    /// Where playback stops before the reverb tail. An End of Track marker
+    /// only extends the ruler end, so a file whose markers sit early still
+    /// plays every note.
+    pub fn end_tick(&self, song_end: SongEnd) -> u64 {
+        match song_end {
+            SongEnd::LastNote => self.ruler_max_tick(),
+            SongEnd::EndOfTrack => self
+                .end_of_track_tick
+                .map_or(self.ruler_max_tick(), |tick| {
+                    tick.max(self.ruler_max_tick())
+                }),
+        }
+    }
+
+    /// Tick-to-seconds map for the file, or at `tempo_override`
+    /// microseconds per beat throughout. SMPTE timing ignores both.
+    pub fn tempo_map(&self, tempo_override: Option<u32>) -> TempoMap {
+        match (self.ticks_per_second, tempo_override) {
+            (Some(ticks_per_second), _) => {
+                TempoMap::timecode(ticks_per_second, self.ticks_per_beat)
+            }
+            (None, Some(us_per_beat)) => TempoMap::new(&[(0, us_per_beat)], self.ticks_per_beat),
+            (None, None) => TempoMap::new(&self.tempo_events, self.ticks_per_beat),
+        }
+    }
+}
+
+fn ticks_per_second(smf: &Smf) -> Option<f64> {
+    match smf.header.timing {
+        midly::Timing::Metrical(_) => None,
+        midly::Timing::Timecode(fps, subframes) => {
+            Some((fps.as_f32() as f64 * subframes as f64).max(1.0))
+        }
+    }
+}
+
+// SMPTE-timed files have no beat length; the grid and metronome count half
+// seconds there, a beat at the default 120 BPM.
+fn ticks_per_beat(smf: &Smf) -> u32 {
+    match smf.header.timing {
+        midly::Timing::Metrical(ticks) => ticks.as_int() as u32,
+        midly::Timing::Timecode(_, _) => {
+            (ticks_per_second(smf).unwrap_or(960.0) / 2.0).round() as u32
+        }
+    }
+    .max(1)
+}
+
+pub fn file_timing(smf: &Smf) -> FileTiming {
+    let mut tempo_events = Vec::new();
+    let mut max_tick = 0u64;
+    let mut max_note_tick = 0u64;
+    let mut end_of_track_tick: Option<u64> = None;
+
+    for track in &smf.tracks {
+        let mut current_tick = 0u64;
+        // Held note count per key; which note-on a note-off closes doesn't
+        // change where the last one ends.
+        let mut held = [0u32; 128];
+        for event in track {
+            current_tick += event.delta.as_int() as u64;
+            max_tick = max_tick.max(current_tick);
+            match event.kind {
+                TrackEventKind::Midi { message, .. } => match message {
+                    midly::MidiMessage::NoteOn { key, vel } if vel.as_int() > 0 => {
+                        held[key.as_int() as usize] += 1;
+                        max_note_tick = max_note_tick.max(current_tick);
+                    }
+                    midly::MidiMessage::NoteOn { key, .. }
+                    | midly::MidiMessage::NoteOff { key, .. } => {
+                        let count = &mut held[key.as_int() as usize];
+                        if *count > 0 {
+                            *count -= 1;
+                            max_note_tick = max_note_tick.max(current_tick);
+                        }
+                    }
+                    _ => {}
+                },
+                TrackEventKind::Meta(MetaMessage::Tempo(us)) => {
+                    tempo_events.push((current_tick, us.as_int()));
+                }
+                TrackEventKind::Meta(MetaMessage::EndOfTrack) => {
+                    end_of_track_tick = end_of_track_tick.max(Some(current_tick));
+                }
+                _ => {}
+            }
+        }
+        if held.iter().any(|count| *count > 0) {
+            max_note_tick = max_note_tick.max(current_tick);
+        }
+    }
+
+    FileTiming {
+        ticks_per_beat: ticks_per_beat(smf),
+        ticks_per_second: ticks_per_second(smf),
+        tempo_events,
+        max_tick,
+        max_note_tick,
+        end_of_track_tick,
+    }
+}
@@ -0,0 +1,228 @@
+use crate::audio::{AudioCommand, AudioState};
+use crate::input::{toggle_playback, FileLoader};
+use crate::state::PlaybackState;
+use bevy::log::{error, info};
+use bevy::prelude::{App, Plugin, Res, Resource, Update};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+#[derive(Debug, PartialEq)]
+enum RemoteCommand {
+    Play,
+    Pause,
+    Stop,
+    Rewind,
+    Seek(u64),
+    Load(PathBuf),
+    Status,
+}
+
+struct RemoteRequest {
+    command: RemoteCommand,
+    reply: Sender<String>,
+}
+
+#[derive(Resource)]
+struct RemoteReceiver(Mutex<Receiver<RemoteRequest>>);
+
+pub struct RemotePlugin {
+    pub port: Option<u16>,
+}
+
+impl Plugin for RemotePlugin {
+    fn build(&self, app: &mut App) {
+        let Some(port) = self.port else {
+            return;
+        };
+        let listener = match TcpListener::bind(("127.0.0.1", port)) {
+            Ok(listener) => listener,
+            Err(err) => {
+                error!("Failed to start remote control on port {port}: {err}");
+                return;
+            }
+        };
+        info!("Remote control listening on 127.0.0.1:{port}");
+
+        let (request_tx, request_rx) = channel::<RemoteRequest>();
+        let _ = thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let request_tx = request_tx.clone();
+                let _ = thread::spawn(move || handle_client(stream, request_tx));
+            }
+        });
+        let _app = app
+            .insert_resource(RemoteReceiver(Mutex::new(request_rx)))
+            .add_systems(Update, handle_remote_requests);
+    }
+}
+
+fn parse_remote_command(line: &str) -> Result<RemoteCommand, String> {
+    let line = line.trim();
+    let (name, arg) = match line.split_once(char::is_whitespace) {
+        Some((name, arg)) => (name, arg.trim()),
+        None => (line, ""),
+    };
+    let no_arg = |command: RemoteCommand| {
+        if arg.is_empty() {
+            Ok(command)
+        } else {
+            Err(format!("{name} takes no arguments"))
+        }
+    };
+    match name.to_lowercase().as_str() {
+        "play" => no_arg(RemoteCommand::Play),
+        "pause" => no_arg(RemoteCommand::Pause),
+        "stop" => no_arg(RemoteCommand::Stop),
+        "rewind" => no_arg(RemoteCommand::Rewind),
+        "status" => no_arg(RemoteCommand::Status),
+        "seek" => arg
+            .parse::<u64>()
+            .map(RemoteCommand::Seek)
+            .map_err(|_| format!("seek expects a tick, got {arg:?}")),
+        "load" if arg.is_empty() => Err("load expects a path".to_string()),
+        "load" => Ok(RemoteCommand::Load(PathBuf::from(arg))),
+        "" => Err("empty command".to_string()),
+        other => Err(format!("unknown command {other:?}")),
+    }
+}
+
+fn handle_client(stream: TcpStream, request_tx: Sender<RemoteRequest>) {
+    let Ok(mut writer) = stream.try_clone() else {
+        return;
+    };
+    for line in BufReader::new(stream).lines() {
+        let Ok(line) = line else {
+            return;
+        };
+        let response = match parse_remote_command(&line) {
+            Ok(command) => {
+                let (reply_tx, reply_rx) = channel();
+                let request = RemoteRequest {
+                    command,
+                    reply: reply_tx,
+                };
+                if request_tx.send(request).is_err() {
+                    return;
+                }
+                reply_rx
+                    .recv_timeout(Duration::from_secs(2))
+                    .unwrap_or_else(|_| "error: timed out".to_string())
+            }
+            Err(err) => format!("error: {err}"),
+        };
+        if writeln!(writer, "{response}").is_err() {
+            return;
+        }
+    }
+}
+
+fn handle_remote_requests(
+    receiver: Res<RemoteReceiver>,
+    audio_state: Res<AudioState>,
+    mut loader: FileLoader,
+) {
+    let Ok(receiver) = receiver.0.lock() else {
+        return;
+    };
+    while let Ok(request) = receiver.try_recv() {
+        let playing = loader.playback_status.state == PlaybackState::Playing;
+        let response = match request.command {
+            RemoteCommand::Play => {
+                if !playing {
+                    toggle(&mut loader);
+                }
+                if loader.playback_status.state == PlaybackState::Playing {
+                    "ok".to_string()
+                } else {
+                    "error: MIDI file and SoundFont must both be set".to_string()
+                }
+            }
+            RemoteCommand::Pause => {
+                if playing {
+                    toggle(&mut loader);
+                }
+                "ok".to_string()
+            }
+            RemoteCommand::Stop => {
+                loader.playback_status.state = PlaybackState::Stopped;
+                let _ = loader.audio_tx.0.send(AudioCommand::Stop);
+                "ok".to_string()
+            }
+            RemoteCommand::Rewind => {
+                let _ = loader.audio_tx.0.send(AudioCommand::Rewind);
+                "ok".to_string()
+            }
+            RemoteCommand::Seek(tick) => {
+                let _ = loader.audio_tx.0.send(AudioCommand::Seek(tick));
+                "ok".to_string()
+            }
+            RemoteCommand::Load(path) => {
+                if path.is_file() {
+                    loader.load_midi(path);
+                    "ok".to_string()
+                } else {
+                    format!("error: {} not found", path.display())
+                }
+            }
+            RemoteCommand::Status => {
+                let debug = audio_state.debug_state();
+                format!(
+                    "state={:?} tick={} max_tick={}",
+                    loader.playback_status.state,
+                    audio_state.current_tick().unwrap_or(0),
+                    debug.max_tick
+                )
+            }
+        };
+        let _ = request.reply.send(response);
+    }
+}
+
+// Play and Pause only toggle when that changes the state, so a repeated
+// command is a no-op.
+fn toggle(loader: &mut FileLoader) {
+    toggle_playback(
+        &mut loader.playback_status,
+        &loader.midi_path,
+        &loader.soundfont_path,
+        &loader.audio_tx,
+        &mut loader.ui_state,
+        &mut loader.status,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_remote_command, RemoteCommand};
+    use std::path::PathBuf;
+
+    #[test]
+    fn parse_remote_command_reads_commands() {
+        assert_eq!(parse_remote_command("play"), Ok(RemoteCommand::Play));
+        assert_eq!(parse_remote_command(" PAUSE \r"), Ok(RemoteCommand::Pause));
+        assert_eq!(
+            parse_remote_command("seek 5000"),
+            Ok(RemoteCommand::Seek(5000))
+        );
+        assert_eq!(
+            parse_remote_command("load /tmp/my song.mid"),
+            Ok(RemoteCommand::Load(PathBuf::from("/tmp/my song.mid")))
+        );
+        assert_eq!(parse_remote_command("status"), Ok(RemoteCommand::Status));
+    }
+
+    #[test]
+    fn parse_remote_command_rejects_malformed_input() {
+        assert!(parse_remote_command("").is_err());
+        assert!(parse_remote_command("seek").is_err());
+        assert!(parse_remote_command("seek soon").is_err());
+        assert!(parse_remote_command("load").is_err());
+        assert!(parse_remote_command("play now").is_err());
+        assert!(parse_remote_command("dance").is_err());
+    }
+}
@@ -0,0 +1,245 @@
+use crate::state::{
+    Equalizer, FileGains, MasterVolume, MidiFilePath, RecentFile, RecentFiles, RecentKind,
+    SoundFontGains, SoundFontPath, StereoWidth,
+};
+use bevy::log::{error, warn};
+use bevy::prelude::{
+    App, DetectChanges, IntoScheduleConfigs, Plugin, Res, ResMut, Startup, Update,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+const SESSION_PATH: &str = "session.toml";
+
+/// What survives between runs, stored next to `keybindings.toml`.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+struct Session {
+    recent: Vec<RecentFile>,
+    file_gains: Vec<FileGain>,
+    soundfont_gains: Vec<FileGain>,
+    stereo_width: Option<f32>,
+    equalizer: Option<Equalizer>,
+    master_volume: Option<f32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct FileGain {
+    path: PathBuf,
+    gain_db: f32,
+}
+
+impl Session {
+    fn parse(content: &str) -> Option<Self> {
+        toml::from_str(content).ok()
+    }
+}
+
+pub struct SessionPlugin;
+
+impl Plugin for SessionPlugin {
+    fn build(&self, app: &mut App) {
+        let _app = app
+            .init_resource::<RecentFiles>()
+            .init_resource::<FileGains>()
+            .init_resource::<SoundFontGains>()
+            .init_resource::<StereoWidth>()
+            .init_resource::<Equalizer>()
+            .init_resource::<MasterVolume>()
+            .add_systems(Startup, load_session)
+            .add_systems(Update, (remember_opened_files, save_session).chain());
+    }
+}
+
+fn load_session(
+    mut recent: ResMut<RecentFiles>,
+    mut file_gains: ResMut<FileGains>,
+    mut soundfont_gains: ResMut<SoundFontGains>,
+    mut stereo_width: ResMut<StereoWidth>,
+    mut equalizer: ResMut<Equalizer>,
+    mut master_volume: ResMut<MasterVolume>,
+) {
+    let Ok(content) = std::fs::read_to_string(SESSION_PATH) else {
+        return;
+    };
+    let Some(session) = Session::parse(&content) else {
+        warn!("Failed to parse {SESSION_PATH}");
+        return;
+    };
+    recent.0 = session.recent;
+    recent.retain_existing(|path| path.is_file());
+    file_gains.0 = session
+        .file_gains
+        .into_iter()
+        .map(|entry| {
+            let gain_db = entry.gain_db.clamp(-FileGains::MAX_DB, FileGains::MAX_DB);
+            (entry.path, gain_db)
+        })
+        .collect();
+    soundfont_gains.0 = session
+        .soundfont_gains
+        .into_iter()
+        .map(|entry| {
+            let gain_db = entry
+                .gain_db
+                .clamp(-SoundFontGains::MAX_DB, SoundFontGains::MAX_DB);
+            (entry.path, gain_db)
+        })
+        .collect();
+    if let Some(width) = session.stereo_width {
+        stereo_width.0 = StereoWidth::clamped(width);
+    }
+    if let Some(saved) = session.equalizer {
+        *equalizer = Equalizer {
+            low_db: Equalizer::clamped(saved.low_db),
+            mid_db: Equalizer::clamped(saved.mid_db),
+            high_db: Equalizer::clamped(saved.high_db),
+        };
+    }
+    if let Some(volume) = session.master_volume {
+        master_volume.0 = MasterVolume::clamped(volume);
+    }
+}
+
+fn remember_opened_files(
+    midi_path: Res<MidiFilePath>,
+    soundfont_path: Res<SoundFontPath>,
+    mut recent: ResMut<RecentFiles>,
+) {
+    if soundfont_path.is_changed() {
+        if let Some(path) = &soundfont_path.0 {
+            recent.remember(RecentKind::SoundFont, path.clone());
+        }
+    }
+    if midi_path.is_changed() {
+        if let Some(path) = &midi_path.0 {
+            recent.remember(RecentKind::Midi, path.clone());
+        }
+    }
+}
+
+fn save_session(
+    recent: Res<RecentFiles>,
+    file_gains: Res<FileGains>,
+    soundfont_gains: Res<SoundFontGains>,
+    stereo_width: Res<StereoWidth>,
+    equalizer: Res<Equalizer>,
+    master_volume: Res<MasterVolume>,
+) {
+    if !recent.is_changed()
+        && !file_gains.is_changed()
+        && !soundfont_gains.is_changed()
+        && !stereo_width.is_changed()
+        && !equalizer.is_changed()
+        && !master_volume.is_changed()
+    {
+        return;
+    }
+    let session = Session {
+        recent: recent.0.clone(),
+        file_gains: sorted_file_gains(&file_gains.0),
+        soundfont_gains: sorted_file_gains(&soundfont_gains.0),
+        stereo_width: Some(stereo_width.0),
+        equalizer: Some(*equalizer),
+        master_volume: Some(master_volume.0),
+    };
+    match toml::to_string(&session) {
+        Ok(content) => {
+            if let Err(err) = std::fs::write(SESSION_PATH, content) {
+                error!("Failed to write {SESSION_PATH}: {err}");
+            }
+        }
+        Err(err) => error!("Failed to serialize session: {err}"),
+    }
+}
+
+// Sorted so the saved file doesn't reshuffle on every write.
+fn sorted_file_gains(gains: &HashMap<PathBuf, f32>) -> Vec<FileGain> {
+    let mut entries: Vec<FileGain> = gains
+        .iter()
+        .map(|(path, gain_db)| FileGain {
+            path: path.clone(),
+            gain_db: *gain_db,
+        })
+        .collect();
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{sorted_file_gains, FileGain, Session};
+    use crate::state::{Equalizer, FileGains, RecentFile, RecentFiles, RecentKind};
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn session_round_trips_recent_files() {
+        let mut recent = RecentFiles::default();
+        recent.remember(RecentKind::SoundFont, PathBuf::from("piano.sf2"));
+        recent.remember(RecentKind::Midi, PathBuf::from("song.mid"));
+        let session = Session {
+            recent: recent.0.clone(),
+            ..Session::default()
+        };
+        let content = toml::to_string(&session).expect("serialize session");
+        assert_eq!(Session::parse(&content), Some(session));
+        assert_eq!(Session::parse(""), Some(Session::default()));
+        assert_eq!(Session::parse("recent = 3"), None);
+    }
+
+    #[test]
+    fn session_round_trips_file_gains() {
+        let mut file_gains = FileGains::default();
+        let _prev = file_gains.0.insert(PathBuf::from("b.mid"), -3.5);
+        let _prev = file_gains.0.insert(PathBuf::from("a.mid"), 6.0);
+        let session = Session {
+            file_gains: sorted_file_gains(&file_gains.0),
+            soundfont_gains: sorted_file_gains(&file_gains.0),
+            stereo_width: Some(0.5),
+            equalizer: Some(Equalizer {
+                low_db: -3.0,
+                mid_db: 0.0,
+                high_db: 1.5,
+            }),
+            master_volume: Some(0.8),
+            ..Session::default()
+        };
+        assert_eq!(
+            session.file_gains,
+            vec![
+                FileGain {
+                    path: PathBuf::from("a.mid"),
+                    gain_db: 6.0,
+                },
+                FileGain {
+                    path: PathBuf::from("b.mid"),
+                    gain_db: -3.5,
+                },
+            ]
+        );
+        let content = toml::to_string(&session).expect("serialize session");
+        assert_eq!(Session::parse(&content), Some(session));
+    }
+
+    #[test]
+    fn recent_files_move_to_front_and_cap() {
+        let mut recent = RecentFiles::default();
+        for index in 0..10 {
+            recent.remember(RecentKind::Midi, PathBuf::from(format!("{index}.mid")));
+        }
+        assert_eq!(recent.0.len(), RecentFiles::LIMIT);
+        assert_eq!(recent.0[0].path, PathBuf::from("9.mid"));
+        recent.remember(RecentKind::Midi, PathBuf::from("5.mid"));
+        assert_eq!(recent.0.len(), RecentFiles::LIMIT);
+        assert_eq!(
+            recent.0[0],
+            RecentFile {
+                kind: RecentKind::Midi,
+                path: PathBuf::from("5.mid"),
+            }
+        );
+        recent.retain_existing(|path| path != Path::new("9.mid"));
+        assert!(recent.0.iter().all(|file| file.path != Path::new("9.mid")));
+    }
+}
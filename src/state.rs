@@ -1,5 +1,9 @@
+use crate::midi::FileTiming;
 use bevy::prelude::Resource;
-use std::path::PathBuf;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub enum UiSelection {
@@ -9,6 +13,7 @@ pub enum UiSelection {
     Play,
     Stop,
     Rewind,
+    Recent(usize),
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
@@ -18,12 +23,59 @@ pub enum UiPage {
     About,
     Tracks,
     PianoRoll,
+    Settings,
+    Lyrics,
+    /// Tracks list and piano roll side by side.
+    Split,
+    Mixer,
+}
+
+impl UiPage {
+    pub fn shows_tracks(self) -> bool {
+        matches!(self, UiPage::Tracks | UiPage::Split)
+    }
+
+    pub fn shows_piano_roll(self) -> bool {
+        matches!(self, UiPage::PianoRoll | UiPage::Split)
+    }
 }
 
 #[derive(Resource, Default)]
 pub struct UiState {
     pub selection: UiSelection,
     pub page: UiPage,
+    /// Pages we came from, most recent last; `back` pops from here.
+    pub page_stack: Vec<UiPage>,
+}
+
+impl UiState {
+    /// Opens `page`, remembering the current one. Opening a page that is
+    /// already on the stack unwinds to it instead of growing the stack.
+    pub fn open_page(&mut self, page: UiPage) {
+        if page == self.page {
+            return;
+        }
+        if let Some(position) = self.page_stack.iter().position(|&p| p == page) {
+            self.page_stack.truncate(position);
+        } else {
+            self.page_stack.push(self.page);
+        }
+        self.page = page;
+    }
+
+    /// Returns to the previous page, or the splash screen when there is none.
+    pub fn back(&mut self) {
+        self.page = self.page_stack.pop().unwrap_or(UiPage::Splash);
+    }
+
+    /// Goes back when `page` is already showing, otherwise opens it.
+    pub fn toggle_page(&mut self, page: UiPage) {
+        if self.page == page {
+            self.back();
+        } else {
+            self.open_page(page);
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -33,37 +85,170 @@ pub struct MidiTrackInfo {
     pub event_count: usize,
     pub end_tick: u64,
     pub ticks_per_beat: u32,
+    /// The file's timing, shared by every track; the tempo map and song end
+    /// come from here, as they do for playback.
+    pub timing: Arc<FileTiming>,
     pub note_count: usize,
     pub min_pitch: u8,
     pub max_pitch: u8,
     pub channels: Vec<u8>,
     pub programs: Vec<(u8, u8)>,
     pub banks: Vec<(u8, u8, u8)>,
-    pub tempo_changes: usize,
     pub time_signature: Option<(u8, u8)>,
+    pub time_signature_events: Vec<(u64, u8, u8)>,
     pub key_signature: Option<(i8, bool)>,
+    pub sustain_events: Vec<(u64, u8, bool)>,
+    /// CC10 changes as (tick, channel, value); 0 is hard left, 64 centre.
+    pub pan_events: Vec<(u64, u8, u8)>,
+    pub lyric_events: Vec<(u64, String)>,
+    /// Standard named by the first GM/GS/XG reset SysEx in the track.
+    pub midi_standard: Option<MidiStandard>,
+    pub instrument_name: Option<String>,
+    /// First MIDI port meta, for files that address more than 16 channels.
+    pub midi_port: Option<u8>,
+    /// First MIDI channel prefix meta (0-based).
+    pub channel_prefix: Option<u8>,
+    /// First copyright notice; by convention only the first track has one.
+    pub copyright: Option<String>,
+    pub cue_points: Vec<(u64, String)>,
+    /// Marker meta events as (tick, text), e.g. rehearsal letters.
+    pub markers: Vec<(u64, String)>,
+    pub articulation: ArticulationCounts,
+    pub rhythm: RhythmSummary,
     pub note_spans: Vec<NoteSpan>,
     pub preview_width: usize,
     pub preview_height: usize,
     pub preview_cells: Vec<u16>,
+    /// Same grid as `preview_cells` with only each note's first column lit.
+    pub onset_preview_cells: Vec<u16>,
+}
+
+/// Sound set a file targets, as announced by its reset SysEx. GS and XG
+/// files may rely on bank layouts and drum kits a plain GM SoundFont lacks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MidiStandard {
+    Gm,
+    Gm2,
+    Gs,
+    Xg,
+}
+
+impl MidiStandard {
+    pub fn label(self) -> &'static str {
+        match self {
+            MidiStandard::Gm => "GM",
+            MidiStandard::Gm2 => "GM2",
+            MidiStandard::Gs => "GS",
+            MidiStandard::Xg => "XG",
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct NoteSpan {
+    pub channel: u8,
     pub pitch: u8,
     pub start: u64,
     pub end: u64,
+    /// NoteOn velocity.
+    pub velocity: u8,
+}
+
+/// Scientific pitch name, e.g. 60 is "C4".
+pub fn note_name(pitch: u8) -> String {
+    const NAMES: [&str; 12] = [
+        "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+    ];
+    let octave = (pitch / 12) as i32 - 1;
+    let name = NAMES[(pitch % 12) as usize];
+    format!("{name}{octave}")
+}
+
+/// `note_name` after shifting by `display_transpose` semitones, clamped to
+/// the MIDI range.
+pub fn display_note_name(pitch: u8, display_transpose: i8) -> String {
+    note_name((pitch as i16 + display_transpose as i16).clamp(0, 127) as u8)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ArticulationCounts {
+    pub staccato: usize,
+    pub normal: usize,
+    pub legato: usize,
+}
+
+/// Note lengths quantized to standard values, plus the time in which the
+/// track is silent before its last note ends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RhythmSummary {
+    /// Counts of whole, half, quarter, eighth, sixteenth and 32nd notes.
+    pub lengths: [usize; 6],
+    /// Notes too far from any standard length, e.g. triplets or ties.
+    pub irregular: usize,
+    pub rest_ticks: u64,
+}
+
+impl RhythmSummary {
+    pub const LENGTH_BEATS: [f64; 6] = [4.0, 2.0, 1.0, 0.5, 0.25, 0.125];
 }
 
 #[derive(Resource, Default)]
 pub struct MidiTracks(pub Vec<MidiTrackInfo>);
 
+/// Indices into `tracks` of the rows the tracks list shows. Tracks without
+/// notes are left out when `hide_empty` is set, unless that would leave
+/// nothing to show.
+pub fn listed_track_indices(tracks: &[MidiTrackInfo], hide_empty: bool) -> Vec<usize> {
+    let with_notes: Vec<usize> = tracks
+        .iter()
+        .enumerate()
+        .filter(|(_, track)| track.note_count > 0)
+        .map(|(index, _)| index)
+        .collect();
+    if hide_empty && !with_notes.is_empty() {
+        with_notes
+    } else {
+        (0..tracks.len()).collect()
+    }
+}
+
+/// File-wide facts worked out once per load, before anything plays.
+#[derive(Resource, Debug, Clone, Copy, Default, PartialEq)]
+pub struct FileSummary {
+    /// Length at the file's own tempo up to where playback stops: the last
+    /// note or the End of Track marker, per the song end preference. Zero
+    /// with no file loaded.
+    pub duration_seconds: f64,
+}
+
+/// A second file whose notes are overlaid on the piano roll for comparison.
+#[derive(Resource, Debug, Default)]
+pub struct CompareFile {
+    pub path: Option<PathBuf>,
+    pub tracks: Vec<MidiTrackInfo>,
+    pub visible: bool,
+}
+
+impl CompareFile {
+    pub fn file_name(&self) -> String {
+        self.path
+            .as_deref()
+            .and_then(Path::file_name)
+            .map_or_else(String::new, |name| name.to_string_lossy().into_owned())
+    }
+}
+
 #[derive(Resource, Default)]
 pub struct MidiFilePath(pub Option<PathBuf>);
 
 #[derive(Resource, Default)]
 pub struct SoundFontPath(pub Option<PathBuf>);
 
+/// SoundFonts stacked over `SoundFontPath`, in the order they were added;
+/// later ones win for presets they share.
+#[derive(Resource, Default)]
+pub struct SoundFontLayers(pub Vec<PathBuf>);
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub enum PlaybackState {
     #[default]
@@ -77,9 +262,396 @@ pub struct PlaybackStatus {
     pub state: PlaybackState,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RecentKind {
+    Midi,
+    SoundFont,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecentFile {
+    pub kind: RecentKind,
+    pub path: PathBuf,
+}
+
+/// Loudness-normalizing gain in dB per MIDI file, measured once by rendering
+/// the file offline and kept in the session.
+#[derive(Resource, Debug, Clone, Default, PartialEq)]
+pub struct FileGains(pub HashMap<PathBuf, f32>);
+
+impl FileGains {
+    /// Largest boost or cut applied, so near-silent or clipping files are
+    /// not pushed to extremes.
+    pub const MAX_DB: f32 = 12.0;
+}
+
+/// Input gain in dB per SoundFont, set by the user so fonts that render
+/// hot or quiet start from a similar level. Kept in the session.
+#[derive(Resource, Debug, Clone, Default, PartialEq)]
+pub struct SoundFontGains(pub HashMap<PathBuf, f32>);
+
+impl SoundFontGains {
+    pub const MAX_DB: f32 = 18.0;
+    pub const STEP_DB: f32 = 1.0;
+
+    /// Gain for `path` moved by `delta_db`, kept within `MAX_DB` either way.
+    pub fn stepped(&self, path: &Path, delta_db: f32) -> f32 {
+        let current = self.0.get(path).copied().unwrap_or(0.0);
+        (current + delta_db).clamp(-Self::MAX_DB, Self::MAX_DB)
+    }
+}
+
+/// What the click track follows during playback.
+#[derive(Resource, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Metronome {
+    #[default]
+    Off,
+    /// Every beat, accented on the first beat of each bar.
+    Beats,
+    /// Every note onset in the focused track, so it clicks the part's
+    /// rhythm; onsets on a downbeat are accented.
+    Onsets,
+}
+
+impl Metronome {
+    pub fn next(self) -> Self {
+        match self {
+            Metronome::Off => Metronome::Beats,
+            Metronome::Beats => Metronome::Onsets,
+            Metronome::Onsets => Metronome::Off,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Metronome::Off => "off",
+            Metronome::Beats => "beats",
+            Metronome::Onsets => "focused track's notes",
+        }
+    }
+}
+
+/// One channel on the mixer page.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChannelStrip {
+    /// Percent of the file's channel volume (CC7) that reaches the synth.
+    pub volume: u8,
+    /// Fixed pan (CC10) in place of the file's, or `None` to follow it.
+    pub pan: Option<u8>,
+    /// GM program (0-based) played in place of the file's ProgramChanges.
+    pub program: Option<u8>,
+    pub mute: bool,
+    pub solo: bool,
+}
+
+impl Default for ChannelStrip {
+    fn default() -> Self {
+        Self {
+            volume: 100,
+            pan: None,
+            program: None,
+            mute: false,
+            solo: false,
+        }
+    }
+}
+
+/// Channels that sound: not muted, and soloed whenever any channel is.
+pub fn audible_channels(strips: &[ChannelStrip; 16]) -> [bool; 16] {
+    let any_solo = strips.iter().any(|strip| strip.solo);
+    std::array::from_fn(|channel| {
+        let strip = strips[channel];
+        !strip.mute && (strip.solo || !any_solo)
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MixerControl {
+    #[default]
+    Volume,
+    Pan,
+    Program,
+    Mute,
+    Solo,
+}
+
+impl MixerControl {
+    pub const ALL: [MixerControl; 5] = [
+        MixerControl::Volume,
+        MixerControl::Pan,
+        MixerControl::Program,
+        MixerControl::Mute,
+        MixerControl::Solo,
+    ];
+}
+
+/// Per-channel volume, pan, mute and solo, and the focused strip and control
+/// on the mixer page.
+#[derive(Resource, Debug, Clone, Default)]
+pub struct ChannelMixer {
+    pub strips: [ChannelStrip; 16],
+    pub channel: usize,
+    pub control: MixerControl,
+}
+
+impl ChannelMixer {
+    pub const VOLUME_STEP: u8 = 5;
+    pub const PAN_STEP: u8 = 8;
+
+    /// Past either end the focus wraps when `wrap` is set and stays put
+    /// otherwise, as on the splash menu.
+    pub fn step_channel(&mut self, forward: bool, wrap: bool) {
+        self.channel = match (forward, self.channel) {
+            (true, 15) if wrap => 0,
+            (true, 15) => 15,
+            (true, channel) => channel + 1,
+            (false, 0) if wrap => 15,
+            (false, 0) => 0,
+            (false, channel) => channel - 1,
+        };
+    }
+
+    pub fn step_control(&mut self, forward: bool) {
+        let count = MixerControl::ALL.len();
+        let current = MixerControl::ALL
+            .iter()
+            .position(|control| *control == self.control)
+            .unwrap_or(0);
+        let next = if forward {
+            (current + 1) % count
+        } else {
+            (current + count - 1) % count
+        };
+        self.control = MixerControl::ALL[next];
+    }
+
+    /// Up raises the volume, pans right or picks the next program; mute and
+    /// solo toggle either way. A pan or program that follows the file starts
+    /// from the centre or the first program.
+    pub fn adjust(&mut self, up: bool) {
+        if self.control == MixerControl::Program {
+            self.step_program(if up { 1 } else { -1 });
+            return;
+        }
+        let strip = &mut self.strips[self.channel];
+        match self.control {
+            MixerControl::Volume => {
+                strip.volume = if up {
+                    (strip.volume + Self::VOLUME_STEP).min(100)
+                } else {
+                    strip.volume.saturating_sub(Self::VOLUME_STEP)
+                };
+            }
+            MixerControl::Pan => {
+                let pan = strip.pan.unwrap_or(64);
+                strip.pan = Some(if up {
+                    (pan + Self::PAN_STEP).min(127)
+                } else {
+                    pan.saturating_sub(Self::PAN_STEP)
+                });
+            }
+            MixerControl::Program => {}
+            MixerControl::Mute => strip.mute = !strip.mute,
+            MixerControl::Solo => strip.solo = !strip.solo,
+        }
+    }
+
+    /// Overrides the focused channel's program, `delta` programs on from the
+    /// current override.
+    pub fn step_program(&mut self, delta: i16) {
+        let strip = &mut self.strips[self.channel];
+        strip.program = Some(match strip.program {
+            Some(program) => (program as i16 + delta).clamp(0, 127) as u8,
+            None => 0,
+        });
+    }
+
+    /// Mutes `channels` unless they all are already, in which case they are
+    /// unmuted. Returns whether they ended up muted.
+    pub fn toggle_mute(&mut self, channels: &[u8]) -> bool {
+        let mute = channels
+            .iter()
+            .any(|channel| !self.strips[*channel as usize % 16].mute);
+        for channel in channels {
+            self.strips[*channel as usize % 16].mute = mute;
+        }
+        mute
+    }
+
+    /// Solos `channels` unless they all are already, in which case their
+    /// solo is cleared. Once no channel is soloed, mutes apply as before.
+    pub fn toggle_solo(&mut self, channels: &[u8]) -> bool {
+        let solo = channels
+            .iter()
+            .any(|channel| !self.strips[*channel as usize % 16].solo);
+        for channel in channels {
+            self.strips[*channel as usize % 16].solo = solo;
+        }
+        solo
+    }
+
+    /// Drops every program override, e.g. once the file they were picked
+    /// for is unloaded.
+    pub fn clear_programs(&mut self) {
+        for strip in &mut self.strips {
+            strip.program = None;
+        }
+    }
+
+    /// Toggles mute or solo, or hands volume, pan or program back to the
+    /// file.
+    pub fn reset_focused(&mut self) {
+        let strip = &mut self.strips[self.channel];
+        match self.control {
+            MixerControl::Volume => strip.volume = 100,
+            MixerControl::Pan => strip.pan = None,
+            MixerControl::Program => strip.program = None,
+            MixerControl::Mute => strip.mute = !strip.mute,
+            MixerControl::Solo => strip.solo = !strip.solo,
+        }
+    }
+}
+
+/// Mid/side width of the synth output: 0 folds it to mono, 1 leaves it as
+/// rendered and above 1 widens it. Kept in the session.
+#[derive(Resource, Debug, Clone, Copy, PartialEq)]
+pub struct StereoWidth(pub f32);
+
+impl Default for StereoWidth {
+    fn default() -> Self {
+        Self(1.0)
+    }
+}
+
+impl StereoWidth {
+    pub const MAX: f32 = 2.0;
+    pub const STEP: f32 = 0.25;
+
+    pub fn clamped(width: f32) -> f32 {
+        if width.is_finite() {
+            width.clamp(0.0, Self::MAX)
+        } else {
+            1.0
+        }
+    }
+}
+
+/// Master output level applied after every other gain: 1 is unity and 2
+/// doubles it. Kept in the session.
+#[derive(Resource, Debug, Clone, Copy, PartialEq)]
+pub struct MasterVolume(pub f32);
+
+impl Default for MasterVolume {
+    fn default() -> Self {
+        Self(1.0)
+    }
+}
+
+impl MasterVolume {
+    pub const MAX: f32 = 2.0;
+    pub const STEP: f32 = 0.1;
+
+    pub fn clamped(volume: f32) -> f32 {
+        if volume.is_finite() {
+            volume.clamp(0.0, Self::MAX)
+        } else {
+            1.0
+        }
+    }
+}
+
+/// Master EQ gains in dB for the low shelf, mid peak and high shelf. Kept
+/// in the session.
+#[derive(Resource, Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Equalizer {
+    pub low_db: f32,
+    pub mid_db: f32,
+    pub high_db: f32,
+}
+
+impl Equalizer {
+    pub const MAX_DB: f32 = 12.0;
+    pub const STEP_DB: f32 = 1.5;
+
+    pub fn clamped(gain_db: f32) -> f32 {
+        if gain_db.is_finite() {
+            gain_db.clamp(-Self::MAX_DB, Self::MAX_DB)
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Recently opened files, newest first.
+#[derive(Resource, Debug, Clone, Default, PartialEq)]
+pub struct RecentFiles(pub Vec<RecentFile>);
+
+impl RecentFiles {
+    pub const LIMIT: usize = 8;
+
+    pub fn remember(&mut self, kind: RecentKind, path: PathBuf) {
+        self.0.retain(|file| file.path != path);
+        self.0.insert(0, RecentFile { kind, path });
+        self.0.truncate(Self::LIMIT);
+    }
+
+    pub fn retain_existing(&mut self, exists: impl Fn(&Path) -> bool) {
+        self.0.retain(|file| exists(&file.path));
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct StatusMessage {
+    pub text: String,
+    pub remaining_secs: f32,
+}
+
+impl StatusMessage {
+    pub const DURATION_SECS: f32 = 3.0;
+
+    pub fn show(&mut self, text: impl Into<String>) {
+        self.text = text.into();
+        self.remaining_secs = Self::DURATION_SECS;
+    }
+}
+
 #[derive(Resource, Default)]
 pub struct TracksFocus {
     pub index: usize,
+    /// Where focus lands when the tracks page is opened.
+    pub home: usize,
+    /// The track focused before the current one, for flipping between two.
+    pub previous: Option<usize>,
+}
+
+impl TracksFocus {
+    /// Moves focus to `index`, remembering the track it left.
+    pub fn focus(&mut self, index: usize) {
+        if index != self.index {
+            self.previous = Some(self.index);
+            self.index = index;
+        }
+    }
+
+    /// Swaps focus with the previously focused track. Returns false when
+    /// there is no other track to go back to.
+    pub fn swap_with_previous(&mut self, track_count: usize) -> bool {
+        match self.previous {
+            Some(previous) if previous < track_count && previous != self.index => {
+                self.previous = Some(self.index);
+                self.index = previous;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Focuses `index` on a freshly loaded file, forgetting the old history.
+    pub fn reset(&mut self, index: usize) {
+        self.index = index;
+        self.previous = None;
+    }
 }
 
 #[derive(Resource, Default)]
@@ -88,7 +660,478 @@ pub struct TrackDetailsPopup {
     pub track_index: usize,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TimeDisplay {
+    #[default]
+    Time,
+    Ticks,
+}
+
+/// Sample interpolation used by the synth. Higher orders sound cleaner on
+/// pitched-up samples but cost more CPU per voice.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Interpolation {
+    /// Fastest, but audibly aliased.
+    None,
+    /// Slightly slower than `None`, reasonable quality.
+    Linear,
+    /// The synth default; takes about half of the DSP time, good quality.
+    #[default]
+    FourthOrder,
+    /// Best quality and the most CPU per voice.
+    SeventhOrder,
+}
+
+/// What happens to sounding notes when a loop jumps back to its start.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LoopSeam {
+    /// Silence everything at the boundary; tight, but can click.
+    #[default]
+    Cut,
+    /// Release held notes and let their tails ring into the next pass.
+    Release,
+    /// Fade the output out just before the boundary, then cut.
+    Fade,
+}
+
+/// Which sounding note a NoteOff ends when several NoteOns on the same
+/// pitch overlap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NotePairing {
+    /// The earliest note ends first. Most sequencers pair notes this way,
+    /// and it keeps overlapping repeats in the order they were written.
+    #[default]
+    Fifo,
+    /// The most recent note ends first.
+    Lifo,
+}
+
+/// Where a song stops playing, before the reverb tail.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SongEnd {
+    /// When the last note ends.
+    #[default]
+    LastNote,
+    /// At the latest End of Track marker, for files that leave deliberate
+    /// silence after their last note.
+    EndOfTrack,
+}
+
+/// Per-channel note colours for the piano roll, previews and channel
+/// lights.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelPalette {
+    Vibrant,
+    /// Hues and lightness chosen to stay apart with deuteranopia and
+    /// protanopia.
+    ColorBlindSafe,
+}
+
+/// How notes are drawn in the track previews.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PreviewMode {
+    /// Fill every column a note sounds through.
+    #[default]
+    Sustain,
+    /// Mark only the column a note starts in, which shows rhythm without
+    /// long pads drowning it out.
+    Onset,
+}
+
+/// Resolution of the track preview grids built at load time. Every track
+/// keeps its cells for as long as the file is open, so this also bounds
+/// their memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PreviewSize {
+    pub max_width: usize,
+    pub height: usize,
+}
+
+impl Default for PreviewSize {
+    fn default() -> Self {
+        Self {
+            max_width: 240,
+            height: 64,
+        }
+    }
+}
+
+impl PreviewSize {
+    pub const WIDTH_RANGE: std::ops::RangeInclusive<usize> = 16..=2048;
+    pub const HEIGHT_RANGE: std::ops::RangeInclusive<usize> = 8..=512;
+
+    /// Any dimension outside its range falls back to the default.
+    pub fn new(max_width: usize, height: usize) -> Self {
+        let default = Self::default();
+        Self {
+            max_width: if Self::WIDTH_RANGE.contains(&max_width) {
+                max_width
+            } else {
+                default.max_width
+            },
+            height: if Self::HEIGHT_RANGE.contains(&height) {
+                height
+            } else {
+                default.height
+            },
+        }
+    }
+}
+
+/// Draws the UI at a fixed internal resolution and scales it up to the
+/// window by whole pixels with nearest-neighbour sampling.
+#[derive(Resource, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PixelRender {
+    pub width: u32,
+    pub height: u32,
+    /// Fixed upscale factor; `None` uses the largest that fits the window.
+    pub scale: Option<u32>,
+}
+
+impl PixelRender {
+    pub const WIDTH_RANGE: std::ops::RangeInclusive<u32> = 320..=3840;
+    pub const HEIGHT_RANGE: std::ops::RangeInclusive<u32> = 180..=2160;
+}
+
 #[derive(Resource)]
+pub struct Preferences {
+    pub time_display: TimeDisplay,
+    pub interpolation: Interpolation,
+    pub beat_pulse: bool,
+    /// How long to keep rendering after the last note so reverb can decay.
+    pub reverb_tail_seconds: f32,
+    /// Level of the synth's reverb, `0.0..=1.0`.
+    pub reverb_level: f32,
+    /// Level of the synth's chorus; 0 leaves it dry.
+    pub chorus_level: f32,
+    /// Requested output sample rate; `None` uses the device default.
+    pub output_sample_rate: Option<u32>,
+    /// Most voices the synth plays at once before stealing old ones. Each
+    /// voice is rendered every sample, so dense files with a high limit cost
+    /// proportionally more CPU.
+    pub polyphony: u16,
+    /// Slowly cycles the splash border colour while idle on the menu.
+    pub menu_animation: bool,
+    /// Focus the track with the most notes after loading instead of the
+    /// first one, which is often an empty conductor track.
+    pub focus_prominent_track: bool,
+    pub loop_seam: LoopSeam,
+    /// Zoom a track opens at in the piano roll; 1.0 fits the whole track.
+    pub default_zoom_x: f32,
+    pub default_zoom_y: f32,
+    /// Clicking a piano roll row plays that pitch on the track's channel.
+    pub click_audition: bool,
+    /// Seconds without input before the app stops redrawing every frame and
+    /// only wakes for input or a slow tick, cutting idle CPU and GPU use.
+    /// Playback always redraws continuously. `None` never idles.
+    pub idle_timeout_seconds: Option<f32>,
+    /// Apply each file's measured gain so files play at a similar loudness.
+    pub normalize_loudness: bool,
+    pub note_pairing: NotePairing,
+    /// Set from the command line; only applies to files loaded afterwards.
+    pub preview_size: PreviewSize,
+    pub preview_mode: PreviewMode,
+    /// Show each track as a small piano roll instead of density cells.
+    pub mini_roll_preview: bool,
+    /// Up on the first splash row jumps to the last one and vice versa.
+    pub menu_wrap: bool,
+    /// Play a short snippet at each seek target while not playing.
+    pub scrub_on_seek: bool,
+    /// Holding H plays just the focused track until it is let go.
+    pub hold_to_preview: bool,
+    /// Share of the window width the tracks list takes in the split view.
+    pub split_divider_percent: f32,
+    /// Start playing as soon as a picked or dropped MIDI file loads.
+    pub autoplay: bool,
+    /// Colour notes by channel; `None` draws every note in one colour.
+    pub channel_palette: Option<ChannelPalette>,
+    /// Leave tracks without notes, such as conductor tracks, out of the
+    /// tracks list.
+    pub hide_empty_tracks: bool,
+    pub song_end: SongEnd,
+    /// Bars Shift+L loops, counting back from the playhead.
+    pub quick_loop_bars: u32,
+    /// Start the song over once it and its reverb tail have played.
+    pub loop_song: bool,
+    /// A metronome to practise to: beats click while stopped or paused too,
+    /// and during playback even with the click track off.
+    pub practice_click: bool,
+}
+
+impl Preferences {
+    pub const DEFAULT_REVERB_TAIL_SECONDS: f32 = 1.5;
+    pub const DEFAULT_POLYPHONY: u16 = 256;
+    // OxiSynth's own default.
+    pub const DEFAULT_REVERB_LEVEL: f32 = 0.9;
+    pub const REVERB_LEVELS: [f32; 6] = [0.0, 0.2, 0.4, 0.6, 0.9, 1.0];
+    pub const DEFAULT_CHORUS_LEVEL: f32 = 2.0;
+    pub const CHORUS_LEVELS: [f32; 5] = [0.0, 0.5, 1.0, 2.0, 4.0];
+    pub const DEFAULT_IDLE_TIMEOUT_SECONDS: f32 = 10.0;
+    pub const DEFAULT_ZOOM_LEVELS: [f32; 5] = [1.0, 2.0, 4.0, 8.0, 16.0];
+    pub const SPLIT_DIVIDER_LEVELS: [f32; 5] = [30.0, 40.0, 50.0, 60.0, 70.0];
+    pub const QUICK_LOOP_BAR_LEVELS: [u32; 4] = [1, 2, 4, 8];
+}
+
+impl Default for Preferences {
+    fn default() -> Self {
+        Self {
+            time_display: TimeDisplay::default(),
+            interpolation: Interpolation::default(),
+            beat_pulse: false,
+            reverb_tail_seconds: Self::DEFAULT_REVERB_TAIL_SECONDS,
+            reverb_level: Self::DEFAULT_REVERB_LEVEL,
+            chorus_level: Self::DEFAULT_CHORUS_LEVEL,
+            output_sample_rate: None,
+            polyphony: Self::DEFAULT_POLYPHONY,
+            menu_animation: true,
+            focus_prominent_track: false,
+            loop_seam: LoopSeam::default(),
+            default_zoom_x: 1.0,
+            default_zoom_y: 1.0,
+            click_audition: true,
+            idle_timeout_seconds: Some(Self::DEFAULT_IDLE_TIMEOUT_SECONDS),
+            normalize_loudness: true,
+            note_pairing: NotePairing::default(),
+            preview_size: PreviewSize::default(),
+            preview_mode: PreviewMode::default(),
+            mini_roll_preview: false,
+            menu_wrap: false,
+            scrub_on_seek: false,
+            hold_to_preview: false,
+            split_divider_percent: 40.0,
+            autoplay: false,
+            channel_palette: None,
+            hide_empty_tracks: false,
+            song_end: SongEnd::LastNote,
+            quick_loop_bars: 2,
+            loop_song: false,
+            practice_click: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SettingsItem {
+    TimeDisplay,
+    Interpolation,
+    BeatPulse,
+    ReverbTail,
+    ReverbLevel,
+    ChorusLevel,
+    SampleRate,
+    Polyphony,
+    MenuAnimation,
+    FocusProminentTrack,
+    LoopSeam,
+    DefaultZoomX,
+    DefaultZoomY,
+    ClickAudition,
+    IdleTimeout,
+    NormalizeLoudness,
+    NotePairing,
+    PreviewMode,
+    MiniRollPreview,
+    MenuWrap,
+    ScrubOnSeek,
+    HoldToPreview,
+    SplitDivider,
+    Autoplay,
+    ChannelPalette,
+    HideEmptyTracks,
+    SongEnd,
+    QuickLoopBars,
+    LoopSong,
+    PracticeClick,
+}
+
+impl SettingsItem {
+    pub const ALL: [SettingsItem; 30] = [
+        SettingsItem::TimeDisplay,
+        SettingsItem::Interpolation,
+        SettingsItem::BeatPulse,
+        SettingsItem::ReverbTail,
+        SettingsItem::ReverbLevel,
+        SettingsItem::ChorusLevel,
+        SettingsItem::SampleRate,
+        SettingsItem::Polyphony,
+        SettingsItem::MenuAnimation,
+        SettingsItem::FocusProminentTrack,
+        SettingsItem::LoopSeam,
+        SettingsItem::DefaultZoomX,
+        SettingsItem::DefaultZoomY,
+        SettingsItem::ClickAudition,
+        SettingsItem::IdleTimeout,
+        SettingsItem::NormalizeLoudness,
+        SettingsItem::NotePairing,
+        SettingsItem::PreviewMode,
+        SettingsItem::MiniRollPreview,
+        SettingsItem::MenuWrap,
+        SettingsItem::ScrubOnSeek,
+        SettingsItem::HoldToPreview,
+        SettingsItem::SplitDivider,
+        SettingsItem::Autoplay,
+        SettingsItem::ChannelPalette,
+        SettingsItem::HideEmptyTracks,
+        SettingsItem::SongEnd,
+        SettingsItem::QuickLoopBars,
+        SettingsItem::LoopSong,
+        SettingsItem::PracticeClick,
+    ];
+}
+
+/// Recent tap-tempo key presses, in seconds since startup.
+#[derive(Resource, Debug, Default)]
+pub struct TapTempo {
+    pub taps: Vec<f64>,
+}
+
+impl TapTempo {
+    pub const MAX_TAPS: usize = 8;
+    /// A pause longer than this starts a fresh count.
+    pub const RESET_SECONDS: f64 = 2.0;
+
+    pub fn tap(&mut self, now: f64) {
+        if self
+            .taps
+            .last()
+            .is_some_and(|&last| now - last > Self::RESET_SECONDS)
+        {
+            self.taps.clear();
+        }
+        self.taps.push(now);
+        if self.taps.len() > Self::MAX_TAPS {
+            let _oldest = self.taps.remove(0);
+        }
+    }
+}
+
+/// Playback speed as a multiple of the tempo, whether the file's or a fixed
+/// one. Positions stay in the file's ticks, so the rulers are unaffected.
+#[derive(Resource, Debug, Clone, Copy, PartialEq)]
+pub struct PlaybackSpeed(pub f32);
+
+impl Default for PlaybackSpeed {
+    fn default() -> Self {
+        Self(1.0)
+    }
+}
+
+impl PlaybackSpeed {
+    pub const MIN: f32 = 0.25;
+    pub const MAX: f32 = 4.0;
+    /// Stops U and Shift+U step through.
+    pub const LEVELS: [f32; 11] = [0.25, 0.5, 0.75, 0.9, 1.0, 1.1, 1.25, 1.5, 2.0, 3.0, 4.0];
+
+    pub fn clamped(speed: f32) -> f32 {
+        if speed.is_finite() {
+            speed.clamp(Self::MIN, Self::MAX)
+        } else {
+            1.0
+        }
+    }
+
+    /// The next level above or below the current speed, staying put at the
+    /// ends of the range.
+    pub fn stepped(self, faster: bool) -> f32 {
+        const SLACK: f32 = 1e-3;
+        if faster {
+            Self::LEVELS
+                .into_iter()
+                .find(|level| *level > self.0 + SLACK)
+                .unwrap_or(Self::MAX)
+        } else {
+            Self::LEVELS
+                .into_iter()
+                .rev()
+                .find(|level| *level < self.0 - SLACK)
+                .unwrap_or(Self::MIN)
+        }
+    }
+}
+
+/// Fixed tempo in microseconds per beat, replacing the file's tempo map.
+#[derive(Resource, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TempoOverride(pub Option<u32>);
+
+/// Semitone shift per track index; drums are never transposed.
+#[derive(Resource, Debug, Clone, Default, PartialEq)]
+pub struct TrackTranspose(pub HashMap<usize, i8>);
+
+impl TrackTranspose {
+    pub const MAX_SEMITONES: i8 = 48;
+
+    pub fn get(&self, track_index: usize) -> i8 {
+        self.0.get(&track_index).copied().unwrap_or(0)
+    }
+}
+
+/// Semitones added to the note names shown for each track index, so a
+/// transposing instrument's part can be read at concert pitch. Playback is
+/// not affected.
+#[derive(Resource, Debug, Clone, Default, PartialEq)]
+pub struct DisplayTranspose(pub HashMap<usize, i8>);
+
+impl DisplayTranspose {
+    pub fn get(&self, track_index: usize) -> i8 {
+        self.0.get(&track_index).copied().unwrap_or(0)
+    }
+}
+
+/// Bars to loop, 1-based and inclusive at both ends.
+#[derive(Resource, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoopRegion {
+    pub enabled: bool,
+    pub start_bar: u32,
+    pub end_bar: u32,
+    /// A and B points in ticks from the piano roll. Once both are set they
+    /// replace the bars, and a B at or before A leaves the loop off.
+    pub points: (Option<u64>, Option<u64>),
+}
+
+impl Default for LoopRegion {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            start_bar: 1,
+            end_bar: 4,
+            points: (None, None),
+        }
+    }
+}
+
+/// Markers from every track, in time order.
+pub fn file_markers(tracks: &[MidiTrackInfo]) -> Vec<(u64, String)> {
+    let mut markers: Vec<(u64, String)> = tracks
+        .iter()
+        .flat_map(|track| track.markers.iter().cloned())
+        .collect();
+    markers.sort_by_key(|(tick, _)| *tick);
+    markers
+}
+
+/// The marker list opened with M on the tracks page; Enter seeks to the
+/// selected marker.
+#[derive(Resource, Default)]
+pub struct MarkerList {
+    pub open: bool,
+    pub selected: usize,
+}
+
+/// The go-to box: a bar number or time typed on the tracks or piano roll
+/// page. `preview` describes where Enter would seek to.
+#[derive(Resource, Default)]
+pub struct GotoEntry {
+    pub open: bool,
+    pub text: String,
+    pub preview: String,
+}
+
+#[derive(Resource, Default)]
+pub struct SettingsFocus {
+    pub index: usize,
+}
+
+#[derive(Resource, Debug, Clone, Copy, PartialEq)]
 pub struct PianoRollViewState {
     pub zoom_x: f32,
     pub zoom_y: f32,
@@ -0,0 +1,184 @@
+use crate::midi::FileTiming;
+use crate::state::{MidiTrackInfo, SongEnd};
+
+#[derive(Clone, Copy)]
+struct TempoSegment {
+    tick: u64,
+    us_per_beat: u32,
+    seconds_at_tick: f64,
+}
+
+fn build_tempo_segments(tempo_events: &[(u64, u32)], ticks_per_beat: f64) -> Vec<TempoSegment> {
+    let mut segments = Vec::new();
+    let mut sorted = tempo_events.to_vec();
+    sorted.sort_by_key(|(tick, _)| *tick);
+
+    let mut current = TempoSegment {
+        tick: 0,
+        us_per_beat: 500_000,
+        seconds_at_tick: 0.0,
+    };
+    segments.push(current);
+
+    for (tick, us_per_beat) in sorted {
+        if tick == current.tick {
+            current.us_per_beat = us_per_beat;
+            segments.last_mut().unwrap().us_per_beat = us_per_beat;
+            continue;
+        }
+        let delta_ticks = tick.saturating_sub(current.tick);
+        let seconds_delta =
+            (delta_ticks as f64 * current.us_per_beat as f64) / (1_000_000.0 * ticks_per_beat);
+        current = TempoSegment {
+            tick,
+            us_per_beat,
+            seconds_at_tick: current.seconds_at_tick + seconds_delta,
+        };
+        segments.push(current);
+    }
+
+    segments
+}
+
+fn ticks_to_seconds(tick: u64, segments: &[TempoSegment], ticks_per_beat: f64) -> f64 {
+    let mut active = segments[0];
+    for segment in segments.iter().skip(1) {
+        if segment.tick > tick {
+            break;
+        }
+        active = *segment;
+    }
+    let delta_ticks = tick.saturating_sub(active.tick);
+    let seconds_delta =
+        (delta_ticks as f64 * active.us_per_beat as f64) / (1_000_000.0 * ticks_per_beat);
+    active.seconds_at_tick + seconds_delta
+}
+
+#[derive(Clone)]
+pub struct TempoMap {
+    segments: Vec<TempoSegment>,
+    ticks_per_beat: f64,
+    /// SMPTE timing: a fixed rate that the tempo segments don't apply to.
+    ticks_per_second: Option<f64>,
+    speed: f64,
+}
+
+impl TempoMap {
+    pub fn new(tempo_events: &[(u64, u32)], ticks_per_beat: u32) -> Self {
+        let ticks_per_beat = ticks_per_beat.max(1) as f64;
+        Self {
+            segments: build_tempo_segments(tempo_events, ticks_per_beat),
+            ticks_per_beat,
+            ticks_per_second: None,
+            speed: 1.0,
+        }
+    }
+
+    /// Ticks at a fixed `ticks_per_second`, as in an SMPTE-timed file.
+    pub fn timecode(ticks_per_second: f64, ticks_per_beat: u32) -> Self {
+        Self {
+            ticks_per_second: Some(ticks_per_second.max(f64::EPSILON)),
+            ..Self::new(&[], ticks_per_beat)
+        }
+    }
+
+    /// The same map played `speed` times as fast; ticks are unchanged.
+    pub fn with_speed(self, speed: f32) -> Self {
+        Self {
+            speed: speed.max(f32::EPSILON) as f64,
+            ..self
+        }
+    }
+
+    pub fn seconds_at(&self, tick: u64) -> f64 {
+        let seconds = match self.ticks_per_second {
+            Some(ticks_per_second) => tick as f64 / ticks_per_second,
+            None => ticks_to_seconds(tick, &self.segments, self.ticks_per_beat),
+        };
+        seconds / self.speed
+    }
+
+    /// Inverse of `seconds_at`, rounded to the nearest tick.
+    pub fn tick_at(&self, seconds: f64) -> u64 {
+        let seconds = seconds * self.speed;
+        if let Some(ticks_per_second) = self.ticks_per_second {
+            return (seconds.max(0.0) * ticks_per_second).round() as u64;
+        }
+        let active = self
+            .segments
+            .iter()
+            .rev()
+            .find(|segment| segment.seconds_at_tick <= seconds)
+            .unwrap_or(&self.segments[0]);
+        let delta = (seconds - active.seconds_at_tick).max(0.0);
+        let ticks = delta * 1_000_000.0 * self.ticks_per_beat / active.us_per_beat.max(1) as f64;
+        active.tick + ticks.round() as u64
+    }
+}
+
+/// The loaded file's tempo map; see `FileTiming::tempo_map`. Without a file
+/// this is 120 BPM, or the override, at 480 ticks per beat.
+pub fn file_tempo_map(tracks: &[MidiTrackInfo], tempo_override: Option<u32>) -> TempoMap {
+    match tracks.first() {
+        Some(track) => track.timing.tempo_map(tempo_override),
+        None => FileTiming {
+            ticks_per_beat: 480,
+            ..FileTiming::default()
+        }
+        .tempo_map(tempo_override),
+    }
+}
+
+/// Length of the file at its own tempo, up to `FileTiming::end_tick`.
+pub fn file_duration_seconds(tracks: &[MidiTrackInfo], song_end: SongEnd) -> f64 {
+    let Some(track) = tracks.first() else {
+        return 0.0;
+    };
+    track
+        .timing
+        .tempo_map(None)
+        .seconds_at(track.timing.end_tick(song_end))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TempoMap;
+
+    #[test]
+    fn tempo_map_converts_ticks_across_tempo_changes() {
+        let tempo_map = TempoMap::new(&[(960, 250_000), (0, 500_000)], 480);
+        assert!((tempo_map.seconds_at(0) - 0.0).abs() < 1e-9);
+        assert!((tempo_map.seconds_at(480) - 0.5).abs() < 1e-9);
+        assert!((tempo_map.seconds_at(960) - 1.0).abs() < 1e-9);
+        assert!((tempo_map.seconds_at(1440) - 1.25).abs() < 1e-9);
+        assert_eq!(tempo_map.tick_at(0.5), 480);
+        assert_eq!(tempo_map.tick_at(1.0), 960);
+        assert_eq!(tempo_map.tick_at(1.25), 1440);
+        assert_eq!(tempo_map.tick_at(-1.0), 0);
+    }
+
+    #[test]
+    fn tempo_map_defaults_to_120_bpm() {
+        let tempo_map = TempoMap::new(&[], 96);
+        assert!((tempo_map.seconds_at(192) - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn tempo_map_speed_scales_seconds_not_ticks() {
+        let tempo_map = TempoMap::new(&[(960, 250_000)], 480).with_speed(0.5);
+        assert!((tempo_map.seconds_at(480) - 1.0).abs() < 1e-9);
+        assert!((tempo_map.seconds_at(1440) - 2.5).abs() < 1e-9);
+        assert_eq!(tempo_map.tick_at(2.0), 960);
+        assert_eq!(tempo_map.tick_at(2.5), 1440);
+    }
+
+    #[test]
+    fn timecode_tempo_map_ignores_beats() {
+        let tempo_map = TempoMap::timecode(25.0 * 40.0, 500);
+        assert!((tempo_map.seconds_at(1500) - 1.5).abs() < 1e-9);
+        assert_eq!(tempo_map.tick_at(1.5), 1500);
+        let tempo_map = tempo_map.with_speed(2.0);
+        assert!((tempo_map.seconds_at(1500) - 0.75).abs() < 1e-9);
+        assert_eq!(tempo_map.tick_at(0.75), 1500);
+    }
+}
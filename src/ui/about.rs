@@ -1,4 +1,4 @@
-use super::AboutPageRoot;
+use super::PageRoot;
 use bevy::prelude::{
     default, AlignItems, BackgroundColor, BorderColor, Color, Commands, Display, Entity,
     FlexDirection, Font, Handle, JustifyContent, Node, Text, TextColor, TextFont, UiRect, Val,
@@ -17,7 +17,7 @@ pub(super) fn spawn_about_page(commands: &mut Commands, parent: Entity, font: Ha
                     display: Display::None,
                     ..default()
                 },
-                AboutPageRoot,
+                PageRoot::About,
             ))
             .with_children(|parent| {
                 let _ = parent
@@ -73,7 +73,132 @@ pub(super) fn spawn_about_page(commands: &mut Commands, parent: Entity, font: Ha
                             TextColor(Color::srgb(0.8, 0.8, 0.8)),
                         ));
                         let _ = parent.spawn((
-                            Text::new("P to play/pause, S to stop."),
+                            Text::new("P to play/pause, S to stop, R to reload."),
+                            TextFont {
+                                font: font.clone(),
+                                font_size: 24.0,
+                                ..default()
+                            },
+                            TextColor(Color::srgb(0.8, 0.8, 0.8)),
+                        ));
+                        let _ = parent.spawn((
+                            Text::new("Ctrl +/- to scale the UI, Ctrl 0 to reset."),
+                            TextFont {
+                                font: font.clone(),
+                                font_size: 24.0,
+                                ..default()
+                            },
+                            TextColor(Color::srgb(0.8, 0.8, 0.8)),
+                        ));
+                        let _ = parent.spawn((
+                            Text::new(
+                                "Ctrl R to reset, Ctrl Shift R to also unload the SoundFont.",
+                            ),
+                            TextFont {
+                                font: font.clone(),
+                                font_size: 24.0,
+                                ..default()
+                            },
+                            TextColor(Color::srgb(0.8, 0.8, 0.8)),
+                        ));
+                        let _ = parent.spawn((
+                            Text::new("L to loop, [ ] to move its start bar, Shift [ ] its end. Shift L loops the bars just played."),
+                            TextFont {
+                                font: font.clone(),
+                                font_size: 24.0,
+                                ..default()
+                            },
+                            TextColor(Color::srgb(0.8, 0.8, 0.8)),
+                        ));
+                        let _ = parent.spawn((
+                            Text::new(
+                                ", . to transpose a track by octaves, Shift , . by semitones, Alt , . its note names only.",
+                            ),
+                            TextFont {
+                                font: font.clone(),
+                                font_size: 24.0,
+                                ..default()
+                            },
+                            TextColor(Color::srgb(0.8, 0.8, 0.8)),
+                        ));
+                        let _ = parent.spawn((
+                            Text::new("B to tap a tempo, Shift B to play at it or undo it."),
+                            TextFont {
+                                font: font.clone(),
+                                font_size: 24.0,
+                                ..default()
+                            },
+                            TextColor(Color::srgb(0.8, 0.8, 0.8)),
+                        ));
+                        let _ = parent.spawn((
+                            Text::new(
+                                "N while paused to step one event, D to jump to the busiest bar.",
+                            ),
+                            TextFont {
+                                font: font.clone(),
+                                font_size: 24.0,
+                                ..default()
+                            },
+                            TextColor(Color::srgb(0.8, 0.8, 0.8)),
+                        ));
+                        let _ = parent.spawn((
+                            Text::new("K on the tracks page for karaoke lyrics, M for markers, J to go to a bar or m:ss."),
+                            TextFont {
+                                font: font.clone(),
+                                font_size: 24.0,
+                                ..default()
+                            },
+                            TextColor(Color::srgb(0.8, 0.8, 0.8)),
+                        ));
+                        let _ = parent.spawn((
+                            Text::new(
+                                "On the piano roll: F to fit, 0 for the default zoom, A to ghost other tracks, Q to snap notes to a grid, Shift+C to compare with another file and C to toggle it, click a row to hear it.",
+                            ),
+                            TextFont {
+                                font: font.clone(),
+                                font_size: 24.0,
+                                ..default()
+                            },
+                            TextColor(Color::srgb(0.8, 0.8, 0.8)),
+                        ));
+                        let _ = parent.spawn((
+                            Text::new("On the tracks page: Left and Right seek a bar, hold to keep going; Tab flips to the last track."),
+                            TextFont {
+                                font: font.clone(),
+                                font_size: 24.0,
+                                ..default()
+                            },
+                            TextColor(Color::srgb(0.8, 0.8, 0.8)),
+                        ));
+                        let _ = parent.spawn((
+                            Text::new("+ and - set the master volume; U speeds playback up and Shift U slows it down; click a track preview, or Shift click the piano roll, to seek there; I loops the whole song; on the splash SoundFont row A layers another SoundFont over it and Backspace removes the layers; Shift M mutes the focused track's channels and Shift S solos them; in the piano roll ; and ' set loop points A and B at the playhead; Alt + and Alt - trim the SoundFont's gain; W widens the stereo image, Shift W narrows it. F5, F6 and F7 boost the low, mid and high EQ; add Shift to cut. X cycles the click between off, beats and the focused track's notes; Shift X turns on a metronome that clicks beats whether or not anything plays."),
+                            TextFont {
+                                font: font.clone(),
+                                font_size: 24.0,
+                                ..default()
+                            },
+                            TextColor(Color::srgb(0.8, 0.8, 0.8)),
+                        ));
+                        let _ = parent.spawn((
+                            Text::new("V shows the tracks and piano roll side by side."),
+                            TextFont {
+                                font: font.clone(),
+                                font_size: 24.0,
+                                ..default()
+                            },
+                            TextColor(Color::srgb(0.8, 0.8, 0.8)),
+                        ));
+                        let _ = parent.spawn((
+                            Text::new("F9 for the mixer, F10 for settings."),
+                            TextFont {
+                                font: font.clone(),
+                                font_size: 24.0,
+                                ..default()
+                            },
+                            TextColor(Color::srgb(0.8, 0.8, 0.8)),
+                        ));
+                        let _ = parent.spawn((
+                            Text::new("F2 to switch between time and ticks."),
                             TextFont {
                                 font: font.clone(),
                                 font_size: 24.0,
@@ -0,0 +1,354 @@
+use super::{PageRoot, UiFonts};
+use crate::audio::AudioState;
+use crate::state::{MidiTrackInfo, MidiTracks, UiPage, UiState};
+use bevy::prelude::{
+    default, AlignItems, Color, Commands, Component, ComputedNode, DetectChanges, Display, Entity,
+    FlexDirection, Font, Handle, Node, Overflow, PositionType, Query, Res, ResMut, Resource, Text,
+    TextColor, TextFont, TextSpan, UiRect, Val, With, Without,
+};
+
+const LYRIC_LINE_HEIGHT: f32 = 44.0;
+const LYRIC_FONT_SIZE: f32 = 32.0;
+const SUNG_COLOR: Color = Color::srgb(0.45, 0.75, 1.0);
+const ACTIVE_COLOR: Color = Color::srgb(1.0, 1.0, 0.0);
+const UPCOMING_COLOR: Color = Color::srgb(0.8, 0.8, 0.8);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct LyricSyllable {
+    pub(super) tick: u64,
+    pub(super) line: usize,
+    pub(super) text: String,
+}
+
+/// Syllables of the file's lyric track, laid out into display lines.
+#[derive(Resource, Default)]
+pub(super) struct LyricsLayout(Vec<LyricSyllable>);
+
+#[derive(Component)]
+pub(super) struct LyricsViewport;
+
+#[derive(Component)]
+pub(super) struct LyricsContent;
+
+#[derive(Component)]
+pub(super) struct LyricsEmptyText;
+
+#[derive(Component)]
+pub(super) struct LyricLine;
+
+#[derive(Component)]
+pub(super) struct LyricSpan(usize);
+
+/// The track with the most lyric events; karaoke files often duplicate the
+/// words into a second text track, so tracks are not merged.
+fn lyric_track(tracks: &[MidiTrackInfo]) -> Option<&MidiTrackInfo> {
+    tracks
+        .iter()
+        .filter(|track| !track.lyric_events.is_empty())
+        .max_by_key(|track| track.lyric_events.len())
+}
+
+/// Splits lyric events into lines. A leading `/` or `\`, or a carriage
+/// return/newline, breaks the line; a trailing `-` joins a syllable to the
+/// next one. Files that spell out their own spaces are left alone, otherwise
+/// words are separated by a space.
+pub(super) fn layout_lyrics(events: &[(u64, String)]) -> Vec<LyricSyllable> {
+    let explicit_spacing = events
+        .iter()
+        .any(|(_, text)| text.starts_with(' ') || text.ends_with(' '));
+    let mut syllables: Vec<LyricSyllable> = Vec::new();
+    let mut line = 0usize;
+    let mut line_has_text = false;
+    let mut joins_next = true;
+    for (tick, raw) in events {
+        let breaks_before = raw.starts_with(['/', '\\', '\r', '\n']);
+        let breaks_after = raw.ends_with(['\r', '\n']);
+        if breaks_before && line_has_text {
+            line += 1;
+            line_has_text = false;
+        }
+        let trimmed = raw.trim_matches(['/', '\\', '\r', '\n']);
+        let (word, hyphenated) = match trimmed.strip_suffix('-') {
+            Some(word) => (word, true),
+            None => (trimmed, false),
+        };
+        if !word.trim().is_empty() {
+            let mut text = String::new();
+            if line_has_text && !joins_next && !explicit_spacing {
+                text.push(' ');
+            }
+            text.push_str(if line_has_text {
+                word
+            } else {
+                word.trim_start()
+            });
+            syllables.push(LyricSyllable {
+                tick: *tick,
+                line,
+                text,
+            });
+            line_has_text = true;
+            joins_next = hyphenated;
+        }
+        if breaks_after && line_has_text {
+            line += 1;
+            line_has_text = false;
+        }
+    }
+    syllables
+}
+
+/// Index of the syllable being sung at `tick`: the last one that has started.
+pub(super) fn active_syllable_index(syllables: &[LyricSyllable], tick: u64) -> Option<usize> {
+    syllables
+        .partition_point(|syllable| syllable.tick <= tick)
+        .checked_sub(1)
+}
+
+fn centered_line_offset(viewport_height: f32, line: usize) -> f32 {
+    viewport_height / 2.0 - (line as f32 + 0.5) * LYRIC_LINE_HEIGHT
+}
+
+pub(super) fn spawn_lyrics_page(commands: &mut Commands, parent: Entity, font: Handle<Font>) {
+    let _ = commands.entity(parent).with_children(|parent| {
+        let _ = parent
+            .spawn((
+                Node {
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    flex_direction: FlexDirection::Column,
+                    padding: UiRect::all(Val::Px(20.0)),
+                    display: Display::None,
+                    ..default()
+                },
+                PageRoot::Lyrics,
+            ))
+            .with_children(|parent| {
+                let _ = parent.spawn((
+                    Text::new("Lyrics (K or Esc to go back)"),
+                    TextFont {
+                        font: font.clone(),
+                        font_size: 24.0,
+                        ..default()
+                    },
+                    TextColor(Color::WHITE),
+                ));
+                let _ = parent.spawn((
+                    Text::new("No lyrics in this file."),
+                    TextFont {
+                        font: font.clone(),
+                        font_size: 24.0,
+                        ..default()
+                    },
+                    TextColor(Color::srgb(0.8, 0.8, 0.8)),
+                    Node {
+                        margin: UiRect::top(Val::Px(20.0)),
+                        ..default()
+                    },
+                    LyricsEmptyText,
+                ));
+                let _ = parent
+                    .spawn((
+                        Node {
+                            flex_grow: 1.0,
+                            width: Val::Percent(100.0),
+                            overflow: Overflow::clip(),
+                            ..default()
+                        },
+                        LyricsViewport,
+                    ))
+                    .with_children(|parent| {
+                        let _ = parent.spawn((
+                            Node {
+                                position_type: PositionType::Absolute,
+                                width: Val::Percent(100.0),
+                                top: Val::Px(0.0),
+                                flex_direction: FlexDirection::Column,
+                                align_items: AlignItems::Center,
+                                ..default()
+                            },
+                            LyricsContent,
+                        ));
+                    });
+            });
+    });
+}
+
+pub(super) fn update_lyrics_lines(
+    mut commands: Commands,
+    midi_tracks: Res<MidiTracks>,
+    fonts: Res<UiFonts>,
+    mut layout: ResMut<LyricsLayout>,
+    content_query: Query<Entity, With<LyricsContent>>,
+    line_query: Query<Entity, With<LyricLine>>,
+    mut empty_query: Query<&mut Node, With<LyricsEmptyText>>,
+) {
+    if !midi_tracks.is_changed() {
+        return;
+    }
+    layout.0 = lyric_track(&midi_tracks.0)
+        .map(|track| layout_lyrics(&track.lyric_events))
+        .unwrap_or_default();
+    for mut node in &mut empty_query {
+        node.display = if layout.0.is_empty() {
+            Display::Flex
+        } else {
+            Display::None
+        };
+    }
+    let Some(content) = content_query.iter().next() else {
+        return;
+    };
+    for line in &line_query {
+        commands.entity(line).despawn();
+    }
+    let font = TextFont {
+        font: fonts.main.clone(),
+        font_size: LYRIC_FONT_SIZE,
+        ..default()
+    };
+    let _ = commands.entity(content).with_children(|parent| {
+        let mut syllables = layout.0.iter().enumerate().peekable();
+        while let Some(&(_, first)) = syllables.peek() {
+            let line = first.line;
+            let _ = parent
+                .spawn((
+                    Text::new(""),
+                    font.clone(),
+                    Node {
+                        height: Val::Px(LYRIC_LINE_HEIGHT),
+                        ..default()
+                    },
+                    LyricLine,
+                ))
+                .with_children(|parent| {
+                    while let Some((index, syllable)) =
+                        syllables.next_if(|(_, syllable)| syllable.line == line)
+                    {
+                        let _ = parent.spawn((
+                            TextSpan::new(syllable.text.clone()),
+                            font.clone(),
+                            TextColor(UPCOMING_COLOR),
+                            LyricSpan(index),
+                        ));
+                    }
+                });
+        }
+    });
+}
+
+pub(super) fn update_lyrics_highlight(
+    ui_state: Res<UiState>,
+    audio_state: Res<AudioState>,
+    layout: Res<LyricsLayout>,
+    mut spans: Query<(&LyricSpan, &mut TextColor)>,
+    viewport_query: Query<&ComputedNode, With<LyricsViewport>>,
+    mut content_query: Query<&mut Node, (With<LyricsContent>, Without<LyricsEmptyText>)>,
+) {
+    if ui_state.page != UiPage::Lyrics {
+        return;
+    }
+    let active = audio_state
+        .current_tick()
+        .and_then(|tick| active_syllable_index(&layout.0, tick));
+    for (span, mut color) in &mut spans {
+        let target = match active {
+            Some(active) if span.0 < active => SUNG_COLOR,
+            Some(active) if span.0 == active => ACTIVE_COLOR,
+            _ => UPCOMING_COLOR,
+        };
+        if color.0 != target {
+            color.0 = target;
+        }
+    }
+
+    let Some(viewport) = viewport_query.iter().next() else {
+        return;
+    };
+    let viewport_height = viewport.size().y * viewport.inverse_scale_factor();
+    let line = active.map_or(0, |index| layout.0[index].line);
+    let top = Val::Px(centered_line_offset(viewport_height, line));
+    for mut node in &mut content_query {
+        if node.top != top {
+            node.top = top;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{active_syllable_index, centered_line_offset, layout_lyrics, LYRIC_LINE_HEIGHT};
+
+    fn events(items: &[(u64, &str)]) -> Vec<(u64, String)> {
+        items
+            .iter()
+            .map(|(tick, text)| (*tick, text.to_string()))
+            .collect()
+    }
+
+    fn lines(events: &[(u64, String)]) -> Vec<(usize, String)> {
+        layout_lyrics(events)
+            .into_iter()
+            .map(|syllable| (syllable.line, syllable.text))
+            .collect()
+    }
+
+    #[test]
+    fn hyphenated_syllables_join_into_words() {
+        let lyrics = events(&[(0, "Hap-"), (10, "py"), (20, "birth-"), (30, "day\r")]);
+        assert_eq!(
+            lines(&lyrics),
+            vec![
+                (0, "Hap".to_string()),
+                (0, "py".to_string()),
+                (0, " birth".to_string()),
+                (0, "day".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn line_breaks_and_explicit_spaces() {
+        let lyrics = events(&[
+            (0, "\\Hel"),
+            (10, "lo "),
+            (20, "world"),
+            (30, "/Next "),
+            (40, "line"),
+        ]);
+        assert_eq!(
+            lines(&lyrics),
+            vec![
+                (0, "Hel".to_string()),
+                (0, "lo ".to_string()),
+                (0, "world".to_string()),
+                (1, "Next ".to_string()),
+                (1, "line".to_string()),
+            ]
+        );
+        assert!(layout_lyrics(&events(&[(0, "\r"), (5, "/")])).is_empty());
+    }
+
+    #[test]
+    fn active_syllable_is_the_last_started() {
+        let syllables = layout_lyrics(&events(&[(100, "a"), (200, "b"), (200, "c"), (300, "d")]));
+        assert_eq!(active_syllable_index(&syllables, 0), None);
+        assert_eq!(active_syllable_index(&syllables, 99), None);
+        assert_eq!(active_syllable_index(&syllables, 100), Some(0));
+        assert_eq!(active_syllable_index(&syllables, 250), Some(2));
+        assert_eq!(active_syllable_index(&syllables, 10_000), Some(3));
+        assert_eq!(active_syllable_index(&[], 10), None);
+    }
+
+    #[test]
+    fn active_line_is_centered() {
+        assert_eq!(
+            centered_line_offset(400.0, 0),
+            200.0 - LYRIC_LINE_HEIGHT / 2.0
+        );
+        assert_eq!(
+            centered_line_offset(400.0, 3),
+            200.0 - 3.5 * LYRIC_LINE_HEIGHT
+        );
+    }
+}
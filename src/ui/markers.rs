@@ -0,0 +1,210 @@
+use super::splash::first_visible_row;
+use super::tracks::position_label;
+use super::UiFonts;
+use crate::state::{
+    file_markers, MarkerList, MidiTracks, Preferences, TempoOverride, TimeDisplay, UiState,
+};
+use crate::tempo::{file_tempo_map, TempoMap};
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::{
+    default, BackgroundColor, BorderColor, Color, Commands, Component, Display, Entity,
+    FlexDirection, Font, Handle, Local, Node, Overflow, PositionType, Query, Res, Text, TextColor,
+    TextFont, UiRect, Val, With, Without, ZIndex,
+};
+
+#[derive(Component)]
+pub(super) struct MarkerListRoot;
+
+#[derive(Component)]
+pub(super) struct MarkerListRows;
+
+#[derive(Component)]
+pub(super) struct MarkerListEntry(usize);
+
+const MARKER_ROW_HEIGHT: f32 = 28.0;
+const MARKER_VISIBLE_ROWS: usize = 10;
+
+fn marker_label(tick: u64, name: &str, tempo_map: &TempoMap, display: TimeDisplay) -> String {
+    format!("{}  {}", position_label(tick, tempo_map, display), name)
+}
+
+pub(super) fn spawn_marker_list(commands: &mut Commands, parent: Entity, font: Handle<Font>) {
+    let _ = commands.entity(parent).with_children(|parent| {
+        let _ = parent
+            .spawn((
+                Node {
+                    position_type: PositionType::Absolute,
+                    top: Val::Percent(15.0),
+                    left: Val::Percent(25.0),
+                    width: Val::Percent(50.0),
+                    padding: UiRect::all(Val::Px(16.0)),
+                    border: UiRect::all(Val::Px(2.0)),
+                    flex_direction: FlexDirection::Column,
+                    row_gap: Val::Px(8.0),
+                    display: Display::None,
+                    ..default()
+                },
+                BackgroundColor(Color::srgb(0.05, 0.05, 0.2)),
+                BorderColor::all(Color::WHITE),
+                ZIndex(20),
+                MarkerListRoot,
+            ))
+            .with_children(|parent| {
+                let _ = parent.spawn((
+                    Text::new("Markers"),
+                    TextFont {
+                        font: font.clone(),
+                        font_size: 28.0,
+                        ..default()
+                    },
+                    TextColor(Color::WHITE),
+                ));
+                let _ = parent.spawn((
+                    Text::new("Up/Down to choose, Enter to jump, Esc to close."),
+                    TextFont {
+                        font: font.clone(),
+                        font_size: 18.0,
+                        ..default()
+                    },
+                    TextColor(Color::srgb(0.8, 0.8, 0.8)),
+                ));
+                let _ = parent
+                    .spawn((Node {
+                        height: Val::Px(MARKER_ROW_HEIGHT * MARKER_VISIBLE_ROWS as f32),
+                        overflow: Overflow::clip(),
+                        ..default()
+                    },))
+                    .with_children(|parent| {
+                        let _ = parent.spawn((
+                            Node {
+                                flex_direction: FlexDirection::Column,
+                                ..default()
+                            },
+                            MarkerListRows,
+                        ));
+                    });
+            });
+    });
+}
+
+type MarkerRows<'w, 's> =
+    Query<'w, 's, (Entity, &'static mut Node), (With<MarkerListRows>, Without<MarkerListRoot>)>;
+
+/// The markers, and the file and settings their positions are shown with.
+#[derive(SystemParam)]
+pub(super) struct MarkerSource<'w> {
+    markers: Res<'w, MarkerList>,
+    midi_tracks: Res<'w, MidiTracks>,
+    tempo_override: Res<'w, TempoOverride>,
+    preferences: Res<'w, Preferences>,
+}
+
+/// The marker list's nodes and how far it is scrolled.
+#[derive(SystemParam)]
+pub(super) struct MarkerListView<'w, 's> {
+    root_query: Query<'w, 's, &'static mut Node, With<MarkerListRoot>>,
+    rows_query: MarkerRows<'w, 's>,
+    entries: Query<'w, 's, (Entity, &'static MarkerListEntry, &'static mut TextColor)>,
+    shown: Local<'s, bool>,
+    first_visible: Local<'s, usize>,
+}
+
+pub(super) fn update_marker_list(
+    mut commands: Commands,
+    ui_state: Res<UiState>,
+    source: MarkerSource,
+    fonts: Res<UiFonts>,
+    view: MarkerListView,
+) {
+    let MarkerSource {
+        markers,
+        midi_tracks,
+        tempo_override,
+        preferences,
+    } = source;
+    let MarkerListView {
+        mut root_query,
+        mut rows_query,
+        mut entries,
+        mut shown,
+        mut first_visible,
+    } = view;
+    let show = markers.open && ui_state.page.shows_tracks();
+    for mut node in &mut root_query {
+        let display = if show { Display::Flex } else { Display::None };
+        if node.display != display {
+            node.display = display;
+        }
+    }
+    let opened = show && !*shown;
+    *shown = show;
+    if !show {
+        return;
+    }
+    let Some((rows_entity, mut rows_node)) = rows_query.iter_mut().next() else {
+        return;
+    };
+
+    // Rows are rebuilt each time the list opens so they follow the loaded
+    // file, the current tempo override and the time display.
+    if opened {
+        *first_visible = 0;
+        for (entity, _, _) in &entries {
+            commands.entity(entity).despawn();
+        }
+        let tempo_map = file_tempo_map(&midi_tracks.0, tempo_override.0);
+        let _ = commands.entity(rows_entity).with_children(|parent| {
+            for (index, (tick, name)) in file_markers(&midi_tracks.0).iter().enumerate() {
+                let _ = parent.spawn((
+                    Text::new(marker_label(
+                        *tick,
+                        name,
+                        &tempo_map,
+                        preferences.time_display,
+                    )),
+                    TextFont {
+                        font: fonts.main.clone(),
+                        font_size: 22.0,
+                        ..default()
+                    },
+                    TextColor(Color::WHITE),
+                    Node {
+                        height: Val::Px(MARKER_ROW_HEIGHT),
+                        ..default()
+                    },
+                    MarkerListEntry(index),
+                ));
+            }
+        });
+    }
+
+    for (_, entry, mut color) in &mut entries {
+        color.0 = if entry.0 == markers.selected {
+            Color::srgb(1.0, 1.0, 0.0)
+        } else {
+            Color::WHITE
+        };
+    }
+    *first_visible = first_visible_row(markers.selected, *first_visible, MARKER_VISIBLE_ROWS);
+    rows_node.top = Val::Px(-(*first_visible as f32) * MARKER_ROW_HEIGHT);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::marker_label;
+    use crate::state::TimeDisplay;
+    use crate::tempo::TempoMap;
+
+    #[test]
+    fn marker_label_shows_position_then_name() {
+        let tempo_map = TempoMap::new(&[(0, 500_000)], 480);
+        assert_eq!(
+            marker_label(480 * 130, "Bridge", &tempo_map, TimeDisplay::Time),
+            "01:05  Bridge"
+        );
+        assert_eq!(
+            marker_label(480 * 130, "Bridge", &tempo_map, TimeDisplay::Ticks),
+            "62400  Bridge"
+        );
+    }
+}
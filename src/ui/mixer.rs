@@ -0,0 +1,336 @@
+use super::palette::channel_color;
+use super::tracks::{
+    pan_at, program_label, CHANNEL_ACTIVE_COLOR, CHANNEL_ACTIVITY_WINDOW_SECS, CHANNEL_IDLE_COLOR,
+};
+use super::PageRoot;
+use crate::audio::AudioState;
+use crate::state::{
+    audible_channels, ChannelMixer, MidiTracks, MixerControl, Preferences, UiPage, UiState,
+};
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::{
+    default, AlignItems, BackgroundColor, BorderColor, Color, Commands, Component, Display, Entity,
+    FlexDirection, Font, Handle, JustifyContent, Node, Query, Res, Text, TextColor, TextFont,
+    UiRect, Val, With, Without,
+};
+
+#[derive(Component)]
+pub(super) struct MixerStrip {
+    channel: usize,
+}
+
+#[derive(Component)]
+pub(super) struct MixerFader {
+    channel: usize,
+}
+
+#[derive(Component)]
+pub(super) struct MixerActivity {
+    channel: usize,
+}
+
+/// Names the focused channel's program under the strips.
+#[derive(Component)]
+pub(super) struct MixerProgramDetail;
+
+#[derive(Component)]
+pub(super) struct MixerControlLabel {
+    channel: usize,
+    control: MixerControl,
+}
+
+const FADER_HEIGHT: f32 = 180.0;
+const FADER_WIDTH: f32 = 14.0;
+const FOCUS_COLOR: Color = Color::srgb(1.0, 1.0, 0.0);
+// A pan or program that follows the file is shown dimmer than one set here.
+const FILE_PAN_COLOR: Color = Color::srgb(0.7, 0.7, 0.7);
+const FADER_TRACK_COLOR: Color = Color::srgb(0.0, 0.0, 0.3);
+const FADER_SILENT_COLOR: Color = Color::srgb(0.3, 0.3, 0.45);
+const MUTE_ON_COLOR: Color = Color::srgb(0.75, 0.1, 0.1);
+const SOLO_ON_COLOR: Color = Color::srgb(0.1, 0.55, 0.2);
+const CONTROL_OFF_COLOR: Color = Color::NONE;
+
+/// "C" at the centre, otherwise the side and distance from it, e.g. "L32".
+fn pan_label(pan: u8) -> String {
+    let offset = pan.min(127) as i16 - 64;
+    match offset {
+        0 => "C".to_string(),
+        offset if offset < 0 => format!("L{}", -offset),
+        offset => format!("R{offset}"),
+    }
+}
+
+pub(super) fn spawn_mixer_page(commands: &mut Commands, parent: Entity, font: Handle<Font>) {
+    let text = |value: &str, size: f32| {
+        (
+            Text::new(value),
+            TextFont {
+                font: font.clone(),
+                font_size: size,
+                ..default()
+            },
+            TextColor(Color::WHITE),
+        )
+    };
+    let _ = commands.entity(parent).with_children(|parent| {
+        let _ = parent
+            .spawn((
+                Node {
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    flex_direction: FlexDirection::Column,
+                    align_items: AlignItems::Center,
+                    justify_content: JustifyContent::Center,
+                    row_gap: Val::Px(12.0),
+                    display: Display::None,
+                    ..default()
+                },
+                PageRoot::Mixer,
+            ))
+            .with_children(|parent| {
+                let _ = parent.spawn(text("Mixer", 40.0));
+                let _ = parent
+                    .spawn(Node {
+                        column_gap: Val::Px(6.0),
+                        ..default()
+                    })
+                    .with_children(|parent| {
+                        for channel in 0..16 {
+                            let _ = parent
+                                .spawn((
+                                    Node {
+                                        flex_direction: FlexDirection::Column,
+                                        align_items: AlignItems::Center,
+                                        row_gap: Val::Px(6.0),
+                                        padding: UiRect::all(Val::Px(6.0)),
+                                        border: UiRect::all(Val::Px(2.0)),
+                                        ..default()
+                                    },
+                                    BackgroundColor(Color::srgb(0.0, 0.0, 0.7)),
+                                    BorderColor::all(Color::NONE),
+                                    MixerStrip { channel },
+                                ))
+                                .with_children(|parent| {
+                                    let _ = parent.spawn(text(&(channel + 1).to_string(), 22.0));
+                                    let _ = parent.spawn((
+                                        Node {
+                                            width: Val::Px(12.0),
+                                            height: Val::Px(12.0),
+                                            ..default()
+                                        },
+                                        BackgroundColor(CHANNEL_IDLE_COLOR),
+                                        MixerActivity { channel },
+                                    ));
+                                    let _ = parent
+                                        .spawn((
+                                            Node {
+                                                width: Val::Px(FADER_WIDTH),
+                                                height: Val::Px(FADER_HEIGHT),
+                                                flex_direction: FlexDirection::Column,
+                                                justify_content: JustifyContent::FlexEnd,
+                                                ..default()
+                                            },
+                                            BackgroundColor(FADER_TRACK_COLOR),
+                                        ))
+                                        .with_children(|parent| {
+                                            let _ = parent.spawn((
+                                                Node {
+                                                    width: Val::Percent(100.0),
+                                                    height: Val::Percent(100.0),
+                                                    ..default()
+                                                },
+                                                BackgroundColor(CHANNEL_ACTIVE_COLOR),
+                                                MixerFader { channel },
+                                            ));
+                                        });
+                                    for control in MixerControl::ALL {
+                                        let _ = parent.spawn((
+                                            text("", 18.0),
+                                            Node {
+                                                padding: UiRect::horizontal(Val::Px(3.0)),
+                                                ..default()
+                                            },
+                                            BackgroundColor(CONTROL_OFF_COLOR),
+                                            MixerControlLabel { channel, control },
+                                        ));
+                                    }
+                                });
+                        }
+                    });
+                let _ = parent.spawn((text("", 24.0), MixerProgramDetail));
+                let _ = parent.spawn((
+                    Text::new(
+                        "Left/Right: channel, Tab: control, Up/Down: adjust, \
+                         PgUp/PgDn: program by 8, Enter: toggle or reset, Esc: leave",
+                    ),
+                    TextFont {
+                        font: font.clone(),
+                        font_size: 22.0,
+                        ..default()
+                    },
+                    TextColor(Color::srgb(0.8, 0.8, 0.8)),
+                ));
+            });
+    });
+}
+
+type MixerLabels<'w, 's> = Query<
+    'w,
+    's,
+    (
+        &'static MixerControlLabel,
+        &'static mut Text,
+        &'static mut TextColor,
+        &'static mut BackgroundColor,
+    ),
+    (Without<MixerFader>, Without<MixerActivity>),
+>;
+
+/// The parts of the mixer page that follow the strips.
+#[derive(SystemParam)]
+pub(super) struct MixerNodes<'w, 's> {
+    strips: Query<'w, 's, (&'static MixerStrip, &'static mut BorderColor)>,
+    faders: Query<
+        'w,
+        's,
+        (
+            &'static MixerFader,
+            &'static mut Node,
+            &'static mut BackgroundColor,
+        ),
+    >,
+    lights:
+        Query<'w, 's, (&'static MixerActivity, &'static mut BackgroundColor), Without<MixerFader>>,
+    labels: MixerLabels<'w, 's>,
+    detail:
+        Query<'w, 's, &'static mut Text, (With<MixerProgramDetail>, Without<MixerControlLabel>)>,
+}
+
+pub(super) fn update_mixer_page(
+    ui_state: Res<UiState>,
+    mixer: Res<ChannelMixer>,
+    audio_state: Res<AudioState>,
+    midi_tracks: Res<MidiTracks>,
+    preferences: Res<Preferences>,
+    nodes: MixerNodes,
+) {
+    if ui_state.page != UiPage::Mixer {
+        return;
+    }
+    let MixerNodes {
+        mut strips,
+        mut faders,
+        mut lights,
+        mut labels,
+        mut detail,
+    } = nodes;
+
+    let color = |channel: usize| {
+        preferences
+            .channel_palette
+            .map_or(CHANNEL_ACTIVE_COLOR, |palette| {
+                channel_color(channel as u8, palette)
+            })
+    };
+    let audible = audible_channels(&mixer.strips);
+    for (strip, mut border) in &mut strips {
+        *border = BorderColor::all(if strip.channel == mixer.channel {
+            Color::WHITE
+        } else {
+            Color::NONE
+        });
+    }
+    for (fader, mut node, mut bg) in &mut faders {
+        node.height = Val::Percent(mixer.strips[fader.channel].volume as f32);
+        bg.0 = if audible[fader.channel] {
+            color(fader.channel)
+        } else {
+            FADER_SILENT_COLOR
+        };
+    }
+    let active = audio_state.active_channels(CHANNEL_ACTIVITY_WINDOW_SECS);
+    for (light, mut bg) in &mut lights {
+        bg.0 = if active[light.channel] && audible[light.channel] {
+            color(light.channel)
+        } else {
+            CHANNEL_IDLE_COLOR
+        };
+    }
+
+    let tick = audio_state.current_tick().unwrap_or(0);
+    for (label, mut text, mut text_color, mut bg) in &mut labels {
+        let strip = mixer.strips[label.channel];
+        let (value, on) = match label.control {
+            MixerControl::Volume => (format!("{}%", strip.volume), None),
+            MixerControl::Pan => {
+                let pan = strip.pan.unwrap_or_else(|| {
+                    pan_at(
+                        midi_tracks.0.iter().flat_map(|track| &track.pan_events),
+                        label.channel as u8,
+                        tick,
+                    )
+                });
+                (pan_label(pan), None)
+            }
+            MixerControl::Program => (
+                strip
+                    .program
+                    .map_or("P-".to_string(), |program| format!("P{}", program + 1)),
+                None,
+            ),
+            MixerControl::Mute => ("M".to_string(), Some((strip.mute, MUTE_ON_COLOR))),
+            MixerControl::Solo => ("S".to_string(), Some((strip.solo, SOLO_ON_COLOR))),
+        };
+        if text.0 != value {
+            text.0 = value;
+        }
+        let focused = label.channel == mixer.channel && label.control == mixer.control;
+        text_color.0 = if focused {
+            FOCUS_COLOR
+        } else if (label.control == MixerControl::Pan && strip.pan.is_none())
+            || (label.control == MixerControl::Program && strip.program.is_none())
+        {
+            FILE_PAN_COLOR
+        } else {
+            Color::WHITE
+        };
+        bg.0 = match on {
+            Some((true, color)) => color,
+            _ => CONTROL_OFF_COLOR,
+        };
+    }
+
+    let label = program_detail(mixer.channel, mixer.strips[mixer.channel].program);
+    for mut text in &mut detail {
+        if text.0 != label {
+            text.0 = label.clone();
+        }
+    }
+}
+
+fn program_detail(channel: usize, program: Option<u8>) -> String {
+    match program {
+        Some(program) => format!("Channel {}: {}", channel + 1, program_label(program)),
+        None => format!("Channel {}: the file's programs", channel + 1),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{pan_label, program_detail};
+
+    #[test]
+    fn pan_label_names_side_and_distance() {
+        assert_eq!(pan_label(64), "C");
+        assert_eq!(pan_label(0), "L64");
+        assert_eq!(pan_label(32), "L32");
+        assert_eq!(pan_label(127), "R63");
+        assert_eq!(pan_label(200), "R63");
+    }
+
+    #[test]
+    fn program_detail_names_the_override() {
+        assert_eq!(program_detail(0, Some(40)), "Channel 1: 41 Violin");
+        assert_eq!(program_detail(9, None), "Channel 10: the file's programs");
+    }
+}
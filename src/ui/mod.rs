@@ -1,31 +1,140 @@
 mod about;
+mod lyrics;
+mod markers;
+mod mixer;
+mod palette;
 mod piano;
+mod pixel;
+mod settings;
 mod splash;
 mod tracks;
 
-use crate::state::{UiPage, UiState};
+pub(crate) use tracks::position_label;
+
+use crate::audio::AudioState;
+use crate::state::{
+    GotoEntry, MidiTracks, PixelRender, PlaybackState, PlaybackStatus, Preferences, StatusMessage,
+    TimeDisplay, UiPage, UiState,
+};
+use bevy::asset::LoadState;
+use bevy::color::Luminance;
+use bevy::ecs::system::SystemParam;
+use bevy::input::mouse::MouseWheel;
+use bevy::log::{debug, warn};
 use bevy::prelude::{
-    default, App, AssetServer, BackgroundColor, Camera2d, Color, Commands, Component, Display,
-    Font, Handle, Node, Plugin, Query, Res, Resource, Startup, Update, Val, With, Without,
+    default, App, AssetServer, Assets, BackgroundColor, ButtonInput, Camera2d, Changed, Color,
+    Commands, Component, CursorMoved, DetectChanges, Display, Font, Handle, Image, Interaction,
+    KeyCode, Local, MessageReader, MouseButton, Node, Plugin, PositionType, Query, Res, ResMut,
+    Resource, Startup, Text, TextColor, TextFont, Time, UVec2, UiRect, UiScale, Update, Val, Vec2,
+    Window, With, ZIndex,
 };
+use bevy::ui::RelativeCursorPosition;
+use bevy::window::PrimaryWindow;
+use bevy::winit::{UpdateMode, WinitSettings};
+use std::time::Duration;
+
+/// The root node of each page; `update_page_visibility` shows the ones the
+/// current `UiPage` needs.
+#[derive(Component, Clone, Copy, PartialEq, Eq)]
+pub enum PageRoot {
+    Splash,
+    About,
+    Tracks,
+    PianoRoll,
+    Settings,
+    Lyrics,
+    Mixer,
+}
+
+/// Nodes with `Marker` whose click state changed this frame, with where the
+/// cursor is on them.
+type ClickedNodes<'w, 's, Marker> = Query<
+    'w,
+    's,
+    (&'static Interaction, &'static RelativeCursorPosition),
+    (Changed<Interaction>, With<Marker>),
+>;
 
 #[derive(Component)]
-pub struct SplashPageRoot;
+struct StatusMessageText;
 
 #[derive(Component)]
-pub struct AboutPageRoot;
+struct GotoEntryText;
 
 #[derive(Component)]
-pub struct TracksPageRoot;
+struct ClipIndicator;
+
+/// How long the clip indicator stays lit after the last clipped buffer.
+const CLIP_HOLD_SECS: f64 = 0.6;
+
+// Below this a node has collapsed, e.g. while the window is minimized.
+const MIN_RENDER_PX: f32 = 1.0;
+
+/// Whether a node of `node_size` is worth redrawing. A zero-sized window or
+/// node keeps its last texture and picks up again once restored.
+fn has_render_area(node_size: Vec2, window_size: Option<UVec2>) -> bool {
+    window_size.is_none_or(|size| size.x > 0 && size.y > 0)
+        && node_size.x >= MIN_RENDER_PX
+        && node_size.y >= MIN_RENDER_PX
+}
+
+fn primary_window_size(windows: &Query<&Window, With<PrimaryWindow>>) -> Option<UVec2> {
+    windows.iter().next().map(|window| window.physical_size())
+}
+
+/// Points `slot` at `image` and frees the texture it held, so redraws don't
+/// pile up GPU memory. Only strong handles are ours to free: the
+/// `Handle::default()` placeholder names Bevy's shared default texture.
+fn replace_image(slot: &mut Handle<Image>, image: Handle<Image>, images: &mut Assets<Image>) {
+    let old = std::mem::replace(slot, image);
+    if old != *slot {
+        release_image(&old, images);
+    }
+}
+
+fn release_image(handle: &Handle<Image>, images: &mut Assets<Image>) {
+    if handle.is_strong() {
+        let _image = images.remove(handle.id());
+    }
+}
 
 #[derive(Component)]
-pub struct PianoRollPageRoot;
+struct PulseBackground {
+    base: Color,
+}
+
+const PULSE_AMPLITUDE: f32 = 0.08;
+
+const NO_MIDI_HINT: &str =
+    "Press O to pick a MIDI file, drag one onto the window, or press T then Enter on the menu.";
+
+fn beat_phase(tick: u64, ticks_per_beat: u32) -> f32 {
+    let ticks_per_beat = ticks_per_beat.max(1) as u64;
+    (tick % ticks_per_beat) as f32 / ticks_per_beat as f32
+}
+
+fn pulse_strength(phase: f32) -> f32 {
+    let decay = 1.0 - phase.clamp(0.0, 1.0);
+    decay * decay
+}
 
 #[derive(Resource)]
 pub(super) struct UiFonts {
     main: Handle<Font>,
 }
 
+const MAIN_FONT_PATH: &str = "PixelifySans-Regular.ttf";
+const UI_SCALE_MIN: f32 = 0.5;
+const UI_SCALE_MAX: f32 = 3.0;
+const UI_SCALE_STEP: f32 = 0.1;
+
+pub(crate) fn clamp_ui_scale(scale: f32) -> f32 {
+    if !scale.is_finite() {
+        return 1.0;
+    }
+    scale.clamp(UI_SCALE_MIN, UI_SCALE_MAX)
+}
+
 pub struct UiPlugin;
 
 impl Plugin for UiPlugin {
@@ -35,32 +144,89 @@ impl Plugin for UiPlugin {
             .add_systems(
                 Update,
                 (
+                    apply_font_fallback,
+                    adjust_ui_scale,
+                    toggle_time_display,
                     update_page_visibility,
+                    update_status_message,
+                    update_goto_entry,
+                    tracks::click_track_rows,
+                    tracks::seek_clicked_preview,
+                    update_beat_pulse,
+                    splash::animate_splash_border,
+                    piano::reset_view_on_track_switch,
+                    piano::reset_piano_roll_view,
+                    piano::audition_clicked_pitch,
+                    update_power_mode,
+                    update_clip_indicator,
+                    splash::update_copyright_text,
+                    piano::toggle_ghost_tracks,
+                    markers::update_marker_list,
+                    piano::sync_piano_roll_style,
+                    piano::cycle_display_quantize,
+                ),
+            )
+            .add_systems(
+                Update,
+                (
                     splash::update_selection_visuals,
+                    splash::update_recent_files_list,
+                    splash::update_recent_files_selection,
+                    tracks::update_tracks_layout,
                     tracks::update_tracks_list,
+                    tracks::update_track_labels,
                     tracks::update_track_ruler,
                     tracks::update_track_previews,
                     tracks::update_track_details_popup,
                     tracks::update_tracks_scroll,
                     tracks::toggle_debug_overlay,
                     tracks::update_tracks_focus_visuals,
+                    tracks::update_channel_activity,
+                    tracks::update_loop_highlights,
                     tracks::update_debug_overlay,
                     piano::update_piano_roll_view,
                     piano::update_piano_roll_ruler,
                     piano::update_piano_roll_labels,
+                    piano::cycle_grid_subdivision,
+                    settings::update_settings_page,
+                ),
+            )
+            .add_systems(
+                Update,
+                (
+                    lyrics::update_lyrics_lines,
+                    lyrics::update_lyrics_highlight,
+                    pixel::fit_pixel_canvas,
+                    mixer::update_mixer_page,
+                    piano::seek_clicked_tick,
+                    splash::update_playback_status_text,
                 ),
             )
             .init_resource::<tracks::DebugOverlayState>()
-            .init_resource::<tracks::TracksScroll>();
+            .init_resource::<tracks::TracksScroll>()
+            .init_resource::<tracks::TracksLayout>()
+            .init_resource::<piano::PianoGridState>()
+            .init_resource::<piano::PianoRollStyle>()
+            .init_resource::<lyrics::LyricsLayout>();
     }
 }
 
-fn setup_ui(mut commands: Commands, asset_server: Res<AssetServer>) {
-    println!("Setting up UI...");
-    let _ = commands.spawn(Camera2d::default());
+fn setup_ui(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    pixel_render: Option<Res<PixelRender>>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    debug!("Setting up UI...");
+    match pixel_render {
+        Some(pixel) => pixel::spawn_pixel_cameras(&mut commands, &mut images, *pixel),
+        None => {
+            let _ = commands.spawn(Camera2d);
+        }
+    }
 
-    let font = asset_server.load("PixelifySans-Regular.ttf");
-    let _ = commands.insert_resource(UiFonts { main: font.clone() });
+    let font = asset_server.load(MAIN_FONT_PATH);
+    commands.insert_resource(UiFonts { main: font.clone() });
 
     let root = commands
         .spawn((
@@ -70,68 +236,425 @@ fn setup_ui(mut commands: Commands, asset_server: Res<AssetServer>) {
                 ..default()
             },
             BackgroundColor(Color::srgb(0.0, 0.0, 0.5)),
+            PulseBackground {
+                base: Color::srgb(0.0, 0.0, 0.5),
+            },
         ))
         .id();
     splash::spawn_splash_page(&mut commands, root, font.clone());
     about::spawn_about_page(&mut commands, root, font.clone());
     tracks::spawn_tracks_page(&mut commands, root, font.clone());
     piano::spawn_piano_roll_page(&mut commands, root, font.clone());
-    println!("UI setup complete.");
+    settings::spawn_settings_page(&mut commands, root, font.clone());
+    lyrics::spawn_lyrics_page(&mut commands, root, font.clone());
+    mixer::spawn_mixer_page(&mut commands, root, font.clone());
+    markers::spawn_marker_list(&mut commands, root, font.clone());
+    let _ = commands.entity(root).with_children(|parent| {
+        let _ = parent.spawn((
+            Text::new(""),
+            TextFont {
+                font: font.clone(),
+                font_size: 22.0,
+                ..default()
+            },
+            TextColor(Color::srgb(1.0, 1.0, 0.0)),
+            Node {
+                position_type: PositionType::Absolute,
+                left: Val::Px(16.0),
+                bottom: Val::Px(12.0),
+                display: Display::None,
+                ..default()
+            },
+            ZIndex(30),
+            StatusMessageText,
+        ));
+        let _ = parent.spawn((
+            Text::new(""),
+            TextFont {
+                font: font.clone(),
+                font_size: 22.0,
+                ..default()
+            },
+            TextColor(Color::WHITE),
+            BackgroundColor(Color::srgb(0.0, 0.0, 0.25)),
+            Node {
+                position_type: PositionType::Absolute,
+                left: Val::Px(16.0),
+                bottom: Val::Px(44.0),
+                padding: UiRect::axes(Val::Px(8.0), Val::Px(4.0)),
+                display: Display::None,
+                ..default()
+            },
+            ZIndex(30),
+            GotoEntryText,
+        ));
+        let _ = parent.spawn((
+            Text::new("CLIP"),
+            TextFont {
+                font: font.clone(),
+                font_size: 18.0,
+                ..default()
+            },
+            TextColor(Color::WHITE),
+            BackgroundColor(Color::srgb(0.85, 0.1, 0.1)),
+            Node {
+                position_type: PositionType::Absolute,
+                right: Val::Px(16.0),
+                top: Val::Px(12.0),
+                padding: UiRect::axes(Val::Px(6.0), Val::Px(2.0)),
+                display: Display::None,
+                ..default()
+            },
+            ZIndex(30),
+            ClipIndicator,
+        ));
+    });
+    debug!("UI setup complete.");
 }
 
-fn update_page_visibility(
-    ui_state: Res<UiState>,
-    mut splash_query: Query<&mut Node, With<SplashPageRoot>>,
-    mut about_query: Query<&mut Node, (With<AboutPageRoot>, Without<SplashPageRoot>)>,
-    mut tracks_query: Query<
-        &mut Node,
-        (
-            With<TracksPageRoot>,
-            Without<SplashPageRoot>,
-            Without<AboutPageRoot>,
-        ),
-    >,
-    mut piano_query: Query<
-        &mut Node,
-        (
-            With<PianoRollPageRoot>,
-            Without<SplashPageRoot>,
-            Without<AboutPageRoot>,
-            Without<TracksPageRoot>,
-        ),
-    >,
+fn apply_font_fallback(
+    asset_server: Res<AssetServer>,
+    mut fonts: ResMut<UiFonts>,
+    mut text_fonts: Query<&mut TextFont>,
 ) {
-    let splash_display = if ui_state.page == UiPage::Splash {
-        Display::Flex
-    } else {
-        Display::None
-    };
-    let about_display = if ui_state.page == UiPage::About {
+    if fonts.main == Handle::default() {
+        return;
+    }
+    if !matches!(
+        asset_server.load_state(fonts.main.id()),
+        LoadState::Failed(_)
+    ) {
+        return;
+    }
+
+    warn!("Failed to load {MAIN_FONT_PATH}, falling back to the default font.");
+    let failed = std::mem::take(&mut fonts.main);
+    for mut text_font in &mut text_fonts {
+        if text_font.font == failed {
+            text_font.font = fonts.main.clone();
+        }
+    }
+}
+
+// UiScale multiplies both font sizes and `Val::Px` column widths, so the
+// label/column ratio used by `max_label_chars` holds at every scale.
+fn adjust_ui_scale(keyboard_input: Res<ButtonInput<KeyCode>>, mut ui_scale: ResMut<UiScale>) {
+    let ctrl = keyboard_input.pressed(KeyCode::ControlLeft)
+        || keyboard_input.pressed(KeyCode::ControlRight);
+    if !ctrl {
+        return;
+    }
+
+    if keyboard_input.just_pressed(KeyCode::Equal)
+        || keyboard_input.just_pressed(KeyCode::NumpadAdd)
+    {
+        ui_scale.0 = clamp_ui_scale(ui_scale.0 + UI_SCALE_STEP);
+    }
+    if keyboard_input.just_pressed(KeyCode::Minus)
+        || keyboard_input.just_pressed(KeyCode::NumpadSubtract)
+    {
+        ui_scale.0 = clamp_ui_scale(ui_scale.0 - UI_SCALE_STEP);
+    }
+    if keyboard_input.just_pressed(KeyCode::Digit0) {
+        ui_scale.0 = 1.0;
+    }
+}
+
+fn toggle_time_display(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mut preferences: ResMut<Preferences>,
+) {
+    if keyboard_input.just_pressed(KeyCode::F2) {
+        preferences.time_display = match preferences.time_display {
+            TimeDisplay::Time => TimeDisplay::Ticks,
+            TimeDisplay::Ticks => TimeDisplay::Time,
+        };
+    }
+}
+
+fn update_status_message(
+    time: Res<Time>,
+    mut status: ResMut<StatusMessage>,
+    mut query: Query<(&mut Text, &mut Node), With<StatusMessageText>>,
+) {
+    if status.remaining_secs > 0.0 {
+        status.remaining_secs = (status.remaining_secs - time.delta_secs()).max(0.0);
+    }
+    let visible = status.remaining_secs > 0.0;
+    for (mut text, mut node) in &mut query {
+        if visible && text.0 != status.text {
+            text.0 = status.text.clone();
+        }
+        node.display = if visible {
+            Display::Flex
+        } else {
+            Display::None
+        };
+    }
+}
+
+fn update_goto_entry(
+    entry: Res<GotoEntry>,
+    mut query: Query<(&mut Text, &mut Node), With<GotoEntryText>>,
+) {
+    if !entry.is_changed() {
+        return;
+    }
+    for (mut text, mut node) in &mut query {
+        text.0 = format!("Go to (bar or m:ss): {}_  -> {}", entry.text, entry.preview);
+        node.display = if entry.open {
+            Display::Flex
+        } else {
+            Display::None
+        };
+    }
+}
+
+fn clip_lit(now: f64, last_clip: Option<f64>) -> bool {
+    last_clip.is_some_and(|at| now - at < CLIP_HOLD_SECS)
+}
+
+// The audio thread only counts clipped buffers; a change in the count since
+// the last frame restarts the hold.
+fn update_clip_indicator(
+    time: Res<Time>,
+    audio_state: Res<AudioState>,
+    mut seen: Local<(u64, Option<f64>)>,
+    mut query: Query<&mut Node, With<ClipIndicator>>,
+) {
+    let now = time.elapsed_secs_f64();
+    let count = audio_state.clip_count();
+    if count != seen.0 {
+        *seen = (count, Some(now));
+    }
+    let display = if clip_lit(now, seen.1) {
         Display::Flex
     } else {
         Display::None
     };
-    let tracks_display = if ui_state.page == UiPage::Tracks {
-        Display::Flex
-    } else {
-        Display::None
+    for mut node in &mut query {
+        if node.display != display {
+            node.display = display;
+        }
+    }
+}
+
+/// How often an idle app still wakes, so timed text such as the status line
+/// and the splash animation keep moving, just less smoothly.
+const IDLE_WAKE_INTERVAL: Duration = Duration::from_millis(250);
+
+fn power_update_mode(playing: bool, idle_seconds: f32, timeout: Option<f32>) -> UpdateMode {
+    match timeout {
+        Some(timeout) if !playing && idle_seconds >= timeout => {
+            UpdateMode::reactive_low_power(IDLE_WAKE_INTERVAL)
+        }
+        _ => UpdateMode::Continuous,
+    }
+}
+
+/// Keyboard and mouse input, to tell whether the user is still around.
+#[derive(SystemParam)]
+struct UserActivity<'w, 's> {
+    keyboard_input: Res<'w, ButtonInput<KeyCode>>,
+    mouse_input: Res<'w, ButtonInput<MouseButton>>,
+    cursor_moved: MessageReader<'w, 's, CursorMoved>,
+    mouse_wheel: MessageReader<'w, 's, MouseWheel>,
+}
+
+impl UserActivity<'_, '_> {
+    /// Whether anything was pressed or moved since the last call.
+    fn take(&mut self) -> bool {
+        let active = self.keyboard_input.get_pressed().next().is_some()
+            || self.mouse_input.get_pressed().next().is_some()
+            || !self.cursor_moved.is_empty()
+            || !self.mouse_wheel.is_empty();
+        self.cursor_moved.clear();
+        self.mouse_wheel.clear();
+        active
+    }
+}
+
+fn update_power_mode(
+    time: Res<Time>,
+    mut activity: UserActivity,
+    playback: Res<PlaybackStatus>,
+    preferences: Res<Preferences>,
+    mut winit_settings: ResMut<WinitSettings>,
+    mut last_input: Local<f64>,
+) {
+    let now = time.elapsed_secs_f64();
+    if activity.take() {
+        *last_input = now;
+    }
+    let mode = power_update_mode(
+        playback.state == PlaybackState::Playing,
+        (now - *last_input) as f32,
+        preferences.idle_timeout_seconds,
+    );
+    if winit_settings.focused_mode != mode {
+        winit_settings.focused_mode = mode;
+        winit_settings.unfocused_mode = mode;
+    }
+}
+
+fn update_beat_pulse(
+    preferences: Res<Preferences>,
+    playback_status: Res<PlaybackStatus>,
+    audio_state: Res<AudioState>,
+    midi_tracks: Res<MidiTracks>,
+    mut backgrounds: Query<(&PulseBackground, &mut BackgroundColor)>,
+) {
+    let tick = audio_state.current_tick();
+    let ticks_per_beat = midi_tracks.0.first().map(|track| track.ticks_per_beat);
+    let strength = match (tick, ticks_per_beat) {
+        (Some(tick), Some(ticks_per_beat))
+            if preferences.beat_pulse && playback_status.state == PlaybackState::Playing =>
+        {
+            pulse_strength(beat_phase(tick, ticks_per_beat))
+        }
+        _ => 0.0,
     };
-    let piano_display = if ui_state.page == UiPage::PianoRoll {
-        Display::Flex
+    for (pulse, mut bg) in &mut backgrounds {
+        let color = pulse.base.lighter(PULSE_AMPLITUDE * strength);
+        if bg.0 != color {
+            bg.0 = color;
+        }
+    }
+}
+
+/// Widths of the tracks and piano roll pages; they share the row in the
+/// split view and fill it otherwise.
+fn page_widths(page: UiPage, divider_percent: f32) -> (Val, Val) {
+    if page == UiPage::Split {
+        let divider = divider_percent.clamp(0.0, 100.0);
+        (Val::Percent(divider), Val::Percent(100.0 - divider))
     } else {
-        Display::None
+        (Val::Percent(100.0), Val::Percent(100.0))
+    }
+}
+
+fn update_page_visibility(
+    ui_state: Res<UiState>,
+    preferences: Res<Preferences>,
+    mut roots: Query<(&PageRoot, &mut Node)>,
+) {
+    let page = ui_state.page;
+    let (tracks_width, piano_width) = page_widths(page, preferences.split_divider_percent);
+    for (root, mut node) in &mut roots {
+        let shown = match root {
+            PageRoot::Splash => page == UiPage::Splash,
+            PageRoot::About => page == UiPage::About,
+            PageRoot::Tracks => page.shows_tracks(),
+            PageRoot::PianoRoll => page.shows_piano_roll(),
+            PageRoot::Settings => page == UiPage::Settings,
+            PageRoot::Lyrics => page == UiPage::Lyrics,
+            PageRoot::Mixer => page == UiPage::Mixer,
+        };
+        node.display = if shown { Display::Flex } else { Display::None };
+        match root {
+            PageRoot::Tracks => node.width = tracks_width,
+            PageRoot::PianoRoll => node.width = piano_width,
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        beat_phase, clamp_ui_scale, clip_lit, has_render_area, page_widths, power_update_mode,
+        pulse_strength, replace_image, IDLE_WAKE_INTERVAL,
     };
+    use crate::state::UiPage;
+    use bevy::asset::AssetId;
+    use bevy::prelude::{Assets, Handle, Image, UVec2, Val, Vec2};
+    use bevy::winit::UpdateMode;
 
-    for mut node in &mut splash_query {
-        node.display = splash_display;
+    #[test]
+    fn collapsed_nodes_and_minimized_windows_skip_rendering() {
+        let window = Some(UVec2::new(1280, 720));
+        assert!(has_render_area(Vec2::new(300.0, 40.0), window));
+        assert!(has_render_area(Vec2::new(300.0, 40.0), None));
+        assert!(!has_render_area(Vec2::new(0.0, 40.0), window));
+        assert!(!has_render_area(Vec2::new(300.0, 0.2), window));
+        assert!(!has_render_area(Vec2::new(300.0, 40.0), Some(UVec2::ZERO)));
     }
-    for mut node in &mut about_query {
-        node.display = about_display;
+
+    #[test]
+    fn replacing_images_keeps_one_live_texture_and_the_default() {
+        let mut images = Assets::<Image>::default();
+        images.insert(AssetId::default(), Image::default()).unwrap();
+        let mut slot = Handle::default();
+        for _ in 0..100 {
+            let image = images.add(Image::default());
+            replace_image(&mut slot, image, &mut images);
+            assert_eq!(images.len(), 2);
+        }
+        let same = slot.clone();
+        replace_image(&mut slot, same, &mut images);
+        assert_eq!(images.len(), 2);
+        assert!(images.get(AssetId::default()).is_some());
     }
-    for mut node in &mut tracks_query {
-        node.display = tracks_display;
+
+    #[test]
+    fn split_view_divides_the_row_between_tracks_and_roll() {
+        assert_eq!(
+            page_widths(UiPage::Split, 40.0),
+            (Val::Percent(40.0), Val::Percent(60.0))
+        );
+        assert_eq!(
+            page_widths(UiPage::Tracks, 40.0),
+            (Val::Percent(100.0), Val::Percent(100.0))
+        );
+        assert_eq!(
+            page_widths(UiPage::Split, 120.0),
+            (Val::Percent(100.0), Val::Percent(0.0))
+        );
     }
-    for mut node in &mut piano_query {
-        node.display = piano_display;
+
+    #[test]
+    fn clip_indicator_holds_briefly_after_a_clip() {
+        assert!(!clip_lit(5.0, None));
+        assert!(clip_lit(5.0, Some(5.0)));
+        assert!(clip_lit(5.5, Some(5.0)));
+        assert!(!clip_lit(5.7, Some(5.0)));
+    }
+
+    #[test]
+    fn power_mode_idles_only_when_stopped_past_timeout() {
+        let low_power = UpdateMode::reactive_low_power(IDLE_WAKE_INTERVAL);
+        assert_eq!(power_update_mode(false, 12.0, Some(10.0)), low_power);
+        assert_eq!(
+            power_update_mode(false, 3.0, Some(10.0)),
+            UpdateMode::Continuous
+        );
+        assert_eq!(
+            power_update_mode(true, 60.0, Some(10.0)),
+            UpdateMode::Continuous
+        );
+        assert_eq!(power_update_mode(false, 60.0, None), UpdateMode::Continuous);
+    }
+
+    #[test]
+    fn clamp_ui_scale_bounds() {
+        assert_eq!(clamp_ui_scale(1.5), 1.5);
+        assert_eq!(clamp_ui_scale(0.1), 0.5);
+        assert_eq!(clamp_ui_scale(10.0), 3.0);
+        assert_eq!(clamp_ui_scale(f32::NAN), 1.0);
+    }
+
+    #[test]
+    fn beat_phase_wraps_each_beat() {
+        assert_eq!(beat_phase(0, 480), 0.0);
+        assert_eq!(beat_phase(240, 480), 0.5);
+        assert_eq!(beat_phase(960, 480), 0.0);
+        assert_eq!(beat_phase(10, 0), 0.0);
+    }
+
+    #[test]
+    fn pulse_strength_decays_over_the_beat() {
+        assert_eq!(pulse_strength(0.0), 1.0);
+        assert_eq!(pulse_strength(0.5), 0.25);
+        assert_eq!(pulse_strength(1.0), 0.0);
     }
 }
@@ -0,0 +1,87 @@
+use crate::state::ChannelPalette;
+use bevy::prelude::Color;
+
+// Evenly spaced hues. Bright, but the reds, oranges and greens blur
+// together with red-green colour blindness.
+const VIBRANT_PALETTE: [Color; 16] = [
+    Color::hsl(0.0, 0.85, 0.6),
+    Color::hsl(22.5, 0.85, 0.6),
+    Color::hsl(45.0, 0.85, 0.6),
+    Color::hsl(67.5, 0.85, 0.6),
+    Color::hsl(90.0, 0.85, 0.6),
+    Color::hsl(112.5, 0.85, 0.6),
+    Color::hsl(135.0, 0.85, 0.6),
+    Color::hsl(157.5, 0.85, 0.6),
+    Color::hsl(180.0, 0.85, 0.6),
+    Color::hsl(202.5, 0.85, 0.6),
+    Color::hsl(225.0, 0.85, 0.6),
+    Color::hsl(247.5, 0.85, 0.6),
+    Color::hsl(270.0, 0.85, 0.6),
+    Color::hsl(292.5, 0.85, 0.6),
+    Color::hsl(315.0, 0.85, 0.6),
+    Color::hsl(337.5, 0.85, 0.6),
+];
+
+// The Okabe-Ito set, with grey in place of black so it shows on the dark
+// roll, then the same eight shifted in lightness for channels 9-16. Pairs
+// that share a hue are told apart by lightness alone.
+const COLOR_BLIND_PALETTE: [Color; 16] = [
+    Color::srgb_u8(230, 159, 0),
+    Color::srgb_u8(86, 180, 233),
+    Color::srgb_u8(0, 158, 115),
+    Color::srgb_u8(240, 228, 66),
+    Color::srgb_u8(0, 114, 178),
+    Color::srgb_u8(213, 94, 0),
+    Color::srgb_u8(204, 121, 167),
+    Color::srgb_u8(153, 153, 153),
+    Color::srgb_u8(242, 207, 128),
+    Color::srgb_u8(170, 218, 244),
+    Color::srgb_u8(128, 206, 185),
+    // Yellow is already near white, so its partner is darker instead.
+    Color::srgb_u8(168, 160, 46),
+    Color::srgb_u8(128, 184, 216),
+    Color::srgb_u8(234, 174, 128),
+    Color::srgb_u8(230, 188, 211),
+    Color::srgb_u8(204, 204, 204),
+];
+
+pub(super) fn channel_color(channel: u8, palette: ChannelPalette) -> Color {
+    let colors = match palette {
+        ChannelPalette::Vibrant => &VIBRANT_PALETTE,
+        ChannelPalette::ColorBlindSafe => &COLOR_BLIND_PALETTE,
+    };
+    colors[channel as usize % colors.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{channel_color, COLOR_BLIND_PALETTE, VIBRANT_PALETTE};
+    use crate::state::ChannelPalette;
+    use crate::ui::piano::PIANO_BACKGROUND_COLOR;
+    use bevy::prelude::Oklaba;
+
+    fn lightness(color: bevy::prelude::Color) -> f32 {
+        Oklaba::from(color).lightness
+    }
+
+    #[test]
+    fn palette_entries_stand_out_from_the_roll() {
+        let background = lightness(PIANO_BACKGROUND_COLOR);
+        for color in VIBRANT_PALETTE.iter().chain(&COLOR_BLIND_PALETTE) {
+            assert!(lightness(*color) - background >= 0.3, "{color:?}");
+        }
+    }
+
+    #[test]
+    fn color_blind_pairs_differ_in_lightness() {
+        for channel in 0..8 {
+            let first = lightness(channel_color(channel, ChannelPalette::ColorBlindSafe));
+            let second = lightness(channel_color(channel + 8, ChannelPalette::ColorBlindSafe));
+            assert!((first - second).abs() >= 0.1, "channel {channel}");
+        }
+        assert_eq!(
+            channel_color(16, ChannelPalette::Vibrant),
+            channel_color(0, ChannelPalette::Vibrant)
+        );
+    }
+}
@@ -1,15 +1,28 @@
-use super::PianoRollPageRoot;
-use crate::audio::AudioState;
-use crate::state::{MidiTracks, PianoRollViewState, TracksFocus, UiPage, UiState};
+use super::palette::channel_color;
+use super::{
+    has_render_area, primary_window_size, replace_image, ClickedNodes, PageRoot, NO_MIDI_HINT,
+};
+use crate::audio::{loop_tick_range, transpose_key, AudioCommand, AudioSender, AudioState};
+use crate::state::{
+    display_note_name, ChannelPalette, CompareFile, DisplayTranspose, LoopRegion, MidiTrackInfo,
+    MidiTracks, NoteSpan, PianoRollViewState, Preferences, SoundFontPath, StatusMessage,
+    TrackTranspose, TracksFocus, UiState,
+};
 use bevy::asset::RenderAssetUsages;
+use bevy::ecs::system::SystemParam;
 use bevy::image::ImageSampler;
 use bevy::prelude::{
-    default, AlignItems, Assets, BackgroundColor, BorderColor, Children, Color, ColorToPacked,
-    Commands, Component, ComputedNode, DetectChanges, Display, Entity, FlexDirection, Font, Handle,
-    Image, ImageNode, JustifyContent, Node, NodeImageMode, Overflow, PositionType, Query, Res,
-    ResMut, Text, TextColor, TextFont, UiRect, Val, With,
+    default, AlignItems, Assets, BackgroundColor, BorderColor, ButtonInput, Children, Color,
+    ColorToPacked, Commands, Component, ComputedNode, DetectChanges, Display, Entity,
+    FlexDirection, Font, Handle, Image, ImageNode, Interaction, JustifyContent, KeyCode, Local,
+    Node, NodeImageMode, Overflow, PositionType, Query, Res, ResMut, Resource, Text, TextColor,
+    TextFont, UiRect, Val, Window, With,
 };
 use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
+use bevy::ui::RelativeCursorPosition;
+use bevy::window::PrimaryWindow;
+use std::borrow::Cow;
+use std::collections::HashMap;
 
 #[derive(Component)]
 pub(super) struct PianoRollView {
@@ -20,6 +33,9 @@ pub(super) struct PianoRollView {
 
 const MAX_TEXTURE_SIZE: u32 = 16_384;
 
+#[derive(Component)]
+pub(super) struct PianoRollEmptyState;
+
 #[derive(Component)]
 pub(super) struct PianoRollRuler {
     image_entity: bevy::prelude::Entity,
@@ -35,18 +51,167 @@ pub(super) struct PianoRollLabelsRoot {
 #[derive(Component)]
 pub(super) struct PianoRollLabel;
 
-const PIANO_BACKGROUND_COLOR: Color = Color::srgb(0.06, 0.06, 0.12);
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(super) enum GridSubdivision {
+    #[default]
+    Off,
+    Quarter,
+    Eighth,
+    Sixteenth,
+}
+
+impl GridSubdivision {
+    fn next(self) -> Self {
+        match self {
+            GridSubdivision::Off => GridSubdivision::Quarter,
+            GridSubdivision::Quarter => GridSubdivision::Eighth,
+            GridSubdivision::Eighth => GridSubdivision::Sixteenth,
+            GridSubdivision::Sixteenth => GridSubdivision::Off,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            GridSubdivision::Off => "off",
+            GridSubdivision::Quarter => "1/4",
+            GridSubdivision::Eighth => "1/8",
+            GridSubdivision::Sixteenth => "1/16",
+        }
+    }
+}
+
+#[derive(Resource, Default)]
+pub(super) struct PianoGridState {
+    subdivision: GridSubdivision,
+    /// Draw the other tracks' notes behind the focused one.
+    ghost_tracks: bool,
+    /// Snap drawn note edges to this grid; playback is unaffected.
+    quantize: GridSubdivision,
+}
+
+/// Note colours for the piano roll, so the focused track stands out from
+/// the ghosted background tracks.
+#[derive(Resource)]
+pub(super) struct PianoRollStyle {
+    pub(super) focused_note_color: Color,
+    pub(super) ghost_note_color: Color,
+    /// Colours the focused track's notes by channel instead of
+    /// `focused_note_color`.
+    pub(super) channel_palette: Option<ChannelPalette>,
+}
+
+impl Default for PianoRollStyle {
+    fn default() -> Self {
+        Self {
+            focused_note_color: PIANO_NOTE_COLOR,
+            ghost_note_color: PIANO_GHOST_NOTE_COLOR,
+            channel_palette: None,
+        }
+    }
+}
+
+impl PianoRollStyle {
+    fn focused_note_colors(&self) -> [[u8; 4]; 16] {
+        std::array::from_fn(|channel| {
+            self.channel_palette
+                .map_or(self.focused_note_color, |palette| {
+                    channel_color(channel as u8, palette)
+                })
+                .to_srgba()
+                .to_u8_array()
+        })
+    }
+}
+
+pub(super) fn sync_piano_roll_style(
+    preferences: Res<Preferences>,
+    mut style: ResMut<PianoRollStyle>,
+) {
+    if preferences.is_changed() && style.channel_palette != preferences.channel_palette {
+        style.channel_palette = preferences.channel_palette;
+    }
+}
+
+// Subdivision lines closer together than this are skipped; they would
+// just shade the whole roll.
+const MIN_SUBDIVISION_PX: f32 = 3.0;
+
+pub(super) const PIANO_BACKGROUND_COLOR: Color = Color::srgb(0.06, 0.06, 0.12);
 const PIANO_NOTE_COLOR: Color = Color::srgb(0.95, 0.9, 0.25);
+const PIANO_GHOST_NOTE_COLOR: Color = Color::srgb(0.3, 0.32, 0.45);
+const PIANO_LOOP_COLOR: Color = Color::srgb(0.1, 0.1, 0.22);
+const PIANO_LOOP_MARKER_COLOR: Color = Color::srgb(1.0, 0.8, 0.2);
+/// Notes only in the loaded file when a comparison file is shown.
+const PIANO_COMPARE_ADDED_COLOR: Color = Color::srgb(0.3, 0.9, 0.45);
+/// Notes only in the comparison file.
+const PIANO_COMPARE_REMOVED_COLOR: Color = Color::srgb(0.95, 0.3, 0.55);
+
+/// General MIDI percussion key map, for channel 10 where a key picks a drum.
+pub(super) fn drum_name(note: u8) -> &'static str {
+    match note {
+        35 => "Kick 2",
+        36 => "Kick",
+        37 => "Side Stick",
+        38 => "Snare",
+        39 => "Clap",
+        40 => "Snare 2",
+        41 => "Low Floor Tom",
+        42 => "Closed HH",
+        43 => "High Floor Tom",
+        44 => "Pedal HH",
+        45 => "Low Tom",
+        46 => "Open HH",
+        47 => "Low-Mid Tom",
+        48 => "High-Mid Tom",
+        49 => "Crash",
+        50 => "High Tom",
+        51 => "Ride",
+        52 => "China",
+        53 => "Ride Bell",
+        54 => "Tambourine",
+        55 => "Splash",
+        56 => "Cowbell",
+        57 => "Crash 2",
+        58 => "Vibraslap",
+        59 => "Ride 2",
+        60 => "High Bongo",
+        61 => "Low Bongo",
+        62 => "Mute Conga",
+        63 => "Open Conga",
+        64 => "Low Conga",
+        65 => "High Timbale",
+        66 => "Low Timbale",
+        67 => "High Agogo",
+        68 => "Low Agogo",
+        69 => "Cabasa",
+        70 => "Maracas",
+        71 => "Short Whistle",
+        72 => "Long Whistle",
+        73 => "Short Guiro",
+        74 => "Long Guiro",
+        75 => "Claves",
+        76 => "High Block",
+        77 => "Low Block",
+        78 => "Mute Cuica",
+        79 => "Open Cuica",
+        80 => "Mute Triangle",
+        81 => "Open Triangle",
+        _ => "Percussion",
+    }
+}
+
+pub(super) fn is_percussion_track(track: &MidiTrackInfo) -> bool {
+    !track.channels.is_empty() && track.channels.iter().all(|channel| *channel == 9)
+}
 
 // TODO: instead of rendering pitch names, render a piano keyboard (white + black keys)
 // and just label the octaves
-fn note_name(pitch: u8) -> String {
-    const NAMES: [&str; 12] = [
-        "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
-    ];
-    let octave = (pitch / 12) as i32 - 1;
-    let name = NAMES[(pitch % 12) as usize];
-    format!("{name}{octave}")
+fn pitch_label(pitch: u8, percussion: bool, display_transpose: i8) -> String {
+    if percussion {
+        drum_name(pitch).to_string()
+    } else {
+        display_note_name(pitch, display_transpose)
+    }
 }
 
 fn pitch_list(start: u8, end: u8) -> Vec<u8> {
@@ -71,6 +236,17 @@ fn note_cell_band(height: u32, pitch_start: u8, pitch_end: u8, pitch: u8) -> (u3
     (start.min(height.saturating_sub(1)), end)
 }
 
+/// Inverse of `note_cell_band`: the pitch whose row covers `fraction` of the
+/// way down the roll.
+fn pitch_at_row_fraction(fraction: f32, pitch_start: u8, pitch_end: u8) -> Option<u8> {
+    if pitch_end < pitch_start || !(0.0..=1.0).contains(&fraction) {
+        return None;
+    }
+    let pitch_count = (pitch_end - pitch_start) as u32 + 1;
+    let index = ((fraction * pitch_count as f32) as u32).min(pitch_count - 1);
+    Some(pitch_end - index as u8)
+}
+
 fn piano_grid_color() -> Color {
     Color::srgb(0.12, 0.12, 0.2)
 }
@@ -79,6 +255,78 @@ fn piano_grid_major_color() -> Color {
     Color::srgb(0.18, 0.18, 0.28)
 }
 
+fn piano_grid_subdivision_color() -> Color {
+    Color::srgb(0.09, 0.09, 0.16)
+}
+
+// Beats are treated as quarter notes, matching the beat grid.
+fn subdivision_ticks(ticks_per_beat: u32, subdivision: GridSubdivision) -> Option<f32> {
+    let per_beat = match subdivision {
+        GridSubdivision::Off => return None,
+        GridSubdivision::Quarter => 1.0,
+        GridSubdivision::Eighth => 2.0,
+        GridSubdivision::Sixteenth => 4.0,
+    };
+    Some(ticks_per_beat.max(1) as f32 / per_beat)
+}
+
+// Rounds both edges to the nearest step; a note that would collapse keeps
+// one step so it stays visible.
+fn quantized_span(span: &NoteSpan, step: Option<f32>) -> (u64, u64) {
+    let end = span.end.max(span.start);
+    let Some(step) = step else {
+        return (span.start, end);
+    };
+    let snap = |tick: u64| ((tick as f32 / step).round() * step) as u64;
+    let start = snap(span.start);
+    let end = snap(end).max(start + step as u64);
+    (start, end)
+}
+
+// Moves spans from one file's resolution to another's so both line up by
+// beat.
+fn rescaled_spans(
+    spans: &[NoteSpan],
+    from_ticks_per_beat: u32,
+    to_ticks_per_beat: u32,
+) -> Vec<NoteSpan> {
+    let from = u128::from(from_ticks_per_beat.max(1));
+    let to = u128::from(to_ticks_per_beat.max(1));
+    let scale = |tick: u64| (u128::from(tick) * to / from) as u64;
+    spans
+        .iter()
+        .map(|span| NoteSpan {
+            start: scale(span.start),
+            end: scale(span.end),
+            ..span.clone()
+        })
+        .collect()
+}
+
+/// Pairs notes with the same pitch, start and end across two lists. Returns
+/// which of `ours` and which of `theirs` found a partner.
+fn diff_spans(ours: &[NoteSpan], theirs: &[NoteSpan]) -> (Vec<bool>, Vec<bool>) {
+    let mut unmatched: HashMap<(u8, u64, u64), Vec<usize>> = HashMap::new();
+    for (index, span) in theirs.iter().enumerate().rev() {
+        unmatched
+            .entry((span.pitch, span.start, span.end))
+            .or_default()
+            .push(index);
+    }
+    let mut ours_matched = vec![false; ours.len()];
+    let mut theirs_matched = vec![false; theirs.len()];
+    for (index, span) in ours.iter().enumerate() {
+        if let Some(other) = unmatched
+            .get_mut(&(span.pitch, span.start, span.end))
+            .and_then(Vec::pop)
+        {
+            ours_matched[index] = true;
+            theirs_matched[other] = true;
+        }
+    }
+    (ours_matched, theirs_matched)
+}
+
 fn compute_visible_ticks(end_tick: u64, zoom_x: f32) -> f32 {
     let zoom = zoom_x.max(1.0);
     (end_tick.max(1) as f32 / zoom).max(1.0)
@@ -110,6 +358,13 @@ fn ruler_left_px(
     Some((ratio * width_px).min(max_left))
 }
 
+// Inverse of `ruler_left_px`: the tick `fraction` of the way across the view.
+fn view_fraction_tick(fraction: f32, track_end: u64, view: &PianoRollViewState) -> u64 {
+    let visible_ticks = compute_visible_ticks(track_end, view.zoom_x);
+    let offset_ticks = clamp_offset_ticks(view.offset_ticks, track_end, view.zoom_x);
+    (offset_ticks + fraction.clamp(0.0, 1.0) * visible_ticks).round() as u64
+}
+
 fn compute_visible_pitch_range(min_pitch: u8, max_pitch: u8, zoom_y: f32) -> f32 {
     let span = (max_pitch.saturating_sub(min_pitch).max(1) + 1) as f32;
     (span / zoom_y.max(1.0)).max(1.0)
@@ -142,6 +397,22 @@ fn visible_pitch_bounds(
     (start_u8, end_u8)
 }
 
+fn transposed_track(track: &MidiTrackInfo, semitones: i8) -> Cow<'_, MidiTrackInfo> {
+    if semitones == 0 {
+        return Cow::Borrowed(track);
+    }
+    let mut shifted = track.clone();
+    for span in &mut shifted.note_spans {
+        span.pitch = transpose_key(span.pitch, span.channel, semitones);
+    }
+    let pitches = shifted.note_spans.iter().map(|span| span.pitch);
+    if let (Some(min), Some(max)) = (pitches.clone().min(), pitches.max()) {
+        shifted.min_pitch = min;
+        shifted.max_pitch = max;
+    }
+    Cow::Owned(shifted)
+}
+
 fn should_rebuild_labels(root: &PianoRollLabelsRoot, start: u8, end: u8, height: u32) -> bool {
     let height_diff = root.height.max(height) - root.height.min(height);
     root.start != start || root.end != end || height_diff > 1
@@ -172,12 +443,46 @@ fn pitch_to_row(height: u32, min_pitch: u8, max_pitch: u8, pitch: u8) -> u32 {
         .clamp(0.0, height as f32 - 1.0) as u32
 }
 
-fn build_piano_roll_data(
+// Column of `tick` in the roll, or `None` when it is scrolled out of view.
+fn visible_tick_column(
+    tick: u64,
+    offset_ticks: f32,
+    visible_ticks: f32,
+    width: u32,
+) -> Option<u32> {
+    let tick = tick as f32;
+    if tick < offset_ticks || tick > offset_ticks + visible_ticks {
+        return None;
+    }
+    let x = ((tick - offset_ticks) / visible_ticks * (width as f32 - 1.0)).round();
+    Some((x.max(0.0) as u32).min(width.saturating_sub(1)))
+}
+
+/// What the roll draws around the focused track's notes.
+#[derive(Clone, Copy, Default)]
+pub(super) struct RollOverlays<'a> {
+    pub(super) ghosts: &'a [&'a MidiTrackInfo],
+    pub(super) compare: Option<&'a MidiTrackInfo>,
+    pub(super) subdivision: GridSubdivision,
+    pub(super) quantize: GridSubdivision,
+    pub(super) loop_ticks: Option<(u64, u64)>,
+}
+
+pub(super) fn build_piano_roll_data(
     track: &crate::state::MidiTrackInfo,
     width: u32,
     height: u32,
     view: &PianoRollViewState,
+    overlays: &RollOverlays,
+    style: &PianoRollStyle,
 ) -> Vec<u8> {
+    let RollOverlays {
+        ghosts,
+        compare,
+        subdivision,
+        quantize,
+        loop_ticks,
+    } = *overlays;
     let width = width.max(1);
     let height = height.max(1);
     let mut data = build_empty_piano_roll_data(width, height);
@@ -190,6 +495,54 @@ fn build_piano_roll_data(
 
     let grid_color = piano_grid_color().to_srgba().to_u8_array();
     let grid_major = piano_grid_major_color().to_srgba().to_u8_array();
+    if let Some((loop_start, loop_end)) = loop_ticks {
+        let loop_color = PIANO_LOOP_COLOR.to_srgba().to_u8_array();
+        let x0 = (((loop_start as f32 - offset_ticks) / visible_ticks) * (width as f32 - 1.0))
+            .round()
+            .clamp(0.0, width as f32 - 1.0) as u32;
+        let x1 = (((loop_end as f32 - offset_ticks) / visible_ticks) * (width as f32 - 1.0))
+            .round()
+            .clamp(0.0, width as f32 - 1.0) as u32;
+        for y in 0..height {
+            for x in x0..x1 {
+                let idx = ((y * width + x) * 4) as usize;
+                if idx + 4 <= data.len() {
+                    data[idx..idx + 4].copy_from_slice(&loop_color);
+                }
+            }
+        }
+        let marker_color = PIANO_LOOP_MARKER_COLOR.to_srgba().to_u8_array();
+        for tick in [loop_start, loop_end] {
+            let Some(x) = visible_tick_column(tick, offset_ticks, visible_ticks, width) else {
+                continue;
+            };
+            for y in 0..height {
+                let idx = ((y * width + x) * 4) as usize;
+                if idx + 4 <= data.len() {
+                    data[idx..idx + 4].copy_from_slice(&marker_color);
+                }
+            }
+        }
+    }
+    if let Some(step) = subdivision_ticks(track.ticks_per_beat, subdivision) {
+        let grid_subdivision = piano_grid_subdivision_color().to_srgba().to_u8_array();
+        if step / visible_ticks * width as f32 >= MIN_SUBDIVISION_PX {
+            let step_start = (offset_ticks / step).floor() as i64;
+            let step_end = ((offset_ticks + visible_ticks) / step).ceil() as i64;
+            for step_index in step_start..=step_end {
+                let tick = step_index as f32 * step;
+                let x = (((tick - offset_ticks) / visible_ticks) * (width as f32 - 1.0))
+                    .round()
+                    .clamp(0.0, width as f32 - 1.0) as u32;
+                for y in 0..height {
+                    let idx = ((y * width + x) * 4) as usize;
+                    if idx + 4 <= data.len() {
+                        data[idx..idx + 4].copy_from_slice(&grid_subdivision);
+                    }
+                }
+            }
+        }
+    }
     let ticks_per_beat = track.ticks_per_beat.max(1) as f32;
     let beat_start = (offset_ticks / ticks_per_beat).floor() as i64;
     let beat_end = ((offset_ticks + visible_ticks) / ticks_per_beat).ceil() as i64;
@@ -231,18 +584,21 @@ fn build_piano_roll_data(
         }
     }
 
-    let note_color = PIANO_NOTE_COLOR.to_srgba().to_u8_array();
-    for span in &track.note_spans {
-        if (span.end as f32) < offset_ticks || (span.start as f32) > offset_ticks + visible_ticks {
-            continue;
+    let quantize_step = subdivision_ticks(track.ticks_per_beat, quantize);
+    let mut draw_span = |span: &NoteSpan, colors: &[[u8; 4]; 16]| {
+        let note_color = colors[span.channel as usize % colors.len()];
+        // Zero-length and backwards spans still get a one-pixel sliver.
+        let (span_start, span_end) = quantized_span(span, quantize_step);
+        if (span_end as f32) < offset_ticks || (span_start as f32) > offset_ticks + visible_ticks {
+            return;
         }
         if (span.pitch as f32) < pitch_start || (span.pitch as f32) > pitch_end {
-            continue;
+            return;
         }
-        let x0 = (((span.start as f32 - offset_ticks) / visible_ticks) * (width as f32 - 1.0))
+        let x0 = (((span_start as f32 - offset_ticks) / visible_ticks) * (width as f32 - 1.0))
             .round()
             .clamp(0.0, width as f32 - 1.0) as u32;
-        let x1 = (((span.end as f32 - offset_ticks) / visible_ticks) * (width as f32 - 1.0))
+        let x1 = (((span_end as f32 - offset_ticks) / visible_ticks) * (width as f32 - 1.0))
             .round()
             .clamp(0.0, width as f32 - 1.0) as u32;
         let (row_start, row_end) = note_cell_band(height, pitch_start_u8, pitch_end_u8, span.pitch);
@@ -256,19 +612,55 @@ fn build_piano_roll_data(
                 }
             }
         }
+    };
+    let ghost_colors = [style.ghost_note_color.to_srgba().to_u8_array(); 16];
+    for ghost in ghosts {
+        for span in &ghost.note_spans {
+            draw_span(span, &ghost_colors);
+        }
+    }
+    let focused_colors = style.focused_note_colors();
+    let Some(compare) = compare else {
+        for span in &track.note_spans {
+            draw_span(span, &focused_colors);
+        }
+        return data;
+    };
+    let theirs = rescaled_spans(
+        &compare.note_spans,
+        compare.ticks_per_beat,
+        track.ticks_per_beat,
+    );
+    let (ours_matched, theirs_matched) = diff_spans(&track.note_spans, &theirs);
+    let removed_colors = [PIANO_COMPARE_REMOVED_COLOR.to_srgba().to_u8_array(); 16];
+    for (span, _) in theirs
+        .iter()
+        .zip(&theirs_matched)
+        .filter(|(_, matched)| !**matched)
+    {
+        draw_span(span, &removed_colors);
+    }
+    let added_colors = [PIANO_COMPARE_ADDED_COLOR.to_srgba().to_u8_array(); 16];
+    for (span, matched) in track.note_spans.iter().zip(&ours_matched) {
+        draw_span(
+            span,
+            if *matched {
+                &focused_colors
+            } else {
+                &added_colors
+            },
+        );
     }
 
     data
 }
 
 fn build_piano_roll_image(
-    track: &crate::state::MidiTrackInfo,
+    data: Vec<u8>,
     width: u32,
     height: u32,
     images: &mut Assets<Image>,
-    view: &PianoRollViewState,
 ) -> Handle<Image> {
-    let data = build_piano_roll_data(track, width, height, view);
     let image = Image::new(
         Extent3d {
             width: width.max(1),
@@ -298,7 +690,7 @@ pub(super) fn spawn_piano_roll_page(commands: &mut Commands, parent: Entity, fon
                     display: Display::None,
                     ..default()
                 },
-                PianoRollPageRoot,
+                PageRoot::PianoRoll,
             ))
             .with_children(|parent| {
                 let _ = parent
@@ -336,7 +728,7 @@ pub(super) fn spawn_piano_roll_page(commands: &mut Commands, parent: Entity, fon
                             TextColor(Color::srgb(0.8, 0.8, 0.8)),
                         ));
                         let _ = parent.spawn((
-                            Text::new("Arrows pan, +/- zoom time, Shift+Up/Down zoom pitch."),
+                            Text::new("Arrows pan, +/- zoom time, Shift+Up/Down zoom pitch, Ctrl+Z/Y undo/redo, G grid."),
                             TextFont {
                                 font: font.clone(),
                                 font_size: 20.0,
@@ -344,6 +736,20 @@ pub(super) fn spawn_piano_roll_page(commands: &mut Commands, parent: Entity, fon
                             },
                             TextColor(Color::srgb(0.7, 0.7, 0.8)),
                         ));
+                        let _ = parent.spawn((
+                            Text::new(format!("No MIDI file loaded yet. {NO_MIDI_HINT}")),
+                            TextFont {
+                                font: font.clone(),
+                                font_size: 22.0,
+                                ..default()
+                            },
+                            TextColor(Color::srgb(1.0, 1.0, 0.0)),
+                            Node {
+                                display: Display::None,
+                                ..default()
+                            },
+                            PianoRollEmptyState,
+                        ));
                         let _ = parent
                             .spawn((
                                 Node {
@@ -359,7 +765,7 @@ pub(super) fn spawn_piano_roll_page(commands: &mut Commands, parent: Entity, fon
                             .with_children(|parent| {
                                 let _ = parent.spawn((
                                     Node {
-                                        width: Val::Px(70.0),
+                                        width: Val::Px(110.0),
                                         height: Val::Percent(100.0),
                                         flex_direction: FlexDirection::Column,
                                         overflow: Overflow::clip(),
@@ -405,6 +811,8 @@ pub(super) fn spawn_piano_roll_page(commands: &mut Commands, parent: Entity, fon
                                                     image: handle,
                                                     last_size: (0, 0),
                                                 },
+                                                Interaction::default(),
+                                                RelativeCursorPosition::default(),
                                             ))
                                             .id();
                                         let _ = parent.spawn((
@@ -426,60 +834,329 @@ pub(super) fn spawn_piano_roll_page(commands: &mut Commands, parent: Entity, fon
     });
 }
 
+/// The focused track and how the roll is looking at it; most piano roll
+/// systems start from this.
+#[derive(SystemParam)]
+pub(super) struct FocusedRoll<'w> {
+    ui_state: Res<'w, UiState>,
+    tracks_focus: Res<'w, TracksFocus>,
+    midi_tracks: Res<'w, MidiTracks>,
+    view_state: Res<'w, PianoRollViewState>,
+    transpose: Res<'w, TrackTranspose>,
+    display_transpose: Res<'w, DisplayTranspose>,
+}
+
+impl FocusedRoll<'_> {
+    fn shown(&self) -> bool {
+        self.ui_state.page.shows_piano_roll()
+    }
+
+    fn track(&self) -> Option<&MidiTrackInfo> {
+        self.midi_tracks.0.get(self.tracks_focus.index)
+    }
+}
+
+/// The settings behind `RollOverlays`.
+#[derive(SystemParam)]
+pub(super) struct RollDecor<'w> {
+    grid_state: Res<'w, PianoGridState>,
+    style: Res<'w, PianoRollStyle>,
+    loop_region: Res<'w, LoopRegion>,
+    compare: Res<'w, CompareFile>,
+}
+
+impl RollDecor<'_> {
+    fn is_changed(&self) -> bool {
+        self.grid_state.is_changed()
+            || self.style.is_changed()
+            || self.loop_region.is_changed()
+            || self.compare.is_changed()
+    }
+}
+
 pub(super) fn update_piano_roll_view(
-    ui_state: Res<UiState>,
-    tracks_focus: Res<TracksFocus>,
-    midi_tracks: Res<MidiTracks>,
-    view_state: Res<PianoRollViewState>,
+    roll: FocusedRoll,
+    decor: RollDecor,
     mut views: Query<(&ComputedNode, &mut PianoRollView, &mut ImageNode)>,
     mut images: ResMut<Assets<Image>>,
+    mut empty_states: Query<&mut Node, With<PianoRollEmptyState>>,
+    windows: Query<&Window, With<PrimaryWindow>>,
 ) {
-    if ui_state.page != UiPage::PianoRoll {
+    if !roll.shown() {
         return;
     }
+    let FocusedRoll {
+        tracks_focus,
+        midi_tracks,
+        view_state,
+        transpose,
+        ..
+    } = roll;
+    let decor_changed = decor.is_changed();
+    let RollDecor {
+        grid_state,
+        style,
+        loop_region,
+        compare,
+    } = decor;
+    let empty_display = if midi_tracks.0.is_empty() {
+        Display::Flex
+    } else {
+        Display::None
+    };
+    for mut node in &mut empty_states {
+        if node.display != empty_display {
+            node.display = empty_display;
+        }
+    }
 
     let track_index = tracks_focus.index;
-    let track = midi_tracks.0.get(track_index);
+    let track = midi_tracks
+        .0
+        .get(track_index)
+        .map(|track| transposed_track(track, transpose.get(track.index)));
+    let window_size = primary_window_size(&windows);
     for (node, mut view, mut image_node) in &mut views {
+        if !has_render_area(node.size, window_size) {
+            continue;
+        }
         let width = node.size.x.round().max(1.0) as u32;
         let height = node.size.y.round().max(1.0) as u32;
         let width = width.min(MAX_TEXTURE_SIZE);
         let height = height.min(MAX_TEXTURE_SIZE);
         let size_changed = view.last_size != (width, height);
         let track_changed = view.track_index != track_index;
-        if !size_changed && !track_changed && !midi_tracks.is_changed() && !view_state.is_changed()
+        if !size_changed
+            && !track_changed
+            && !midi_tracks.is_changed()
+            && !view_state.is_changed()
+            && !transpose.is_changed()
+            && !decor_changed
         {
             continue;
         }
 
-        let new_handle = if let Some(track) = track {
-            build_piano_roll_image(track, width, height, &mut images, &view_state)
+        let data = if let Some(track) = &track {
+            let ghosts: Vec<Cow<'_, MidiTrackInfo>> = if grid_state.ghost_tracks {
+                midi_tracks
+                    .0
+                    .iter()
+                    .enumerate()
+                    .filter(|(index, _)| *index != track_index)
+                    .map(|(_, track)| transposed_track(track, transpose.get(track.index)))
+                    .collect()
+            } else {
+                Vec::new()
+            };
+            let ghosts: Vec<&MidiTrackInfo> = ghosts.iter().map(|ghost| ghost.as_ref()).collect();
+            let compare_track = compare
+                .visible
+                .then(|| compare.tracks.get(track_index))
+                .flatten();
+            let overlays = RollOverlays {
+                ghosts: &ghosts,
+                compare: compare_track,
+                subdivision: grid_state.subdivision,
+                quantize: grid_state.quantize,
+                loop_ticks: loop_tick_range(&loop_region, &midi_tracks.0),
+            };
+            build_piano_roll_data(track, width, height, &view_state, &overlays, &style)
         } else {
-            let data = build_empty_piano_roll_data(width, height);
-            let image = Image::new(
-                Extent3d {
-                    width: width.max(1),
-                    height: height.max(1),
-                    depth_or_array_layers: 1,
-                },
-                TextureDimension::D2,
-                data,
-                TextureFormat::Rgba8UnormSrgb,
-                RenderAssetUsages::MAIN_WORLD | RenderAssetUsages::RENDER_WORLD,
-            );
-            let mut image = image;
-            image.sampler = ImageSampler::nearest();
-            images.add(image)
+            build_empty_piano_roll_data(width, height)
         };
+        let new_handle = build_piano_roll_image(data, width, height, &mut images);
 
-        let old_handle = std::mem::replace(&mut view.image, new_handle.clone());
+        image_node.image = new_handle.clone();
+        replace_image(&mut view.image, new_handle, &mut images);
         view.last_size = (width, height);
         view.track_index = track_index;
-        image_node.image = new_handle;
-        if old_handle != view.image && images.get(old_handle.id()).is_some() {
-            let _image = images.remove(old_handle.id());
+    }
+}
+
+/// The view a track opens at: the configured default zoom, scrolled to the
+/// start of the track and the lowest pitches.
+pub(super) fn default_view(preferences: &Preferences) -> PianoRollViewState {
+    PianoRollViewState {
+        zoom_x: preferences.default_zoom_x,
+        zoom_y: preferences.default_zoom_y,
+        ..PianoRollViewState::default()
+    }
+}
+
+/// Returns the view to switch to when focus has moved to another track since
+/// `last_track`, so tracks don't inherit the zoom the previous one was left at.
+pub(super) fn view_for_track_switch(
+    last_track: &mut Option<usize>,
+    track_index: usize,
+    preferences: &Preferences,
+) -> Option<PianoRollViewState> {
+    if *last_track == Some(track_index) {
+        return None;
+    }
+    *last_track = Some(track_index);
+    Some(default_view(preferences))
+}
+
+pub(super) fn reset_view_on_track_switch(
+    tracks_focus: Res<TracksFocus>,
+    midi_tracks: Res<MidiTracks>,
+    preferences: Res<Preferences>,
+    mut view_state: ResMut<PianoRollViewState>,
+    mut last_track: Local<Option<usize>>,
+) {
+    if midi_tracks.is_changed() {
+        *last_track = None;
+    }
+    if let Some(view) = view_for_track_switch(&mut last_track, tracks_focus.index, &preferences) {
+        if *view_state != view {
+            *view_state = view;
+        }
+    }
+}
+
+// A click on the roll moves the playhead to the tick under it. With click
+// audition on, plain clicks play the pitch instead and Shift+click seeks.
+pub(super) fn seek_clicked_tick(
+    roll: FocusedRoll,
+    preferences: Res<Preferences>,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    audio_tx: Res<AudioSender>,
+    views: ClickedNodes<PianoRollView>,
+) {
+    let shift =
+        keyboard_input.pressed(KeyCode::ShiftLeft) || keyboard_input.pressed(KeyCode::ShiftRight);
+    if !roll.shown() || (preferences.click_audition && !shift) {
+        return;
+    }
+    for (interaction, cursor) in &views {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        let (Some(track), Some(position)) = (roll.track(), cursor.normalized) else {
+            continue;
+        };
+        let tick = view_fraction_tick(position.x + 0.5, track.end_tick, &roll.view_state);
+        let _ = audio_tx.0.send(AudioCommand::Seek(tick));
+    }
+}
+
+pub(super) fn audition_clicked_pitch(
+    roll: FocusedRoll,
+    preferences: Res<Preferences>,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    soundfont_path: Res<SoundFontPath>,
+    audio_tx: Res<AudioSender>,
+    mut status: ResMut<StatusMessage>,
+    views: ClickedNodes<PianoRollView>,
+) {
+    let shift =
+        keyboard_input.pressed(KeyCode::ShiftLeft) || keyboard_input.pressed(KeyCode::ShiftRight);
+    if !roll.shown() || !preferences.click_audition || shift {
+        return;
+    }
+    for (interaction, cursor) in &views {
+        if *interaction != Interaction::Pressed {
+            continue;
         }
+        let Some(track) = roll.track() else {
+            continue;
+        };
+        let Some(position) = cursor.normalized else {
+            continue;
+        };
+        let track = transposed_track(track, roll.transpose.get(track.index));
+        let (pitch_start, pitch_end) = visible_pitch_bounds(&track, &roll.view_state);
+        let Some(key) = pitch_at_row_fraction(position.y + 0.5, pitch_start, pitch_end) else {
+            continue;
+        };
+        let Some(soundfont) = soundfont_path.0.clone() else {
+            status.show("Select a SoundFont to audition notes");
+            continue;
+        };
+        let channel = track.channels.first().copied().unwrap_or(0);
+        let program = track
+            .programs
+            .iter()
+            .find(|(program_channel, _)| *program_channel == channel)
+            .map(|(_, program)| *program);
+        let _ = audio_tx.0.send(AudioCommand::Audition {
+            channel,
+            key,
+            program,
+            soundfont,
+        });
+        let label = pitch_label(
+            key,
+            is_percussion_track(&track),
+            roll.display_transpose.get(track.index),
+        );
+        status.show(format!("Audition: {label}"));
+    }
+}
+
+/// F fits the whole track, 0 goes back to the configured default zoom.
+pub(super) fn reset_piano_roll_view(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    ui_state: Res<UiState>,
+    preferences: Res<Preferences>,
+    mut view_state: ResMut<PianoRollViewState>,
+    mut status: ResMut<StatusMessage>,
+) {
+    if !ui_state.page.shows_piano_roll() {
+        return;
+    }
+    let ctrl = keyboard_input.pressed(KeyCode::ControlLeft)
+        || keyboard_input.pressed(KeyCode::ControlRight);
+    if keyboard_input.just_pressed(KeyCode::KeyF) {
+        *view_state = PianoRollViewState::default();
+        status.show("Fit to track");
+    } else if !ctrl && keyboard_input.just_pressed(KeyCode::Digit0) {
+        *view_state = default_view(&preferences);
+        status.show("Default zoom");
+    }
+}
+
+pub(super) fn cycle_grid_subdivision(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    ui_state: Res<UiState>,
+    mut grid_state: ResMut<PianoGridState>,
+    mut status: ResMut<StatusMessage>,
+) {
+    if !ui_state.page.shows_piano_roll() || !keyboard_input.just_pressed(KeyCode::KeyG) {
+        return;
     }
+    grid_state.subdivision = grid_state.subdivision.next();
+    status.show(format!("Grid: {}", grid_state.subdivision.label()));
+}
+
+pub(super) fn cycle_display_quantize(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    ui_state: Res<UiState>,
+    mut grid_state: ResMut<PianoGridState>,
+    mut status: ResMut<StatusMessage>,
+) {
+    if !ui_state.page.shows_piano_roll() || !keyboard_input.just_pressed(KeyCode::KeyQ) {
+        return;
+    }
+    grid_state.quantize = grid_state.quantize.next();
+    status.show(format!("Snap notes: {}", grid_state.quantize.label()));
+}
+
+pub(super) fn toggle_ghost_tracks(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    ui_state: Res<UiState>,
+    mut grid_state: ResMut<PianoGridState>,
+    mut status: ResMut<StatusMessage>,
+) {
+    if !ui_state.page.shows_piano_roll() || !keyboard_input.just_pressed(KeyCode::KeyA) {
+        return;
+    }
+    grid_state.ghost_tracks = !grid_state.ghost_tracks;
+    status.show(if grid_state.ghost_tracks {
+        "Other tracks: shown"
+    } else {
+        "Other tracks: hidden"
+    });
 }
 
 fn collect_descendants(entity: Entity, children_query: &Query<&Children>, out: &mut Vec<Entity>) {
@@ -492,26 +1169,38 @@ fn collect_descendants(entity: Entity, children_query: &Query<&Children>, out: &
     }
 }
 
+/// The pitch label rows under a `PianoRollLabelsRoot` and what they hold.
+#[derive(SystemParam)]
+pub(super) struct LabelRows<'w, 's> {
+    label_nodes: Query<'w, 's, (Entity, &'static Children), With<PianoRollLabel>>,
+    nodes: Query<'w, 's, &'static mut Node>,
+    texts: Query<'w, 's, &'static mut Text>,
+    children_query: Query<'w, 's, &'static Children>,
+}
+
 pub(super) fn update_piano_roll_labels(
-    ui_state: Res<UiState>,
-    tracks_focus: Res<TracksFocus>,
-    midi_tracks: Res<MidiTracks>,
-    view_state: Res<PianoRollViewState>,
+    roll: FocusedRoll,
     mut commands: Commands,
     mut roots: Query<(Entity, &mut PianoRollLabelsRoot, &ComputedNode, &Children)>,
-    label_nodes: Query<(Entity, &Children), With<PianoRollLabel>>,
-    mut nodes: Query<&mut Node>,
-    mut texts: Query<&mut Text>,
-    children_query: Query<&Children>,
+    rows: LabelRows,
     fonts: Res<super::UiFonts>,
 ) {
-    if ui_state.page != UiPage::PianoRoll {
+    if !roll.shown() {
         return;
     }
-    let Some(track) = midi_tracks.0.get(tracks_focus.index) else {
+    let Some(track) = roll.track() else {
         return;
     };
-    let (start_pitch, end_pitch) = visible_pitch_bounds(track, &view_state);
+    let LabelRows {
+        label_nodes,
+        mut nodes,
+        mut texts,
+        children_query,
+    } = rows;
+    let shown_transpose = roll.display_transpose.get(track.index);
+    let track = transposed_track(track, roll.transpose.get(track.index));
+    let (start_pitch, end_pitch) = visible_pitch_bounds(&track, &roll.view_state);
+    let percussion = is_percussion_track(&track);
 
     for (root_entity, mut root, node, root_children) in &mut roots {
         let height = node.size.y.round().max(1.0) as u32;
@@ -539,7 +1228,7 @@ pub(super) fn update_piano_roll_labels(
                         children.iter().find(|child| texts.get_mut(**child).is_ok())
                     {
                         if let Ok(mut text) = texts.get_mut(*text_entity) {
-                            text.0 = note_name(*pitch);
+                            text.0 = pitch_label(*pitch, percussion, shown_transpose);
                         }
                     }
                 }
@@ -565,7 +1254,7 @@ pub(super) fn update_piano_roll_labels(
                         ))
                         .with_children(|parent| {
                             let _ = parent.spawn((
-                                Text::new(note_name(pitch)),
+                                Text::new(pitch_label(pitch, percussion, shown_transpose)),
                                 TextFont {
                                     font: fonts.main.clone(),
                                     font_size: 16.0,
@@ -585,15 +1274,13 @@ pub(super) fn update_piano_roll_labels(
 }
 
 pub(super) fn update_piano_roll_ruler(
-    ui_state: Res<UiState>,
+    roll: FocusedRoll,
     audio_state: Res<AudioState>,
-    midi_tracks: Res<MidiTracks>,
-    tracks_focus: Res<TracksFocus>,
-    view_state: Res<PianoRollViewState>,
     mut rulers: Query<(&mut Node, &PianoRollRuler)>,
     computed_nodes: Query<&ComputedNode>,
+    windows: Query<&Window, With<PrimaryWindow>>,
 ) {
-    if ui_state.page != UiPage::PianoRoll {
+    if !roll.shown() {
         return;
     }
 
@@ -603,19 +1290,24 @@ pub(super) fn update_piano_roll_ruler(
         }
         return;
     };
-    let Some(track) = midi_tracks.0.get(tracks_focus.index) else {
+    let Some(track) = roll.track() else {
         for (mut node, _) in &mut rulers {
             node.display = Display::None;
         }
         return;
     };
 
+    let window_size = primary_window_size(&windows);
     for (mut node, ruler) in &mut rulers {
         let Ok(image_node) = computed_nodes.get(ruler.image_entity) else {
             node.display = Display::None;
             continue;
         };
-        let Some(left_px) = ruler_left_px(tick, track.end_tick, &view_state, image_node.size.x)
+        if !has_render_area(image_node.size, window_size) {
+            continue;
+        }
+        let Some(left_px) =
+            ruler_left_px(tick, track.end_tick, &roll.view_state, image_node.size.x)
         else {
             node.display = Display::None;
             continue;
@@ -630,11 +1322,48 @@ pub(super) fn update_piano_roll_ruler(
 mod tests {
     use super::{
         build_empty_piano_roll_data, build_piano_roll_data, clamp_offset_pitch, clamp_offset_ticks,
-        compute_visible_pitch_range, compute_visible_ticks, note_cell_band, note_name, pitch_list,
-        pitch_to_row, ruler_left_px, should_rebuild_labels, visible_pitch_bounds,
-        PianoRollLabelsRoot,
+        compute_visible_pitch_range, compute_visible_ticks, diff_spans, drum_name, note_cell_band,
+        pitch_at_row_fraction, pitch_label, pitch_list, pitch_to_row, quantized_span,
+        rescaled_spans, ruler_left_px, should_rebuild_labels, subdivision_ticks, transposed_track,
+        view_for_track_switch, view_fraction_tick, visible_pitch_bounds, visible_tick_column,
+        GridSubdivision, PianoRollLabelsRoot, PianoRollStyle, RollOverlays,
+        PIANO_COMPARE_ADDED_COLOR, PIANO_COMPARE_REMOVED_COLOR,
     };
-    use crate::state::{MidiTrackInfo, NoteSpan, PianoRollViewState};
+    use crate::state::{
+        display_note_name, note_name, ArticulationCounts, MidiTrackInfo, NoteSpan,
+        PianoRollViewState, Preferences, RhythmSummary,
+    };
+    use bevy::prelude::ColorToPacked;
+    use std::sync::Arc;
+
+    #[test]
+    fn switching_tracks_applies_default_view() {
+        let preferences = Preferences {
+            default_zoom_x: 4.0,
+            default_zoom_y: 2.0,
+            ..Preferences::default()
+        };
+        let expected = PianoRollViewState {
+            zoom_x: 4.0,
+            zoom_y: 2.0,
+            offset_ticks: 0.0,
+            offset_pitch: 0.0,
+        };
+        let mut last_track = None;
+        assert_eq!(
+            view_for_track_switch(&mut last_track, 0, &preferences),
+            Some(expected)
+        );
+        assert_eq!(
+            view_for_track_switch(&mut last_track, 0, &preferences),
+            None
+        );
+        assert_eq!(
+            view_for_track_switch(&mut last_track, 3, &preferences),
+            Some(expected)
+        );
+        assert_eq!(last_track, Some(3));
+    }
 
     #[test]
     fn pitch_to_row_maps_bounds() {
@@ -642,6 +1371,60 @@ mod tests {
         assert_eq!(pitch_to_row(10, 60, 72, 60), 9);
     }
 
+    #[test]
+    fn transposed_track_shifts_pitched_spans() {
+        let span = |channel: u8, pitch: u8| NoteSpan {
+            channel,
+            pitch,
+            start: 0,
+            end: 10,
+            velocity: 100,
+        };
+        let track = MidiTrackInfo {
+            index: 0,
+            name: None,
+            event_count: 0,
+            end_tick: 10,
+            ticks_per_beat: 10,
+            timing: Arc::default(),
+            note_count: 2,
+            min_pitch: 38,
+            max_pitch: 60,
+            channels: vec![0, 9],
+            programs: vec![],
+            banks: vec![],
+            time_signature: None,
+            time_signature_events: Vec::new(),
+            key_signature: None,
+            sustain_events: Vec::new(),
+            pan_events: Vec::new(),
+            lyric_events: Vec::new(),
+            articulation: ArticulationCounts::default(),
+            rhythm: RhythmSummary::default(),
+            midi_standard: None,
+            instrument_name: None,
+            midi_port: None,
+            channel_prefix: None,
+            copyright: None,
+            cue_points: Vec::new(),
+            markers: Vec::new(),
+            note_spans: vec![span(0, 60), span(9, 38)],
+            preview_width: 1,
+            preview_height: 1,
+            preview_cells: vec![0],
+            onset_preview_cells: vec![0],
+        };
+        let shifted = transposed_track(&track, -12);
+        let pitches = shifted
+            .note_spans
+            .iter()
+            .map(|span| span.pitch)
+            .collect::<Vec<_>>();
+        assert_eq!(pitches, vec![48, 38]);
+        assert_eq!((shifted.min_pitch, shifted.max_pitch), (38, 48));
+        assert_eq!(transposed_track(&track, 0).note_spans[0].pitch, 60);
+    }
+
     #[test]
     fn build_piano_roll_data_draws_notes() {
         let view = PianoRollViewState::default();
@@ -651,29 +1434,239 @@ mod tests {
             event_count: 0,
             end_tick: 100,
             ticks_per_beat: 10,
+            timing: Arc::default(),
             note_count: 1,
             min_pitch: 60,
             max_pitch: 60,
             channels: vec![0],
             programs: vec![],
             banks: vec![],
-            tempo_changes: 0,
             time_signature: None,
+            time_signature_events: Vec::new(),
             key_signature: None,
+            sustain_events: Vec::new(),
+            pan_events: Vec::new(),
+            lyric_events: Vec::new(),
+            articulation: ArticulationCounts::default(),
+            rhythm: RhythmSummary::default(),
+            midi_standard: None,
+            instrument_name: None,
+            midi_port: None,
+            channel_prefix: None,
+            copyright: None,
+            cue_points: Vec::new(),
+            markers: Vec::new(),
             note_spans: vec![NoteSpan {
+                channel: 0,
                 pitch: 60,
                 start: 10,
                 end: 20,
+                velocity: 100,
             }],
             preview_width: 1,
             preview_height: 1,
             preview_cells: vec![0],
+            onset_preview_cells: vec![0],
         };
-        let data = build_piano_roll_data(&track, 20, 10, &view);
+        let data = build_piano_roll_data(
+            &track,
+            20,
+            10,
+            &view,
+            &RollOverlays::default(),
+            &PianoRollStyle::default(),
+        );
         assert_eq!(data.len(), 20 * 10 * 4);
         assert!(data.iter().any(|value| *value > 0));
     }
 
+    // A one-pitch track at ten ticks per beat holding the given spans.
+    fn track_with_spans(note_spans: Vec<NoteSpan>) -> MidiTrackInfo {
+        MidiTrackInfo {
+            index: 0,
+            name: None,
+            event_count: 0,
+            end_tick: 100,
+            ticks_per_beat: 10,
+            timing: Arc::default(),
+            note_count: note_spans.len(),
+            min_pitch: 60,
+            max_pitch: 60,
+            channels: vec![0],
+            programs: vec![],
+            banks: vec![],
+            time_signature: None,
+            time_signature_events: Vec::new(),
+            key_signature: None,
+            sustain_events: Vec::new(),
+            pan_events: Vec::new(),
+            lyric_events: Vec::new(),
+            articulation: ArticulationCounts::default(),
+            rhythm: RhythmSummary::default(),
+            midi_standard: None,
+            instrument_name: None,
+            midi_port: None,
+            channel_prefix: None,
+            copyright: None,
+            cue_points: Vec::new(),
+            markers: Vec::new(),
+            note_spans,
+            preview_width: 1,
+            preview_height: 1,
+            preview_cells: vec![0],
+            onset_preview_cells: vec![0],
+        }
+    }
+
+    #[test]
+    fn ghost_tracks_draw_in_the_ghost_colour() {
+        let span = |start, end| NoteSpan {
+            channel: 0,
+            pitch: 60,
+            start,
+            end,
+            velocity: 100,
+        };
+        let view = PianoRollViewState::default();
+        let track = track_with_spans(vec![span(10, 20)]);
+        // A ghost note later on the same pitch takes the ghost colour.
+        let ghost = track_with_spans(vec![span(70, 90)]);
+        let style = PianoRollStyle::default();
+        let data = build_piano_roll_data(
+            &track,
+            20,
+            10,
+            &view,
+            &RollOverlays {
+                ghosts: &[&ghost],
+                ..RollOverlays::default()
+            },
+            &style,
+        );
+        let pixel = |x: usize| {
+            let idx = (5 * 20 + x) * 4;
+            [data[idx], data[idx + 1], data[idx + 2], data[idx + 3]]
+        };
+        let focused = style.focused_note_color.to_srgba().to_u8_array();
+        let ghosted = style.ghost_note_color.to_srgba().to_u8_array();
+        assert_ne!(focused, ghosted);
+        assert_eq!(pixel(3), focused);
+        assert_eq!(pixel(15), ghosted);
+    }
+
+    #[test]
+    fn comparison_notes_line_up_by_beat() {
+        let span = |pitch, start, end| NoteSpan {
+            channel: 0,
+            pitch,
+            start,
+            end,
+            velocity: 100,
+        };
+        let track = MidiTrackInfo {
+            index: 0,
+            name: None,
+            event_count: 0,
+            end_tick: 100,
+            ticks_per_beat: 10,
+            timing: Arc::default(),
+            note_count: 1,
+            min_pitch: 60,
+            max_pitch: 60,
+            channels: vec![0],
+            programs: vec![],
+            banks: vec![],
+            time_signature: None,
+            time_signature_events: Vec::new(),
+            key_signature: None,
+            sustain_events: Vec::new(),
+            pan_events: Vec::new(),
+            lyric_events: Vec::new(),
+            articulation: ArticulationCounts::default(),
+            rhythm: RhythmSummary::default(),
+            midi_standard: None,
+            instrument_name: None,
+            midi_port: None,
+            channel_prefix: None,
+            copyright: None,
+            cue_points: Vec::new(),
+            markers: Vec::new(),
+            note_spans: vec![span(60, 10, 20), span(60, 40, 50)],
+            preview_width: 1,
+            preview_height: 1,
+            preview_cells: vec![0],
+            onset_preview_cells: vec![0],
+        };
+        // Same first note at twice the resolution, then one the file lacks.
+        let compare = MidiTrackInfo {
+            ticks_per_beat: 20,
+            note_spans: vec![span(60, 20, 40), span(60, 140, 180)],
+            ..track.clone()
+        };
+        let theirs = rescaled_spans(&compare.note_spans, 20, 10);
+        assert_eq!(
+            diff_spans(&track.note_spans, &theirs),
+            (vec![true, false], vec![true, false])
+        );
+
+        let style = PianoRollStyle::default();
+        let data = build_piano_roll_data(
+            &track,
+            20,
+            10,
+            &PianoRollViewState::default(),
+            &RollOverlays {
+                compare: Some(&compare),
+                ..RollOverlays::default()
+            },
+            &style,
+        );
+        let pixel = |x: usize| {
+            let idx = (5 * 20 + x) * 4;
+            [data[idx], data[idx + 1], data[idx + 2], data[idx + 3]]
+        };
+        assert_eq!(pixel(3), style.focused_note_color.to_srgba().to_u8_array());
+        assert_eq!(pixel(9), PIANO_COMPARE_ADDED_COLOR.to_srgba().to_u8_array());
+        assert_eq!(
+            pixel(15),
+            PIANO_COMPARE_REMOVED_COLOR.to_srgba().to_u8_array()
+        );
+    }
+
+    #[test]
+    fn subdivision_ticks_follow_ticks_per_beat() {
+        assert_eq!(
+            subdivision_ticks(480, GridSubdivision::Sixteenth),
+            Some(120.0)
+        );
+        assert_eq!(subdivision_ticks(480, GridSubdivision::Eighth), Some(240.0));
+        assert_eq!(
+            subdivision_ticks(480, GridSubdivision::Quarter),
+            Some(480.0)
+        );
+        assert_eq!(subdivision_ticks(480, GridSubdivision::Off), None);
+    }
+
+    #[test]
+    fn quantized_span_snaps_to_sixteenths() {
+        let span = NoteSpan {
+            channel: 0,
+            pitch: 60,
+            start: 123,
+            end: 290,
+            velocity: 100,
+        };
+        let step = subdivision_ticks(480, GridSubdivision::Sixteenth);
+        assert_eq!(quantized_span(&span, step), (120, 240));
+        assert_eq!(quantized_span(&span, None), (123, 290));
+        let short = NoteSpan {
+            start: 123,
+            end: 130,
+            ..span
+        };
+        assert_eq!(quantized_span(&short, step), (120, 240));
+    }
+
     #[test]
     fn build_empty_piano_roll_data_fills() {
         let data = build_empty_piano_roll_data(4, 3);
@@ -702,6 +1695,29 @@ mod tests {
         assert_eq!(note_cell_band(10, 60, 69, 60), (9, 9));
     }
 
+    #[test]
+    fn pitch_at_row_fraction_inverts_note_cell_band() {
+        let height = 130;
+        for pitch in 60..=72 {
+            let (start, end) = note_cell_band(height, 60, 72, pitch);
+            let middle = (start + end) as f32 / 2.0 / height as f32;
+            assert_eq!(pitch_at_row_fraction(middle, 60, 72), Some(pitch));
+        }
+        assert_eq!(pitch_at_row_fraction(0.0, 60, 72), Some(72));
+        assert_eq!(pitch_at_row_fraction(1.0, 60, 72), Some(60));
+        assert_eq!(pitch_at_row_fraction(-0.1, 60, 72), None);
+        assert_eq!(pitch_at_row_fraction(0.5, 72, 60), None);
+    }
+
+    #[test]
+    fn visible_tick_column_hides_offscreen_ticks() {
+        assert_eq!(visible_tick_column(0, 0.0, 100.0, 101), Some(0));
+        assert_eq!(visible_tick_column(50, 0.0, 100.0, 101), Some(50));
+        assert_eq!(visible_tick_column(150, 100.0, 100.0, 101), Some(50));
+        assert_eq!(visible_tick_column(99, 100.0, 100.0, 101), None);
+        assert_eq!(visible_tick_column(201, 100.0, 100.0, 101), None);
+    }
+
     #[test]
     fn note_cell_band_full_height() {
         assert_eq!(note_cell_band(10, 60, 60, 60), (0, 9));
@@ -714,6 +1730,19 @@ mod tests {
         assert!(left.is_some());
     }
 
+    #[test]
+    fn view_fraction_tick_inverts_ruler_placement() {
+        let view = PianoRollViewState {
+            zoom_x: 2.0,
+            offset_ticks: 100.0,
+            ..PianoRollViewState::default()
+        };
+        let tick = view_fraction_tick(0.25, 400, &view);
+        assert_eq!(tick, 150);
+        assert_eq!(ruler_left_px(tick, 400, &view, 200.0), Some(50.0));
+        assert_eq!(view_fraction_tick(-1.0, 400, &view), 100);
+    }
+
     #[test]
     fn ruler_left_px_outside_view() {
         let view = PianoRollViewState::default();
@@ -721,6 +1750,17 @@ mod tests {
         assert!(left.is_none());
     }
 
+    #[test]
+    fn drum_name_maps_general_midi_keys() {
+        assert_eq!(drum_name(36), "Kick");
+        assert_eq!(drum_name(38), "Snare");
+        assert_eq!(drum_name(42), "Closed HH");
+        assert_eq!(drum_name(20), "Percussion");
+        assert_eq!(pitch_label(38, true, 2), "Snare");
+        assert_eq!(pitch_label(58, false, 2), "C4");
+        assert_eq!(pitch_label(38, false, 0), "D2");
+    }
+
     #[test]
     fn note_name_formats() {
         assert_eq!(note_name(60), "C4");
@@ -728,6 +1768,13 @@ mod tests {
         assert_eq!(note_name(0), "C-1");
     }
 
+    #[test]
+    fn display_note_name_applies_the_transpose() {
+        assert_eq!(display_note_name(60, 2), "D4");
+        assert_eq!(display_note_name(60, -2), "A#3");
+        assert_eq!(display_note_name(126, 5), "G9");
+    }
+
     #[test]
     fn pitch_list_reversed() {
         assert_eq!(pitch_list(60, 62), vec![62, 61, 60]);
@@ -743,23 +1790,39 @@ mod tests {
             event_count: 0,
             end_tick: 1,
             ticks_per_beat: 1,
+            timing: Arc::default(),
             note_count: 1,
             min_pitch: 60,
             max_pitch: 60,
             channels: vec![0],
             programs: vec![],
             banks: vec![],
-            tempo_changes: 0,
             time_signature: None,
+            time_signature_events: Vec::new(),
             key_signature: None,
+            sustain_events: Vec::new(),
+            pan_events: Vec::new(),
+            lyric_events: Vec::new(),
+            articulation: ArticulationCounts::default(),
+            rhythm: RhythmSummary::default(),
+            midi_standard: None,
+            instrument_name: None,
+            midi_port: None,
+            channel_prefix: None,
+            copyright: None,
+            cue_points: Vec::new(),
+            markers: Vec::new(),
             note_spans: vec![NoteSpan {
+                channel: 0,
                 pitch: 60,
                 start: 0,
                 end: 1,
+                velocity: 100,
             }],
             preview_width: 1,
             preview_height: 1,
             preview_cells: vec![0],
+            onset_preview_cells: vec![0],
         };
         let (start, end) = visible_pitch_bounds(&track, &view);
         assert_eq!(start, 60);
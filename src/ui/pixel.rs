@@ -0,0 +1,114 @@
+use crate::state::PixelRender;
+use bevy::camera::{Camera, ClearColorConfig, RenderTarget};
+use bevy::image::ImageSampler;
+use bevy::prelude::{
+    default, Assets, Camera2d, Color, Commands, Component, DetectChanges, Image, IsDefaultUiCamera,
+    Query, Ref, Res, Sprite, Transform, UVec2, Vec3, Window, With,
+};
+use bevy::render::render_resource::TextureFormat;
+use bevy::window::PrimaryWindow;
+
+/// The window-sized sprite showing the low-resolution UI texture.
+#[derive(Component)]
+pub(super) struct PixelCanvas;
+
+/// Largest whole-number upscale of `resolution` that fits `window`, never
+/// below 1, unless `fixed` asks for a particular one.
+pub(super) fn integer_scale(window: UVec2, resolution: UVec2, fixed: Option<u32>) -> u32 {
+    fixed
+        .unwrap_or_else(|| {
+            let resolution = resolution.max(UVec2::ONE);
+            (window.x / resolution.x).min(window.y / resolution.y)
+        })
+        .max(1)
+}
+
+// Half a physical pixel when the border left around the canvas is odd, so
+// canvas pixels land on whole screen pixels.
+fn centring_offset(window: u32, canvas: u32) -> f32 {
+    if window.abs_diff(canvas) % 2 == 1 {
+        0.5
+    } else {
+        0.0
+    }
+}
+
+/// Points the UI at an offscreen texture of the configured size and shows
+/// that texture on the window through a second camera.
+pub(super) fn spawn_pixel_cameras(
+    commands: &mut Commands,
+    images: &mut Assets<Image>,
+    pixel: PixelRender,
+) {
+    let mut image =
+        Image::new_target_texture(pixel.width, pixel.height, TextureFormat::Rgba8UnormSrgb);
+    image.sampler = ImageSampler::nearest();
+    let image = images.add(image);
+
+    let _ = commands.spawn((
+        Camera2d,
+        Camera {
+            target: RenderTarget::Image(image.clone().into()),
+            order: -1,
+            ..default()
+        },
+        IsDefaultUiCamera,
+    ));
+    let _ = commands.spawn((
+        Camera2d,
+        Camera {
+            clear_color: ClearColorConfig::Custom(Color::BLACK),
+            ..default()
+        },
+    ));
+    let _ = commands.spawn((Sprite::from_image(image), Transform::default(), PixelCanvas));
+}
+
+pub(super) fn fit_pixel_canvas(
+    pixel: Option<Res<PixelRender>>,
+    windows: Query<Ref<Window>, With<PrimaryWindow>>,
+    mut canvases: Query<&mut Transform, With<PixelCanvas>>,
+) {
+    let (Some(pixel), Some(window)) = (pixel, windows.iter().next()) else {
+        return;
+    };
+    if !window.is_changed() && !pixel.is_changed() {
+        return;
+    }
+    let window_size = window.physical_size();
+    let resolution = UVec2::new(pixel.width, pixel.height);
+    let scale = integer_scale(window_size, resolution, pixel.scale);
+    let canvas = resolution * scale;
+    // The 2D camera works in logical pixels.
+    let to_logical = 1.0 / window.scale_factor();
+    let offset = Vec3::new(
+        centring_offset(window_size.x, canvas.x),
+        centring_offset(window_size.y, canvas.y),
+        0.0,
+    ) * to_logical;
+    for mut transform in &mut canvases {
+        transform.scale = Vec3::new(scale as f32 * to_logical, scale as f32 * to_logical, 1.0);
+        transform.translation = offset;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{centring_offset, integer_scale};
+    use bevy::prelude::UVec2;
+
+    #[test]
+    fn integer_scale_fits_whole_multiples() {
+        let resolution = UVec2::new(640, 360);
+        assert_eq!(integer_scale(UVec2::new(1920, 1080), resolution, None), 3);
+        assert_eq!(integer_scale(UVec2::new(1919, 1080), resolution, None), 2);
+        assert_eq!(integer_scale(UVec2::new(2560, 1080), resolution, None), 3);
+        assert_eq!(integer_scale(UVec2::new(320, 200), resolution, None), 1);
+        assert_eq!(
+            integer_scale(UVec2::new(1920, 1080), resolution, Some(2)),
+            2
+        );
+        assert_eq!(centring_offset(1921, 1920), 0.5);
+        assert_eq!(centring_offset(1920, 1280), 0.0);
+    }
+}
@@ -0,0 +1,460 @@
+use super::PageRoot;
+use crate::state::{
+    ChannelPalette, Interpolation, LoopSeam, NotePairing, Preferences, PreviewMode, SettingsFocus,
+    SettingsItem, SongEnd, TimeDisplay, UiPage, UiState,
+};
+use bevy::prelude::{
+    default, AlignItems, BackgroundColor, BorderColor, Color, Commands, Component, Display, Entity,
+    FlexDirection, Font, Handle, JustifyContent, Node, Query, Res, Text, TextColor, TextFont,
+    UiRect, Val,
+};
+
+#[derive(Component)]
+pub(super) struct SettingsRow {
+    item: SettingsItem,
+}
+
+fn interpolation_label(mode: Interpolation) -> &'static str {
+    match mode {
+        Interpolation::None => "None (fastest)",
+        Interpolation::Linear => "Linear",
+        Interpolation::FourthOrder => "4th order (default)",
+        Interpolation::SeventhOrder => "7th order (most CPU)",
+    }
+}
+
+fn loop_seam_label(seam: LoopSeam) -> &'static str {
+    match seam {
+        LoopSeam::Cut => "Cut",
+        LoopSeam::Release => "Let notes ring",
+        LoopSeam::Fade => "Fade out",
+    }
+}
+
+fn setting_label(item: SettingsItem, preferences: &Preferences) -> String {
+    match item {
+        SettingsItem::TimeDisplay => format!(
+            "Time display: {}",
+            match preferences.time_display {
+                TimeDisplay::Time => "mm:ss",
+                TimeDisplay::Ticks => "Ticks",
+            }
+        ),
+        SettingsItem::Interpolation => format!(
+            "Interpolation: {}",
+            interpolation_label(preferences.interpolation)
+        ),
+        SettingsItem::BeatPulse => format!(
+            "Beat pulse: {}",
+            if preferences.beat_pulse { "On" } else { "Off" }
+        ),
+        SettingsItem::ReverbTail => {
+            format!("Reverb tail: {:.1}s", preferences.reverb_tail_seconds)
+        }
+        SettingsItem::ReverbLevel => {
+            format!("Reverb level: {:.0}%", preferences.reverb_level * 100.0)
+        }
+        SettingsItem::ChorusLevel => format!("Chorus level: {:.1}", preferences.chorus_level),
+        SettingsItem::SampleRate => match preferences.output_sample_rate {
+            Some(rate) => format!("Output rate: {rate} Hz"),
+            None => "Output rate: Device default".to_string(),
+        },
+        SettingsItem::Polyphony => format!("Polyphony: {} voices", preferences.polyphony),
+        SettingsItem::MenuAnimation => format!(
+            "Menu animation: {}",
+            if preferences.menu_animation {
+                "On"
+            } else {
+                "Off"
+            }
+        ),
+        SettingsItem::FocusProminentTrack => format!(
+            "Focus busiest track on load: {}",
+            if preferences.focus_prominent_track {
+                "On"
+            } else {
+                "Off"
+            }
+        ),
+        SettingsItem::LoopSeam => {
+            format!("Loop seam: {}", loop_seam_label(preferences.loop_seam))
+        }
+        SettingsItem::DefaultZoomX => {
+            format!("Default time zoom: {}x", preferences.default_zoom_x)
+        }
+        SettingsItem::DefaultZoomY => {
+            format!("Default pitch zoom: {}x", preferences.default_zoom_y)
+        }
+        SettingsItem::IdleTimeout => match preferences.idle_timeout_seconds {
+            Some(seconds) => format!("Idle power saving: After {seconds}s"),
+            None => "Idle power saving: Off".to_string(),
+        },
+        SettingsItem::NormalizeLoudness => format!(
+            "Loudness normalization: {}",
+            if preferences.normalize_loudness {
+                "On"
+            } else {
+                "Off"
+            }
+        ),
+        SettingsItem::MenuWrap => format!(
+            "Menu wrap-around: {}",
+            if preferences.menu_wrap { "On" } else { "Off" }
+        ),
+        SettingsItem::HoldToPreview => format!(
+            "Hold H to preview the focused track: {}",
+            if preferences.hold_to_preview {
+                "On"
+            } else {
+                "Off"
+            }
+        ),
+        SettingsItem::Autoplay => format!(
+            "Play files as soon as they load: {}",
+            if preferences.autoplay { "On" } else { "Off" }
+        ),
+        SettingsItem::HideEmptyTracks => format!(
+            "Empty tracks in the list: {}",
+            if preferences.hide_empty_tracks {
+                "Hidden"
+            } else {
+                "Shown"
+            }
+        ),
+        SettingsItem::SongEnd => format!(
+            "Song ends at: {}",
+            match preferences.song_end {
+                SongEnd::LastNote => "Last note",
+                SongEnd::EndOfTrack => "End of Track marker",
+            }
+        ),
+        SettingsItem::ChannelPalette => format!(
+            "Note colors: {}",
+            match preferences.channel_palette {
+                None => "Single",
+                Some(ChannelPalette::Vibrant) => "By channel",
+                Some(ChannelPalette::ColorBlindSafe) => "By channel (color-blind safe)",
+            }
+        ),
+        SettingsItem::QuickLoopBars => format!(
+            "Shift+L loops the last: {} bar{}",
+            preferences.quick_loop_bars,
+            if preferences.quick_loop_bars == 1 {
+                ""
+            } else {
+                "s"
+            }
+        ),
+        SettingsItem::LoopSong => format!(
+            "Loop the whole song: {}",
+            if preferences.loop_song { "On" } else { "Off" }
+        ),
+        SettingsItem::PracticeClick => format!(
+            "Metronome, also while stopped: {}",
+            if preferences.practice_click {
+                "On"
+            } else {
+                "Off"
+            }
+        ),
+        SettingsItem::SplitDivider => format!(
+            "Split view tracks width: {}%",
+            preferences.split_divider_percent
+        ),
+        SettingsItem::ScrubOnSeek => format!(
+            "Scrub preview while seeking: {}",
+            if preferences.scrub_on_seek {
+                "On"
+            } else {
+                "Off"
+            }
+        ),
+        SettingsItem::PreviewMode => format!(
+            "Track preview: {}",
+            match preferences.preview_mode {
+                PreviewMode::Sustain => "Full notes",
+                PreviewMode::Onset => "Onsets only",
+            }
+        ),
+        SettingsItem::MiniRollPreview => format!(
+            "Track preview style: {}",
+            if preferences.mini_roll_preview {
+                "Mini piano roll"
+            } else {
+                "Density"
+            }
+        ),
+        SettingsItem::NotePairing => format!(
+            "Overlapping same-pitch notes: {}",
+            match preferences.note_pairing {
+                NotePairing::Fifo => "First on, first off",
+                NotePairing::Lifo => "Last on, first off",
+            }
+        ),
+        SettingsItem::ClickAudition => format!(
+            "Click to audition notes: {}",
+            if preferences.click_audition {
+                "On"
+            } else {
+                "Off"
+            }
+        ),
+    }
+}
+
+pub(super) fn spawn_settings_page(commands: &mut Commands, parent: Entity, font: Handle<Font>) {
+    let _ = commands.entity(parent).with_children(|parent| {
+        let _ = parent
+            .spawn((
+                Node {
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    flex_direction: FlexDirection::Column,
+                    align_items: AlignItems::Center,
+                    justify_content: JustifyContent::Center,
+                    display: Display::None,
+                    ..default()
+                },
+                PageRoot::Settings,
+            ))
+            .with_children(|parent| {
+                let _ = parent
+                    .spawn((
+                        Node {
+                            flex_direction: FlexDirection::Column,
+                            padding: UiRect::all(Val::Px(20.0)),
+                            border: UiRect::all(Val::Px(2.0)),
+                            row_gap: Val::Px(8.0),
+                            ..default()
+                        },
+                        BackgroundColor(Color::srgb(0.0, 0.0, 0.7)),
+                        BorderColor::all(Color::WHITE),
+                    ))
+                    .with_children(|parent| {
+                        let _ = parent.spawn((
+                            Text::new("Settings"),
+                            TextFont {
+                                font: font.clone(),
+                                font_size: 40.0,
+                                ..default()
+                            },
+                            TextColor(Color::WHITE),
+                        ));
+                        for item in SettingsItem::ALL {
+                            let _ = parent.spawn((
+                                Text::new(""),
+                                TextFont {
+                                    font: font.clone(),
+                                    font_size: 28.0,
+                                    ..default()
+                                },
+                                TextColor(Color::WHITE),
+                                SettingsRow { item },
+                            ));
+                        }
+                        let _ = parent.spawn((
+                            Text::new("Up/Down to choose, Left/Right to change, Esc to leave."),
+                            TextFont {
+                                font: font.clone(),
+                                font_size: 22.0,
+                                ..default()
+                            },
+                            TextColor(Color::srgb(0.8, 0.8, 0.8)),
+                        ));
+                    });
+            });
+    });
+}
+
+pub(super) fn update_settings_page(
+    ui_state: Res<UiState>,
+    preferences: Res<Preferences>,
+    settings_focus: Res<SettingsFocus>,
+    mut rows: Query<(&SettingsRow, &mut Text, &mut TextColor)>,
+) {
+    if ui_state.page != UiPage::Settings {
+        return;
+    }
+
+    let selected = SettingsItem::ALL.get(settings_focus.index).copied();
+    for (row, mut text, mut color) in &mut rows {
+        text.0 = setting_label(row.item, &preferences);
+        color.0 = if selected == Some(row.item) {
+            Color::srgb(1.0, 1.0, 0.0)
+        } else {
+            Color::WHITE
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::setting_label;
+    use crate::state::{
+        ChannelPalette, Interpolation, LoopSeam, NotePairing, Preferences, PreviewMode,
+        SettingsItem, SongEnd,
+    };
+
+    #[test]
+    fn setting_label_shows_current_values() {
+        let mut preferences = Preferences::default();
+        assert_eq!(
+            setting_label(SettingsItem::TimeDisplay, &preferences),
+            "Time display: mm:ss"
+        );
+        preferences.interpolation = Interpolation::SeventhOrder;
+        assert_eq!(
+            setting_label(SettingsItem::Interpolation, &preferences),
+            "Interpolation: 7th order (most CPU)"
+        );
+        assert_eq!(
+            setting_label(SettingsItem::BeatPulse, &preferences),
+            "Beat pulse: Off"
+        );
+        assert_eq!(
+            setting_label(SettingsItem::ReverbTail, &preferences),
+            "Reverb tail: 1.5s"
+        );
+        assert_eq!(
+            setting_label(SettingsItem::ReverbLevel, &preferences),
+            "Reverb level: 90%"
+        );
+        assert_eq!(
+            setting_label(SettingsItem::ChorusLevel, &preferences),
+            "Chorus level: 2.0"
+        );
+        preferences.output_sample_rate = Some(48_000);
+        assert_eq!(
+            setting_label(SettingsItem::SampleRate, &preferences),
+            "Output rate: 48000 Hz"
+        );
+        assert_eq!(
+            setting_label(SettingsItem::Polyphony, &preferences),
+            "Polyphony: 256 voices"
+        );
+        preferences.menu_animation = false;
+        assert_eq!(
+            setting_label(SettingsItem::MenuAnimation, &preferences),
+            "Menu animation: Off"
+        );
+        assert_eq!(
+            setting_label(SettingsItem::MenuWrap, &preferences),
+            "Menu wrap-around: Off"
+        );
+        preferences.scrub_on_seek = true;
+        assert_eq!(
+            setting_label(SettingsItem::ScrubOnSeek, &preferences),
+            "Scrub preview while seeking: On"
+        );
+        assert_eq!(
+            setting_label(SettingsItem::HoldToPreview, &preferences),
+            "Hold H to preview the focused track: Off"
+        );
+        assert_eq!(
+            setting_label(SettingsItem::SplitDivider, &preferences),
+            "Split view tracks width: 40%"
+        );
+        assert_eq!(
+            setting_label(SettingsItem::Autoplay, &preferences),
+            "Play files as soon as they load: Off"
+        );
+        assert_eq!(
+            setting_label(SettingsItem::ChannelPalette, &preferences),
+            "Note colors: Single"
+        );
+        preferences.channel_palette = Some(ChannelPalette::ColorBlindSafe);
+        assert_eq!(
+            setting_label(SettingsItem::HideEmptyTracks, &preferences),
+            "Empty tracks in the list: Shown"
+        );
+        assert_eq!(
+            setting_label(SettingsItem::SongEnd, &preferences),
+            "Song ends at: Last note"
+        );
+        assert_eq!(
+            setting_label(SettingsItem::QuickLoopBars, &preferences),
+            "Shift+L loops the last: 2 bars"
+        );
+        preferences.quick_loop_bars = 1;
+        assert_eq!(
+            setting_label(SettingsItem::QuickLoopBars, &preferences),
+            "Shift+L loops the last: 1 bar"
+        );
+        preferences.song_end = SongEnd::EndOfTrack;
+        assert_eq!(
+            setting_label(SettingsItem::SongEnd, &preferences),
+            "Song ends at: End of Track marker"
+        );
+        assert_eq!(
+            setting_label(SettingsItem::LoopSong, &preferences),
+            "Loop the whole song: Off"
+        );
+        preferences.practice_click = true;
+        assert_eq!(
+            setting_label(SettingsItem::PracticeClick, &preferences),
+            "Metronome, also while stopped: On"
+        );
+        assert_eq!(
+            setting_label(SettingsItem::ChannelPalette, &preferences),
+            "Note colors: By channel (color-blind safe)"
+        );
+        assert_eq!(
+            setting_label(SettingsItem::FocusProminentTrack, &preferences),
+            "Focus busiest track on load: Off"
+        );
+        preferences.loop_seam = LoopSeam::Release;
+        assert_eq!(
+            setting_label(SettingsItem::LoopSeam, &preferences),
+            "Loop seam: Let notes ring"
+        );
+        preferences.default_zoom_x = 4.0;
+        assert_eq!(
+            setting_label(SettingsItem::DefaultZoomX, &preferences),
+            "Default time zoom: 4x"
+        );
+        assert_eq!(
+            setting_label(SettingsItem::DefaultZoomY, &preferences),
+            "Default pitch zoom: 1x"
+        );
+        assert_eq!(
+            setting_label(SettingsItem::ClickAudition, &preferences),
+            "Click to audition notes: On"
+        );
+        assert_eq!(
+            setting_label(SettingsItem::IdleTimeout, &preferences),
+            "Idle power saving: After 10s"
+        );
+        preferences.idle_timeout_seconds = None;
+        assert_eq!(
+            setting_label(SettingsItem::IdleTimeout, &preferences),
+            "Idle power saving: Off"
+        );
+        assert_eq!(
+            setting_label(SettingsItem::NormalizeLoudness, &preferences),
+            "Loudness normalization: On"
+        );
+        assert_eq!(
+            setting_label(SettingsItem::NotePairing, &preferences),
+            "Overlapping same-pitch notes: First on, first off"
+        );
+        preferences.note_pairing = NotePairing::Lifo;
+        assert_eq!(
+            setting_label(SettingsItem::NotePairing, &preferences),
+            "Overlapping same-pitch notes: Last on, first off"
+        );
+        assert_eq!(
+            setting_label(SettingsItem::PreviewMode, &preferences),
+            "Track preview: Full notes"
+        );
+        preferences.preview_mode = PreviewMode::Onset;
+        assert_eq!(
+            setting_label(SettingsItem::PreviewMode, &preferences),
+            "Track preview: Onsets only"
+        );
+        preferences.mini_roll_preview = true;
+        assert_eq!(
+            setting_label(SettingsItem::MiniRollPreview, &preferences),
+            "Track preview style: Mini piano roll"
+        );
+    }
+}
@@ -1,30 +1,98 @@
-use super::SplashPageRoot;
+use super::tracks::{file_copyright, time_label};
+use super::{PageRoot, PulseBackground, UiFonts};
 use crate::state::{
-    MidiFilePath, PlaybackState, PlaybackStatus, SoundFontPath, UiPage, UiSelection, UiState,
+    FileSummary, MidiFilePath, MidiTracks, PlaybackState, PlaybackStatus, Preferences, RecentFile,
+    RecentFiles, RecentKind, SoundFontLayers, SoundFontPath, UiPage, UiSelection, UiState,
 };
 use bevy::prelude::{
-    default, AlignItems, BackgroundColor, BorderColor, Color, Commands, Component, Display, Entity,
-    FlexDirection, Font, Handle, JustifyContent, Node, Query, Res, Text, TextColor, TextFont,
-    UiRect, Val, With, Without,
+    default, AlignItems, BackgroundColor, BorderColor, Color, Commands, Component, DetectChanges,
+    Display, Entity, FlexDirection, Font, Handle, JustifyContent, Local, Node, Overflow, Query,
+    Res, Text, TextColor, TextFont, Time, UiRect, Val, With,
 };
+use std::path::{Path, PathBuf};
 
+/// A menu row or button, named by the selection that highlights it.
 #[derive(Component)]
-pub(super) struct MidiFileText;
+pub(super) struct MenuEntry(UiSelection);
 
 #[derive(Component)]
-pub(super) struct SoundFontText;
+pub(super) struct CopyrightText;
 
 #[derive(Component)]
-pub(super) struct PlayButton;
+pub(super) struct PlaybackStatusText;
 
 #[derive(Component)]
-pub(super) struct StopButton;
+pub(super) struct SplashBorder;
 
 #[derive(Component)]
-pub(super) struct RewindButton;
+pub(super) struct RecentFilesSection;
 
 #[derive(Component)]
-pub(super) struct PlaybackStatusText;
+pub(super) struct RecentFilesList;
+
+#[derive(Component)]
+pub(super) struct RecentFileEntry(usize);
+
+const MENU_ANIMATION_CYCLE_SECS: f32 = 12.0;
+const MENU_ANIMATION_SATURATION: f32 = 0.6;
+const RECENT_ROW_HEIGHT: f32 = 30.0;
+const RECENT_VISIBLE_ROWS: usize = 4;
+
+fn recent_file_label(file: &RecentFile) -> String {
+    let kind = match file.kind {
+        RecentKind::Midi => "MIDI",
+        RecentKind::SoundFont => "SF2",
+    };
+    format!("{kind}: {}", file_name(&file.path))
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| path.display().to_string())
+}
+
+/// The base SoundFont, then each layer over it on a line of its own.
+fn soundfont_label(base: Option<&Path>, layers: &[PathBuf]) -> String {
+    let base = base.map_or("[None]".to_string(), file_name);
+    if layers.is_empty() {
+        return format!("SoundFont: {base}");
+    }
+    let mut label = format!("SoundFonts: {base}");
+    for layer in layers {
+        label.push_str(&format!("\n  + {}", file_name(layer)));
+    }
+    label
+}
+
+fn playback_status_label(state: PlaybackState, loop_song: bool) -> String {
+    if loop_song {
+        format!("Status: {state:?} (looping the song)")
+    } else {
+        format!("Status: {state:?}")
+    }
+}
+
+fn menu_border_color(elapsed_secs: f32) -> Color {
+    let hue = (elapsed_secs / MENU_ANIMATION_CYCLE_SECS).fract() * 360.0;
+    Color::hsl(hue, MENU_ANIMATION_SATURATION, 0.8)
+}
+
+// Scrolls the least amount needed to keep the selected row in view.
+pub(super) fn first_visible_row(
+    selected: usize,
+    first_visible: usize,
+    visible_rows: usize,
+) -> usize {
+    let visible_rows = visible_rows.max(1);
+    if selected < first_visible {
+        selected
+    } else if selected >= first_visible + visible_rows {
+        selected + 1 - visible_rows
+    } else {
+        first_visible
+    }
+}
 
 pub(super) fn spawn_splash_page(commands: &mut Commands, parent: Entity, font: Handle<Font>) {
     let _ = commands.entity(parent).with_children(|parent| {
@@ -39,7 +107,7 @@ pub(super) fn spawn_splash_page(commands: &mut Commands, parent: Entity, font: H
                     display: Display::Flex,
                     ..default()
                 },
-                SplashPageRoot,
+                PageRoot::Splash,
             ))
             .with_children(|parent| {
                 let _ = parent
@@ -52,6 +120,10 @@ pub(super) fn spawn_splash_page(commands: &mut Commands, parent: Entity, font: H
                         },
                         BackgroundColor(Color::srgb(0.0, 0.0, 0.7)),
                         BorderColor::all(Color::WHITE),
+                        PulseBackground {
+                            base: Color::srgb(0.0, 0.0, 0.7),
+                        },
+                        SplashBorder,
                     ))
                     .with_children(|parent| {
                         let _ = parent.spawn((
@@ -78,7 +150,7 @@ pub(super) fn spawn_splash_page(commands: &mut Commands, parent: Entity, font: H
                                 ..default()
                             },
                             TextColor(Color::WHITE),
-                            MidiFileText,
+                            MenuEntry(UiSelection::MidiFile),
                         ));
                         let _ = parent.spawn((
                             Text::new("SoundFont: [None]"),
@@ -88,7 +160,21 @@ pub(super) fn spawn_splash_page(commands: &mut Commands, parent: Entity, font: H
                                 ..default()
                             },
                             TextColor(Color::WHITE),
-                            SoundFontText,
+                            MenuEntry(UiSelection::SoundFont),
+                        ));
+                        let _ = parent.spawn((
+                            Text::new(""),
+                            TextFont {
+                                font: font.clone(),
+                                font_size: 22.0,
+                                ..default()
+                            },
+                            TextColor(Color::srgb(0.8, 0.8, 0.8)),
+                            Node {
+                                display: Display::None,
+                                ..default()
+                            },
+                            CopyrightText,
                         ));
 
                         let _ = parent.spawn((Node {
@@ -111,7 +197,7 @@ pub(super) fn spawn_splash_page(commands: &mut Commands, parent: Entity, font: H
                                         ..default()
                                     },
                                     TextColor(Color::WHITE),
-                                    PlayButton,
+                                    MenuEntry(UiSelection::Play),
                                 ));
                                 let _ = parent.spawn((
                                     Text::new("[ Stop ]"),
@@ -121,7 +207,7 @@ pub(super) fn spawn_splash_page(commands: &mut Commands, parent: Entity, font: H
                                         ..default()
                                     },
                                     TextColor(Color::WHITE),
-                                    StopButton,
+                                    MenuEntry(UiSelection::Stop),
                                 ));
                                 let _ = parent.spawn((
                                     Text::new("[ Rewind ]"),
@@ -131,85 +217,172 @@ pub(super) fn spawn_splash_page(commands: &mut Commands, parent: Entity, font: H
                                         ..default()
                                     },
                                     TextColor(Color::WHITE),
-                                    RewindButton,
+                                    MenuEntry(UiSelection::Rewind),
                                 ));
                             });
+
+                        let _ = parent
+                            .spawn((
+                                Node {
+                                    flex_direction: FlexDirection::Column,
+                                    margin: UiRect::top(Val::Px(20.0)),
+                                    display: Display::None,
+                                    ..default()
+                                },
+                                RecentFilesSection,
+                            ))
+                            .with_children(|parent| {
+                                let _ = parent.spawn((
+                                    Text::new("Recent:"),
+                                    TextFont {
+                                        font: font.clone(),
+                                        font_size: 24.0,
+                                        ..default()
+                                    },
+                                    TextColor(Color::srgb(0.8, 0.8, 0.8)),
+                                ));
+                                let _ = parent
+                                    .spawn((Node {
+                                        height: Val::Px(
+                                            RECENT_ROW_HEIGHT * RECENT_VISIBLE_ROWS as f32,
+                                        ),
+                                        overflow: Overflow::clip(),
+                                        ..default()
+                                    },))
+                                    .with_children(|parent| {
+                                        let _ = parent.spawn((
+                                            Node {
+                                                flex_direction: FlexDirection::Column,
+                                                ..default()
+                                            },
+                                            RecentFilesList,
+                                        ));
+                                    });
+                            });
                     });
             });
     });
 }
 
+pub(super) fn animate_splash_border(
+    ui_state: Res<UiState>,
+    preferences: Res<Preferences>,
+    time: Res<Time>,
+    mut borders: Query<&mut BorderColor, With<SplashBorder>>,
+) {
+    if ui_state.page != UiPage::Splash {
+        return;
+    }
+    let color = if preferences.menu_animation {
+        menu_border_color(time.elapsed_secs())
+    } else {
+        Color::WHITE
+    };
+    for mut border in &mut borders {
+        *border = BorderColor::all(color);
+    }
+}
+
+pub(super) fn update_recent_files_list(
+    mut commands: Commands,
+    recent: Res<RecentFiles>,
+    fonts: Res<UiFonts>,
+    list_query: Query<Entity, With<RecentFilesList>>,
+    entry_query: Query<Entity, With<RecentFileEntry>>,
+    mut section_query: Query<&mut Node, With<RecentFilesSection>>,
+) {
+    if !recent.is_changed() {
+        return;
+    }
+    for mut node in &mut section_query {
+        node.display = if recent.0.is_empty() {
+            Display::None
+        } else {
+            Display::Flex
+        };
+    }
+    let Some(list_entity) = list_query.iter().next() else {
+        return;
+    };
+    for entry in &entry_query {
+        commands.entity(entry).despawn();
+    }
+    let _ = commands.entity(list_entity).with_children(|parent| {
+        for (index, file) in recent.0.iter().enumerate() {
+            let _ = parent.spawn((
+                Text::new(recent_file_label(file)),
+                TextFont {
+                    font: fonts.main.clone(),
+                    font_size: 24.0,
+                    ..default()
+                },
+                TextColor(Color::WHITE),
+                Node {
+                    height: Val::Px(RECENT_ROW_HEIGHT),
+                    ..default()
+                },
+                RecentFileEntry(index),
+            ));
+        }
+    });
+}
+
+pub(super) fn update_recent_files_selection(
+    ui_state: Res<UiState>,
+    mut first_visible: Local<usize>,
+    mut entries: Query<(&RecentFileEntry, &mut TextColor)>,
+    mut list_query: Query<&mut Node, With<RecentFilesList>>,
+) {
+    if ui_state.page != UiPage::Splash {
+        return;
+    }
+    let selected = match ui_state.selection {
+        UiSelection::Recent(index) => Some(index),
+        UiSelection::MidiFile
+        | UiSelection::SoundFont
+        | UiSelection::Play
+        | UiSelection::Stop
+        | UiSelection::Rewind => None,
+    };
+    for (entry, mut color) in &mut entries {
+        color.0 = if selected == Some(entry.0) {
+            Color::srgb(1.0, 1.0, 0.0)
+        } else {
+            Color::WHITE
+        };
+    }
+    *first_visible = first_visible_row(selected.unwrap_or(0), *first_visible, RECENT_VISIBLE_ROWS);
+    for mut node in &mut list_query {
+        node.top = Val::Px(-(*first_visible as f32) * RECENT_ROW_HEIGHT);
+    }
+}
+
+pub(super) fn update_copyright_text(
+    midi_tracks: Res<MidiTracks>,
+    mut query: Query<(&mut Text, &mut Node), With<CopyrightText>>,
+) {
+    if !midi_tracks.is_changed() {
+        return;
+    }
+    let copyright = file_copyright(&midi_tracks.0);
+    for (mut text, mut node) in &mut query {
+        text.0 = copyright.unwrap_or_default().to_string();
+        node.display = if copyright.is_some() {
+            Display::Flex
+        } else {
+            Display::None
+        };
+    }
+}
+
 pub(super) fn update_selection_visuals(
     ui_state: Res<UiState>,
     midi_path: Res<MidiFilePath>,
     soundfont_path: Res<SoundFontPath>,
+    soundfont_layers: Res<SoundFontLayers>,
     playback_status: Res<PlaybackStatus>,
-    mut midi_query: Query<
-        (&mut TextColor, &mut Text),
-        (
-            With<MidiFileText>,
-            Without<SoundFontText>,
-            Without<PlayButton>,
-            Without<StopButton>,
-            Without<RewindButton>,
-            Without<PlaybackStatusText>,
-        ),
-    >,
-    mut soundfont_query: Query<
-        (&mut TextColor, &mut Text),
-        (
-            With<SoundFontText>,
-            Without<MidiFileText>,
-            Without<PlayButton>,
-            Without<StopButton>,
-            Without<RewindButton>,
-            Without<PlaybackStatusText>,
-        ),
-    >,
-    mut play_query: Query<
-        (&mut TextColor, &mut Text),
-        (
-            With<PlayButton>,
-            Without<MidiFileText>,
-            Without<SoundFontText>,
-            Without<StopButton>,
-            Without<RewindButton>,
-            Without<PlaybackStatusText>,
-        ),
-    >,
-    mut stop_query: Query<
-        &mut TextColor,
-        (
-            With<StopButton>,
-            Without<MidiFileText>,
-            Without<SoundFontText>,
-            Without<PlayButton>,
-            Without<RewindButton>,
-            Without<PlaybackStatusText>,
-        ),
-    >,
-    mut rewind_query: Query<
-        &mut TextColor,
-        (
-            With<RewindButton>,
-            Without<MidiFileText>,
-            Without<SoundFontText>,
-            Without<PlayButton>,
-            Without<StopButton>,
-            Without<PlaybackStatusText>,
-        ),
-    >,
-    mut status_query: Query<
-        &mut Text,
-        (
-            With<PlaybackStatusText>,
-            Without<MidiFileText>,
-            Without<SoundFontText>,
-            Without<PlayButton>,
-            Without<StopButton>,
-            Without<RewindButton>,
-        ),
-    >,
+    file_summary: Res<FileSummary>,
+    mut entries: Query<(&MenuEntry, &mut TextColor, &mut Text)>,
 ) {
     if ui_state.page != UiPage::Splash {
         return;
@@ -218,53 +391,119 @@ pub(super) fn update_selection_visuals(
     let selected_color = Color::srgb(1.0, 1.0, 0.0);
     let default_color = Color::WHITE;
 
-    for (mut color, mut text) in &mut midi_query {
-        color.0 = if ui_state.selection == UiSelection::MidiFile {
+    for (entry, mut color, mut text) in &mut entries {
+        color.0 = if ui_state.selection == entry.0 {
             selected_color
         } else {
             default_color
         };
-        if let Some(path) = &midi_path.0 {
-            text.0 = format!("MIDI File: {}", path.file_name().unwrap().to_string_lossy());
+        match entry.0 {
+            UiSelection::MidiFile => {
+                if let Some(path) = &midi_path.0 {
+                    let name = path.file_name().unwrap().to_string_lossy();
+                    text.0 = if file_summary.duration_seconds > 0.0 {
+                        format!(
+                            "MIDI File: {} ({})",
+                            name,
+                            time_label(file_summary.duration_seconds)
+                        )
+                    } else {
+                        format!("MIDI File: {}", name)
+                    };
+                }
+            }
+            UiSelection::SoundFont => {
+                let label = soundfont_label(soundfont_path.0.as_deref(), &soundfont_layers.0);
+                if text.0 != label {
+                    text.0 = label;
+                }
+            }
+            UiSelection::Play => {
+                text.0 = if playback_status.state == PlaybackState::Playing {
+                    "[ Pause ]".to_string()
+                } else {
+                    "[ Play ]".to_string()
+                };
+            }
+            UiSelection::Stop | UiSelection::Rewind | UiSelection::Recent(_) => {}
         }
     }
-    for (mut color, mut text) in &mut soundfont_query {
-        color.0 = if ui_state.selection == UiSelection::SoundFont {
-            selected_color
-        } else {
-            default_color
-        };
-        if let Some(path) = &soundfont_path.0 {
-            text.0 = format!("SoundFont: {}", path.file_name().unwrap().to_string_lossy());
-        }
+}
+
+pub(super) fn update_playback_status_text(
+    ui_state: Res<UiState>,
+    playback_status: Res<PlaybackStatus>,
+    preferences: Res<Preferences>,
+    mut texts: Query<&mut Text, With<PlaybackStatusText>>,
+) {
+    if ui_state.page != UiPage::Splash {
+        return;
     }
-    for (mut color, mut text) in &mut play_query {
-        color.0 = if ui_state.selection == UiSelection::Play {
-            selected_color
-        } else {
-            default_color
-        };
-        text.0 = if playback_status.state == PlaybackState::Playing {
-            "[ Pause ]".to_string()
-        } else {
-            "[ Play ]".to_string()
-        };
+    for mut text in &mut texts {
+        text.0 = playback_status_label(playback_status.state, preferences.loop_song);
     }
-    for mut color in &mut stop_query {
-        color.0 = if ui_state.selection == UiSelection::Stop {
-            selected_color
-        } else {
-            default_color
-        };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        first_visible_row, menu_border_color, playback_status_label, recent_file_label,
+        soundfont_label,
+    };
+    use crate::state::{PlaybackState, RecentFile, RecentKind};
+    use bevy::prelude::Hsla;
+    use std::path::PathBuf;
+
+    #[test]
+    fn menu_border_color_cycles_hue() {
+        let hue = |secs: f32| Hsla::from(menu_border_color(secs)).hue;
+        assert!(hue(0.0).abs() < 0.5);
+        assert!((hue(3.0) - 90.0).abs() < 0.5);
+        assert!((hue(12.0) - hue(0.0)).abs() < 0.5);
     }
-    for mut color in &mut rewind_query {
-        color.0 = if ui_state.selection == UiSelection::Rewind {
-            selected_color
-        } else {
-            default_color
+
+    #[test]
+    fn playback_status_label_mentions_the_song_loop() {
+        assert_eq!(
+            playback_status_label(PlaybackState::Playing, false),
+            "Status: Playing"
+        );
+        assert_eq!(
+            playback_status_label(PlaybackState::Stopped, true),
+            "Status: Stopped (looping the song)"
+        );
+    }
+
+    #[test]
+    fn recent_file_label_shows_kind_and_name() {
+        let file = RecentFile {
+            kind: RecentKind::SoundFont,
+            path: PathBuf::from("fonts/piano.sf2"),
         };
+        assert_eq!(recent_file_label(&file), "SF2: piano.sf2");
     }
-    for mut text in &mut status_query {
-        text.0 = format!("Status: {:?}", playback_status.state);
+
+    #[test]
+    fn soundfont_label_lists_the_layers() {
+        let base = PathBuf::from("fonts/piano.sf2");
+        assert_eq!(soundfont_label(None, &[]), "SoundFont: [None]");
+        assert_eq!(soundfont_label(Some(&base), &[]), "SoundFont: piano.sf2");
+        let layers = [
+            PathBuf::from("strings.sf2"),
+            PathBuf::from("kits/drums.sf2"),
+        ];
+        assert_eq!(
+            soundfont_label(Some(&base), &layers),
+            "SoundFonts: piano.sf2\n  + strings.sf2\n  + drums.sf2"
+        );
+    }
+
+    #[test]
+    fn first_visible_row_follows_selection() {
+        assert_eq!(first_visible_row(0, 0, 4), 0);
+        assert_eq!(first_visible_row(3, 0, 4), 0);
+        assert_eq!(first_visible_row(4, 0, 4), 1);
+        assert_eq!(first_visible_row(7, 1, 4), 4);
+        assert_eq!(first_visible_row(2, 4, 4), 2);
     }
 }
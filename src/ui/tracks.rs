@@ -1,18 +1,31 @@
-use super::{TracksPageRoot, UiFonts};
-use crate::audio::AudioState;
-use crate::state::{MidiTrackInfo, MidiTracks, TrackDetailsPopup, TracksFocus, UiPage, UiState};
+use super::palette::channel_color;
+use super::piano::{
+    build_piano_roll_data, drum_name, is_percussion_track, PianoRollStyle, RollOverlays,
+};
+use super::{
+    has_render_area, primary_window_size, release_image, replace_image, ClickedNodes, PageRoot,
+    PulseBackground, UiFonts, NO_MIDI_HINT,
+};
+use crate::audio::{loop_tick_range, AudioCommand, AudioSender, AudioState};
+use crate::state::{
+    listed_track_indices, ArticulationCounts, ChannelPalette, LoopRegion, MidiStandard,
+    MidiTrackInfo, MidiTracks, PianoRollViewState, Preferences, PreviewMode, RhythmSummary,
+    TempoOverride, TimeDisplay, TrackDetailsPopup, TracksFocus, UiState,
+};
+use crate::tempo::{file_tempo_map, TempoMap};
 use bevy::asset::RenderAssetUsages;
+use bevy::ecs::system::SystemParam;
 use bevy::image::ImageSampler;
 use bevy::prelude::Window;
 use bevy::prelude::{
     default, AlignItems, Assets, BackgroundColor, BorderColor, ButtonInput, Changed, Children,
     Color, ColorToPacked, Commands, Component, ComputedNode, DetectChanges, Display, Entity,
-    FlexDirection, Font, Handle, Image, ImageNode, JustifyContent, KeyCode, Node, NodeImageMode,
-    Overflow, PositionType, Query, Res, ResMut, Resource, Text, TextColor, TextFont, UiRect, Val,
-    With, ZIndex,
+    FlexDirection, Font, Handle, Image, ImageNode, Interaction, JustifyContent, KeyCode, Local,
+    Node, NodeImageMode, Overflow, PositionType, Query, Ref, Res, ResMut, Resource, Text,
+    TextColor, TextFont, Time, UiRect, Val, Vec2, With, Without, ZIndex,
 };
 use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
-use bevy::ui::UiGlobalTransform;
+use bevy::ui::{FocusPolicy, RelativeCursorPosition, UiGlobalTransform};
 use bevy::window::PrimaryWindow;
 
 #[derive(Component)]
@@ -21,16 +34,52 @@ pub(super) struct TracksList;
 #[derive(Component)]
 pub(super) struct TracksListViewport;
 
+/// Says how many tracks the list is leaving out.
+#[derive(Component)]
+pub(super) struct HiddenTracksNote;
+
+/// `index` points into `MidiTracks`, whatever the row's position on screen.
 #[derive(Component)]
 pub(super) struct TrackRow {
     index: usize,
 }
 
+const DOUBLE_CLICK_SECS: f64 = 0.4;
+
+#[derive(Component)]
+pub(super) struct TrackNameColumn;
+
+#[derive(Component)]
+pub(super) struct EventColumn;
+
+#[derive(Component)]
+pub(super) struct TrackLabel {
+    full: String,
+}
+
+#[derive(Component)]
+pub(super) struct ChannelActivityCell {
+    channel: usize,
+}
+
+#[derive(Component)]
+pub(super) struct ChannelPanMarker {
+    channel: usize,
+}
+
 #[derive(Component)]
 pub(super) struct TrackRuler {
     image_entity: Entity,
 }
 
+#[derive(Component)]
+pub(super) struct TrackLoopHighlight;
+
+/// The preview image; clicking it seeks. It passes the click on so the row
+/// under it still takes focus.
+#[derive(Component)]
+pub(super) struct TrackPreviewImage;
+
 #[derive(Component)]
 pub(super) struct DebugOverlayText;
 
@@ -61,6 +110,14 @@ enum TrackDetailsFieldKind {
     TempoChanges,
     TimeSignature,
     KeySignature,
+    Articulation,
+    NoteLengths,
+    Rests,
+    MidiStandard,
+    Instrument,
+    MidiPort,
+    CuePoints,
+    Copyright,
 }
 
 #[derive(Component)]
@@ -68,6 +125,9 @@ pub(super) struct TrackPreview {
     track_index: usize,
     image: Handle<Image>,
     last_size: (u32, u32),
+    last_mode: PreviewMode,
+    last_mini_roll: bool,
+    last_palette: Option<ChannelPalette>,
 }
 
 #[derive(Resource, Default)]
@@ -75,6 +135,22 @@ pub(super) struct DebugOverlayState {
     visible: bool,
 }
 
+/// Column widths in UI pixels, resized to the list viewport.
+#[derive(Resource, Debug, Clone, Copy, PartialEq)]
+pub(super) struct TracksLayout {
+    track_col: f32,
+    event_col: f32,
+}
+
+impl Default for TracksLayout {
+    fn default() -> Self {
+        Self {
+            track_col: TRACK_COL_WIDTH,
+            event_col: EVENT_COL_WIDTH,
+        }
+    }
+}
+
 #[derive(Resource, Default)]
 pub(super) struct TracksScroll {
     offset: f32,
@@ -82,8 +158,36 @@ pub(super) struct TracksScroll {
 
 const TRACK_COL_WIDTH: f32 = 220.0;
 const EVENT_COL_WIDTH: f32 = 80.0;
+const TRACK_COL_FRACTION: f32 = 0.2;
+const TRACK_COL_MIN: f32 = 140.0;
+const TRACK_COL_MAX: f32 = 360.0;
+const EVENT_COL_FRACTION: f32 = 0.07;
+const EVENT_COL_MIN: f32 = 64.0;
+const EVENT_COL_MAX: f32 = 110.0;
 const PREVIEW_CELL_SIZE: f32 = 2.0;
+const MINI_ROLL_WIDTH: u32 = 320;
+const MINI_ROLL_HEIGHT: u32 = 48;
 const TRACK_LABEL_FONT_SIZE: f32 = 24.0;
+const CHANNEL_CELL_SIZE: f32 = 22.0;
+pub(super) const CHANNEL_ACTIVITY_WINDOW_SECS: f64 = 0.1;
+pub(super) const CHANNEL_IDLE_COLOR: Color = Color::srgb(0.1, 0.1, 0.3);
+pub(super) const CHANNEL_ACTIVE_COLOR: Color = Color::srgb(0.2, 1.0, 0.4);
+const CHANNEL_PAN_MARKER_SIZE: f32 = 3.0;
+const LOOP_MARKER_WIDTH: f32 = 2.0;
+const LOOP_MARKER_COLOR: Color = Color::srgb(1.0, 0.8, 0.2);
+const CENTER_PAN: u8 = 64;
+const DENSITY_BAR_HEIGHT: f32 = 4.0;
+const DENSITY_BAR_TRACK_COLOR: Color = Color::srgb(0.15, 0.15, 0.2);
+const DENSITY_BAR_FILL_COLOR: Color = Color::srgb(0.3, 0.7, 1.0);
+
+// The name column scales with the viewport within readable bounds; the
+// preview takes whatever is left via flex_grow.
+fn compute_column_widths(viewport_width: f32) -> TracksLayout {
+    TracksLayout {
+        track_col: (viewport_width * TRACK_COL_FRACTION).clamp(TRACK_COL_MIN, TRACK_COL_MAX),
+        event_col: (viewport_width * EVENT_COL_FRACTION).clamp(EVENT_COL_MIN, EVENT_COL_MAX),
+    }
+}
 
 fn max_label_chars(column_width: f32, font_size: f32) -> usize {
     let avg_char_width = font_size * 0.6;
@@ -93,6 +197,51 @@ fn max_label_chars(column_width: f32, font_size: f32) -> usize {
     (column_width / avg_char_width).floor().max(0.0) as usize
 }
 
+fn glyph_advances(face: &ttf_parser::Face<'_>, text: &str, font_size: f32) -> Vec<f32> {
+    let units_per_em = face.units_per_em().max(1) as f32;
+    let scale = font_size / units_per_em;
+    text.chars()
+        .map(|ch| {
+            face.glyph_index(ch)
+                .and_then(|glyph| face.glyph_hor_advance(glyph))
+                .map(|advance| advance as f32 * scale)
+                .unwrap_or(font_size * 0.6)
+        })
+        .collect()
+}
+
+fn fit_label_chars(advances: &[f32], dot_advance: f32, column_width: f32) -> usize {
+    let total: f32 = advances.iter().sum();
+    if total <= column_width {
+        return advances.len();
+    }
+    let ellipsis_width = dot_advance * 3.0;
+    if ellipsis_width > column_width {
+        return (column_width / dot_advance.max(f32::EPSILON)).floor() as usize;
+    }
+    let mut width = ellipsis_width;
+    let mut fitted = 0;
+    for advance in advances {
+        if width + advance > column_width {
+            break;
+        }
+        width += advance;
+        fitted += 1;
+    }
+    fitted + 3
+}
+
+fn measured_label_chars(
+    face: &ttf_parser::Face<'_>,
+    text: &str,
+    font_size: f32,
+    column_width: f32,
+) -> usize {
+    let advances = glyph_advances(face, text, font_size);
+    let dot_advance = glyph_advances(face, ".", font_size)[0];
+    fit_label_chars(&advances, dot_advance, column_width)
+}
+
 fn ellipsize_text(text: &str, max_chars: usize) -> String {
     if max_chars == 0 {
         return String::new();
@@ -118,6 +267,16 @@ fn pitch_range_label(min_pitch: u8, max_pitch: u8) -> String {
     format!("{} - {}", min_pitch, max_pitch)
 }
 
+fn drum_range_label(min_pitch: u8, max_pitch: u8) -> String {
+    format!(
+        "{} ({}) - {} ({})",
+        min_pitch,
+        drum_name(min_pitch),
+        max_pitch,
+        drum_name(max_pitch)
+    )
+}
+
 fn channel_list_label(channels: &[u8]) -> String {
     if channels.is_empty() {
         return "-".to_string();
@@ -144,7 +303,153 @@ fn time_signature_label(signature: Option<(u8, u8)>) -> String {
     }
 }
 
-fn program_label(program: u8) -> String {
+pub(super) fn time_label(seconds: f64) -> String {
+    let total = seconds.max(0.0).round() as u64;
+    format!("{:02}:{:02}", total / 60, total % 60)
+}
+
+/// `tick` as time or raw ticks, following the F2 toggle.
+pub(crate) fn position_label(tick: u64, tempo_map: &TempoMap, display: TimeDisplay) -> String {
+    match display {
+        TimeDisplay::Time => time_label(tempo_map.seconds_at(tick)),
+        TimeDisplay::Ticks => tick.to_string(),
+    }
+}
+
+// Tempo is file-wide, so every track lists the file's changes.
+fn tempo_changes_label(
+    tempo_events: &[(u64, u32)],
+    tempo_map: &TempoMap,
+    display: TimeDisplay,
+) -> String {
+    if tempo_events.is_empty() {
+        return "0".to_string();
+    }
+    let list = tempo_events
+        .iter()
+        .map(|(tick, _)| position_label(*tick, tempo_map, display))
+        .collect::<Vec<_>>()
+        .join(", ");
+    format!("{} ({})", tempo_events.len(), list)
+}
+
+fn articulation_label(counts: ArticulationCounts) -> String {
+    let total = counts.staccato + counts.normal + counts.legato;
+    if total == 0 {
+        return "-".to_string();
+    }
+    let percent = |count: usize| (count as f64 * 100.0 / total as f64).round() as u32;
+    format!(
+        "{}% legato, {}% normal, {}% staccato",
+        percent(counts.legato),
+        percent(counts.normal),
+        percent(counts.staccato)
+    )
+}
+
+const NOTE_LENGTH_NAMES: [&str; 6] = ["1/1", "1/2", "1/4", "1/8", "1/16", "1/32"];
+const NOTE_LENGTH_BAR_WIDTH: usize = 12;
+
+// One line per length that occurs, with a bar scaled to the most common one.
+fn note_lengths_label(rhythm: &RhythmSummary) -> String {
+    let buckets = NOTE_LENGTH_NAMES
+        .iter()
+        .zip(rhythm.lengths)
+        .chain(std::iter::once((&"other", rhythm.irregular)))
+        .filter(|(_, count)| *count > 0)
+        .collect::<Vec<_>>();
+    let Some(max) = buckets.iter().map(|(_, count)| *count).max() else {
+        return "Note lengths: -".to_string();
+    };
+    let mut lines = vec!["Note lengths:".to_string()];
+    for (name, count) in buckets {
+        let bar = "#".repeat((count * NOTE_LENGTH_BAR_WIDTH).div_ceil(max));
+        lines.push(format!("  {name:<5} {bar} {count}"));
+    }
+    lines.join("\n")
+}
+
+/// A reset usually sits in the first track, but any track may carry it.
+fn file_midi_standard(tracks: &[MidiTrackInfo]) -> Option<MidiStandard> {
+    tracks.iter().find_map(|track| track.midi_standard)
+}
+
+pub(super) fn file_copyright(tracks: &[MidiTrackInfo]) -> Option<&str> {
+    tracks.iter().find_map(|track| track.copyright.as_deref())
+}
+
+fn instrument_label(name: Option<&str>) -> String {
+    format!("Instrument: {}", name.unwrap_or("-"))
+}
+
+// Ports and prefixes are shown 1-based, like channels.
+fn port_label(port: Option<u8>, channel_prefix: Option<u8>) -> String {
+    let port = port.map_or("-".to_string(), |port| (port + 1).to_string());
+    match channel_prefix {
+        Some(channel) => format!("Port: {port} (channel prefix {})", channel + 1),
+        None => format!("Port: {port}"),
+    }
+}
+
+const CUE_LABEL_LIMIT: usize = 3;
+
+fn cue_points_label(cues: &[(u64, String)]) -> String {
+    if cues.is_empty() {
+        return "Cues: None".to_string();
+    }
+    let names = cues
+        .iter()
+        .take(CUE_LABEL_LIMIT)
+        .map(|(_, text)| text.as_str())
+        .collect::<Vec<_>>()
+        .join(", ");
+    match cues.len().saturating_sub(CUE_LABEL_LIMIT) {
+        0 => format!("Cues: {names}"),
+        more => format!("Cues: {names} +{more} more"),
+    }
+}
+
+// Notes per tick for each `(note_count, end_tick)`, scaled so the busiest
+// track is 1.0.
+fn note_density_shares(tracks: &[(usize, u64)]) -> Vec<f32> {
+    let densities: Vec<f64> = tracks
+        .iter()
+        .map(|&(note_count, end_tick)| {
+            if end_tick == 0 {
+                0.0
+            } else {
+                note_count as f64 / end_tick as f64
+            }
+        })
+        .collect();
+    let max = densities.iter().copied().fold(0.0, f64::max);
+    densities
+        .into_iter()
+        .map(|density| {
+            if max > 0.0 {
+                (density / max) as f32
+            } else {
+                0.0
+            }
+        })
+        .collect()
+}
+
+fn midi_standard_label(standard: Option<MidiStandard>) -> String {
+    match standard {
+        Some(standard) => format!("Standard: {} mode", standard.label()),
+        None => "Standard: Not specified".to_string(),
+    }
+}
+
+fn rests_label(rest_ticks: u64, ticks_per_beat: u32) -> String {
+    format!(
+        "Rests: {:.1} beats",
+        rest_ticks as f64 / ticks_per_beat.max(1) as f64
+    )
+}
+
+pub(super) fn program_label(program: u8) -> String {
     const GM_NAMES: [&str; 128] = [
         "Acoustic Grand Piano",
         "Bright Acoustic Piano",
@@ -316,7 +621,7 @@ pub(super) fn spawn_tracks_page(commands: &mut Commands, parent: Entity, font: H
                     display: Display::None,
                     ..default()
                 },
-                TracksPageRoot,
+                PageRoot::Tracks,
             ))
             .with_children(|parent| {
                 let _ = parent
@@ -533,6 +838,102 @@ pub(super) fn spawn_tracks_page(commands: &mut Commands, parent: Entity, font: H
                                 field: TrackDetailsFieldKind::KeySignature,
                             },
                         ));
+                        let _ = parent.spawn((
+                            Text::new("Articulation:"),
+                            TextFont {
+                                font: font.clone(),
+                                font_size: 22.0,
+                                ..default()
+                            },
+                            TextColor(Color::WHITE),
+                            TrackDetailsField {
+                                field: TrackDetailsFieldKind::Articulation,
+                            },
+                        ));
+                        let _ = parent.spawn((
+                            Text::new("Note lengths:"),
+                            TextFont {
+                                font: font.clone(),
+                                font_size: 22.0,
+                                ..default()
+                            },
+                            TextColor(Color::WHITE),
+                            TrackDetailsField {
+                                field: TrackDetailsFieldKind::NoteLengths,
+                            },
+                        ));
+                        let _ = parent.spawn((
+                            Text::new("Rests:"),
+                            TextFont {
+                                font: font.clone(),
+                                font_size: 22.0,
+                                ..default()
+                            },
+                            TextColor(Color::WHITE),
+                            TrackDetailsField {
+                                field: TrackDetailsFieldKind::Rests,
+                            },
+                        ));
+                        let _ = parent.spawn((
+                            Text::new("Standard:"),
+                            TextFont {
+                                font: font.clone(),
+                                font_size: 22.0,
+                                ..default()
+                            },
+                            TextColor(Color::WHITE),
+                            TrackDetailsField {
+                                field: TrackDetailsFieldKind::MidiStandard,
+                            },
+                        ));
+                        let _ = parent.spawn((
+                            Text::new("Instrument:"),
+                            TextFont {
+                                font: font.clone(),
+                                font_size: 22.0,
+                                ..default()
+                            },
+                            TextColor(Color::WHITE),
+                            TrackDetailsField {
+                                field: TrackDetailsFieldKind::Instrument,
+                            },
+                        ));
+                        let _ = parent.spawn((
+                            Text::new("Port:"),
+                            TextFont {
+                                font: font.clone(),
+                                font_size: 22.0,
+                                ..default()
+                            },
+                            TextColor(Color::WHITE),
+                            TrackDetailsField {
+                                field: TrackDetailsFieldKind::MidiPort,
+                            },
+                        ));
+                        let _ = parent.spawn((
+                            Text::new("Cues:"),
+                            TextFont {
+                                font: font.clone(),
+                                font_size: 22.0,
+                                ..default()
+                            },
+                            TextColor(Color::WHITE),
+                            TrackDetailsField {
+                                field: TrackDetailsFieldKind::CuePoints,
+                            },
+                        ));
+                        let _ = parent.spawn((
+                            Text::new("Copyright:"),
+                            TextFont {
+                                font: font.clone(),
+                                font_size: 22.0,
+                                ..default()
+                            },
+                            TextColor(Color::WHITE),
+                            TrackDetailsField {
+                                field: TrackDetailsFieldKind::Copyright,
+                            },
+                        ));
                         let _ = parent.spawn((
                             Text::new("Press Esc to close."),
                             TextFont {
@@ -557,6 +958,9 @@ pub(super) fn spawn_tracks_page(commands: &mut Commands, parent: Entity, font: H
                         },
                         BackgroundColor(Color::srgb(0.0, 0.0, 0.7)),
                         BorderColor::all(Color::WHITE),
+                        PulseBackground {
+                            base: Color::srgb(0.0, 0.0, 0.7),
+                        },
                     ))
                     .with_children(|parent| {
                         let _ = parent.spawn((
@@ -586,6 +990,65 @@ pub(super) fn spawn_tracks_page(commands: &mut Commands, parent: Entity, font: H
                             },
                             TextColor(Color::srgb(0.8, 0.8, 0.8)),
                         ));
+                        let _ = parent.spawn((
+                            Text::new(""),
+                            TextFont {
+                                font: font.clone(),
+                                font_size: 22.0,
+                                ..default()
+                            },
+                            TextColor(Color::srgb(0.8, 0.8, 0.8)),
+                            HiddenTracksNote,
+                        ));
+                        let _ = parent
+                            .spawn((Node {
+                                flex_direction: FlexDirection::Row,
+                                column_gap: Val::Px(4.0),
+                                ..default()
+                            },))
+                            .with_children(|parent| {
+                                for channel in 0..16 {
+                                    let _ = parent
+                                        .spawn((
+                                            Node {
+                                                width: Val::Px(CHANNEL_CELL_SIZE),
+                                                height: Val::Px(CHANNEL_CELL_SIZE),
+                                                border: UiRect::all(Val::Px(1.0)),
+                                                align_items: AlignItems::Center,
+                                                justify_content: JustifyContent::Center,
+                                                ..default()
+                                            },
+                                            BackgroundColor(CHANNEL_IDLE_COLOR),
+                                            BorderColor::all(Color::WHITE),
+                                            ChannelActivityCell { channel },
+                                        ))
+                                        .with_children(|parent| {
+                                            let _ = parent.spawn((
+                                                Text::new(format!("{}", channel + 1)),
+                                                TextFont {
+                                                    font: font.clone(),
+                                                    font_size: 14.0,
+                                                    ..default()
+                                                },
+                                                TextColor(Color::WHITE),
+                                            ));
+                                            let _ = parent.spawn((
+                                                Node {
+                                                    position_type: PositionType::Absolute,
+                                                    bottom: Val::Px(0.0),
+                                                    left: Val::Percent(pan_marker_percent(
+                                                        CENTER_PAN,
+                                                    )),
+                                                    width: Val::Px(CHANNEL_PAN_MARKER_SIZE),
+                                                    height: Val::Px(CHANNEL_PAN_MARKER_SIZE),
+                                                    ..default()
+                                                },
+                                                BackgroundColor(Color::srgb(1.0, 0.8, 0.2)),
+                                                ChannelPanMarker { channel },
+                                            ));
+                                        });
+                                }
+                            });
                         let _ = parent.spawn((Node {
                             height: Val::Px(10.0),
                             ..default()
@@ -598,10 +1061,13 @@ pub(super) fn spawn_tracks_page(commands: &mut Commands, parent: Entity, font: H
                             },))
                             .with_children(|parent| {
                                 let _ = parent
-                                    .spawn((Node {
-                                        width: Val::Px(TRACK_COL_WIDTH),
-                                        ..default()
-                                    },))
+                                    .spawn((
+                                        Node {
+                                            width: Val::Px(TRACK_COL_WIDTH),
+                                            ..default()
+                                        },
+                                        TrackNameColumn,
+                                    ))
                                     .with_children(|parent| {
                                         let _ = parent.spawn((
                                             Text::new("Track"),
@@ -614,10 +1080,13 @@ pub(super) fn spawn_tracks_page(commands: &mut Commands, parent: Entity, font: H
                                         ));
                                     });
                                 let _ = parent
-                                    .spawn((Node {
-                                        width: Val::Px(EVENT_COL_WIDTH),
-                                        ..default()
-                                    },))
+                                    .spawn((
+                                        Node {
+                                            width: Val::Px(EVENT_COL_WIDTH),
+                                            ..default()
+                                        },
+                                        EventColumn,
+                                    ))
                                     .with_children(|parent| {
                                         let _ = parent.spawn((
                                             Text::new("Events"),
@@ -674,18 +1143,46 @@ pub(super) fn spawn_tracks_page(commands: &mut Commands, parent: Entity, font: H
     });
 }
 
+/// The track list's rows and the hide-empty setting they were built with.
+#[derive(SystemParam)]
+pub(super) struct TrackListRows<'w, 's> {
+    list_query: Query<'w, 's, Entity, With<TracksList>>,
+    track_row_query: Query<'w, 's, Entity, With<TrackRow>>,
+    children_query: Query<'w, 's, &'static Children>,
+    previews: Query<'w, 's, &'static TrackPreview>,
+    hidden_notes: Query<'w, 's, &'static mut Text, With<HiddenTracksNote>>,
+    last_hide_empty: Local<'s, bool>,
+}
+
 pub(super) fn update_tracks_list(
     midi_tracks: Res<MidiTracks>,
     mut commands: Commands,
-    list_query: Query<Entity, With<TracksList>>,
-    track_row_query: Query<Entity, With<TrackRow>>,
-    children_query: Query<&Children>,
+    rows: TrackListRows,
     fonts: Res<UiFonts>,
+    layout: Res<TracksLayout>,
+    preferences: Res<Preferences>,
     mut images: ResMut<Assets<Image>>,
 ) {
-    if !midi_tracks.is_changed() && !track_row_query.is_empty() {
+    let TrackListRows {
+        list_query,
+        track_row_query,
+        children_query,
+        previews,
+        mut hidden_notes,
+        mut last_hide_empty,
+    } = rows;
+    let hide_changed = *last_hide_empty != preferences.hide_empty_tracks;
+    if !midi_tracks.is_changed() && !hide_changed && !track_row_query.is_empty() {
         return;
     }
+    *last_hide_empty = preferences.hide_empty_tracks;
+    let listed = listed_track_indices(&midi_tracks.0, preferences.hide_empty_tracks);
+    let hidden_label = hidden_tracks_label(midi_tracks.0.len() - listed.len());
+    for mut text in &mut hidden_notes {
+        if text.0 != hidden_label {
+            text.0.clone_from(&hidden_label);
+        }
+    }
 
     let font = fonts.main.clone();
 
@@ -701,6 +1198,9 @@ pub(super) fn update_tracks_list(
     for row in &track_row_query {
         collect_descendants(row, &children_query, &mut descendants);
         for entity in descendants.drain(..) {
+            if let Ok(preview) = previews.get(entity) {
+                release_image(&preview.image, &mut images);
+            }
             commands.entity(entity).despawn();
         }
         commands.entity(row).despawn();
@@ -718,26 +1218,51 @@ pub(super) fn update_tracks_list(
                     TrackRow { index: 0 },
                 ))
                 .with_children(|parent| {
-                    let _ = parent.spawn((
-                        Text::new("No tracks loaded."),
-                        TextFont {
-                            font: font.clone(),
-                            font_size: 24.0,
+                    let _ = parent
+                        .spawn(Node {
+                            flex_direction: FlexDirection::Column,
+                            row_gap: Val::Px(8.0),
                             ..default()
-                        },
-                        TextColor(Color::srgb(0.8, 0.8, 0.8)),
-                    ));
+                        })
+                        .with_children(|parent| {
+                            let _ = parent.spawn((
+                                Text::new("No MIDI file loaded yet."),
+                                TextFont {
+                                    font: font.clone(),
+                                    font_size: 28.0,
+                                    ..default()
+                                },
+                                TextColor(Color::WHITE),
+                            ));
+                            let _ = parent.spawn((
+                                Text::new(NO_MIDI_HINT),
+                                TextFont {
+                                    font: font.clone(),
+                                    font_size: 22.0,
+                                    ..default()
+                                },
+                                TextColor(Color::srgb(0.8, 0.8, 0.8)),
+                            ));
+                        });
                 });
         } else {
-            for (row_index, track) in midi_tracks.0.iter().enumerate() {
+            let densities = note_density_shares(
+                &midi_tracks
+                    .0
+                    .iter()
+                    .map(|track| (track.note_count, track.end_tick))
+                    .collect::<Vec<_>>(),
+            );
+            for &row_index in &listed {
+                let track = &midi_tracks.0[row_index];
                 let name = track
                     .name
                     .as_deref()
                     .filter(|value| !value.is_empty())
                     .unwrap_or("Unnamed");
                 let label = format!("[{:02}] {}", track.index + 1, name);
-                let max_chars = max_label_chars(TRACK_COL_WIDTH, TRACK_LABEL_FONT_SIZE);
-                let label = ellipsize_text(&label, max_chars);
+                let max_chars = max_label_chars(layout.track_col, TRACK_LABEL_FONT_SIZE);
+                let short_label = ellipsize_text(&label, max_chars);
                 let _ = parent
                     .spawn((
                         Node {
@@ -747,29 +1272,39 @@ pub(super) fn update_tracks_list(
                         },
                         BackgroundColor(Color::NONE),
                         TrackRow { index: row_index },
+                        Interaction::default(),
                     ))
                     .with_children(|parent| {
                         let _ = parent
-                            .spawn((Node {
-                                width: Val::Px(TRACK_COL_WIDTH),
-                                ..default()
-                            },))
+                            .spawn((
+                                Node {
+                                    width: Val::Px(layout.track_col),
+                                    ..default()
+                                },
+                                TrackNameColumn,
+                            ))
                             .with_children(|parent| {
                                 let _ = parent.spawn((
-                                    Text::new(label),
+                                    Text::new(short_label),
                                     TextFont {
                                         font: font.clone(),
                                         font_size: TRACK_LABEL_FONT_SIZE,
                                         ..default()
                                     },
                                     TextColor(Color::WHITE),
+                                    TrackLabel { full: label },
                                 ));
                             });
                         let _ = parent
-                            .spawn((Node {
-                                width: Val::Px(EVENT_COL_WIDTH),
-                                ..default()
-                            },))
+                            .spawn((
+                                Node {
+                                    width: Val::Px(layout.event_col),
+                                    flex_direction: FlexDirection::Column,
+                                    row_gap: Val::Px(2.0),
+                                    ..default()
+                                },
+                                EventColumn,
+                            ))
                             .with_children(|parent| {
                                 let _ = parent.spawn((
                                     Text::new(track.event_count.to_string()),
@@ -780,6 +1315,25 @@ pub(super) fn update_tracks_list(
                                     },
                                     TextColor(Color::WHITE),
                                 ));
+                                let _ = parent
+                                    .spawn((
+                                        Node {
+                                            width: Val::Percent(100.0),
+                                            height: Val::Px(DENSITY_BAR_HEIGHT),
+                                            ..default()
+                                        },
+                                        BackgroundColor(DENSITY_BAR_TRACK_COLOR),
+                                    ))
+                                    .with_children(|parent| {
+                                        let _ = parent.spawn((
+                                            Node {
+                                                width: Val::Percent(densities[row_index] * 100.0),
+                                                height: Val::Percent(100.0),
+                                                ..default()
+                                            },
+                                            BackgroundColor(DENSITY_BAR_FILL_COLOR),
+                                        ));
+                                    });
                             });
                         let width_px = (track.preview_width as f32 * PREVIEW_CELL_SIZE).round();
                         let height_px = (track.preview_height as f32 * PREVIEW_CELL_SIZE).round();
@@ -787,6 +1341,9 @@ pub(super) fn update_tracks_list(
                         let height_px = height_px.max(1.0) as u32;
                         let image = build_track_preview_image_scaled(
                             track,
+                            preferences.preview_mode,
+                            preferences.mini_roll_preview,
+                            preferences.channel_palette,
                             width_px,
                             height_px,
                             &mut images,
@@ -807,6 +1364,9 @@ pub(super) fn update_tracks_list(
                                     track_index: track.index,
                                     image: image.clone(),
                                     last_size: (width_px, height_px),
+                                    last_mode: preferences.preview_mode,
+                                    last_mini_roll: preferences.mini_roll_preview,
+                                    last_palette: preferences.channel_palette,
                                 },
                             ))
                             .with_children(|parent| {
@@ -825,8 +1385,25 @@ pub(super) fn update_tracks_list(
                                             image_mode: NodeImageMode::Stretch,
                                             ..default()
                                         },
+                                        TrackPreviewImage,
+                                        Interaction::default(),
+                                        RelativeCursorPosition::default(),
+                                        FocusPolicy::Pass,
                                     ))
                                     .id();
+                                let _ = parent.spawn((
+                                    Node {
+                                        position_type: PositionType::Absolute,
+                                        top: Val::Px(0.0),
+                                        height: Val::Percent(100.0),
+                                        border: UiRect::horizontal(Val::Px(LOOP_MARKER_WIDTH)),
+                                        display: Display::None,
+                                        ..default()
+                                    },
+                                    BackgroundColor(Color::srgba(1.0, 1.0, 1.0, 0.15)),
+                                    BorderColor::all(LOOP_MARKER_COLOR),
+                                    TrackLoopHighlight,
+                                ));
                                 let _ = parent.spawn((
                                     Node {
                                         position_type: PositionType::Absolute,
@@ -849,6 +1426,71 @@ pub(super) fn update_tracks_list(
     });
 }
 
+pub(super) fn update_tracks_layout(
+    ui_state: Res<UiState>,
+    mut layout: ResMut<TracksLayout>,
+    viewports: Query<&ComputedNode, With<TracksListViewport>>,
+    mut name_columns: Query<&mut Node, (With<TrackNameColumn>, Without<EventColumn>)>,
+    mut event_columns: Query<&mut Node, (With<EventColumn>, Without<TrackNameColumn>)>,
+) {
+    if !ui_state.page.shows_tracks() {
+        return;
+    }
+    let Some(viewport) = viewports.iter().next() else {
+        return;
+    };
+    let width = viewport.size.x * viewport.inverse_scale_factor;
+    if width <= 0.0 {
+        return;
+    }
+    let next = compute_column_widths(width);
+    if *layout != next {
+        *layout = next;
+    }
+    for mut node in &mut name_columns {
+        if node.width != Val::Px(layout.track_col) {
+            node.width = Val::Px(layout.track_col);
+        }
+    }
+    for mut node in &mut event_columns {
+        if node.width != Val::Px(layout.event_col) {
+            node.width = Val::Px(layout.event_col);
+        }
+    }
+}
+
+pub(super) fn update_track_labels(
+    fonts: Res<UiFonts>,
+    font_assets: Res<Assets<Font>>,
+    layout: Res<TracksLayout>,
+    mut labels: Query<(Ref<TrackLabel>, &mut Text)>,
+) {
+    let font_changed = font_assets.is_changed() || fonts.is_changed() || layout.is_changed();
+    let face = font_assets
+        .get(&fonts.main)
+        .and_then(|font| ttf_parser::Face::parse(&font.data, 0).ok());
+    let Some(face) = face else {
+        return;
+    };
+
+    for (label, mut text) in &mut labels {
+        if !font_changed && !label.is_added() {
+            continue;
+        }
+        let max_chars =
+            measured_label_chars(&face, &label.full, TRACK_LABEL_FONT_SIZE, layout.track_col);
+        text.0 = ellipsize_text(&label.full, max_chars);
+    }
+}
+
+fn hidden_tracks_label(hidden: usize) -> String {
+    match hidden {
+        0 => String::new(),
+        1 => "1 empty track hidden.".to_string(),
+        count => format!("{count} empty tracks hidden."),
+    }
+}
+
 fn collect_descendants(entity: Entity, children_query: &Query<&Children>, out: &mut Vec<Entity>) {
     let Ok(children) = children_query.get(entity) else {
         return;
@@ -894,20 +1536,21 @@ fn scale_preview_cells(
     scaled
 }
 
-fn render_preview_rgba(cells: &[u16], width: u32, height: u32) -> Vec<u8> {
+fn render_preview_rgba(cells: &[u16], width: u32, height: u32, on_color: Color) -> Vec<u8> {
     let width = width.max(1);
     let height = height.max(1);
     let mut data = vec![0u8; (width * height * 4) as usize];
     let base_color = preview_color(0).to_srgba().to_u8_array();
+    let on_color = on_color.to_srgba().to_u8_array();
     for pixel in data.chunks_exact_mut(4) {
         pixel.copy_from_slice(&base_color);
     }
 
     for (idx, intensity) in cells.iter().enumerate() {
         let color = if *intensity == 0 {
-            preview_color(0).to_srgba().to_u8_array()
+            base_color
         } else {
-            preview_color(1).to_srgba().to_u8_array()
+            on_color
         };
         let offset = idx * 4;
         if offset + 4 <= data.len() {
@@ -923,6 +1566,25 @@ fn compute_ruler_left(ratio: f32, width_px: f32) -> f32 {
     (ratio * width_px).min(max_left)
 }
 
+// A node's size in the logical units `Val::Px` takes. The node carries its
+// own scale factor, so this holds without a window, as in headless runs or
+// the frame before one exists.
+fn logical_size(node: &ComputedNode) -> Vec2 {
+    node.size * node.inverse_scale_factor
+}
+
+// Horizontal extent of a node in window coordinates as `(left, centre,
+// right)`, or `None` without a window to measure from.
+fn window_span_x(
+    window_width: Option<f32>,
+    translation_x: f32,
+    width: f32,
+) -> Option<(f32, f32, f32)> {
+    let centre = window_width? * 0.5 + translation_x;
+    let half = width * 0.5;
+    Some((centre - half, centre, centre + half))
+}
+
 pub(super) fn update_track_ruler(
     ui_state: Res<UiState>,
     audio_state: Res<AudioState>,
@@ -930,48 +1592,213 @@ pub(super) fn update_track_ruler(
     computed_nodes: Query<&ComputedNode>,
     windows: Query<&Window, With<PrimaryWindow>>,
 ) {
-    if ui_state.page != UiPage::Tracks {
+    if !ui_state.page.shows_tracks() {
         return;
     }
 
     let ratio = audio_state.current_tick_ratio();
-    let scale = windows
-        .iter()
-        .next()
-        .map(|window| window.scale_factor() as f32)
-        .unwrap_or(1.0);
+    let window_size = primary_window_size(&windows);
     for (mut node, ruler) in &mut rulers {
         let Ok(image_node) = computed_nodes.get(ruler.image_entity) else {
             node.display = Display::None;
             continue;
         };
+        if !has_render_area(image_node.size, window_size) {
+            continue;
+        }
 
         let Some(ratio) = ratio else {
             node.display = Display::None;
             continue;
         };
 
-        let width_px = image_node.size.x / scale.max(1.0);
-        let left_px = compute_ruler_left(ratio, width_px);
+        let size = logical_size(image_node);
         node.display = Display::Flex;
-        node.left = Val::Px(left_px);
-        node.height = Val::Px(image_node.size.y);
+        node.left = Val::Px(compute_ruler_left(ratio, size.x));
+        node.height = Val::Px(size.y);
+    }
+}
+
+fn polyphony_label(limit: u16, held: u64, peak: u64) -> String {
+    let label = format!("{limit} voices, {held} notes held (peak {peak})");
+    // Every held note needs at least one voice, so a peak past the limit
+    // means the synth had to steal voices.
+    if peak > limit as u64 {
+        format!("{label}, voices stolen")
+    } else {
+        label
+    }
+}
+
+// The pedal state on a channel is whatever its latest CC64 at or before
+// `tick` set; a channel with no sustain events is treated as released.
+fn pedal_down_at<'a>(
+    events: impl IntoIterator<Item = &'a (u64, u8, bool)>,
+    channel: u8,
+    tick: u64,
+) -> bool {
+    events
+        .into_iter()
+        .filter(|(event_tick, event_channel, _)| *event_channel == channel && *event_tick <= tick)
+        .max_by_key(|(event_tick, _, _)| *event_tick)
+        .is_some_and(|(_, _, down)| *down)
+}
+
+// Like the pedal, a channel's pan is set by its latest CC10 at or before
+// `tick`; channels that never set it sit at the centre.
+pub(super) fn pan_at<'a>(
+    events: impl IntoIterator<Item = &'a (u64, u8, u8)>,
+    channel: u8,
+    tick: u64,
+) -> u8 {
+    events
+        .into_iter()
+        .filter(|(event_tick, event_channel, _)| *event_channel == channel && *event_tick <= tick)
+        .max_by_key(|(event_tick, _, _)| *event_tick)
+        .map_or(CENTER_PAN, |(_, _, value)| *value)
+}
+
+// Left edge of the pan marker inside its channel cell, keeping the marker
+// within the cell at both extremes.
+fn pan_marker_percent(pan: u8) -> f32 {
+    let travel = 100.0 * (1.0 - CHANNEL_PAN_MARKER_SIZE / CHANNEL_CELL_SIZE);
+    pan.min(127) as f32 / 127.0 * travel
+}
+
+fn sustain_label(tracks: &[MidiTrackInfo], tick: Option<u64>) -> String {
+    let Some(tick) = tick else {
+        return "---".to_string();
+    };
+    let held = (0..16u8)
+        .filter(|channel| {
+            pedal_down_at(
+                tracks.iter().flat_map(|track| &track.sustain_events),
+                *channel,
+                tick,
+            )
+        })
+        .collect::<Vec<_>>();
+    if held.is_empty() {
+        "---".to_string()
+    } else {
+        format!("SUS ch {}", channel_list_label(&held))
+    }
+}
+
+// Position of `tick` across a preview that spans the file up to its last
+// note, as the same 0..1 ratio the playback ruler is placed with.
+fn preview_tick_ratio(tick: u64, ruler_max_tick: u64) -> f32 {
+    if ruler_max_tick == 0 {
+        return 0.0;
+    }
+    (tick as f64 / ruler_max_tick as f64).min(1.0) as f32
+}
+
+// Inverse of `preview_tick_ratio`, for a click across the preview.
+fn preview_ratio_tick(ratio: f32, ruler_max_tick: u64) -> u64 {
+    (ratio.clamp(0.0, 1.0) as f64 * ruler_max_tick as f64).round() as u64
+}
+
+pub(super) fn seek_clicked_preview(
+    ui_state: Res<UiState>,
+    audio_state: Res<AudioState>,
+    audio_tx: Res<AudioSender>,
+    previews: ClickedNodes<TrackPreviewImage>,
+) {
+    if !ui_state.page.shows_tracks() {
+        return;
+    }
+    let Some(ruler_max_tick) = audio_state.ruler_max_tick() else {
+        return;
+    };
+    for (interaction, cursor) in &previews {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        if let Some(position) = cursor.normalized {
+            let tick = preview_ratio_tick(position.x + 0.5, ruler_max_tick);
+            let _ = audio_tx.0.send(AudioCommand::Seek(tick));
+        }
     }
 }
 
+// Returns the left edge and width of the loop band as percentages of the
+// preview.
+fn loop_highlight_span(loop_ticks: (u64, u64), ruler_max_tick: u64) -> Option<(f32, f32)> {
+    let (start, end) = loop_ticks;
+    if ruler_max_tick == 0 || start >= ruler_max_tick || end <= start {
+        return None;
+    }
+    let left = preview_tick_ratio(start, ruler_max_tick) * 100.0;
+    let right = preview_tick_ratio(end, ruler_max_tick) * 100.0;
+    Some((left, right - left))
+}
+
+pub(super) fn update_loop_highlights(
+    ui_state: Res<UiState>,
+    loop_region: Res<LoopRegion>,
+    midi_tracks: Res<MidiTracks>,
+    mut highlights: Query<&mut Node, With<TrackLoopHighlight>>,
+) {
+    if !ui_state.page.shows_tracks() {
+        return;
+    }
+
+    let ruler_max_tick = midi_tracks
+        .0
+        .iter()
+        .flat_map(|track| track.note_spans.iter().map(|span| span.end))
+        .max()
+        .or_else(|| midi_tracks.0.iter().map(|track| track.end_tick).max())
+        .unwrap_or(0);
+    let range = loop_tick_range(&loop_region, &midi_tracks.0);
+    let span = range.and_then(|range| loop_highlight_span(range, ruler_max_tick));
+    // The end marker is hidden when the loop runs past the last note.
+    let end_marker = match range {
+        Some((_, end)) if end <= ruler_max_tick => Val::Px(LOOP_MARKER_WIDTH),
+        _ => Val::Px(0.0),
+    };
+    for mut node in &mut highlights {
+        match span {
+            Some((left, width)) => {
+                node.display = Display::Flex;
+                node.left = Val::Percent(left);
+                node.width = Val::Percent(width);
+                node.border.right = end_marker;
+            }
+            None => node.display = Display::None,
+        }
+    }
+}
+
+/// The debug overlay and the ruler geometry it reports.
+#[derive(SystemParam)]
+pub(super) struct DebugOverlayNodes<'w, 's> {
+    query: Query<'w, 's, &'static mut Text, With<DebugOverlayText>>,
+    overlay_nodes: Query<'w, 's, &'static mut Node, With<DebugOverlayRoot>>,
+    rulers: Query<'w, 's, (Entity, &'static TrackRuler)>,
+    nodes: Query<'w, 's, (&'static ComputedNode, &'static UiGlobalTransform)>,
+    windows: Query<'w, 's, &'static Window, With<PrimaryWindow>>,
+}
+
 pub(super) fn update_debug_overlay(
     ui_state: Res<UiState>,
     audio_state: Res<AudioState>,
     overlay_state: Res<DebugOverlayState>,
-    mut query: Query<&mut Text, With<DebugOverlayText>>,
-    rulers: Query<(Entity, &TrackRuler)>,
-    nodes: Query<(&ComputedNode, &UiGlobalTransform)>,
-    windows: Query<&Window, With<PrimaryWindow>>,
-    mut overlay_nodes: Query<&mut Node, With<DebugOverlayRoot>>,
+    preferences: Res<Preferences>,
+    midi_tracks: Res<MidiTracks>,
+    overlay: DebugOverlayNodes,
 ) {
-    if ui_state.page != UiPage::Tracks {
+    if !ui_state.page.shows_tracks() {
         return;
     }
+    let DebugOverlayNodes {
+        mut query,
+        mut overlay_nodes,
+        rulers,
+        nodes,
+        windows,
+    } = overlay;
 
     let show_overlay = overlay_state.visible;
     for mut node in &mut overlay_nodes {
@@ -992,29 +1819,33 @@ pub(super) fn update_debug_overlay(
     let mut ruler_x = None;
     let mut ruler_left = None;
 
-    let window = windows.iter().next();
-    let center_x = window.map(|w| w.resolution.width() * 0.5).unwrap_or(0.0);
-
+    let window_width = windows.iter().next().map(|w| w.resolution.width());
     if let Some((ruler_entity, ruler)) = rulers.iter().next() {
         if let Ok((ruler_node, ruler_transform)) = nodes.get(ruler_entity) {
-            let ruler_center = center_x + ruler_transform.translation.x;
-            let ruler_half = ruler_node.size.x * 0.5;
-            ruler_x = Some(ruler_center);
-            ruler_left = Some(ruler_center - ruler_half);
+            if let Some((left, centre, _)) = window_span_x(
+                window_width,
+                ruler_transform.translation.x,
+                ruler_node.size.x,
+            ) {
+                ruler_x = Some(centre);
+                ruler_left = Some(left);
+            }
         }
         if let Ok((image_node, image_transform)) = nodes.get(ruler.image_entity) {
-            let image_center = center_x + image_transform.translation.x;
-            let half = image_node.size.x * 0.5;
-            let left = image_center - half;
-            let right = image_center + half;
-            image_left = Some(left);
-            image_right = Some(right);
+            if let Some((left, _, right)) = window_span_x(
+                window_width,
+                image_transform.translation.x,
+                image_node.size.x,
+            ) {
+                image_left = Some(left);
+                image_right = Some(right);
+            }
         }
     }
 
     for mut text in &mut query {
         text.0 = format!(
-            "samples: {}/{}\nlast: {} -> {}\nnext: {} -> {}\nmax_tick: {}\nratio: {:.4}\nimg_x: {:?}..{:?}\nruler_x: {:?}\nruler_left: {:?}",
+            "samples: {}/{}\nlast: {} -> {}\nnext: {} -> {}\nmax_tick: {}\nratio: {:.4}\nimg_x: {:?}..{:?}\nruler_x: {:?}\nruler_left: {:?}\ninterpolation: {:?}\nsample_rate: {}\nsustain: {}\npolyphony: {}",
             debug.samples_played,
             debug.total_samples,
             debug.last_event_sample,
@@ -1026,8 +1857,51 @@ pub(super) fn update_debug_overlay(
             image_left,
             image_right,
             ruler_x,
-            ruler_left
+            ruler_left,
+            preferences.interpolation,
+            audio_state.sample_rate(),
+            sustain_label(&midi_tracks.0, audio_state.current_tick()),
+            polyphony_label(preferences.polyphony, debug.held_notes, debug.peak_notes)
+        );
+    }
+}
+
+pub(super) fn update_channel_activity(
+    ui_state: Res<UiState>,
+    audio_state: Res<AudioState>,
+    midi_tracks: Res<MidiTracks>,
+    preferences: Res<Preferences>,
+    mut cells: Query<(&ChannelActivityCell, &mut BackgroundColor)>,
+    mut pan_markers: Query<(&ChannelPanMarker, &mut Node)>,
+) {
+    if !ui_state.page.shows_tracks() {
+        return;
+    }
+
+    let active = audio_state.active_channels(CHANNEL_ACTIVITY_WINDOW_SECS);
+    for (cell, mut bg) in &mut cells {
+        bg.0 = if active[cell.channel] {
+            preferences
+                .channel_palette
+                .map_or(CHANNEL_ACTIVE_COLOR, |palette| {
+                    channel_color(cell.channel as u8, palette)
+                })
+        } else {
+            CHANNEL_IDLE_COLOR
+        };
+    }
+
+    let tick = audio_state.current_tick().unwrap_or(0);
+    for (marker, mut node) in &mut pan_markers {
+        let pan = pan_at(
+            midi_tracks.0.iter().flat_map(|track| &track.pan_events),
+            marker.channel as u8,
+            tick,
         );
+        let left = Val::Percent(pan_marker_percent(pan));
+        if node.left != left {
+            node.left = left;
+        }
     }
 }
 
@@ -1046,7 +1920,7 @@ pub(super) fn update_tracks_focus_visuals(
     midi_tracks: Res<MidiTracks>,
     mut rows: Query<(&TrackRow, &mut BackgroundColor)>,
 ) {
-    if ui_state.page != UiPage::Tracks {
+    if !ui_state.page.shows_tracks() {
         return;
     }
 
@@ -1061,7 +1935,7 @@ pub(super) fn update_tracks_focus_visuals(
     };
 
     for (row, mut bg) in &mut rows {
-        let is_focused = focused.map_or(false, |index| row.index == index);
+        let is_focused = focused.is_some_and(|index| row.index == index);
         bg.0 = if is_focused {
             Color::srgb(0.2, 0.3, 0.6)
         } else {
@@ -1073,17 +1947,37 @@ pub(super) fn update_tracks_focus_visuals(
 pub(super) fn update_track_previews(
     ui_state: Res<UiState>,
     midi_tracks: Res<MidiTracks>,
-    mut previews: Query<(&ComputedNode, &mut TrackPreview, &mut ImageNode), Changed<ComputedNode>>,
+    preferences: Res<Preferences>,
+    mut previews: Query<(&ComputedNode, &mut TrackPreview, &mut ImageNode)>,
     mut images: ResMut<Assets<Image>>,
+    windows: Query<&Window, With<PrimaryWindow>>,
 ) {
-    if ui_state.page != UiPage::Tracks {
+    if !ui_state.page.shows_tracks() {
         return;
     }
 
+    let window_size = primary_window_size(&windows);
     for (computed, mut preview, mut image_node) in &mut previews {
+        if !has_render_area(computed.size, window_size) {
+            continue;
+        }
         let width_px = computed.size.x.round().max(1.0) as u32;
         let height_px = computed.size.y.round().max(1.0) as u32;
-        if preview.last_size == (width_px, height_px) {
+        let mode = preferences.preview_mode;
+        let mini_roll = preferences.mini_roll_preview;
+        let palette = preferences.channel_palette;
+        // Mini rolls render at a fixed size and stretch, so only a style
+        // change rebuilds them.
+        let stale = if preview.last_palette != palette {
+            true
+        } else if mini_roll {
+            !preview.last_mini_roll
+        } else {
+            preview.last_mini_roll
+                || preview.last_size != (width_px, height_px)
+                || preview.last_mode != mode
+        };
+        if !stale {
             continue;
         }
 
@@ -1091,13 +1985,21 @@ pub(super) fn update_track_previews(
             continue;
         };
 
-        let new_handle = build_track_preview_image_scaled(track, width_px, height_px, &mut images);
-        let old_handle = std::mem::replace(&mut preview.image, new_handle.clone());
+        let new_handle = build_track_preview_image_scaled(
+            track,
+            mode,
+            mini_roll,
+            palette,
+            width_px,
+            height_px,
+            &mut images,
+        );
+        image_node.image = new_handle.clone();
+        replace_image(&mut preview.image, new_handle, &mut images);
         preview.last_size = (width_px, height_px);
-        image_node.image = new_handle;
-        if old_handle != preview.image {
-            let _image = images.remove(old_handle.id());
-        }
+        preview.last_mode = mode;
+        preview.last_mini_roll = mini_roll;
+        preview.last_palette = palette;
     }
 }
 
@@ -1105,10 +2007,12 @@ pub(super) fn update_track_details_popup(
     ui_state: Res<UiState>,
     popup: Res<TrackDetailsPopup>,
     midi_tracks: Res<MidiTracks>,
+    preferences: Res<Preferences>,
+    tempo_override: Res<TempoOverride>,
     mut root_query: Query<&mut Node, With<TrackDetailsPopupRoot>>,
     mut fields: Query<(&TrackDetailsField, &mut Text)>,
 ) {
-    if ui_state.page != UiPage::Tracks {
+    if !ui_state.page.shows_tracks() {
         for mut node in &mut root_query {
             node.display = Display::None;
         }
@@ -1124,6 +2028,8 @@ pub(super) fn update_track_details_popup(
     }
 
     let track = midi_tracks.0.get(popup.track_index);
+    let tempo_map = file_tempo_map(&midi_tracks.0, tempo_override.0);
+    let display = preferences.time_display;
     for (field, mut text) in &mut fields {
         text.0 = match field.field {
             TrackDetailsFieldKind::Title => "Track Details".to_string(),
@@ -1138,9 +2044,21 @@ pub(super) fn update_track_details_popup(
             TrackDetailsFieldKind::Events => track
                 .map(|t| format!("Events: {}", t.event_count))
                 .unwrap_or_else(|| "Events: -".to_string()),
-            TrackDetailsFieldKind::EndTick => track
-                .map(|t| format!("End tick: {}", t.end_tick))
-                .unwrap_or_else(|| "End tick: -".to_string()),
+            TrackDetailsFieldKind::EndTick => {
+                let prefix = match display {
+                    TimeDisplay::Time => "End",
+                    TimeDisplay::Ticks => "End tick",
+                };
+                track
+                    .map(|t| {
+                        format!(
+                            "{}: {}",
+                            prefix,
+                            position_label(t.end_tick, &tempo_map, display)
+                        )
+                    })
+                    .unwrap_or_else(|| format!("{}: -", prefix))
+            }
             TrackDetailsFieldKind::TicksPerBeat => track
                 .map(|t| format!("Ticks per beat: {}", t.ticks_per_beat))
                 .unwrap_or_else(|| "Ticks per beat: -".to_string()),
@@ -1149,10 +2067,14 @@ pub(super) fn update_track_details_popup(
                 .unwrap_or_else(|| "Notes: -".to_string()),
             TrackDetailsFieldKind::PitchRange => track
                 .map(|t| {
-                    format!(
-                        "Pitch range: {}",
-                        pitch_range_label(t.min_pitch, t.max_pitch)
-                    )
+                    if is_percussion_track(t) {
+                        format!("Drums: {}", drum_range_label(t.min_pitch, t.max_pitch))
+                    } else {
+                        format!(
+                            "Pitch range: {}",
+                            pitch_range_label(t.min_pitch, t.max_pitch)
+                        )
+                    }
                 })
                 .unwrap_or_else(|| "Pitch range: -".to_string()),
             TrackDetailsFieldKind::Channels => track
@@ -1165,7 +2087,12 @@ pub(super) fn update_track_details_popup(
                 .map(|t| format!("Banks: {}", banks_label(&t.banks)))
                 .unwrap_or_else(|| "Banks: -".to_string()),
             TrackDetailsFieldKind::TempoChanges => track
-                .map(|t| format!("Tempo changes: {}", t.tempo_changes))
+                .map(|t| {
+                    format!(
+                        "Tempo changes: {}",
+                        tempo_changes_label(&t.timing.tempo_events, &tempo_map, display)
+                    )
+                })
                 .unwrap_or_else(|| "Tempo changes: -".to_string()),
             TrackDetailsFieldKind::TimeSignature => track
                 .map(|t| format!("Time signature: {}", time_signature_label(t.time_signature)))
@@ -1173,10 +2100,69 @@ pub(super) fn update_track_details_popup(
             TrackDetailsFieldKind::KeySignature => track
                 .map(|t| format!("Key signature: {}", key_signature_label(t.key_signature)))
                 .unwrap_or_else(|| "Key signature: -".to_string()),
+            TrackDetailsFieldKind::Articulation => track
+                .map(|t| format!("Articulation: {}", articulation_label(t.articulation)))
+                .unwrap_or_else(|| "Articulation: -".to_string()),
+            TrackDetailsFieldKind::NoteLengths => track
+                .map(|t| note_lengths_label(&t.rhythm))
+                .unwrap_or_else(|| "Note lengths: -".to_string()),
+            TrackDetailsFieldKind::Rests => track
+                .map(|t| rests_label(t.rhythm.rest_ticks, t.ticks_per_beat))
+                .unwrap_or_else(|| "Rests: -".to_string()),
+            TrackDetailsFieldKind::MidiStandard => {
+                midi_standard_label(file_midi_standard(&midi_tracks.0))
+            }
+            TrackDetailsFieldKind::Instrument => {
+                instrument_label(track.and_then(|t| t.instrument_name.as_deref()))
+            }
+            TrackDetailsFieldKind::MidiPort => port_label(
+                track.and_then(|t| t.midi_port),
+                track.and_then(|t| t.channel_prefix),
+            ),
+            TrackDetailsFieldKind::CuePoints => {
+                cue_points_label(track.map(|t| t.cue_points.as_slice()).unwrap_or(&[]))
+            }
+            TrackDetailsFieldKind::Copyright => format!(
+                "Copyright: {}",
+                file_copyright(&midi_tracks.0).unwrap_or("-")
+            ),
         };
     }
 }
 
+fn is_double_click(previous: Option<(usize, f64)>, index: usize, now: f64) -> bool {
+    previous.is_some_and(|(last_index, last_time)| {
+        last_index == index && now - last_time <= DOUBLE_CLICK_SECS
+    })
+}
+
+pub(super) fn click_track_rows(
+    ui_state: Res<UiState>,
+    time: Res<Time>,
+    rows: Query<(&TrackRow, &Interaction), Changed<Interaction>>,
+    mut tracks_focus: ResMut<TracksFocus>,
+    mut popup: ResMut<TrackDetailsPopup>,
+    mut last_click: Local<Option<(usize, f64)>>,
+) {
+    if !ui_state.page.shows_tracks() {
+        return;
+    }
+    let now = time.elapsed_secs_f64();
+    for (row, interaction) in &rows {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        tracks_focus.focus(row.index);
+        if is_double_click(*last_click, row.index, now) {
+            popup.visible = true;
+            popup.track_index = row.index;
+            *last_click = None;
+        } else {
+            *last_click = Some((row.index, now));
+        }
+    }
+}
+
 pub(super) fn update_tracks_scroll(
     ui_state: Res<UiState>,
     keyboard_input: Res<ButtonInput<KeyCode>>,
@@ -1185,7 +2171,7 @@ pub(super) fn update_tracks_scroll(
     viewport_query: Query<&ComputedNode, With<TracksListViewport>>,
     content_size_query: Query<&ComputedNode, With<TracksList>>,
 ) {
-    if ui_state.page != UiPage::Tracks {
+    if !ui_state.page.shows_tracks() {
         return;
     }
 
@@ -1222,20 +2208,51 @@ pub(super) fn update_tracks_scroll(
 
 fn build_track_preview_image_scaled(
     track: &MidiTrackInfo,
+    mode: PreviewMode,
+    mini_roll: bool,
+    palette: Option<ChannelPalette>,
     width: u32,
     height: u32,
     images: &mut Assets<Image>,
 ) -> Handle<Image> {
-    let width = width.max(1);
-    let height = height.max(1);
-    let scaled = scale_preview_cells(
-        &track.preview_cells,
-        track.preview_width,
-        track.preview_height,
-        width,
-        height,
-    );
-    let data = render_preview_rgba(&scaled, width, height);
+    let (width, height, data) = if mini_roll {
+        let data = build_piano_roll_data(
+            track,
+            MINI_ROLL_WIDTH,
+            MINI_ROLL_HEIGHT,
+            &PianoRollViewState::default(),
+            &RollOverlays::default(),
+            &PianoRollStyle {
+                channel_palette: palette,
+                ..default()
+            },
+        );
+        (MINI_ROLL_WIDTH, MINI_ROLL_HEIGHT, data)
+    } else {
+        let width = width.max(1);
+        let height = height.max(1);
+        let cells = match mode {
+            PreviewMode::Sustain => &track.preview_cells,
+            PreviewMode::Onset => &track.onset_preview_cells,
+        };
+        let scaled = scale_preview_cells(
+            cells,
+            track.preview_width,
+            track.preview_height,
+            width,
+            height,
+        );
+        // A density preview has no per-note channel, so it takes the
+        // colour of the track's first channel.
+        let on_color = palette
+            .map(|palette| channel_color(track.channels.first().copied().unwrap_or(0), palette))
+            .unwrap_or(preview_color(1));
+        (
+            width,
+            height,
+            render_preview_rgba(&scaled, width, height, on_color),
+        )
+    };
 
     let image = Image::new(
         Extent3d {
@@ -1257,11 +2274,20 @@ fn build_track_preview_image_scaled(
 #[cfg(test)]
 mod tests {
     use super::{
-        banks_label, channel_list_label, clamp_scroll_offset, compute_ruler_left, ellipsize_text,
-        key_signature_label, max_label_chars, pitch_range_label, preview_color, program_label,
-        programs_label, render_preview_rgba, scale_preview_cells, time_signature_label,
+        articulation_label, banks_label, channel_list_label, clamp_scroll_offset,
+        compute_column_widths, compute_ruler_left, cue_points_label, drum_range_label,
+        ellipsize_text, fit_label_chars, instrument_label, is_double_click, key_signature_label,
+        logical_size, loop_highlight_span, max_label_chars, measured_label_chars,
+        midi_standard_label, note_density_shares, note_lengths_label, pan_at, pan_marker_percent,
+        pedal_down_at, pitch_range_label, polyphony_label, port_label, position_label,
+        preview_color, preview_ratio_tick, preview_tick_ratio, program_label, programs_label,
+        render_preview_rgba, rests_label, scale_preview_cells, tempo_changes_label, time_label,
+        time_signature_label, window_span_x,
     };
+    use crate::state::{ArticulationCounts, MidiStandard, RhythmSummary, TimeDisplay};
+    use crate::tempo::TempoMap;
     use bevy::prelude::ColorToPacked;
+    use bevy::prelude::{default, ComputedNode, Vec2};
 
     #[test]
     fn scale_preview_cells_expands_nearest() {
@@ -1277,7 +2303,7 @@ mod tests {
     #[test]
     fn render_preview_rgba_writes_colors() {
         let cells = vec![0u16, 1u16, 0u16, 1u16];
-        let data = render_preview_rgba(&cells, 2, 2);
+        let data = render_preview_rgba(&cells, 2, 2, preview_color(1));
         assert_eq!(data.len(), 16);
         let off = preview_color(0).to_srgba().to_u8_array();
         let on = preview_color(1).to_srgba().to_u8_array();
@@ -1291,6 +2317,22 @@ mod tests {
         assert_eq!(compute_ruler_left(2.0, 10.0), 9.0);
     }
 
+    #[test]
+    fn ruler_geometry_does_not_need_a_window() {
+        let node = ComputedNode {
+            size: Vec2::new(800.0, 40.0),
+            inverse_scale_factor: 0.5,
+            ..default()
+        };
+        assert_eq!(logical_size(&node), Vec2::new(400.0, 20.0));
+        assert_eq!(compute_ruler_left(0.25, logical_size(&node).x), 100.0);
+        assert_eq!(window_span_x(None, 10.0, 100.0), None);
+        assert_eq!(
+            window_span_x(Some(1000.0), -100.0, 100.0),
+            Some((350.0, 400.0, 450.0))
+        );
+    }
+
     #[test]
     fn ellipsize_text_truncates() {
         assert_eq!(ellipsize_text("Hello", 10), "Hello");
@@ -1300,6 +2342,17 @@ mod tests {
         assert_eq!(ellipsize_text("Hello", 2), "..");
     }
 
+    #[test]
+    fn compute_column_widths_tracks_viewport() {
+        let narrow = compute_column_widths(640.0);
+        assert_eq!((narrow.track_col, narrow.event_col), (140.0, 64.0));
+        let medium = compute_column_widths(1280.0);
+        assert_eq!(medium.track_col, 256.0);
+        assert!((medium.event_col - 89.6).abs() < 1e-3);
+        let wide = compute_column_widths(3840.0);
+        assert_eq!((wide.track_col, wide.event_col), (360.0, 110.0));
+    }
+
     #[test]
     fn max_label_chars_scales_with_width() {
         let small = max_label_chars(50.0, 20.0);
@@ -1307,6 +2360,117 @@ mod tests {
         assert!(large > small);
     }
 
+    #[test]
+    fn articulation_label_reports_percentages() {
+        assert_eq!(articulation_label(ArticulationCounts::default()), "-");
+        let counts = ArticulationCounts {
+            staccato: 1,
+            normal: 1,
+            legato: 3,
+        };
+        assert_eq!(
+            articulation_label(counts),
+            "60% legato, 20% normal, 20% staccato"
+        );
+    }
+
+    #[test]
+    fn instrument_and_cue_labels_summarize() {
+        assert_eq!(instrument_label(Some("Strings")), "Instrument: Strings");
+        assert_eq!(instrument_label(None), "Instrument: -");
+        assert_eq!(port_label(None, None), "Port: -");
+        assert_eq!(port_label(Some(1), Some(9)), "Port: 2 (channel prefix 10)");
+        assert_eq!(cue_points_label(&[]), "Cues: None");
+        let cues = ["Intro", "Hit", "Fade", "End", "Tag"]
+            .iter()
+            .enumerate()
+            .map(|(i, text)| (i as u64 * 480, text.to_string()))
+            .collect::<Vec<_>>();
+        assert_eq!(cue_points_label(&cues[..2]), "Cues: Intro, Hit");
+        assert_eq!(cue_points_label(&cues), "Cues: Intro, Hit, Fade +2 more");
+    }
+
+    #[test]
+    fn midi_standard_label_names_mode() {
+        assert_eq!(
+            midi_standard_label(Some(MidiStandard::Gs)),
+            "Standard: GS mode"
+        );
+        assert_eq!(midi_standard_label(None), "Standard: Not specified");
+    }
+
+    #[test]
+    fn note_lengths_label_draws_scaled_bars() {
+        assert_eq!(
+            note_lengths_label(&RhythmSummary::default()),
+            "Note lengths: -"
+        );
+        let rhythm = RhythmSummary {
+            lengths: [0, 1, 12, 6, 0, 0],
+            irregular: 2,
+            rest_ticks: 720,
+        };
+        assert_eq!(
+            note_lengths_label(&rhythm),
+            "Note lengths:\n  1/2   # 1\n  1/4   ############ 12\n  1/8   ###### 6\n  other ## 2"
+        );
+        assert_eq!(rests_label(rhythm.rest_ticks, 480), "Rests: 1.5 beats");
+    }
+
+    #[test]
+    fn double_click_needs_the_same_row_in_time() {
+        assert!(!is_double_click(None, 2, 1.0));
+        assert!(is_double_click(Some((2, 1.0)), 2, 1.3));
+        assert!(!is_double_click(Some((2, 1.0)), 2, 1.5));
+        assert!(!is_double_click(Some((1, 1.0)), 2, 1.1));
+    }
+
+    #[test]
+    fn time_label_formats_minutes_and_seconds() {
+        assert_eq!(time_label(0.0), "00:00");
+        assert_eq!(time_label(65.4), "01:05");
+        assert_eq!(time_label(-1.0), "00:00");
+    }
+
+    #[test]
+    fn position_label_follows_time_display() {
+        let tempo_map = TempoMap::new(&[(0, 500_000), (960, 1_000_000)], 480);
+        assert_eq!(position_label(960, &tempo_map, TimeDisplay::Ticks), "960");
+        assert_eq!(position_label(960, &tempo_map, TimeDisplay::Time), "00:01");
+        assert_eq!(
+            position_label(60 * 480, &tempo_map, TimeDisplay::Time),
+            "00:59"
+        );
+        assert_eq!(
+            tempo_changes_label(
+                &[(0, 500_000), (960, 1_000_000)],
+                &tempo_map,
+                TimeDisplay::Ticks
+            ),
+            "2 (0, 960)"
+        );
+        assert_eq!(tempo_changes_label(&[], &tempo_map, TimeDisplay::Time), "0");
+    }
+
+    #[test]
+    fn fit_label_chars_uses_advances() {
+        let advances = [10.0; 8];
+        assert_eq!(fit_label_chars(&advances, 4.0, 80.0), 8);
+        assert_eq!(fit_label_chars(&advances, 4.0, 60.0), 7);
+        assert_eq!(fit_label_chars(&advances, 4.0, 10.0), 2);
+    }
+
+    #[test]
+    fn measured_label_chars_fits_column() {
+        let data = std::fs::read("assets/PixelifySans-Regular.ttf").expect("read font");
+        let face = ttf_parser::Face::parse(&data, 0).expect("parse font");
+        let label = "[01] Acoustic Grand Piano Left Hand";
+        let max_chars = measured_label_chars(&face, label, 24.0, 220.0);
+        assert!(max_chars > 3);
+        assert!(max_chars < label.chars().count());
+        assert_eq!(measured_label_chars(&face, "[01] Bass", 24.0, 220.0), 9);
+    }
+
     #[test]
     fn clamp_scroll_offset_bounds() {
         let offset = clamp_scroll_offset(0.0, 10.0, 100.0, 50.0);
@@ -1324,12 +2488,90 @@ mod tests {
         assert_eq!(pitch_range_label(60, 72), "60 - 72");
     }
 
+    #[test]
+    fn drum_range_label_names_the_drums() {
+        assert_eq!(drum_range_label(36, 49), "36 (Kick) - 49 (Crash)");
+    }
+
     #[test]
     fn channel_list_label_formats() {
         assert_eq!(channel_list_label(&[]), "-");
         assert_eq!(channel_list_label(&[0, 2, 9]), "1, 3, 10");
     }
 
+    #[test]
+    fn note_density_shares_scale_to_the_busiest_track() {
+        assert_eq!(
+            note_density_shares(&[(100, 1000), (50, 1000), (10, 200), (0, 500), (5, 0)]),
+            vec![1.0, 0.5, 0.5, 0.0, 0.0]
+        );
+        assert_eq!(note_density_shares(&[(0, 100), (0, 0)]), vec![0.0, 0.0]);
+        assert!(note_density_shares(&[]).is_empty());
+    }
+
+    #[test]
+    fn pan_at_uses_latest_event_and_defaults_to_center() {
+        let events = [(0, 0, 0), (480, 0, 127), (240, 1, 32)];
+        assert_eq!(pan_at(&events, 0, 0), 0);
+        assert_eq!(pan_at(&events, 0, 480), 127);
+        assert_eq!(pan_at(&events, 1, 100), 64);
+        assert_eq!(pan_at(&events, 1, 240), 32);
+        assert_eq!(pan_at(&events, 5, 1000), 64);
+        assert_eq!(pan_marker_percent(0), 0.0);
+        assert!((pan_marker_percent(127) - 100.0 * (1.0 - 3.0 / 22.0)).abs() < 1e-4);
+    }
+
+    #[test]
+    fn pedal_down_at_uses_latest_event_per_channel() {
+        let events = [
+            (0, 0, true),
+            (480, 0, false),
+            (240, 1, true),
+            (960, 0, true),
+        ];
+        assert!(pedal_down_at(&events, 0, 0));
+        assert!(pedal_down_at(&events, 0, 479));
+        assert!(!pedal_down_at(&events, 0, 480));
+        assert!(pedal_down_at(&events, 0, 1000));
+        assert!(!pedal_down_at(&events, 1, 100));
+        assert!(pedal_down_at(&events, 1, 240));
+        assert!(!pedal_down_at(&events, 2, 1000));
+    }
+
+    #[test]
+    fn polyphony_label_flags_stealing() {
+        assert_eq!(
+            polyphony_label(256, 12, 40),
+            "256 voices, 12 notes held (peak 40)"
+        );
+        assert_eq!(
+            polyphony_label(64, 0, 80),
+            "64 voices, 0 notes held (peak 80), voices stolen"
+        );
+    }
+
+    #[test]
+    fn preview_tick_ratio_matches_ruler_placement() {
+        assert_eq!(preview_tick_ratio(0, 1000), 0.0);
+        assert_eq!(preview_tick_ratio(250, 1000), 0.25);
+        assert_eq!(preview_tick_ratio(5000, 1000), 1.0);
+        assert_eq!(preview_tick_ratio(10, 0), 0.0);
+        assert_eq!(preview_ratio_tick(0.25, 1000), 250);
+        assert_eq!(preview_ratio_tick(1.5, 1000), 1000);
+        assert_eq!(
+            compute_ruler_left(preview_tick_ratio(250, 1000), 480.0),
+            120.0
+        );
+    }
+
+    #[test]
+    fn loop_highlight_span_clips_to_preview() {
+        assert_eq!(loop_highlight_span((0, 500), 1000), Some((0.0, 50.0)));
+        assert_eq!(loop_highlight_span((750, 4000), 1000), Some((75.0, 25.0)));
+        assert_eq!(loop_highlight_span((1000, 2000), 1000), None);
+        assert_eq!(loop_highlight_span((0, 500), 0), None);
+    }
+
     #[test]
     fn time_signature_label_formats() {
         assert_eq!(time_signature_label(None), "-");